@@ -0,0 +1,90 @@
+//! Telling when everyone online is idle, so that playtime does not run while nobody plays.
+//!
+//! A player counts as active when they chat, die, make an advancement or log in. If position
+//! polling is on, the server is also asked where each online player stands every so often, and
+//! moving counts as activity too.
+
+use serde_derive::Deserialize;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct AfkConfig {
+    pub enabled: bool,
+    /// How long a player may go without doing anything before they count as AFK.
+    pub idle_minutes: u64,
+    /// How often to ask the server where players are, or 0 to only go by their messages.
+    pub poll_seconds: u64,
+}
+impl Default for AfkConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            idle_minutes: 10,
+            poll_seconds: 60,
+        }
+    }
+}
+
+pub struct AfkTracker {
+    idle: Duration,
+    poll: Option<Duration>,
+    /// When each online player last did something.
+    active_at: HashMap<String, Instant>,
+    /// Where each online player stood when last polled, as the server printed it.
+    positions: HashMap<String, String>,
+    polled_at: Instant,
+}
+impl AfkTracker {
+    pub fn new(conf: &AfkConfig) -> Self {
+        Self {
+            idle: Duration::from_secs(conf.idle_minutes * 60),
+            poll: match conf.poll_seconds {
+                0 => None,
+                secs => Some(Duration::from_secs(secs)),
+            },
+            active_at: HashMap::new(),
+            positions: HashMap::new(),
+            polled_at: Instant::now(),
+        }
+    }
+
+    /// Note that a player did something.
+    pub fn active(&mut self, player: &str) {
+        self.active_at.insert(player.to_string(), Instant::now());
+    }
+
+    pub fn left(&mut self, player: &str) {
+        self.active_at.remove(player);
+        self.positions.remove(player);
+    }
+
+    /// Note where a player stands, counting them as active if they moved since last time.
+    pub fn position(&mut self, player: &str, pos: &str) {
+        if !self.active_at.contains_key(player) {
+            return;
+        }
+        match self.positions.insert(player.to_string(), pos.to_string()) {
+            Some(old) if old != pos => self.active(player),
+            _ => {}
+        }
+    }
+
+    /// The players to ask the position of, if it is time to poll again.
+    pub fn poll_due(&mut self) -> Option<Vec<String>> {
+        let poll = self.poll?;
+        if self.active_at.is_empty() || self.polled_at.elapsed() < poll {
+            return None;
+        }
+        self.polled_at = Instant::now();
+        Some(self.active_at.keys().cloned().collect())
+    }
+
+    /// Whether there are players online and all of them are idle.
+    pub fn everyone_afk(&self) -> bool {
+        !self.active_at.is_empty() && self.active_at.values().all(|at| at.elapsed() >= self.idle)
+    }
+}
@@ -0,0 +1,468 @@
+//! Paced queue of outgoing `say` announcements, or `tellraw` ones when `message_style` asks for
+//! colors.
+//!
+//! Announcements are sent one at a time with a minimum spacing between them, so chat stays
+//! readable even when several things happen at once. Standalone messages that are repeated while
+//! still queued are coalesced into a single message with a counter, and sequences (such as a
+//! death ceremony) are never interleaved with each other.
+
+use crate::logging;
+use serde_derive::Deserialize;
+use serde_json::{json, Value};
+use std::{
+    collections::VecDeque,
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    thread,
+    time::{Duration, Instant},
+};
+
+pub enum Step {
+    Say(Message),
+    /// Show a message across the screen: rolls as the big title, anything else as the subtitle.
+    Title(Message),
+    /// Send a server command as is.
+    Command(String),
+    Pause(Duration),
+}
+
+/// How the death ceremony is shown, as written in `ceremony_display`.
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(default)]
+pub struct CeremonyDisplay {
+    /// Show the ceremony with `title` instead of in chat, flashing the roll as the big title.
+    pub titles: bool,
+    /// Count down to the roll on a bossbar.
+    pub bossbar: bool,
+}
+
+/// Bossbar that counts down to the roll.
+const ROLL_BOSSBAR: &str = "trust_hardcore:roll";
+
+/// Add a bossbar that counts down the pauses before the roll, ticking once a second.
+pub fn with_countdown(steps: Vec<Step>) -> Vec<Step> {
+    let is_roll = |step: &Step| match step {
+        Step::Say(msg) | Step::Title(msg) => msg.kind == Kind::Roll,
+        _ => false,
+    };
+    let roll_at = steps.iter().position(is_roll).unwrap_or(steps.len());
+    let mut left_ms = steps[..roll_at]
+        .iter()
+        .map(|step| match step {
+            Step::Pause(pause) => pause.as_millis() as u64,
+            _ => 0,
+        })
+        .sum::<u64>();
+    let secs = left_ms.div_ceil(1000);
+    if secs == 0 {
+        return steps;
+    }
+    let bar = |cmd: String| Step::Command(format!("bossbar {}", cmd));
+    let mut out = vec![
+        bar(format!("add {} {}", ROLL_BOSSBAR, json!("Rolling..."))),
+        bar(format!("set {} color red", ROLL_BOSSBAR)),
+        bar(format!("set {} max {}", ROLL_BOSSBAR, secs)),
+        bar(format!("set {} value {}", ROLL_BOSSBAR, secs)),
+        bar(format!("set {} players @a", ROLL_BOSSBAR)),
+    ];
+    let len = steps.len();
+    for (idx, step) in steps.into_iter().enumerate() {
+        if idx == roll_at {
+            out.push(bar(format!("remove {}", ROLL_BOSSBAR)));
+        }
+        match step {
+            Step::Pause(pause) if idx < roll_at => {
+                let mut pause_ms = pause.as_millis() as u64;
+                while pause_ms > 0 {
+                    let tick_at = (left_ms.div_ceil(1000) - 1) * 1000;
+                    let wait = pause_ms.min(left_ms - tick_at);
+                    out.push(Step::Pause(Duration::from_millis(wait)));
+                    pause_ms -= wait;
+                    left_ms -= wait;
+                    if left_ms == tick_at {
+                        out.push(bar(format!(
+                            "set {} value {}",
+                            ROLL_BOSSBAR,
+                            left_ms / 1000
+                        )));
+                    }
+                }
+            }
+            step => out.push(step),
+        }
+    }
+    if roll_at == len {
+        out.push(bar(format!("remove {}", ROLL_BOSSBAR)));
+    }
+    out
+}
+
+/// What an announcement is about, which decides how it is styled.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Kind {
+    Info,
+    Death,
+    Roll,
+    Checkpoint,
+}
+
+/// An announcement, with what `tellraw` can show on top of the text.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Message {
+    pub text: String,
+    pub kind: Kind,
+    /// Shown when hovering over the message.
+    pub hover: Option<String>,
+    /// Put into the chat box when the message is clicked.
+    pub suggest: Option<String>,
+}
+impl Message {
+    pub fn new(kind: Kind, text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            kind,
+            hover: None,
+            suggest: None,
+        }
+    }
+
+    pub fn hover(mut self, hover: impl Into<String>) -> Self {
+        self.hover = Some(hover.into());
+        self
+    }
+
+    pub fn suggest(mut self, cmd: impl Into<String>) -> Self {
+        self.suggest = Some(cmd.into());
+        self
+    }
+}
+impl From<String> for Message {
+    fn from(text: String) -> Self {
+        Self::new(Kind::Info, text)
+    }
+}
+impl From<&str> for Message {
+    fn from(text: &str) -> Self {
+        Self::new(Kind::Info, text)
+    }
+}
+
+/// How announcements look, as written in `message_style`.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct MessageStyle {
+    /// Announce with `tellraw @a` instead of `say`, which allows colors, hover text and clicks.
+    pub tellraw: bool,
+    /// Text in front of every announcement, in place of the `[Server]` that `say` adds.
+    pub prefix: String,
+    pub prefix_style: TextStyle,
+    pub info: TextStyle,
+    pub death: TextStyle,
+    pub roll: TextStyle,
+    pub checkpoint: TextStyle,
+}
+impl Default for MessageStyle {
+    fn default() -> Self {
+        let color = |color: &str, bold| TextStyle {
+            color: Some(color.to_string()),
+            bold,
+            italic: false,
+        };
+        Self {
+            tellraw: false,
+            prefix: "[Hardcore] ".to_string(),
+            prefix_style: color("gold", false),
+            info: TextStyle::default(),
+            death: color("red", true),
+            roll: color("yellow", true),
+            checkpoint: color("green", false),
+        }
+    }
+}
+impl MessageStyle {
+    /// The server command that announces `msg`.
+    fn command(&self, msg: &Message, count: u32) -> String {
+        let text = if count > 1 {
+            format!("{} (x{})", msg.text, count)
+        } else {
+            msg.text.clone()
+        };
+        if !self.tellraw {
+            return format!("say {}", text);
+        }
+        let prefix = self.prefix_style.apply(json!({ "text": self.prefix }));
+        format!(
+            "tellraw @a {}",
+            json!(["", prefix, self.component(msg, text)])
+        )
+    }
+
+    /// The server commands that show `msg` as a title, which is always styled.
+    fn title_commands(&self, msg: &Message) -> Vec<String> {
+        let body = self.component(msg, msg.text.clone());
+        if msg.kind == Kind::Roll {
+            vec![
+                "title @a subtitle \"\"".to_string(),
+                format!("title @a title {}", body),
+            ]
+        } else {
+            //A subtitle only shows along with a title
+            vec![
+                format!("title @a subtitle {}", body),
+                "title @a title \"\"".to_string(),
+            ]
+        }
+    }
+
+    fn component(&self, msg: &Message, text: String) -> Value {
+        let style = match msg.kind {
+            Kind::Info => &self.info,
+            Kind::Death => &self.death,
+            Kind::Roll => &self.roll,
+            Kind::Checkpoint => &self.checkpoint,
+        };
+        let mut body = style.apply(json!({ "text": text }));
+        //Minecraft 1.21.5 renamed the events and their fields, older versions ignore the new names
+        //and newer ones the old
+        if let Some(hover) = &msg.hover {
+            body["hoverEvent"] = json!({ "action": "show_text", "contents": hover });
+            body["hover_event"] = json!({ "action": "show_text", "value": hover });
+        }
+        if let Some(cmd) = &msg.suggest {
+            body["clickEvent"] = json!({ "action": "suggest_command", "value": cmd });
+            body["click_event"] = json!({ "action": "suggest_command", "command": cmd });
+        }
+        body
+    }
+}
+
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct TextStyle {
+    /// A color name such as `"red"`, or a hex color such as `"#ff8800"`.
+    pub color: Option<String>,
+    pub bold: bool,
+    pub italic: bool,
+}
+impl TextStyle {
+    fn apply(&self, mut component: Value) -> Value {
+        if let Some(color) = &self.color {
+            component["color"] = json!(color);
+        }
+        if self.bold {
+            component["bold"] = json!(true);
+        }
+        if self.italic {
+            component["italic"] = json!(true);
+        }
+        component
+    }
+}
+
+/// A step of the death ceremony, as written in `ceremony`.
+///
+/// `say` may contain `{username}`, `{roll}`, `{breakdown}` (eg. `" (3 + 5)"` when several dice
+/// were rolled), `{outcome}` and `{deadly_rolls}`.
+#[derive(Deserialize, Clone)]
+pub struct CeremonyStep {
+    #[serde(default)]
+    pub say: Option<String>,
+    /// Seconds to wait after the message.
+    #[serde(default)]
+    pub pause: f32,
+    #[serde(default)]
+    pub when: When,
+}
+
+/// Which rolls a ceremony step plays for.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum When {
+    #[default]
+    Always,
+    /// Rolls that cost the world progress.
+    Deadly,
+    Safe,
+}
+
+pub fn default_ceremony() -> Vec<CeremonyStep> {
+    let step = |say: &str, pause, when| CeremonyStep {
+        say: Some(say.to_string()),
+        pause,
+        when,
+    };
+    vec![
+        step("{username} died", 3.0, When::Always),
+        step("Rolling dice...", 6.0, When::Always),
+        step("Rolled {roll}{breakdown}", 2.0, When::Always),
+        step("Always lucky boii", 1.0, When::Deadly),
+    ]
+}
+
+enum Item {
+    Say {
+        msg: Message,
+        count: u32,
+        standalone: bool,
+    },
+    Title(Message),
+    Command(String),
+    Pause(Duration),
+    Flush(Sender<()>),
+    Then(Callback),
+}
+
+type Callback = Box<dyn FnOnce() + Send>;
+
+enum Request {
+    Say(Message),
+    Sequence(Vec<Step>),
+    Style(MessageStyle),
+    Flush(Sender<()>),
+    Then(Callback),
+}
+
+pub struct Announcer {
+    tx: Sender<Request>,
+}
+impl Announcer {
+    /// Start the announcer thread, which sends its commands to `input`.
+    pub fn new(input: Sender<String>, min_interval: Duration, style: MessageStyle) -> Self {
+        let (tx, rx) = mpsc::channel();
+        logging::spawn(move || run(rx, input, min_interval, style));
+        Self { tx }
+    }
+
+    /// Queue a standalone message, merging it with an identical queued message if there is one.
+    pub fn say(&self, msg: impl Into<Message>) {
+        let _ = self.tx.send(Request::Say(msg.into()));
+    }
+
+    /// Style the messages sent from now on differently.
+    pub fn set_style(&self, style: MessageStyle) {
+        let _ = self.tx.send(Request::Style(style));
+    }
+
+    /// Queue a sequence of steps that will be played back without interruptions.
+    pub fn sequence(&self, steps: Vec<Step>) {
+        let _ = self.tx.send(Request::Sequence(steps));
+    }
+
+    /// Call `f` on the announcer thread once everything queued so far has been sent, without
+    /// waiting for it.
+    pub fn then(&self, f: impl FnOnce() + Send + 'static) {
+        let _ = self.tx.send(Request::Then(Box::new(f)));
+    }
+
+    /// Block until everything queued so far has been sent.
+    pub fn flush(&self) {
+        let (ack_tx, ack_rx) = mpsc::channel();
+        if self.tx.send(Request::Flush(ack_tx)).is_ok() {
+            let _ = ack_rx.recv();
+        }
+    }
+}
+
+fn enqueue(queue: &mut VecDeque<Item>, req: Request) {
+    match req {
+        Request::Say(msg) => {
+            for item in queue.iter_mut() {
+                if let Item::Say {
+                    msg: queued,
+                    count,
+                    standalone: true,
+                } = item
+                {
+                    if *queued == msg {
+                        *count += 1;
+                        return;
+                    }
+                }
+            }
+            queue.push_back(Item::Say {
+                msg,
+                count: 1,
+                standalone: true,
+            });
+        }
+        Request::Sequence(steps) => {
+            queue.extend(steps.into_iter().map(|step| match step {
+                Step::Say(msg) => Item::Say {
+                    msg,
+                    count: 1,
+                    standalone: false,
+                },
+                Step::Title(msg) => Item::Title(msg),
+                Step::Command(cmd) => Item::Command(cmd),
+                Step::Pause(time) => Item::Pause(time),
+            }));
+        }
+        Request::Flush(ack) => queue.push_back(Item::Flush(ack)),
+        Request::Then(f) => queue.push_back(Item::Then(f)),
+        Request::Style(_) => {}
+    }
+}
+
+fn run(
+    rx: Receiver<Request>,
+    input: Sender<String>,
+    min_interval: Duration,
+    mut style: MessageStyle,
+) {
+    let mut queue = VecDeque::new();
+    //Time before which no message may be sent
+    let mut ready_at = Instant::now();
+    loop {
+        //Gather new requests, blocking only if there is nothing to do
+        let req = if queue.is_empty() {
+            rx.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        } else {
+            rx.recv_timeout(ready_at.saturating_duration_since(Instant::now()))
+        };
+        match req {
+            Ok(Request::Style(new)) => {
+                style = new;
+                continue;
+            }
+            Ok(req) => {
+                enqueue(&mut queue, req);
+                continue;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) if queue.is_empty() => break,
+            Err(RecvTimeoutError::Disconnected) => {
+                thread::sleep(ready_at.saturating_duration_since(Instant::now()));
+            }
+        }
+        //Play back the next item
+        match queue.pop_front() {
+            Some(Item::Say { msg, count, .. }) => {
+                if input.send(style.command(&msg, count)).is_err() {
+                    break;
+                }
+                ready_at = Instant::now() + min_interval;
+            }
+            Some(Item::Title(msg)) => {
+                let commands = style.title_commands(&msg);
+                if commands.into_iter().any(|cmd| input.send(cmd).is_err()) {
+                    break;
+                }
+                ready_at = Instant::now() + min_interval;
+            }
+            Some(Item::Command(cmd)) => {
+                //Sent without holding back the next message
+                let sent = input.send(cmd);
+                if sent.is_err() {
+                    break;
+                }
+            }
+            Some(Item::Pause(time)) => {
+                ready_at = ready_at.max(Instant::now()) + time;
+            }
+            Some(Item::Flush(ack)) => {
+                let _ = ack.send(());
+            }
+            Some(Item::Then(f)) => f(),
+            None => {}
+        }
+    }
+}
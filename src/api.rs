@@ -0,0 +1,239 @@
+//! Optional HTTP control API, for frontends that cannot reach the wrapper's console.
+//!
+//! Every request must carry `Authorization: Bearer <api_token>`. Requests are handed to the main
+//! loop as [`Event::Api`] and answered from there, so they act on the same state as the console.
+//!
+//! - `GET /status`: online players, playtime and the last roll.
+//! - `POST /backup`: make a checkpoint, named if the body is `{"name": "..."}`.
+//! - `POST /restore`: rewind to `{"name": "..."}` or to automatic checkpoint `{"slot": n}`.
+//! - `POST /command`: send `{"command": "..."}` to the server.
+//! - `POST /stop`: stop the server and the wrapper.
+//! - `GET /events`: a WebSocket streaming the [`Feed`]. Browsers cannot set headers on
+//!   WebSockets, so the token may also be passed as `?token=<api_token>`.
+
+use crate::events::Event;
+use serde_json::Value;
+use std::sync::{
+    mpsc::{self, Receiver, Sender},
+    Arc, Mutex,
+};
+
+/// Live stream of run events as JSON objects, such as
+/// `{"type": "roll", "player": "bob", "roll": 4, "outcome": "reset"}`.
+#[derive(Clone, Default)]
+pub struct Feed {
+    subscribers: Arc<Mutex<Vec<Sender<String>>>>,
+}
+impl Feed {
+    pub fn subscribe(&self) -> Receiver<String> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// Send an event of the given type to every subscriber, forgetting the ones that went away.
+    pub fn publish(&self, kind: &str, mut event: Value) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        if subscribers.is_empty() {
+            return;
+        }
+        event["type"] = kind.into();
+        let text = event.to_string();
+        subscribers.retain(|tx| tx.send(text.clone()).is_ok());
+    }
+}
+
+/// What a request asks the main loop to do.
+pub enum ApiAction {
+    Status,
+    Backup(Option<String>),
+    RestoreNamed(String),
+    RestoreSlot(usize),
+    Command(String),
+}
+
+/// A request waiting for the main loop, which sends the response body or an error back through
+/// `reply`.
+pub struct ApiRequest {
+    pub action: ApiAction,
+    pub reply: Sender<Result<Value, String>>,
+}
+impl ApiRequest {
+    pub fn respond(self, res: Result<Value, String>) {
+        let _ = self.reply.send(res);
+    }
+}
+
+/// Start serving the API on `bind`, or do nothing if the build has no API support.
+pub fn spawn(
+    bind: &str,
+    token: &str,
+    events: Sender<Event>,
+    feed: Feed,
+) -> Result<(), Box<dyn std::error::Error>> {
+    imp::spawn(bind, token, events, feed)
+}
+
+#[cfg(feature = "api")]
+mod imp {
+    use super::{ApiAction, ApiRequest, Feed};
+    use crate::{events::Event, logging};
+    use log::{info, warn};
+    use serde_json::{json, Value};
+    use std::{
+        error::Error,
+        sync::mpsc::{self, Sender},
+        time::Duration,
+    };
+    use tiny_http::{Header, Method, Request, Response, Server};
+    use tungstenite::{handshake::derive_accept_key, protocol::Role, Message, WebSocket};
+
+    /// How long to wait for the main loop, which may be busy with a death or a checkpoint.
+    const REPLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+    pub fn spawn(
+        bind: &str,
+        token: &str,
+        events: Sender<Event>,
+        feed: Feed,
+    ) -> Result<(), Box<dyn Error>> {
+        let server = Server::http(bind).map_err(|err| format!("api on {}: {}", bind, err))?;
+        info!("serving control api on {}", bind);
+        let token = token.to_string();
+        logging::spawn(move || {
+            for mut req in server.incoming_requests() {
+                let (status, body) = if !authorized(&req, &token) {
+                    (401, json!({ "error": "missing or wrong api token" }))
+                } else if path(&req) == "/events" {
+                    match header(&req, "Sec-WebSocket-Key") {
+                        Some(key) => {
+                            let accept = derive_accept_key(key.as_bytes());
+                            stream_feed(req, accept, &feed);
+                            continue;
+                        }
+                        None => (400, json!({ "error": "expected a websocket upgrade" })),
+                    }
+                } else {
+                    handle(&mut req, &events)
+                };
+                respond(req, status, body);
+            }
+        });
+        Ok(())
+    }
+
+    fn respond(req: Request, status: u16, body: Value) {
+        let header = Header::from_bytes("Content-Type", "application/json").unwrap();
+        let res = Response::from_string(body.to_string())
+            .with_status_code(status)
+            .with_header(header);
+        if let Err(err) = req.respond(res) {
+            warn!("failed to answer api request: {}", err);
+        }
+    }
+
+    fn header<'a>(req: &'a Request, name: &'static str) -> Option<&'a str> {
+        req.headers()
+            .iter()
+            .find(|h| h.field.equiv(name))
+            .map(|h| h.value.as_str())
+    }
+
+    fn path(req: &Request) -> &str {
+        req.url().split('?').next().unwrap_or_default()
+    }
+
+    /// Check the token, in the `Authorization` header or in the `token` query parameter.
+    fn authorized(req: &Request, token: &str) -> bool {
+        let query = req.url().split_once('?').map(|(_, query)| query);
+        header(req, "Authorization").and_then(|auth| auth.strip_prefix("Bearer ")) == Some(token)
+            || query.is_some_and(|query| {
+                query
+                    .split('&')
+                    .any(|param| param.strip_prefix("token=") == Some(token))
+            })
+    }
+
+    /// Upgrade the request to a WebSocket and stream the feed into it.
+    fn stream_feed(req: Request, accept: String, feed: &Feed) {
+        let header = Header::from_bytes("Sec-WebSocket-Accept", accept).unwrap();
+        let stream = req.upgrade("websocket", Response::empty(101).with_header(header));
+        let events = feed.subscribe();
+        logging::spawn(move || {
+            let mut ws = WebSocket::from_raw_socket(stream, Role::Server, None);
+            for event in events.iter() {
+                if ws.send(Message::Text(event)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    fn handle(req: &mut Request, events: &Sender<Event>) -> (u16, Value) {
+        let error = |status, msg: &str| (status, json!({ "error": msg }));
+        let mut body = String::new();
+        if req.as_reader().read_to_string(&mut body).is_err() {
+            return error(400, "unreadable body");
+        }
+        let body: Value = if body.trim().is_empty() {
+            Value::Null
+        } else {
+            match serde_json::from_str(&body) {
+                Ok(body) => body,
+                Err(err) => return error(400, &format!("invalid json: {}", err)),
+            }
+        };
+        let text = |key: &str| body.get(key).and_then(Value::as_str).map(str::to_string);
+        let action = match (req.method(), path(req)) {
+            (Method::Get, "/status") => ApiAction::Status,
+            (Method::Post, "/backup") => ApiAction::Backup(text("name")),
+            (Method::Post, "/restore") => {
+                match (text("name"), body.get("slot").and_then(Value::as_u64)) {
+                    (Some(name), _) => ApiAction::RestoreNamed(name),
+                    (None, Some(slot)) => ApiAction::RestoreSlot(slot as usize),
+                    (None, None) => return error(400, "expected a checkpoint `name` or `slot`"),
+                }
+            }
+            (Method::Post, "/command") => match text("command") {
+                Some(cmd) => ApiAction::Command(cmd),
+                None => return error(400, "expected a `command`"),
+            },
+            //Stopping goes the same way as SIGTERM
+            (Method::Post, "/stop") => {
+                return match events.send(Event::Shutdown) {
+                    Ok(()) => (200, json!({ "stopping": true })),
+                    Err(_) => error(503, "the wrapper is not running"),
+                };
+            }
+            (_, "/status" | "/backup" | "/restore" | "/command" | "/stop") => {
+                return error(405, "method not allowed")
+            }
+            _ => return error(404, "no such endpoint"),
+        };
+        let (tx, rx) = mpsc::channel();
+        let sent = events.send(Event::Api(ApiRequest { action, reply: tx }));
+        match sent.ok().and_then(|()| rx.recv_timeout(REPLY_TIMEOUT).ok()) {
+            Some(Ok(res)) => (200, res),
+            Some(Err(msg)) => error(409, &msg),
+            None => error(503, "the wrapper is busy, try again later"),
+        }
+    }
+}
+
+#[cfg(not(feature = "api"))]
+mod imp {
+    use super::Feed;
+    use crate::events::Event;
+    use log::warn;
+    use std::{error::Error, sync::mpsc::Sender};
+
+    pub fn spawn(
+        _bind: &str,
+        _token: &str,
+        _events: Sender<Event>,
+        _feed: Feed,
+    ) -> Result<(), Box<dyn Error>> {
+        warn!("`api_bind` needs the `api` feature, which this build was compiled without");
+        Ok(())
+    }
+}
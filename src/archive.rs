@@ -0,0 +1,361 @@
+//! Packing worlds into compressed checkpoint archives, and unpacking them again.
+//!
+//! Archives start with the checkpoint metadata followed by `level.dat`, so both can be checked
+//! without decompressing the whole world.
+
+use crate::matcher::GlobMatcher;
+use serde_derive::Deserialize;
+use std::{
+    error::Error,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum BackupFormat {
+    /// Plain copies of the world directory.
+    #[default]
+    #[serde(rename = "directory")]
+    Directory,
+    #[serde(rename = "tar.zst")]
+    TarZst,
+    #[serde(rename = "zip")]
+    Zip,
+}
+impl BackupFormat {
+    /// File extension of checkpoints in this format, including the dot.
+    pub fn extension(self) -> &'static str {
+        match self {
+            BackupFormat::Directory => "",
+            BackupFormat::TarZst => ".tar.zst",
+            BackupFormat::Zip => ".zip",
+        }
+    }
+
+    /// Split a checkpoint file name into its stem and format.
+    pub fn split(name: &str) -> (&str, BackupFormat) {
+        for format in [BackupFormat::TarZst, BackupFormat::Zip] {
+            if let Some(stem) = name.strip_suffix(format.extension()) {
+                return (stem, format);
+            }
+        }
+        (name, BackupFormat::Directory)
+    }
+}
+
+#[cfg(feature = "archives")]
+mod imp {
+    use super::{BackupFormat, EachFile};
+    use crate::matcher::GlobMatcher;
+    use std::{
+        error::Error,
+        fs::{self, File},
+        io::{self, prelude::*},
+        path::{Path, PathBuf},
+    };
+
+    /// All files under `dir` that `exclude` does not match, relative to it, with `first` moved to
+    /// the front if present.
+    fn world_files(
+        dir: &Path,
+        first: &str,
+        exclude: &GlobMatcher,
+    ) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+        fn walk(
+            dir: &Path,
+            rel: &Path,
+            exclude: &GlobMatcher,
+            out: &mut Vec<PathBuf>,
+        ) -> Result<(), Box<dyn Error>> {
+            for entry in fs::read_dir(dir)? {
+                let entry = entry?;
+                let rel = rel.join(entry.file_name());
+                if exclude.matches(&rel) {
+                    continue;
+                }
+                let meta = entry.metadata()?;
+                if meta.is_dir() {
+                    walk(&entry.path(), &rel, exclude, out)?;
+                } else if meta.is_file() {
+                    out.push(rel);
+                }
+            }
+            Ok(())
+        }
+        let mut files = Vec::new();
+        walk(dir, Path::new(""), exclude, &mut files)?;
+        if let Some(idx) = files.iter().position(|file| file == Path::new(first)) {
+            let file = files.remove(idx);
+            files.insert(0, file);
+        }
+        Ok(files)
+    }
+
+    /// Zip entry names always use forward slashes.
+    fn zip_name(rel: &Path) -> String {
+        rel.components()
+            .map(|part| part.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    pub fn pack(
+        roots: &[(&Path, PathBuf)],
+        out: &Path,
+        format: BackupFormat,
+        meta: (&str, &[u8]),
+        first: &str,
+        last: (&str, &[u8]),
+        exclude: &GlobMatcher,
+    ) -> Result<(), Box<dyn Error>> {
+        //Pairs of where each file is and where it goes
+        let mut files = Vec::new();
+        for (dir, place) in roots {
+            for rel in world_files(dir, first, exclude)? {
+                files.push((dir.join(&rel), place.join(rel)));
+            }
+        }
+        match format {
+            BackupFormat::Directory => unreachable!(),
+            BackupFormat::TarZst => {
+                let mut tar = tar::Builder::new(zstd::Encoder::new(File::create(out)?, 0)?);
+                let header = |data: &[u8]| {
+                    let mut header = tar::Header::new_gnu();
+                    header.set_size(data.len() as u64);
+                    header.set_mode(0o644);
+                    header.set_mtime(crate::unix_now());
+                    header.set_cksum();
+                    header
+                };
+                tar.append_data(&mut header(meta.1), meta.0, meta.1)?;
+                for (path, rel) in &files {
+                    tar.append_path_with_name(path, rel)?;
+                }
+                tar.append_data(&mut header(last.1), last.0, last.1)?;
+                tar.into_inner()?.finish()?.sync_all()?;
+            }
+            BackupFormat::Zip => {
+                use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+                let options =
+                    SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+                let mut zip = ZipWriter::new(File::create(out)?);
+                zip.start_file(meta.0, options)?;
+                zip.write_all(meta.1)?;
+                for (path, rel) in &files {
+                    let mut file = File::open(path)?;
+                    let large = file.metadata()?.len() >= u32::MAX as u64;
+                    zip.start_file(zip_name(rel), options.large_file(large))?;
+                    io::copy(&mut file, &mut zip)?;
+                }
+                zip.start_file(last.0, options)?;
+                zip.write_all(last.1)?;
+                zip.finish()?.sync_all()?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn unpack(
+        archive: &Path,
+        format: BackupFormat,
+        world: &Path,
+        exclude: &GlobMatcher,
+    ) -> Result<(), Box<dyn Error>> {
+        fs::create_dir_all(world)?;
+        match format {
+            BackupFormat::Directory => unreachable!(),
+            BackupFormat::TarZst => {
+                let mut tar = tar::Archive::new(zstd::Decoder::new(File::open(archive)?)?);
+                for entry in tar.entries()? {
+                    let mut entry = entry?;
+                    if !exclude.matches(&entry.path()?) {
+                        entry.unpack_in(world)?;
+                    }
+                }
+            }
+            BackupFormat::Zip => {
+                let mut zip = zip::ZipArchive::new(File::open(archive)?)?;
+                for idx in 0..zip.len() {
+                    let mut entry = zip.by_index(idx)?;
+                    let rel = match entry.enclosed_name() {
+                        Some(rel) if !exclude.matches(&rel) => rel,
+                        _ => continue,
+                    };
+                    let path = world.join(rel);
+                    if entry.is_dir() {
+                        fs::create_dir_all(&path)?;
+                        continue;
+                    }
+                    if let Some(parent) = path.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    io::copy(&mut entry, &mut File::create(&path)?)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn for_each_file(
+        archive: &Path,
+        format: BackupFormat,
+        f: &mut EachFile,
+    ) -> Result<(), Box<dyn Error>> {
+        match format {
+            BackupFormat::Directory => unreachable!(),
+            BackupFormat::TarZst => {
+                let mut tar = tar::Archive::new(zstd::Decoder::new(File::open(archive)?)?);
+                for entry in tar.entries()? {
+                    let mut entry = entry?;
+                    if entry.header().entry_type().is_file() {
+                        let path = entry.path()?.into_owned();
+                        f(&path, &mut entry)?;
+                    }
+                }
+            }
+            BackupFormat::Zip => {
+                let mut zip = zip::ZipArchive::new(File::open(archive)?)?;
+                for idx in 0..zip.len() {
+                    let mut entry = zip.by_index(idx)?;
+                    if let (true, Some(path)) = (entry.is_file(), entry.enclosed_name()) {
+                        f(&path, &mut entry)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read_entry(
+        archive: &Path,
+        format: BackupFormat,
+        name: &str,
+        within: usize,
+    ) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        let mut data = Vec::new();
+        match format {
+            BackupFormat::Directory => unreachable!(),
+            BackupFormat::TarZst => {
+                let mut tar = tar::Archive::new(zstd::Decoder::new(File::open(archive)?)?);
+                for entry in tar.entries()?.take(within) {
+                    let mut entry = entry?;
+                    if entry.path()? == Path::new(name) {
+                        entry.read_to_end(&mut data)?;
+                        return Ok(Some(data));
+                    }
+                }
+            }
+            BackupFormat::Zip => {
+                let mut zip = zip::ZipArchive::new(File::open(archive)?)?;
+                let found = match zip.by_name(name) {
+                    Ok(mut entry) => entry.read_to_end(&mut data).map(|_| true)?,
+                    Err(_) => false,
+                };
+                if found {
+                    return Ok(Some(data));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(not(feature = "archives"))]
+mod imp {
+    use super::{BackupFormat, EachFile};
+    use crate::matcher::GlobMatcher;
+    use std::{
+        error::Error,
+        path::{Path, PathBuf},
+    };
+
+    const UNSUPPORTED: &str =
+        "archive checkpoints need the `archives` feature, which this build was compiled without";
+
+    pub fn pack(
+        _roots: &[(&Path, PathBuf)],
+        _out: &Path,
+        _format: BackupFormat,
+        _meta: (&str, &[u8]),
+        _first: &str,
+        _last: (&str, &[u8]),
+        _exclude: &GlobMatcher,
+    ) -> Result<(), Box<dyn Error>> {
+        Err(UNSUPPORTED.into())
+    }
+
+    pub fn unpack(
+        _archive: &Path,
+        _format: BackupFormat,
+        _world: &Path,
+        _exclude: &GlobMatcher,
+    ) -> Result<(), Box<dyn Error>> {
+        Err(UNSUPPORTED.into())
+    }
+
+    pub fn for_each_file(
+        _archive: &Path,
+        _format: BackupFormat,
+        _f: &mut EachFile,
+    ) -> Result<(), Box<dyn Error>> {
+        Err(UNSUPPORTED.into())
+    }
+
+    pub fn read_entry(
+        _archive: &Path,
+        _format: BackupFormat,
+        _name: &str,
+        _within: usize,
+    ) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        Err(UNSUPPORTED.into())
+    }
+}
+
+/// Pack world directories into an archive, each under the path given with it, with the metadata
+/// file `meta` as the first entry, the file `first` of the first world right after it and the file
+/// `last` at the end, leaving out the paths that `exclude` matches.
+pub fn pack(
+    roots: &[(&Path, PathBuf)],
+    out: &Path,
+    format: BackupFormat,
+    meta: (&str, &[u8]),
+    first: &str,
+    last: (&str, &[u8]),
+    exclude: &GlobMatcher,
+) -> Result<(), Box<dyn Error>> {
+    imp::pack(roots, out, format, meta, first, last, exclude)
+}
+
+/// Callback for each file read out of an archive, with its path and contents.
+type EachFile<'a> = dyn FnMut(&Path, &mut dyn Read) -> Result<(), Box<dyn Error>> + 'a;
+
+/// Read every file in an archive, front to back.
+pub fn for_each_file(
+    archive: &Path,
+    format: BackupFormat,
+    mut f: impl FnMut(&Path, &mut dyn Read) -> Result<(), Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    imp::for_each_file(archive, format, &mut f)
+}
+
+/// Unpack an archive into the world directory, creating it if needed and leaving out the paths
+/// that `exclude` matches.
+pub fn unpack(
+    archive: &Path,
+    format: BackupFormat,
+    world: &Path,
+    exclude: &GlobMatcher,
+) -> Result<(), Box<dyn Error>> {
+    imp::unpack(archive, format, world, exclude)
+}
+
+/// Read the file `name` out of an archive, looking only at the first `within` entries of formats
+/// that must be read front to back.
+pub fn read_entry(
+    archive: &Path,
+    format: BackupFormat,
+    name: &str,
+    within: usize,
+) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+    imp::read_entry(archive, format, name, within)
+}
@@ -0,0 +1,958 @@
+//! On-disk layout of world checkpoints.
+//!
+//! Automatic checkpoints live in `<backup_dir>/<world>-checkpoints/ckpt-<unix time>`, and only the
+//! newest `slots` of them are kept (`checkpoint_slots`, also accepted as `backup_slots`). Named
+//! checkpoints live next to them in `named-<label>` and are never rotated out.
+//!
+//! Every checkpoint carries a `checkpoint.json` file describing the run at the time it was made,
+//! and a `manifest.json` file listing the SHA-256 of every world file in it, which is checked
+//! before the checkpoint is restored.
+//!
+//! Restores are unpacked next to the world in `<world>.restore-tmp` and only then swapped in, with
+//! the old world kept as `<world>.old` until the swap is done, so a failed restore never leaves the
+//! world half-written or missing.
+//!
+//! With a `backup_format` other than `directory`, checkpoints are single archives named like the
+//! directories plus an extension, eg. `ckpt-<unix time>.tar.zst`. Directory checkpoints can be
+//! incremental instead, sharing the files that did not change with the previous checkpoint through
+//! hardlinks. Deleting any of them leaves the others whole.
+//!
+//! On servers that split dimensions into worlds of their own, such as `world_nether` on Spigot,
+//! those worlds go into the checkpoint under `dimension_worlds/<name>`, and are restored and
+//! swapped in along with the main world.
+
+use crate::{
+    archive::{self, BackupFormat},
+    hex,
+    matcher::GlobMatcher,
+    unix_now,
+};
+use log::{info, warn};
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+const AUTO_PREFIX: &str = "ckpt-";
+const NAMED_PREFIX: &str = "named-";
+const PARTIAL_SUFFIX: &str = ".partial";
+const STAGING_SUFFIX: &str = ".restore-tmp";
+const OLD_SUFFIX: &str = ".old";
+const META_FILE: &str = "checkpoint.json";
+const MANIFEST_FILE: &str = "manifest.json";
+/// Directory of a checkpoint holding the worlds of other dimensions, each under its own name.
+const DIMENSIONS_DIR: &str = "dimension_worlds";
+/// File every usable world has, used to tell whether a checkpoint survived intact.
+const LEVEL_FILE: &str = "level.dat";
+
+/// Copy a directory tree, merging into `to` if it already exists and leaving out the paths that
+/// `exclude` matches.
+pub fn copy_dir(from: &Path, to: &Path, exclude: &GlobMatcher) -> Result<(), Box<dyn Error>> {
+    copy_tree(from, to, None, exclude).map(|_| ())
+}
+
+/// Copy a directory tree like `copy_dir`, but hardlink files that have not changed since they were
+/// copied into `prev`, an earlier copy of the same tree.
+///
+/// A file counts as unchanged if it has the same size as in `prev`, and was last modified before
+/// its copy there was made. Returns how many files were linked and how many copied.
+pub fn snapshot_dir(
+    from: &Path,
+    to: &Path,
+    prev: &Path,
+    exclude: &GlobMatcher,
+) -> Result<(u64, u64), Box<dyn Error>> {
+    copy_tree(from, to, Some(prev), exclude)
+}
+
+fn copy_tree(
+    from: &Path,
+    to: &Path,
+    prev: Option<&Path>,
+    exclude: &GlobMatcher,
+) -> Result<(u64, u64), Box<dyn Error>> {
+    fn unchanged(from: &fs::Metadata, prev: &Path) -> bool {
+        match (prev.metadata(), from.modified()) {
+            (Ok(prev), Ok(modified)) => {
+                prev.is_file()
+                    && prev.len() == from.len()
+                    && prev.modified().is_ok_and(|copied| modified < copied)
+            }
+            _ => false,
+        }
+    }
+    fn copy(
+        from: &mut PathBuf,
+        to: &mut PathBuf,
+        rel: &mut PathBuf,
+        prev: Option<&Path>,
+        exclude: &GlobMatcher,
+        counts: &mut (u64, u64),
+    ) -> Result<(), Box<dyn Error>> {
+        if !to.exists() {
+            fs::create_dir(&*to)?;
+        }
+        for entry in fs::read_dir(&*from)? {
+            let name = entry?.file_name();
+            rel.push(&name);
+            if !exclude.matches(rel) {
+                from.push(&name);
+                to.push(&name);
+                if let Ok(meta) = from.metadata() {
+                    if meta.is_dir() {
+                        copy(from, to, rel, prev, exclude, counts)?;
+                    } else if meta.is_file() {
+                        //Unchanged files share their data with the previous copy
+                        let old = prev.map(|prev| prev.join(&*rel));
+                        let linked = match &old {
+                            Some(old) if unchanged(&meta, old) => fs::hard_link(old, &*to).is_ok(),
+                            _ => false,
+                        };
+                        if linked {
+                            counts.0 += 1;
+                        } else {
+                            fs::copy(&*from, &*to)?;
+                            counts.1 += 1;
+                        }
+                    }
+                }
+                from.pop();
+                to.pop();
+            }
+            rel.pop();
+        }
+        Ok(())
+    }
+    let mut counts = (0, 0);
+    copy(
+        &mut from.to_path_buf(),
+        &mut to.to_path_buf(),
+        &mut PathBuf::new(),
+        prev,
+        exclude,
+        &mut counts,
+    )?;
+    Ok(counts)
+}
+
+/// `world` with `suffix` appended to its directory name.
+fn sibling(world: &Path, suffix: &str) -> PathBuf {
+    let mut name = world.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    world.with_file_name(name)
+}
+
+/// Move the `staged` directory into place as `world`, keeping the old world around until the swap
+/// went through and putting it back if it did not.
+fn swap_in(staged: &Path, world: &Path) -> Result<(), Box<dyn Error>> {
+    let old = sibling(world, OLD_SUFFIX);
+    remove_path(&old)?;
+    if world.exists() {
+        fs::rename(world, &old)?;
+    }
+    if let Err(err) = fs::rename(staged, world) {
+        if old.exists() {
+            fs::rename(&old, world)?;
+        }
+        return Err(err.into());
+    }
+    remove_path(&old)
+}
+
+/// Clean up after a restore that was interrupted, putting the old world back if it was moved out
+/// of the way and the new one never made it in. The worlds of other dimensions next to it, named
+/// like it, get the same treatment.
+pub fn recover_restore(world: &Path) -> Result<(), Box<dyn Error>> {
+    let name = world.file_name().unwrap_or_default().to_string_lossy();
+    let parent = world.parent().unwrap_or_else(|| Path::new("."));
+    if let Ok(entries) = fs::read_dir(parent) {
+        for entry in entries.flatten() {
+            let entry = entry.file_name().to_string_lossy().into_owned();
+            let stem = entry
+                .strip_suffix(OLD_SUFFIX)
+                .or_else(|| entry.strip_suffix(STAGING_SUFFIX));
+            if let Some(stem) = stem.filter(|stem| *stem != name && stem.starts_with(&*name)) {
+                recover_world(&world.with_file_name(stem))?;
+            }
+        }
+    }
+    recover_world(world)
+}
+
+fn recover_world(world: &Path) -> Result<(), Box<dyn Error>> {
+    let old = sibling(world, OLD_SUFFIX);
+    if old.exists() {
+        if world.exists() {
+            remove_path(&old)?;
+        } else {
+            warn!(
+                "a restore was interrupted, moving \"{}\" back into place",
+                old.display()
+            );
+            fs::rename(&old, world)?;
+        }
+    }
+    remove_path(&sibling(world, STAGING_SUFFIX))
+}
+
+/// The worlds that go into a checkpoint of `world`: the world itself, and those of the
+/// `dimensions` that exist, each with where it goes in the checkpoint.
+fn world_roots<'a>(world: &'a Path, dimensions: &'a [PathBuf]) -> Vec<(&'a Path, PathBuf)> {
+    let mut roots = vec![(world, PathBuf::new())];
+    for dim in dimensions.iter().filter(|dim| dim.is_dir()) {
+        let name = dim.file_name().unwrap_or_default();
+        roots.push((dim, Path::new(DIMENSIONS_DIR).join(name)));
+    }
+    roots
+}
+
+/// Remove a checkpoint, whether it is a directory or an archive.
+pub fn remove_path(path: &Path) -> Result<(), Box<dyn Error>> {
+    if path.is_dir() {
+        fs::remove_dir_all(path)?;
+    } else if path.exists() {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Total size of the files in a directory tree, leaving out the paths that `exclude` matches.
+pub fn dir_size(dir: &Path, exclude: &GlobMatcher) -> Result<u64, Box<dyn Error>> {
+    fn walk(dir: &Path, rel: &Path, exclude: &GlobMatcher) -> Result<u64, Box<dyn Error>> {
+        let mut size = 0;
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let rel = rel.join(entry.file_name());
+            if exclude.matches(&rel) {
+                continue;
+            }
+            let meta = entry.metadata()?;
+            if meta.is_dir() {
+                size += walk(&entry.path(), &rel, exclude)?;
+            } else if meta.is_file() {
+                size += meta.len();
+            }
+        }
+        Ok(size)
+    }
+    walk(dir, Path::new(""), exclude)
+}
+
+/// Space left for unprivileged use on the volume holding `path`, if the platform can tell.
+#[cfg(unix)]
+pub fn free_space(path: &Path) -> Option<u64> {
+    use std::{ffi::CString, mem, os::unix::ffi::OsStrExt};
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    //The field types differ between platforms
+    #[allow(clippy::unnecessary_cast)]
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+pub fn free_space(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// What the run looked like when a checkpoint was made.
+#[derive(Serialize, Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct CheckpointMeta {
+    pub playtime_secs: u64,
+    /// In-game day, if the server answered the query.
+    pub day: Option<u64>,
+    /// How many worlds have been played, counting this one.
+    pub attempt: u32,
+    pub online_players: Vec<String>,
+    /// World seed, if the server answered the query.
+    pub seed: Option<String>,
+    /// Advancements made in the world so far.
+    pub advancements: u32,
+    /// Deaths in the world so far.
+    pub deaths: u32,
+    /// Size of the world files, in bytes.
+    pub world_size: u64,
+}
+impl CheckpointMeta {
+    /// Store the metadata inside a checkpoint directory.
+    pub fn write(&self, dir: &Path) -> Result<(), Box<dyn Error>> {
+        fs::write(dir.join(META_FILE), serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Remove the metadata file from a world restored from a checkpoint.
+    pub fn remove(dir: &Path) -> Result<(), Box<dyn Error>> {
+        let path = dir.join(META_FILE);
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checksums of the world files in a checkpoint, to tell whether it rotted on disk.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct Manifest {
+    /// When the checkpoint was made, in seconds since the Unix epoch.
+    pub created: u64,
+    pub playtime_secs: u64,
+    /// Total size of the world files, in bytes.
+    pub size: u64,
+    /// SHA-256 of every world file, by its path relative to the world with `/` separators.
+    pub files: BTreeMap<String, String>,
+}
+impl Manifest {
+    /// Hash every file in `dir` but the checkpoint's own files and the paths that `exclude` matches.
+    fn of_dir(dir: &Path, exclude: &GlobMatcher) -> Result<Self, Box<dyn Error>> {
+        fn walk(
+            dir: &Path,
+            rel: &Path,
+            exclude: &GlobMatcher,
+            manifest: &mut Manifest,
+        ) -> Result<(), Box<dyn Error>> {
+            for entry in fs::read_dir(dir)? {
+                let entry = entry?;
+                let rel = rel.join(entry.file_name());
+                if exclude.matches(&rel) || is_own_file(&rel) {
+                    continue;
+                }
+                let meta = entry.metadata()?;
+                if meta.is_dir() {
+                    walk(&entry.path(), &rel, exclude, manifest)?;
+                } else if meta.is_file() {
+                    manifest.add(&rel, &mut File::open(entry.path())?)?;
+                }
+            }
+            Ok(())
+        }
+        let mut manifest = Self::default();
+        walk(dir, Path::new(""), exclude, &mut manifest)?;
+        Ok(manifest)
+    }
+
+    /// Hash every file in each world, under its place in the checkpoint.
+    fn of_roots(roots: &[(&Path, PathBuf)], exclude: &GlobMatcher) -> Result<Self, Box<dyn Error>> {
+        let mut manifest = Self::default();
+        for (dir, place) in roots {
+            let part = Self::of_dir(dir, exclude)?;
+            manifest.size += part.size;
+            for (file, hash) in part.files {
+                let key = match manifest_key(place).as_str() {
+                    "" => file,
+                    place => format!("{}/{}", place, file),
+                };
+                manifest.files.insert(key, hash);
+            }
+        }
+        Ok(manifest)
+    }
+
+    /// Hash a file and add it to the manifest.
+    fn add(&mut self, rel: &Path, data: &mut dyn Read) -> Result<(), Box<dyn Error>> {
+        let mut hasher = Sha256::new();
+        self.size += io::copy(data, &mut hasher)?;
+        self.files
+            .insert(manifest_key(rel), hex(&hasher.finalize()));
+        Ok(())
+    }
+
+    /// Describe how `actual` differs from this manifest, or `None` if they hold the same files.
+    fn diff(&self, actual: &Manifest) -> Option<String> {
+        let mut problems = Vec::new();
+        for (file, hash) in &self.files {
+            match actual.files.get(file) {
+                None => problems.push(format!("\"{}\" is missing", file)),
+                Some(actual) if actual != hash => {
+                    problems.push(format!("\"{}\" does not match its checksum", file))
+                }
+                Some(_) => {}
+            }
+        }
+        for file in actual.files.keys() {
+            if !self.files.contains_key(file) {
+                problems.push(format!("\"{}\" is not in the manifest", file));
+            }
+        }
+        match problems.len() {
+            0 => None,
+            1..=3 => Some(problems.join(", ")),
+            n => Some(format!("{} and {} more", problems[..3].join(", "), n - 3)),
+        }
+    }
+}
+
+/// Whether a path relative to a checkpoint is one of the files the checkpoint adds to the world.
+fn is_own_file(rel: &Path) -> bool {
+    rel == Path::new(META_FILE) || rel == Path::new(MANIFEST_FILE)
+}
+
+fn manifest_key(rel: &Path) -> String {
+    let parts: Vec<_> = rel
+        .components()
+        .map(|part| part.as_os_str().to_string_lossy())
+        .collect();
+    parts.join("/")
+}
+
+pub struct Checkpoint {
+    pub path: PathBuf,
+    pub name: String,
+    /// Creation time, in seconds since the Unix epoch.
+    pub created: u64,
+    pub format: BackupFormat,
+}
+impl Checkpoint {
+    /// Read the checkpoint metadata. Checkpoints made by older versions have none.
+    pub fn meta(&self) -> Option<CheckpointMeta> {
+        let raw = match self.format {
+            BackupFormat::Directory => fs::read(self.path.join(META_FILE)).ok()?,
+            format => archive::read_entry(&self.path, format, META_FILE, 1).ok()??,
+        };
+        match serde_json::from_slice(&raw) {
+            Ok(meta) => Some(meta),
+            Err(err) => {
+                warn!(
+                    "ignoring corrupted metadata of checkpoint \"{}\": {}",
+                    self.name, err
+                );
+                None
+            }
+        }
+    }
+
+    /// Whether the checkpoint still looks like a usable world.
+    pub fn is_intact(&self) -> bool {
+        match self.format {
+            BackupFormat::Directory => fs::metadata(self.path.join(LEVEL_FILE))
+                .map(|meta| meta.is_file() && meta.len() > 0)
+                .unwrap_or(false),
+            format => matches!(
+                archive::read_entry(&self.path, format, LEVEL_FILE, 2),
+                Ok(Some(data)) if !data.is_empty()
+            ),
+        }
+    }
+
+    /// Whether the checkpoint looks like a usable world and its files match the manifest, if it has
+    /// one.
+    pub fn is_usable(&self) -> bool {
+        if !self.is_intact() {
+            return false;
+        }
+        match self.verify() {
+            Ok(_) => true,
+            Err(err) => {
+                warn!("checkpoint \"{}\" failed verification: {}", self.name, err);
+                false
+            }
+        }
+    }
+
+    /// Check every file in the checkpoint against its manifest. Returns `Ok(false)` if there is no
+    /// manifest to check against, as with checkpoints made by older versions, and an error
+    /// describing the damage if any file is missing or changed.
+    pub fn verify(&self) -> Result<bool, Box<dyn Error>> {
+        let (expected, actual) = match self.format {
+            BackupFormat::Directory => {
+                let raw = match fs::read(self.path.join(MANIFEST_FILE)) {
+                    Ok(raw) => raw,
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+                    Err(err) => return Err(err.into()),
+                };
+                let none = GlobMatcher::new::<&str>(&[])?;
+                (raw, Manifest::of_dir(&self.path, &none)?)
+            }
+            format => {
+                //The manifest is the last entry, so read everything in a single pass
+                let mut raw = None;
+                let mut actual = Manifest::default();
+                archive::for_each_file(&self.path, format, |rel, data| {
+                    if rel == Path::new(MANIFEST_FILE) {
+                        let mut buf = Vec::new();
+                        data.read_to_end(&mut buf)?;
+                        raw = Some(buf);
+                    } else if !is_own_file(rel) {
+                        actual.add(rel, data)?;
+                    }
+                    Ok(())
+                })?;
+                match raw {
+                    Some(raw) => (raw, actual),
+                    None => return Ok(false),
+                }
+            }
+        };
+        let expected: Manifest = serde_json::from_slice(&expected)
+            .map_err(|err| format!("corrupted manifest: {}", err))?;
+        match expected.diff(&actual) {
+            Some(problems) => Err(problems.into()),
+            None => Ok(true),
+        }
+    }
+
+    /// Put the checkpoint's copies of some files, by their path within the world, in place of the
+    /// ones in `world`. Those the checkpoint has no copy of are removed.
+    pub fn restore_files(&self, world: &Path, files: &[PathBuf]) -> Result<(), Box<dyn Error>> {
+        let mut found = Vec::new();
+        let mut put = |rel: &Path, data: &mut dyn Read| -> Result<(), Box<dyn Error>> {
+            let path = world.join(rel);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let staging = sibling(&path, STAGING_SUFFIX);
+            io::copy(data, &mut File::create(&staging)?)?;
+            fs::rename(&staging, &path)?;
+            found.push(rel.to_path_buf());
+            Ok(())
+        };
+        match self.format {
+            BackupFormat::Directory => {
+                for rel in files {
+                    match File::open(self.path.join(rel)) {
+                        Ok(mut file) => put(rel, &mut file)?,
+                        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                        Err(err) => return Err(err.into()),
+                    }
+                }
+            }
+            format => archive::for_each_file(&self.path, format, |rel, data| {
+                if files.iter().any(|file| file == rel) {
+                    put(rel, data)?;
+                }
+                Ok(())
+            })?,
+        }
+        for rel in files {
+            if !found.contains(rel) {
+                remove_path(&world.join(rel))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace `world` with the contents of the checkpoint, leaving out the paths that `exclude`
+    /// matches. The world is left untouched if the checkpoint cannot be read.
+    ///
+    /// The worlds of other dimensions in the checkpoint are put next to `world`, and those of the
+    /// current `dimensions` that the checkpoint does not have are removed, so that none is left
+    /// ahead of the rest.
+    pub fn restore(
+        &self,
+        world: &Path,
+        dimensions: &[PathBuf],
+        exclude: &GlobMatcher,
+    ) -> Result<(), Box<dyn Error>> {
+        let staging = sibling(world, STAGING_SUFFIX);
+        remove_path(&staging)?;
+        let res = match self.format {
+            BackupFormat::Directory => copy_dir(&self.path, &staging, exclude),
+            format => archive::unpack(&self.path, format, &staging, exclude),
+        };
+        if let Err(err) = res {
+            remove_path(&staging)?;
+            return Err(err);
+        }
+        let manifest = staging.join(MANIFEST_FILE);
+        if manifest.exists() {
+            fs::remove_file(manifest)?;
+        }
+        CheckpointMeta::remove(&staging)?;
+        let stored = staging.join(DIMENSIONS_DIR);
+        let mut restored = Vec::new();
+        if stored.is_dir() {
+            for entry in fs::read_dir(&stored)? {
+                let entry = entry?;
+                let dim = world.with_file_name(entry.file_name());
+                let dim_staging = sibling(&dim, STAGING_SUFFIX);
+                remove_path(&dim_staging)?;
+                fs::rename(entry.path(), &dim_staging)?;
+                swap_in(&dim_staging, &dim)?;
+                restored.push(dim);
+            }
+            fs::remove_dir(&stored)?;
+        }
+        for dim in dimensions {
+            if dim.exists() && !restored.contains(dim) {
+                info!(
+                    "removing \"{}\", which the checkpoint has no copy of",
+                    dim.display()
+                );
+                remove_path(dim)?;
+            }
+        }
+        swap_in(&staging, world)
+    }
+}
+
+pub struct BackupManager {
+    dir: PathBuf,
+    slots: usize,
+    format: BackupFormat,
+    /// Paths in the world that are neither saved nor restored.
+    exclude: GlobMatcher,
+    /// Hardlink files that did not change since the latest checkpoint, instead of copying them.
+    incremental: bool,
+    /// Free space to leave on a volume after a checkpoint or a restore, in bytes.
+    space_margin: u64,
+}
+impl BackupManager {
+    pub fn new(
+        backup_dir: &Path,
+        world_name: &str,
+        slots: usize,
+        format: BackupFormat,
+        exclude: GlobMatcher,
+        incremental: bool,
+        space_margin_mb: u64,
+    ) -> Self {
+        Self {
+            dir: backup_dir.join(format!("{}-checkpoints", world_name)),
+            slots: slots.max(1),
+            format,
+            exclude,
+            incremental,
+            space_margin: space_margin_mb * 1024 * 1024,
+        }
+    }
+
+    pub fn exclude(&self) -> &GlobMatcher {
+        &self.exclude
+    }
+
+    /// Fail unless the volume holding `path`, or its closest existing ancestor, has room for
+    /// `needed` bytes plus the margin.
+    fn ensure_space(&self, path: &Path, needed: u64) -> Result<(), Box<dyn Error>> {
+        let path = path.ancestors().find(|dir| dir.exists()).unwrap_or(path);
+        let free = match free_space(path) {
+            Some(free) => free,
+            None => return Ok(()),
+        };
+        let needed = needed + self.space_margin;
+        if free < needed {
+            return Err(format!(
+                "not enough disk space on \"{}\", {} MB free but {} MB needed",
+                path.display(),
+                free / (1024 * 1024),
+                needed.div_ceil(1024 * 1024)
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Check that there is room for a checkpoint of `world` and its `dimensions`.
+    pub fn check_backup_space(
+        &self,
+        world: &Path,
+        dimensions: &[PathBuf],
+    ) -> Result<(), Box<dyn Error>> {
+        let mut size = 0;
+        for (dir, _) in world_roots(world, dimensions) {
+            size += dir_size(dir, &self.exclude)?;
+        }
+        self.ensure_space(&self.dir, size)
+    }
+
+    /// Check that there is room to unpack `ckpt` next to `world`, which stays in place until the
+    /// restore is done.
+    pub fn check_restore_space(
+        &self,
+        ckpt: &Checkpoint,
+        world: &Path,
+    ) -> Result<(), Box<dyn Error>> {
+        let recorded = ckpt
+            .meta()
+            .map(|meta| meta.world_size)
+            .filter(|&size| size > 0);
+        let size = match (recorded, ckpt.format) {
+            (Some(size), _) => size,
+            //Older checkpoints do not record their size, go by what they take on disk
+            (None, BackupFormat::Directory) => dir_size(&ckpt.path, &self.exclude)?,
+            (None, _) => fs::metadata(&ckpt.path)?.len(),
+        };
+        self.ensure_space(world, size)
+    }
+
+    /// Move a backup made by older versions, which kept a single backup named after the world,
+    /// into the checkpoint directory.
+    pub fn migrate_legacy(&self, legacy: &Path, now: u64) -> Result<(), Box<dyn Error>> {
+        if legacy.is_dir() && !self.dir.exists() {
+            fs::create_dir_all(&self.dir)?;
+            let to = self.dir.join(format!("{}{}", AUTO_PREFIX, now));
+            info!(
+                "moving old backup \"{}\" to \"{}\"",
+                legacy.display(),
+                to.display()
+            );
+            fs::rename(legacy, to)?;
+        }
+        Ok(())
+    }
+
+    /// All complete automatic checkpoints, oldest first.
+    pub fn list(&self) -> Result<Vec<Checkpoint>, Box<dyn Error>> {
+        let mut ckpts = Vec::new();
+        if !self.dir.exists() {
+            return Ok(ckpts);
+        }
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let (stem, format) = BackupFormat::split(&name);
+            let created = match stem.strip_prefix(AUTO_PREFIX).map(str::parse) {
+                Some(Ok(created)) => created,
+                _ => continue,
+            };
+            ckpts.push(Checkpoint {
+                path: entry.path(),
+                name: stem.to_string(),
+                created,
+                format,
+            });
+        }
+        ckpts.sort_by_key(|ckpt| ckpt.created);
+        Ok(ckpts)
+    }
+
+    /// All named checkpoints, oldest first. Their name is the label they were saved with.
+    pub fn list_named(&self) -> Result<Vec<Checkpoint>, Box<dyn Error>> {
+        let mut ckpts = Vec::new();
+        if !self.dir.exists() {
+            return Ok(ckpts);
+        }
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let (stem, format) = BackupFormat::split(&name);
+            let label = match stem.strip_prefix(NAMED_PREFIX) {
+                Some(label) if !label.ends_with(PARTIAL_SUFFIX) => label.to_string(),
+                _ => continue,
+            };
+            let created = entry
+                .metadata()?
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            ckpts.push(Checkpoint {
+                path: entry.path(),
+                name: label,
+                created,
+                format,
+            });
+        }
+        ckpts.sort_by_key(|ckpt| ckpt.created);
+        Ok(ckpts)
+    }
+
+    pub fn named(&self, label: &str) -> Result<Option<Checkpoint>, Box<dyn Error>> {
+        Ok(self
+            .list_named()?
+            .into_iter()
+            .find(|ckpt| ckpt.name == label))
+    }
+
+    pub fn latest(&self) -> Result<Option<Checkpoint>, Box<dyn Error>> {
+        Ok(self.list()?.pop())
+    }
+
+    /// The newest usable automatic checkpoint made before `ckpt`, to fall back to when `ckpt` is
+    /// not.
+    pub fn usable_before(&self, ckpt: &Checkpoint) -> Result<Option<Checkpoint>, Box<dyn Error>> {
+        Ok(self
+            .list()?
+            .into_iter()
+            .rev()
+            .filter(|older| older.created < ckpt.created)
+            .find(Checkpoint::is_usable))
+    }
+
+    /// Where to write a new checkpoint, and where to move it once it is complete.
+    pub fn new_paths(&self, now: u64) -> Result<(PathBuf, PathBuf), Box<dyn Error>> {
+        fs::create_dir_all(&self.dir)?;
+        let name = format!("{}{}{}", AUTO_PREFIX, now, self.format.extension());
+        Ok((
+            self.dir.join(format!("{}{}", name, PARTIAL_SUFFIX)),
+            self.dir.join(name),
+        ))
+    }
+
+    /// Where to write a named checkpoint, and where to move it once it is complete.
+    pub fn named_paths(&self, label: &str) -> Result<(PathBuf, PathBuf), Box<dyn Error>> {
+        if label.is_empty()
+            || !label
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(format!(
+                "invalid checkpoint name \"{}\", use only letters, digits, `-` and `_`",
+                label
+            )
+            .into());
+        }
+        fs::create_dir_all(&self.dir)?;
+        let name = format!("{}{}{}", NAMED_PREFIX, label, self.format.extension());
+        Ok((
+            self.dir.join(format!("{}{}", name, PARTIAL_SUFFIX)),
+            self.dir.join(name),
+        ))
+    }
+
+    /// Delete the oldest automatic checkpoints beyond the slot count.
+    pub fn prune(&self) -> Result<(), Box<dyn Error>> {
+        let ckpts = self.list()?;
+        let excess = ckpts.len().saturating_sub(self.slots);
+        for ckpt in &ckpts[..excess] {
+            info!("deleting old checkpoint \"{}\"", ckpt.path.display());
+            remove_path(&ckpt.path)?;
+        }
+        Ok(())
+    }
+
+    /// Where to write an archive of the world named `name` into `dir`, outside of the checkpoints,
+    /// and where to move it once it is complete.
+    pub fn archive_paths(
+        &self,
+        dir: &Path,
+        name: &str,
+    ) -> Result<(PathBuf, PathBuf), Box<dyn Error>> {
+        fs::create_dir_all(dir)?;
+        let name = format!("{}{}", name, self.format.extension());
+        Ok((
+            dir.join(format!("{}{}", name, PARTIAL_SUFFIX)),
+            dir.join(name),
+        ))
+    }
+
+    /// Write a checkpoint of `world` and its `dimensions` to `partial`, moving it to `done` once it
+    /// is complete.
+    pub fn write(
+        &self,
+        world: &Path,
+        dimensions: &[PathBuf],
+        partial: &Path,
+        done: &Path,
+        meta: &CheckpointMeta,
+    ) -> Result<(), Box<dyn Error>> {
+        let roots = world_roots(world, dimensions);
+        self.write_copy(&roots, partial, done, meta, self.incremental)
+    }
+
+    /// Like `write`, but always a full copy, so that it stands on its own once the checkpoints it
+    /// could be linked to are gone.
+    pub fn write_archive(
+        &self,
+        world: &Path,
+        dimensions: &[PathBuf],
+        partial: &Path,
+        done: &Path,
+        meta: &CheckpointMeta,
+    ) -> Result<(), Box<dyn Error>> {
+        let roots = world_roots(world, dimensions);
+        self.write_copy(&roots, partial, done, meta, false)
+    }
+
+    fn write_copy(
+        &self,
+        roots: &[(&Path, PathBuf)],
+        partial: &Path,
+        done: &Path,
+        meta: &CheckpointMeta,
+        incremental: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        //Remove leftovers from a failed attempt
+        remove_path(partial)?;
+        let mut world_size = 0;
+        for (dir, _) in roots {
+            world_size += dir_size(dir, &self.exclude)?;
+        }
+        let meta = &CheckpointMeta {
+            world_size,
+            ..meta.clone()
+        };
+        match self.format {
+            BackupFormat::Directory => {
+                let prev = match incremental {
+                    true => self
+                        .list()?
+                        .pop()
+                        .filter(|ckpt| ckpt.format == BackupFormat::Directory),
+                    false => None,
+                };
+                let (mut linked, mut copied) = (0, 0);
+                for (dir, place) in roots {
+                    let to = partial.join(place);
+                    if let Some(parent) = to.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    match &prev {
+                        Some(prev) => {
+                            let counts =
+                                snapshot_dir(dir, &to, &prev.path.join(place), &self.exclude)?;
+                            linked += counts.0;
+                            copied += counts.1;
+                        }
+                        None => copy_dir(dir, &to, &self.exclude)?,
+                    }
+                }
+                if let Some(prev) = &prev {
+                    info!(
+                        "copied {} changed files, linked {} unchanged ones from \"{}\"",
+                        copied, linked, prev.name
+                    );
+                }
+                //Hash the copy rather than the world, so the manifest vouches for what was written
+                let manifest = Manifest {
+                    created: unix_now(),
+                    playtime_secs: meta.playtime_secs,
+                    ..Manifest::of_dir(partial, &self.exclude)?
+                };
+                fs::write(
+                    partial.join(MANIFEST_FILE),
+                    serde_json::to_string_pretty(&manifest)?,
+                )?;
+                meta.write(partial)?;
+            }
+            format => {
+                let manifest = Manifest {
+                    created: unix_now(),
+                    playtime_secs: meta.playtime_secs,
+                    ..Manifest::of_roots(roots, &self.exclude)?
+                };
+                let meta = serde_json::to_vec_pretty(meta)?;
+                let manifest = serde_json::to_vec_pretty(&manifest)?;
+                archive::pack(
+                    roots,
+                    partial,
+                    format,
+                    (META_FILE, &meta),
+                    LEVEL_FILE,
+                    (MANIFEST_FILE, &manifest),
+                    &self.exclude,
+                )?;
+            }
+        }
+        remove_path(done)?;
+        fs::rename(partial, done)?;
+        Ok(())
+    }
+
+    /// Delete every checkpoint.
+    pub fn clear(&self) -> Result<(), Box<dyn Error>> {
+        if self.dir.exists() {
+            info!("deleting checkpoint directory \"{}\"", self.dir.display());
+            fs::remove_dir_all(&self.dir)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,186 @@
+//! Bedrock Dedicated Server support, for `server_type = "bedrock"`.
+//!
+//! BDS prints no death messages, so the wrapper installs a small behavior pack into the world that
+//! logs each player death on the console. Its worlds live in `worlds/<level-name>`, next to the
+//! `server.properties` one directory up, and it is backed up with `save hold`, which hands out the
+//! exact length each file has to be copied to.
+
+use crate::{
+    backups::{copy_dir, remove_path},
+    matcher::GlobMatcher,
+};
+use serde_derive::Deserialize;
+use serde_json::{json, Value};
+use std::{
+    error::Error,
+    fs::{self, File},
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ServerType {
+    /// Minecraft: Java Edition, of any `server_flavor`.
+    #[default]
+    Java,
+    /// Bedrock Dedicated Server.
+    Bedrock,
+}
+impl ServerType {
+    /// The command that turns saving back on after a checkpoint.
+    pub fn resume_saving(self) -> &'static str {
+        match self {
+            ServerType::Java => "save-on",
+            ServerType::Bedrock => "save resume",
+        }
+    }
+}
+
+/// Something that happened on a Bedrock server.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Line<'a> {
+    Started,
+    Connected(&'a str),
+    Disconnected(&'a str),
+    /// A player death, as logged by the behavior pack, with its message.
+    Death {
+        player: &'a str,
+        message: &'a str,
+    },
+}
+
+/// Make sense of a line of BDS output, with its `[date time INFO]` prefix stripped.
+pub fn parse_line(line: &str) -> Option<Line<'_>> {
+    let line = line.trim();
+    if line == "Server started." {
+        return Some(Line::Started);
+    }
+    //`Player connected: Steve, xuid: 2535...`
+    if let Some(rest) = line.strip_prefix("Player connected: ") {
+        return Some(Line::Connected(rest.split_once(", xuid")?.0));
+    }
+    if let Some(rest) = line.strip_prefix("Player disconnected: ") {
+        return Some(Line::Disconnected(rest.split_once(", xuid")?.0));
+    }
+    //`[Scripting] [trust_hardcore] death Steve: Steve was slain by Zombie`
+    let (_, rest) = line.split_once(DEATH_TAG)?;
+    let (player, message) = rest.split_once(": ")?;
+    Some(Line::Death { player, message })
+}
+
+/// Parse the files listed after `save query` says they are ready to be copied, eg.
+/// `Bedrock level/db/000005.ldb:2048, Bedrock level/level.dat:2600`, into their paths and how many
+/// bytes of each to copy.
+pub fn parse_file_list(line: &str) -> Option<Vec<(PathBuf, u64)>> {
+    let line = line.trim();
+    //Some versions print the list with the usual prefix
+    let line = match line.strip_prefix('[') {
+        Some(rest) => rest.split_once(']')?.1.trim_start(),
+        None => line,
+    };
+    line.split(", ")
+        .map(|entry| {
+            let (path, len) = entry.rsplit_once(':')?;
+            Some((PathBuf::from(path), len.parse().ok()?))
+        })
+        .collect::<Option<Vec<_>>>()
+        .filter(|files| !files.is_empty())
+}
+
+/// Where a held world is copied to before it goes into a checkpoint.
+pub fn staging_path(partial: &Path) -> PathBuf {
+    let mut name = partial.file_name().unwrap_or_default().to_os_string();
+    name.push(".hold-tmp");
+    partial.with_file_name(name)
+}
+
+/// Copy a held world, with the files that `save query` listed cut to their listed length. Anything
+/// else in the world directory, such as the behavior pack, is copied as it is.
+pub fn stage_world(
+    world: &Path,
+    files: &[(PathBuf, u64)],
+    staging: &Path,
+) -> Result<(), Box<dyn Error>> {
+    remove_path(staging)?;
+    copy_dir(world, staging, &GlobMatcher::new::<&str>(&[])?)?;
+    for (path, len) in files {
+        //Listed paths start with the world directory itself
+        let rel = path.components().skip(1).collect::<PathBuf>();
+        if rel
+            .components()
+            .any(|part| !matches!(part, Component::Normal(_)))
+        {
+            return Err(format!("server listed a strange path \"{}\"", path.display()).into());
+        }
+        let dest = staging.join(&rel);
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let src = File::open(world.join(&rel))?;
+        io::copy(&mut io::Read::take(src, *len), &mut File::create(dest)?)?;
+    }
+    Ok(())
+}
+
+const DEATH_TAG: &str = "[trust_hardcore] death ";
+const PACK_UUID: &str = "5b1c4a3e-6f0d-4e8a-9c2b-7d3e1f0a2b64";
+const MODULE_UUID: &str = "9e2f7c1a-3b4d-4c5e-8f6a-1d2c3b4a5e6f";
+const PACK_SCRIPT: &str = r#"import { world } from "@minecraft/server";
+
+world.afterEvents.entityDie.subscribe(
+  (event) => {
+    const player = event.deadEntity;
+    const killer = event.damageSource.damagingEntity;
+    let message = `${player.name} died (${event.damageSource.cause})`;
+    if (killer) {
+      const name = killer.nameTag || killer.typeId.replace("minecraft:", "");
+      message = `${player.name} was slain by ${name}`;
+    }
+    console.warn(`[trust_hardcore] death ${player.name}: ${message}`);
+  },
+  { entityTypes: ["minecraft:player"] }
+);
+"#;
+
+/// Put the behavior pack that logs deaths into a world, creating the world directory if the
+/// server is yet to generate it.
+pub fn install_pack(world: &Path) -> Result<(), Box<dyn Error>> {
+    let pack = world.join("behavior_packs").join("trust_hardcore");
+    fs::create_dir_all(pack.join("scripts"))?;
+    let manifest = json!({
+        "format_version": 2,
+        "header": {
+            "name": "trust_hardcore",
+            "description": "Logs player deaths for the trust_hardcore wrapper",
+            "uuid": PACK_UUID,
+            "version": [1, 0, 0],
+            "min_engine_version": [1, 21, 0],
+        },
+        "modules": [{
+            "type": "script",
+            "language": "javascript",
+            "uuid": MODULE_UUID,
+            "entry": "scripts/main.js",
+            "version": [1, 0, 0],
+        }],
+        "dependencies": [{ "module_name": "@minecraft/server", "version": "1.11.0" }],
+    });
+    fs::write(
+        pack.join("manifest.json"),
+        serde_json::to_string_pretty(&manifest)?,
+    )?;
+    fs::write(pack.join("scripts").join("main.js"), PACK_SCRIPT)?;
+    //Turn the pack on for the world, next to whatever other packs it has
+    let list_path = world.join("world_behavior_packs.json");
+    let mut list = match fs::read_to_string(&list_path) {
+        Ok(raw) => serde_json::from_str::<Vec<Value>>(&raw)?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(err) => return Err(err.into()),
+    };
+    if !list.iter().any(|entry| entry["pack_id"] == PACK_UUID) {
+        list.push(json!({ "pack_id": PACK_UUID, "version": [1, 0, 0] }));
+        fs::write(&list_path, serde_json::to_string_pretty(&list)?)?;
+    }
+    Ok(())
+}
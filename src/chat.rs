@@ -0,0 +1,225 @@
+//! In-game chat commands, such as `!roll`, `!stats` and `!playtime`.
+
+use serde_derive::Deserialize;
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct ChatConfig {
+    pub enabled: bool,
+    /// Cooldowns by command name (without the leading `!`).
+    pub cooldowns: HashMap<String, Cooldown>,
+    /// How many extra checkpoints each tracked player may request per day.
+    pub checkpoint_quota: u32,
+    pub rewind_vote: RewindVoteConfig,
+    pub death_vote: DeathVoteConfig,
+    /// Who may use each command, by name or UUID, or `@admins` and `@players` for everyone in the
+    /// `admins` or `players` lists. Commands that are not listed are open to everyone, except for
+    /// the `ADMIN_COMMANDS`, which are for admins.
+    pub permissions: HashMap<String, Vec<String>>,
+}
+
+/// Commands that are only for `@admins` unless `permissions` says otherwise.
+pub const ADMIN_COMMANDS: &[&str] = &["backup", "restore"];
+
+/// Every chat command there is, without the leading `!`.
+pub const COMMANDS: &[&str] = &[
+    "roll",
+    "stats",
+    "playtime",
+    "lives",
+    "tokens",
+    "deaths",
+    "checkpoint",
+    "backup",
+    "rewind",
+    "restore",
+    "vote",
+    "help",
+];
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct RewindVoteConfig {
+    pub enabled: bool,
+    /// Fraction of online tracked players that must vote for the rewind.
+    pub majority: f64,
+    /// How long a vote stays valid.
+    pub window_seconds: u64,
+    /// How long to wait after a successful vote before another one can start.
+    pub cooldown_hours: u64,
+}
+impl Default for RewindVoteConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            majority: 0.6,
+            window_seconds: 120,
+            cooldown_hours: 24,
+        }
+    }
+}
+/// Letting the players online vote on what a death costs, with `!vote mercy` or `!vote doom`.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct DeathVoteConfig {
+    pub mode: DeathVoteMode,
+    /// How long players have to vote.
+    pub window_seconds: u64,
+    /// What a tied vote comes down to, including one where nobody voted.
+    pub tie_break: TieBreak,
+}
+impl Default for DeathVoteConfig {
+    fn default() -> Self {
+        Self {
+            mode: DeathVoteMode::Off,
+            window_seconds: 60,
+            tie_break: TieBreak::Roll,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeathVoteMode {
+    Off,
+    /// The vote decides whether the world resets instead of the dice.
+    Replace,
+    /// Bad rolls go to a vote, which may spare the world.
+    Appeal,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum TieBreak {
+    /// Go with the roll.
+    Roll,
+    Mercy,
+    Doom,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Ballot {
+    Mercy,
+    Doom,
+}
+impl Ballot {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "mercy" => Some(Ballot::Mercy),
+            "doom" => Some(Ballot::Doom),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Ballot::Mercy => "mercy",
+            Ballot::Doom => "doom",
+        }
+    }
+}
+
+/// How many votes went to mercy and to doom.
+pub fn tally(ballots: &HashMap<String, Ballot>) -> (usize, usize) {
+    let mercy = ballots.values().filter(|&&b| b == Ballot::Mercy).count();
+    (mercy, ballots.len() - mercy)
+}
+
+/// Which side won a vote, or nothing if it goes with the roll.
+pub fn verdict(conf: &DeathVoteConfig, mercy: usize, doom: usize) -> Option<Ballot> {
+    match mercy.cmp(&doom) {
+        Ordering::Greater => Some(Ballot::Mercy),
+        Ordering::Less => Some(Ballot::Doom),
+        Ordering::Equal => match conf.tie_break {
+            TieBreak::Roll => None,
+            TieBreak::Mercy => Some(Ballot::Mercy),
+            TieBreak::Doom => Some(Ballot::Doom),
+        },
+    }
+}
+
+impl Default for ChatConfig {
+    fn default() -> Self {
+        let mut cooldowns = HashMap::new();
+        cooldowns.insert(
+            "roll".to_string(),
+            Cooldown {
+                player_seconds: 30,
+                global_seconds: 5,
+            },
+        );
+        for cmd in ["stats", "playtime", "lives", "deaths"] {
+            cooldowns.insert(
+                cmd.to_string(),
+                Cooldown {
+                    player_seconds: 60,
+                    global_seconds: 10,
+                },
+            );
+        }
+        Self {
+            enabled: false,
+            cooldowns,
+            checkpoint_quota: 1,
+            rewind_vote: RewindVoteConfig::default(),
+            death_vote: DeathVoteConfig::default(),
+            permissions: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(default)]
+pub struct Cooldown {
+    /// How long a single player must wait between uses.
+    pub player_seconds: u64,
+    /// How long everyone must wait after anyone uses the command.
+    pub global_seconds: u64,
+}
+
+/// Split a chat message into a command name and its arguments, if it is a command.
+pub fn parse_command(text: &str) -> Option<(&str, &str)> {
+    let text = text.strip_prefix('!')?;
+    let name_len = text.find(char::is_whitespace).unwrap_or(text.len());
+    let (name, args) = text.split_at(name_len);
+    if name.is_empty() {
+        return None;
+    }
+    Some((name, args.trim()))
+}
+
+/// Keeps track of when each command was last used.
+#[derive(Default)]
+pub struct Cooldowns {
+    per_player: HashMap<(String, String), Instant>,
+    global: HashMap<String, Instant>,
+}
+impl Cooldowns {
+    /// Check whether `player` may use `cmd` right now, recording the use if so.
+    /// If the command is on cooldown, returns how long is left.
+    pub fn try_use(&mut self, conf: &ChatConfig, player: &str, cmd: &str) -> Result<(), Duration> {
+        let cooldown = match conf.cooldowns.get(cmd) {
+            Some(cooldown) => *cooldown,
+            None => return Ok(()),
+        };
+        let now = Instant::now();
+        let key = (player.to_string(), cmd.to_string());
+        let remaining = |last: Option<&Instant>, secs: u64| {
+            last.map(|&last| Duration::from_secs(secs).saturating_sub(now - last))
+                .unwrap_or_default()
+        };
+        let wait = remaining(self.per_player.get(&key), cooldown.player_seconds)
+            .max(remaining(self.global.get(cmd), cooldown.global_seconds));
+        if wait > Duration::from_secs(0) {
+            return Err(wait);
+        }
+        self.per_player.insert(key, now);
+        self.global.insert(cmd.to_string(), now);
+        Ok(())
+    }
+}
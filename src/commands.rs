@@ -0,0 +1,478 @@
+//! The `backup`, `restore`, `verify-backups`, `verify-rolls`, `validate` and `stats` subcommands,
+//! which work on a campaign while the server is offline.
+
+use crate::{
+    backups::{self, Checkpoint, CheckpointMeta},
+    bedrock::ServerType,
+    config::{load_config, load_fleet, Config},
+    export::{self, CheckpointRow, Export, ExportFormat, PlayerRow, SeasonRow},
+    fairness, fmt_duration,
+    history::History,
+    lang::{embedded_death_messages, parse_langs},
+    lives::Lives,
+    plugins::Plugins,
+    remote,
+    script::Script,
+    server::server_jar,
+    state::State,
+    supervisor::{
+        attempt_world, backup_manager, check_properties, describe_meta, history_path,
+        load_playtime, playtime_path, recover_interrupted, restore_checkpoint, roll_audit_path,
+        state_path,
+    },
+    unix_now,
+};
+use log::{info, warn};
+use std::{collections::BTreeSet, error::Error, fs, path::Path, sync::mpsc, time::Duration};
+
+/// Which checkpoint to restore.
+pub enum RestoreTarget {
+    /// Only list the checkpoints.
+    List,
+    /// An automatic checkpoint, by its number in the list.
+    Slot(usize),
+    /// A named checkpoint.
+    Named(String),
+}
+
+/// Everything the subcommands need to find their way around a campaign.
+struct Campaign {
+    config: Config,
+    state: State,
+    world_name: String,
+}
+impl Campaign {
+    fn load(config_path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut config = load_config(config_path)?;
+        let state_path = state_path(&config);
+        let mut state = State::load(&state_path)?;
+        let mut world_name = attempt_world(&mut config, &state)?;
+        backups::recover_restore(&config.world)?;
+        recover_interrupted(&mut config, &mut state, &state_path, &mut world_name)?;
+        Ok(Self {
+            config,
+            state,
+            world_name,
+        })
+    }
+
+    fn playtime(&self) -> Duration {
+        load_playtime(&playtime_path(&self.config)).unwrap_or_else(|err| {
+            warn!("failed to read playtime: {}", err);
+            Duration::from_secs(0)
+        })
+    }
+
+    fn lives(&self) -> Result<Option<Lives>, Box<dyn Error>> {
+        match self.config.lives {
+            Some(start) => Ok(Some(Lives::load(
+                playtime_path(&self.config).with_file_name("lives.json"),
+                start,
+            )?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Describe a checkpoint for a listing, eg. "auto-1690000000 (made 2h03m ago, day 4, ...)".
+fn describe(ckpt: &Checkpoint, now: u64) -> String {
+    let meta = ckpt
+        .meta()
+        .map(|meta| format!(", {}", describe_meta(&meta)))
+        .unwrap_or_default();
+    let damaged = if ckpt.is_intact() { "" } else { ", damaged" };
+    format!(
+        "{} (made {} ago{}{})",
+        ckpt.name,
+        fmt_duration(Duration::from_secs(now.saturating_sub(ckpt.created))),
+        meta,
+        damaged
+    )
+}
+
+/// Check the config, without starting anything.
+pub fn validate(config_path: &Path) -> Result<(), Box<dyn Error>> {
+    if let Some(servers) = load_fleet(config_path)? {
+        for (name, path) in &servers {
+            info!("checking server \"{}\"", name);
+            validate(path)?;
+        }
+        info!("fleet \"{}\" is valid", config_path.display());
+        return Ok(());
+    }
+    let config = load_config(config_path)?;
+    if config.server_type == ServerType::Bedrock {
+        info!("bedrock deaths are logged by a behavior pack, so no lang files are read");
+    } else {
+        validate_langs(&config)?;
+    }
+    check_properties(&config, false)?;
+    if !config.world.exists() && config.world_name_template.is_none() {
+        warn!(
+            "world directory \"{}\" does not exist yet, the server will generate a new world",
+            config.world.display()
+        );
+    }
+    if let Some(path) = &config.script {
+        Script::load(path)?;
+        info!("script \"{}\" compiles", path.display());
+    }
+    if !config.plugins.is_empty() {
+        Plugins::load(&config.plugins, mpsc::channel().0)?;
+        info!("{} plugins load", config.plugins.len());
+    }
+    info!("config \"{}\" is valid", config_path.display());
+    Ok(())
+}
+
+/// Check that the lang files have death messages in them.
+fn validate_langs(config: &Config) -> Result<(), Box<dyn Error>> {
+    let mut langs = config.lang.as_slice().to_vec();
+    if config.lang_from_jar {
+        match server_jar(&config.server) {
+            Some(jar) if config.server_version.is_none() => langs.push(jar),
+            _ => info!(
+                "death messages will also be read out of the server jar once it is downloaded"
+            ),
+        }
+    }
+    let death_msg = parse_langs(&langs)?;
+    if death_msg.is_empty() {
+        let (version, embedded) = embedded_death_messages(config.server_version.as_deref());
+        if langs.is_empty() {
+            info!(
+                "no lang files configured, would use {} built-in death messages from minecraft {}",
+                embedded.len(),
+                version
+            );
+        } else {
+            return Err(format!("no death messages found in lang files {:?}", langs).into());
+        }
+    } else {
+        info!("{} death messages", death_msg.len());
+    }
+    Ok(())
+}
+
+/// Print how the current attempt is going.
+pub fn stats(config_path: &Path) -> Result<(), Box<dyn Error>> {
+    let campaign = Campaign::load(config_path)?;
+    let state = &campaign.state;
+    println!(
+        "season {} (world \"{}\")",
+        state.resets + 1,
+        campaign.world_name
+    );
+    println!("played for {}", fmt_duration(campaign.playtime()));
+    println!(
+        "{} advancements, {} deaths",
+        state.advancements, state.deaths
+    );
+    if let Some(lives) = campaign.lives()? {
+        let mut players = campaign.config.players.clone();
+        players.sort();
+        for player in &players {
+            let left = lives.left(state.tracked_id(player));
+            println!("{} has {} lives left", player, left);
+        }
+    }
+    let season = state.resets + 1;
+    let history = History::open(&history_path(&campaign.config))?;
+    let deaths = history.leaderboard(Some(season))?;
+    if !deaths.is_empty() {
+        println!("deaths this season, including ones undone by rewinds:");
+        for entry in &deaths {
+            println!(
+                "    {} died {} times, survived {} rolls",
+                entry.player, entry.deaths, entry.survived
+            );
+        }
+    }
+    if history.last_season()?.unwrap_or(0) > 1 {
+        println!("deaths over all seasons:");
+        for entry in history.leaderboard(None)? {
+            println!(
+                "    {} died {} times, survived {} rolls",
+                entry.player, entry.deaths, entry.survived
+            );
+        }
+    }
+    let board = state.playtime_leaderboard();
+    if !board.is_empty() {
+        println!("playtime leaderboard:");
+        for (idx, (player, secs)) in board.iter().enumerate() {
+            println!(
+                "    {}. {} {}",
+                idx + 1,
+                player,
+                fmt_duration(Duration::from_secs(*secs))
+            );
+        }
+    }
+    let backups = backup_manager(&campaign.config, &campaign.world_name);
+    let ckpts = backups.list()?;
+    let now = unix_now();
+    match ckpts.last() {
+        Some(latest) => println!(
+            "{} checkpoints, latest {}",
+            ckpts.len(),
+            describe(latest, now)
+        ),
+        None => println!("no checkpoints yet"),
+    }
+    let named = backups.list_named()?;
+    if !named.is_empty() {
+        let names: Vec<_> = named.iter().map(|ckpt| ckpt.name.as_str()).collect();
+        println!("named checkpoints: {}", names.join(", "));
+    }
+    Ok(())
+}
+
+/// Write the campaign statistics into `dir`, for end-of-season recaps.
+pub fn export_stats(
+    config_path: &Path,
+    format: ExportFormat,
+    dir: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let campaign = Campaign::load(config_path)?;
+    let state = &campaign.state;
+    let lives = campaign.lives()?;
+    let history = History::open(&history_path(&campaign.config))?;
+    let deaths = history.records(None)?;
+    let board = history.leaderboard(None)?;
+    let mut export = Export::default();
+
+    //Everyone who played or may play, by UUID where known
+    let mut ids: BTreeSet<&str> = state.player_playtime.keys().map(String::as_str).collect();
+    ids.extend(campaign.config.players.iter().map(|p| state.tracked_id(p)));
+    for id in ids {
+        let name = state.player_name(id);
+        let entry = board.iter().find(|entry| entry.player == name);
+        export.players.push(PlayerRow {
+            player: name.to_string(),
+            uuid: Some(id.to_string()).filter(|id| id != name),
+            playtime_secs: state.player_playtime.get(id).copied().unwrap_or(0),
+            deaths: entry.map_or(0, |entry| entry.deaths),
+            survived: entry.map_or(0, |entry| entry.survived),
+            lives_left: lives.as_ref().map(|lives| lives.left(id)),
+        });
+    }
+
+    let backups = backup_manager(&campaign.config, &campaign.world_name);
+    let named = backups.list_named()?;
+    for (ckpt, is_named) in backups
+        .list()?
+        .iter()
+        .map(|ckpt| (ckpt, false))
+        .chain(named.iter().map(|ckpt| (ckpt, true)))
+    {
+        let meta = ckpt.meta();
+        export.checkpoints.push(CheckpointRow {
+            name: ckpt.name.clone(),
+            named: is_named,
+            created: ckpt.created,
+            intact: ckpt.is_intact(),
+            attempt: meta.as_ref().map(|meta| meta.attempt),
+            playtime_secs: meta.as_ref().map(|meta| meta.playtime_secs),
+            day: meta.as_ref().and_then(|meta| meta.day),
+            deaths: meta.as_ref().map(|meta| meta.deaths),
+            advancements: meta.as_ref().map(|meta| meta.advancements),
+            world_size: meta.as_ref().map(|meta| meta.world_size),
+        });
+    }
+
+    for season in 1..=state.resets + 1 {
+        let in_season = || deaths.iter().filter(move |record| record.season == season);
+        let reset = in_season().find(|record| record.penalty == "reset");
+        export.seasons.push(SeasonRow {
+            season,
+            deaths: in_season().count() as u32,
+            reset_by: reset.map(|record| record.player.clone()),
+            reset_at: reset.map(|record| record.at),
+            won: season == state.resets + 1 && state.won.is_some(),
+        });
+    }
+    export.deaths = deaths;
+
+    for file in export::write(&export, format, dir)? {
+        info!("wrote \"{}\"", dir.join(file).display());
+    }
+    Ok(())
+}
+
+/// Checkpoint the world while the server is offline, or say when the next one is due.
+pub fn backup(config_path: &Path, now: bool, label: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let campaign = Campaign::load(config_path)?;
+    let config = &campaign.config;
+    let playtime = campaign.playtime();
+    if !now && label.is_none() {
+        let interval = config.checkpoint_minutes * 60;
+        let next = (playtime.as_secs() + interval - 30) / interval * interval + 30;
+        println!(
+            "played for {}, next checkpoint at {} played",
+            fmt_duration(playtime),
+            fmt_duration(Duration::from_secs(next))
+        );
+        println!("pass --now to make one right away");
+        return Ok(());
+    }
+    if !config.world.exists() {
+        return Err(format!(
+            "world directory \"{}\" does not exist",
+            config.world.display()
+        )
+        .into());
+    }
+    let backups = backup_manager(config, &campaign.world_name);
+    backups.check_backup_space(&config.world, &config.dimensions())?;
+    let (partial, done) = match label {
+        Some(label) => backups.named_paths(label)?,
+        None => backups.new_paths(unix_now())?,
+    };
+    let meta = CheckpointMeta {
+        playtime_secs: playtime.as_secs(),
+        day: None,
+        attempt: campaign.state.resets + 1,
+        online_players: Vec::new(),
+        seed: None,
+        advancements: campaign.state.advancements,
+        deaths: campaign.state.deaths,
+        world_size: 0,
+    };
+    info!("making backup of \"{}\"", config.world.display());
+    backups.write(&config.world, &config.dimensions(), &partial, &done, &meta)?;
+    if label.is_none() {
+        backups.prune()?;
+    }
+    info!("checkpoint saved to \"{}\"", done.display());
+    if let Some(remote) = &config.remote_backup {
+        remote::upload(remote, &config.backup_dir, &done)?;
+    }
+    Ok(())
+}
+
+/// Check every checkpoint against its manifest, failing if any of them is damaged.
+pub fn verify_backups(config_path: &Path) -> Result<(), Box<dyn Error>> {
+    let campaign = Campaign::load(config_path)?;
+    let backups = backup_manager(&campaign.config, &campaign.world_name);
+    let ckpts: Vec<_> = backups
+        .list()?
+        .into_iter()
+        .chain(backups.list_named()?)
+        .collect();
+    if ckpts.is_empty() {
+        println!("no checkpoints for world \"{}\"", campaign.world_name);
+        return Ok(());
+    }
+    let mut damaged = 0;
+    for ckpt in &ckpts {
+        let status = match ckpt.verify() {
+            Ok(_) if !ckpt.is_intact() => {
+                damaged += 1;
+                "DAMAGED, not a usable world".to_string()
+            }
+            Ok(true) => "ok".to_string(),
+            Ok(false) => "no manifest, made by an older version".to_string(),
+            Err(err) => {
+                damaged += 1;
+                format!("DAMAGED, {}", err)
+            }
+        };
+        println!("{}: {}", ckpt.name, status);
+    }
+    match damaged {
+        0 => Ok(()),
+        n => Err(format!("{} of {} checkpoints are damaged", n, ckpts.len()).into()),
+    }
+}
+
+/// Redo every fair roll in the audit file from its revealed secret, failing if any of them does not
+/// add up.
+pub fn verify_rolls(config_path: &Path) -> Result<(), Box<dyn Error>> {
+    let config = load_config(config_path)?;
+    let path = roll_audit_path(&config);
+    if !path.exists() {
+        println!("no fair rolls yet, \"{}\" does not exist", path.display());
+        return Ok(());
+    }
+    let entries = fairness::read_audit(&path)?;
+    let mut bad = 0;
+    for entry in &entries {
+        let status = match entry.verify() {
+            Ok(()) => "ok".to_string(),
+            Err(err) => {
+                bad += 1;
+                format!("DOES NOT CHECK OUT, {}", err)
+            }
+        };
+        println!(
+            "{}: rolled {} with commitment {}: {}",
+            entry.event, entry.roll, entry.commitment, status
+        );
+    }
+    match bad {
+        0 => Ok(()),
+        n => Err(format!("{} of {} rolls do not check out", n, entries.len()).into()),
+    }
+}
+
+/// Restore a checkpoint while the server is offline, or list the ones there are.
+pub fn restore(config_path: &Path, target: RestoreTarget) -> Result<(), Box<dyn Error>> {
+    let mut campaign = Campaign::load(config_path)?;
+    let backups = backup_manager(&campaign.config, &campaign.world_name);
+    let ckpts = backups.list()?;
+    let ckpt = match target {
+        RestoreTarget::List => {
+            let now = unix_now();
+            if ckpts.is_empty() {
+                println!("no checkpoints for world \"{}\"", campaign.world_name);
+            }
+            for (idx, ckpt) in ckpts.iter().enumerate() {
+                println!("{}: {}", idx, describe(ckpt, now));
+            }
+            for ckpt in backups.list_named()? {
+                println!("{}", describe(&ckpt, now));
+            }
+            println!("pick one with --slot <n> or --name <name>");
+            return Ok(());
+        }
+        RestoreTarget::Slot(idx) => ckpts.into_iter().nth(idx).ok_or_else(|| {
+            format!(
+                "no checkpoint in slot {}, run `restore` without --slot to list them",
+                idx
+            )
+        })?,
+        RestoreTarget::Named(label) => backups
+            .named(&label)?
+            .ok_or_else(|| format!("no checkpoint named \"{}\"", label))?,
+    };
+    if !ckpt.is_intact() {
+        return Err(format!("checkpoint \"{}\" is damaged", ckpt.name).into());
+    }
+    match ckpt.verify() {
+        Ok(true) => info!("checkpoint \"{}\" matches its manifest", ckpt.name),
+        Ok(false) => warn!(
+            "checkpoint \"{}\" has no manifest, restoring it unchecked",
+            ckpt.name
+        ),
+        Err(err) => return Err(format!("checkpoint \"{}\" is damaged: {}", ckpt.name, err).into()),
+    }
+    backups.check_restore_space(&ckpt, &campaign.config.world)?;
+    //Keep lives as they are, like a rewind does
+    let lives = campaign.lives()?;
+    let config = &campaign.config;
+    if let Some(parent) = config.world.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    restore_checkpoint(
+        &ckpt,
+        config,
+        &playtime_path(config),
+        &mut campaign.state,
+        &state_path(config),
+        lives.as_ref(),
+        backups.exclude(),
+    )?;
+    info!("restored checkpoint \"{}\"", ckpt.name);
+    Ok(())
+}
@@ -0,0 +1,1113 @@
+//! Wrapper configuration, as loaded from the config file: TOML if it ends in `.toml`, JSON
+//! otherwise.
+
+use crate::{
+    afk::AfkConfig,
+    announce::{default_ceremony, CeremonyDisplay, CeremonyStep, MessageStyle},
+    archive::BackupFormat,
+    bedrock::ServerType,
+    chat::{ChatConfig, DeathVoteMode},
+    download::Flavor,
+    fairness::FairRollsConfig,
+    faults::Fault,
+    hooks::{self, HookAction},
+    java::{JvmConfig, MemoryPressureConfig},
+    matcher::GlobMatcher,
+    penalty::{
+        parse_rolls, DeathRule, Dice, Escalation, Outcome, PenaltyEngine, PlayerRolls, RuleAction,
+        MAX_SIDES,
+    },
+    proxy::ProxyConfig,
+    rcon::RconConfig,
+    remote::{RemoteConfig, RemoteTarget},
+    schedule::Schedule,
+    server::{read_properties, AttachConfig, CrashRestartConfig, SeedPolicy},
+    telegram::TelegramConfig,
+    twitch::TwitchConfig,
+};
+use log::{info, warn};
+use serde_derive::Deserialize;
+use serde_json as json;
+use std::{
+    collections::BTreeMap,
+    env,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+#[derive(Deserialize)]
+pub struct Config {
+    /// Command that launches the server, or just the jar and its arguments when `jvm` is given.
+    /// Unused with `attach`.
+    #[serde(default)]
+    pub server: Vec<String>,
+    /// Have the wrapper compose the Java command line around `server`.
+    #[serde(default)]
+    pub jvm: Option<JvmConfig>,
+    /// Minecraft version whose server jar to download and launch, instead of a jar on disk.
+    /// The jar path replaces `{server_jar}` in `server`, or the argument after `-jar`.
+    #[serde(default)]
+    pub server_version: Option<String>,
+    /// Server software, which decides which jar to download and how output lines are prefixed.
+    #[serde(default)]
+    pub server_flavor: Flavor,
+    /// Java or Bedrock edition, which decides how the server is talked to and backed up.
+    #[serde(default)]
+    pub server_type: ServerType,
+    /// What to do when the JVM runs low on memory.
+    #[serde(default)]
+    pub memory_pressure: MemoryPressureConfig,
+    /// Whether and how to restart the server after a crash.
+    #[serde(default)]
+    pub crash_restart: CrashRestartConfig,
+    /// Send console commands over RCON instead of the server's stdin.
+    #[serde(default)]
+    pub rcon: Option<RconConfig>,
+    /// Watch a server that something else runs, through its log file and RCON.
+    #[serde(default)]
+    pub attach: Option<AttachConfig>,
+    /// Park players on a proxy's limbo server during rewinds and resets.
+    #[serde(default)]
+    pub proxy: Option<ProxyConfig>,
+    /// Discord webhook URL to post deaths, rolls, checkpoints and resets to.
+    #[serde(default)]
+    pub discord_webhook: Option<String>,
+    /// Telegram bot to post the same events as `discord_webhook` with, and take commands through.
+    #[serde(default)]
+    pub telegram: Option<TelegramConfig>,
+    /// Address to serve the HTTP control API on, eg. `127.0.0.1:8080`.
+    #[serde(default)]
+    pub api_bind: Option<String>,
+    /// Token that API requests must send as `Authorization: Bearer <token>`.
+    #[serde(default)]
+    pub api_token: Option<String>,
+    /// Java runtime to launch the server with, replacing `java` in `server`.
+    #[serde(default)]
+    pub java: Option<String>,
+    /// World directory, or a list of them with the main world first for servers that split
+    /// dimensions into worlds of their own, as Spigot and Paper do. Found through the
+    /// `server.properties` next to the config file if not given.
+    #[serde(rename = "world", default)]
+    worlds: OneOrMany<PathBuf>,
+    /// The main world directory, the first of `world`.
+    #[serde(skip)]
+    pub world: PathBuf,
+    /// Language files to take death messages from, or jars to read the language file out of.
+    /// The built-in English set is used if there are none, or if they have no death messages.
+    #[serde(default)]
+    pub lang: OneOrMany<PathBuf>,
+    /// Also read death messages out of the server jar.
+    #[serde(default)]
+    pub lang_from_jar: bool,
+    pub ignore_phrases: Vec<String>,
+    /// Rules for deaths whose message matches a pattern, checked in order. The first one that
+    /// applies decides whether the death is ignored, rolled for with its own outcomes, or has a
+    /// fixed outcome.
+    #[serde(default)]
+    pub death_rules: Vec<DeathRule>,
+    /// Roll settings for some players, by name or UUID, eg. harsher odds for a veteran. Deaths
+    /// rolled for as a group use the global settings.
+    #[serde(default)]
+    pub player_rolls: BTreeMap<String, PlayerRolls>,
+    /// Steps that make the odds worse as playtime or deaths add up, reached in order.
+    #[serde(default)]
+    pub escalation: Vec<Escalation>,
+    /// Roll from a secret committed to before each death, and reveal it afterwards.
+    #[serde(default)]
+    pub fair_rolls: FairRollsConfig,
+    /// Let the viewers of a Twitch channel vote along in `death_vote`s.
+    #[serde(default)]
+    pub twitch: Option<TwitchConfig>,
+    pub make_backups: bool,
+    pub backup_dir: PathBuf,
+    /// Players whose deaths roll, by name or UUID.
+    pub players: Vec<String>,
+    pub allow_all_players: bool,
+    /// Console command sent on each death, with `{username}` replaced by who died. Works like a
+    /// `player_death` command in `hooks`, except that it can be skipped during the roll cooldown.
+    pub on_death_command: Option<String>,
+    #[serde(default)]
+    pub death_location: DeathLocationConfig,
+    /// Actions to take on run events, by event name.
+    #[serde(default)]
+    pub hooks: BTreeMap<String, Vec<HookAction>>,
+    /// A Rhai script with custom rules for rolls, ceremonies and penalties. See the `script`
+    /// module for what it may define.
+    #[serde(default)]
+    pub script: Option<PathBuf>,
+    /// Sandboxed WASM plugins, as `.wasm` or `.wat` files. See the `plugins` module for what they
+    /// may export and import.
+    #[serde(default)]
+    pub plugins: Vec<PathBuf>,
+    /// How long a player who survived a roll is immune to further rolls, in seconds. Deaths in
+    /// that window are still announced.
+    #[serde(default)]
+    pub roll_cooldown_seconds: u64,
+    /// Also skip `on_death_command` for deaths during the roll cooldown.
+    #[serde(default)]
+    pub roll_cooldown_skips_death_command: bool,
+    pub checkpoint_minutes: u64,
+    /// Also checkpoint on the clock while anyone is online, eg. `"every day at 04:00"` or
+    /// `"every 2 hours"`.
+    #[serde(default)]
+    pub checkpoint_schedule: Vec<Schedule>,
+    /// Stop the playtime clock while everyone online is AFK.
+    #[serde(default)]
+    pub afk: AfkConfig,
+    /// Inclusive range of a single die, used when there are no `dice`.
+    #[serde(default = "default_roll_range")]
+    pub roll_range: (i32, i32),
+    /// Dice to roll in `NdM+K` notation, eg. `"2d6+1"`, instead of `roll_range`.
+    #[serde(default)]
+    pub dice: Option<Dice>,
+    /// Messages and pauses played out when a player dies, before the outcome is applied.
+    #[serde(default = "default_ceremony")]
+    pub ceremony: Vec<CeremonyStep>,
+    /// Show the ceremony full-screen with titles and a bossbar countdown, instead of in chat.
+    #[serde(default)]
+    pub ceremony_display: CeremonyDisplay,
+    /// Colors and such for announcements, sent with `tellraw` when enabled.
+    #[serde(default)]
+    pub message_style: MessageStyle,
+    /// Relative weights of roll totals or ranges of them, eg. `{"1": 0.5, "2-3": 2}`. Totals not
+    /// listed weigh 1.
+    #[serde(default)]
+    pub roll_weights: BTreeMap<String, f64>,
+    /// Rolls that reset the world, used when there are no `roll_outcomes`.
+    #[serde(default)]
+    pub deadly_rolls: Vec<i32>,
+    /// What each roll or range of rolls leads to, eg. `{"1": "reset", "2-3": "rewind"}`.
+    #[serde(default)]
+    pub roll_outcomes: BTreeMap<String, Outcome>,
+    /// How `border` roll outcomes shrink the world border.
+    #[serde(default)]
+    pub border_penalty: BorderPenalty,
+    /// How `spectator` roll outcomes take players out of the game.
+    #[serde(default)]
+    pub spectator_penalty: SpectatorPenalty,
+    /// How long `ban` roll outcomes ban the players who died for.
+    #[serde(default = "default_ban_minutes")]
+    pub ban_minutes: u64,
+    /// Whether deaths that happen close together get a roll each (`"sequential"`) or share a
+    /// single roll (`"group"`).
+    #[serde(default)]
+    pub multi_death_mode: MultiDeathMode,
+    /// How long a group keeps gathering deaths after the first one, in milliseconds.
+    #[serde(default = "default_multi_death_window")]
+    pub multi_death_window_ms: u64,
+    /// Lives each player starts a world with. Bad rolls take a life, and only cost the world once
+    /// the player has none left.
+    #[serde(default)]
+    pub lives: Option<u32>,
+    /// How many `[...]` prefixes to strip off each line, overriding `server_flavor`.
+    #[serde(default)]
+    pub bracket_count: Option<u32>,
+    #[serde(default)]
+    pub chat_commands: ChatConfig,
+    /// Minimum spacing between consecutive announcements, in milliseconds.
+    #[serde(default = "default_announce_interval")]
+    pub announce_interval_ms: u64,
+    /// How many lines of recent output to keep around for failure reports.
+    #[serde(default = "default_recent_lines")]
+    pub recent_lines: usize,
+    /// Where to keep a rotating log of the server console, if anywhere.
+    #[serde(default)]
+    pub log_dir: Option<PathBuf>,
+    #[serde(default = "default_log_max_kb")]
+    pub log_max_kb: u64,
+    /// How many rotated log files to keep besides the current one.
+    #[serde(default = "default_log_keep")]
+    pub log_keep: u32,
+    /// Log every output line that was not acted upon, along with the reason.
+    #[serde(default)]
+    pub debug_unmatched: bool,
+    /// Refuse to run with a configuration that could never trigger a penalty.
+    #[serde(default = "default_true")]
+    pub strict: bool,
+    /// How many times to retry a failed checkpoint copy.
+    #[serde(default = "default_backup_retries")]
+    pub backup_retries: u32,
+    /// How long to wait for the server to confirm a save before copying the world anyway.
+    #[serde(default = "default_save_timeout")]
+    pub save_timeout_secs: u64,
+    /// How long to wait for the server to stop before killing it.
+    #[serde(default = "default_stop_timeout")]
+    pub stop_timeout_secs: u64,
+    /// What players are kicked with when the server stops for a rewind or reset.
+    #[serde(default)]
+    pub kick_messages: KickMessages,
+    /// Advancements that trigger an extra checkpoint as soon as anyone gets them.
+    #[serde(default)]
+    pub milestone_advancements: Vec<String>,
+    /// Advancements that win the run, after which deaths no longer roll.
+    #[serde(default = "default_win_advancements")]
+    pub win_advancements: Vec<String>,
+    /// Mercy tokens a player gets for each of these advancements, once per world. A token is
+    /// spent on its own to spare the world a deadly roll of whoever holds it.
+    #[serde(default)]
+    pub mercy_advancements: BTreeMap<String, u32>,
+    /// Where won worlds are archived, `hall_of_fame` in the backup directory by default.
+    #[serde(default)]
+    pub hall_of_fame_dir: Option<PathBuf>,
+    /// Keep each reset world and its stats under `seasons/<n>/` instead of deleting it.
+    #[serde(default)]
+    pub archive_seasons: bool,
+    /// Where seasons are archived, `seasons` in the backup directory by default.
+    #[serde(default)]
+    pub seasons_dir: Option<PathBuf>,
+    /// The `level-seed` each new season's world gets: `"keep"`, `"random"`, `{"fixed": <seed>}`
+    /// or `{"list": [<seeds>]}` to go through a list.
+    #[serde(default)]
+    pub reset_seed: SeedPolicy,
+    /// Other `server.properties` keys to set whenever the world is reset, eg.
+    /// `{"difficulty": "hard"}`, with `{season}` replaced by the number of the new season.
+    #[serde(default)]
+    pub reset_properties: BTreeMap<String, String>,
+    /// Refuse to start unless `server.properties` has `hardcore=true`, instead of only warning.
+    #[serde(default)]
+    pub require_hardcore: bool,
+    /// Setup for worlds the server is about to generate.
+    #[serde(default)]
+    pub on_new_world: NewWorldConfig,
+    /// Console commands to run every time the server is up.
+    #[serde(default)]
+    pub startup_commands: Vec<String>,
+    /// Make a checkpoint once the server has been empty for this many minutes.
+    #[serde(default)]
+    pub checkpoint_on_logoff_minutes: Option<u64>,
+    /// How many automatic checkpoints to keep around.
+    #[serde(default = "default_checkpoint_slots", alias = "backup_slots")]
+    pub checkpoint_slots: usize,
+    /// Hardlink files that did not change since the latest checkpoint instead of copying them, with
+    /// the `directory` backup format.
+    #[serde(default)]
+    pub incremental_backups: bool,
+    /// Also upload each checkpoint to S3-compatible storage or over SFTP.
+    #[serde(default)]
+    pub remote_backup: Option<RemoteConfig>,
+    /// Free space to leave on the disk after a checkpoint or a restore, in megabytes. Checkpoints
+    /// and restores that would leave less are not started.
+    #[serde(default = "default_disk_space_margin")]
+    pub disk_space_margin_mb: u64,
+    /// Glob patterns of paths in the world to leave out of checkpoints and restores, eg.
+    /// `"DIM*/poi"`. Patterns without a `/` match at any depth.
+    #[serde(default = "default_backup_exclude")]
+    pub backup_exclude: Vec<String>,
+    /// How checkpoints are stored on disk: `"directory"`, `"tar.zst"` or `"zip"`.
+    #[serde(default)]
+    pub backup_format: BackupFormat,
+    /// Which checkpoint a rewind goes back to.
+    #[serde(default)]
+    pub rewind_target: RewindTarget,
+    /// Rewind to the newest checkpoint that undoes at least this many minutes of playtime, or the
+    /// oldest one if none is that far back, instead of following `rewind_target`.
+    #[serde(default)]
+    pub rewind_minutes: Option<u64>,
+    /// How long to wait for an admin to pick a checkpoint with `rewind_target: "prompt"`.
+    #[serde(default = "default_rewind_prompt")]
+    pub rewind_prompt_seconds: u64,
+    /// What a rewind rolled on a death takes back.
+    #[serde(default)]
+    pub rewind_scope: RewindScope,
+    /// Players allowed to use admin chat commands, such as `!restore`.
+    #[serde(default)]
+    pub admins: Vec<String>,
+    /// Where to keep persistent wrapper state. Defaults to a file inside `backup_dir`.
+    #[serde(default)]
+    pub state_file: Option<PathBuf>,
+    /// Name each attempt's world after this template, where `{n}` is the attempt number, so a
+    /// reset starts a brand-new world directory next to `world`.
+    #[serde(default)]
+    pub world_name_template: Option<String>,
+    /// Directory for campaign state, such as playtime, instead of keeping it inside the world.
+    #[serde(default)]
+    pub state_dir: Option<PathBuf>,
+    /// Failures to inject when running with `--debug`.
+    #[serde(default)]
+    pub faults: Vec<Fault>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct NewWorldConfig {
+    /// Console commands to run once the server is up, such as gamerules or the world border.
+    pub commands: Vec<String>,
+    /// Directory whose contents are copied into the world's `datapacks` before it is generated.
+    pub datapacks: Option<PathBuf>,
+}
+
+/// Kick messages for the players online when the server stops for a penalty, with `{player}`
+/// replaced by who is kicked and `{season}` by the season that comes next. Set to `null` or empty
+/// to leave it to the server's own stop message, which looks like a crash.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct KickMessages {
+    pub rewind: Option<String>,
+    pub reset: Option<String>,
+    /// For players sent back on their own with `rewind_scope: "player"`, who are always kicked so
+    /// that the server lets go of their files.
+    pub player_rewind: Option<String>,
+}
+impl Default for KickMessages {
+    fn default() -> Self {
+        Self {
+            rewind: Some(
+                "§6The world is being rewound to a checkpoint.§r Reconnect in a minute!"
+                    .to_string(),
+            ),
+            reset: Some(
+                "§4The world is being destroyed.§r Season {season} starts in a minute, reconnect then!"
+                    .to_string(),
+            ),
+            player_rewind: Some(
+                "§6You are being rewound to a checkpoint.§r Reconnect in a moment!".to_string(),
+            ),
+        }
+    }
+}
+
+/// Telling everyone where a player died, so the others can recover their gear before the verdict.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct DeathLocationConfig {
+    /// Say in chat where the player died.
+    pub announce: bool,
+    /// Console command sent to mark the spot, with `{player}`, `{x}`, `{y}`, `{z}` and
+    /// `{dimension}` replaced.
+    pub marker_command: Option<String>,
+}
+impl DeathLocationConfig {
+    pub fn enabled(&self) -> bool {
+        self.announce || self.marker_command.is_some()
+    }
+}
+
+/// How much the world border shrinks on each `border` roll, in blocks of diameter.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct BorderPenalty {
+    pub step: f64,
+    /// Smallest the border gets, after which `border` rolls do nothing.
+    pub min: f64,
+    /// How long the border takes to close in, in seconds.
+    pub shrink_seconds: u64,
+}
+impl Default for BorderPenalty {
+    fn default() -> Self {
+        Self {
+            step: 1000.0,
+            min: 500.0,
+            shrink_seconds: 60,
+        }
+    }
+}
+
+/// Players sit out a `spectator` roll in a game mode other than survival for a while, and are put
+/// back in survival once the time is up and they are online.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct SpectatorPenalty {
+    pub minutes: u64,
+    pub game_mode: SitOutMode,
+}
+impl Default for SpectatorPenalty {
+    fn default() -> Self {
+        Self {
+            minutes: 30,
+            game_mode: SitOutMode::Spectator,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SitOutMode {
+    /// Flying around, seeing everything but touching nothing.
+    Spectator,
+    /// Walking around, unable to break or place blocks.
+    Adventure,
+}
+impl SitOutMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            SitOutMode::Spectator => "spectator",
+            SitOutMode::Adventure => "adventure",
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RewindTarget {
+    /// Always the most recent checkpoint.
+    #[default]
+    Latest,
+    /// A random checkpoint among the kept slots.
+    Random,
+    /// Ask the admin on the wrapper console, falling back to the latest.
+    Prompt,
+}
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RewindScope {
+    /// The whole world goes back to the checkpoint.
+    #[default]
+    World,
+    /// Only the players who died go back, with their inventory, position, advancements and stats,
+    /// while the world and everyone else carry on. Rewinds asked for by admins or votes still take
+    /// the whole world back.
+    Player,
+}
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MultiDeathMode {
+    /// Roll for each death in turn.
+    #[default]
+    Sequential,
+    /// Roll once for everyone who died within `multi_death_window_ms`.
+    Group,
+}
+fn default_ban_minutes() -> u64 {
+    60
+}
+
+fn default_multi_death_window() -> u64 {
+    2000
+}
+
+fn default_backup_exclude() -> Vec<String> {
+    vec!["session.lock".to_string()]
+}
+
+fn default_disk_space_margin() -> u64 {
+    1024
+}
+
+fn default_checkpoint_slots() -> usize {
+    1
+}
+
+fn default_rewind_prompt() -> u64 {
+    120
+}
+
+fn default_backup_retries() -> u32 {
+    2
+}
+
+fn default_save_timeout() -> u64 {
+    30
+}
+
+fn default_stop_timeout() -> u64 {
+    60
+}
+
+fn default_win_advancements() -> Vec<String> {
+    vec!["Free the End".to_string()]
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_announce_interval() -> u64 {
+    1000
+}
+
+impl Config {
+    /// The worlds holding the other dimensions of `world`, as listed in the config, or else the
+    /// `_nether` and `_the_end` directories that Spigot and Paper put next to it, if there are any.
+    pub fn dimensions(&self) -> Vec<PathBuf> {
+        let name = self.world.file_name().unwrap_or_default().to_string_lossy();
+        match self.worlds.as_slice() {
+            [] | [_] if self.server_type == ServerType::Bedrock => Vec::new(),
+            [] | [_] => ["_nether", "_the_end"]
+                .iter()
+                .map(|suffix| self.world.with_file_name(format!("{}{}", name, suffix)))
+                .filter(|dim| dim.is_dir())
+                .collect(),
+            [first, rest @ ..] => {
+                //Follow the main world to the world of the current attempt
+                let first = first.file_name().unwrap_or_default().to_string_lossy();
+                rest.iter()
+                    .map(|dim| {
+                        let dim_name = dim.file_name().unwrap_or_default().to_string_lossy();
+                        match dim_name.strip_prefix(&*first) {
+                            Some(suffix) => dim.with_file_name(format!("{}{}", name, suffix)),
+                            None => dim.clone(),
+                        }
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// The `server.properties` of the server running `world`.
+    pub fn properties_path(&self) -> PathBuf {
+        let dir = self.world.parent().unwrap_or_else(|| Path::new("."));
+        match self.server_type {
+            //Bedrock keeps its worlds in `worlds/`
+            ServerType::Bedrock => dir.parent().unwrap_or_else(|| Path::new(".")),
+            ServerType::Java => dir,
+        }
+        .join("server.properties")
+    }
+
+    /// Paths in the world to leave out of checkpoints.
+    pub fn backup_exclude(&self) -> GlobMatcher {
+        GlobMatcher::new(&self.backup_exclude).expect("checked by load_config")
+    }
+
+    /// The `player_rolls` of a player, given their name and UUID.
+    pub fn player_rolls(&self, name: &str, id: &str) -> Option<&PlayerRolls> {
+        self.player_rolls
+            .iter()
+            .find(|(who, _)| *who == name || *who == id)
+            .map(|(_, rolls)| rolls)
+    }
+
+    /// How many `escalation` steps have been reached.
+    pub fn escalation_level(&self, playtime: Duration, deaths: u32) -> usize {
+        self.escalation
+            .iter()
+            .take_while(|step| step.reached(playtime, deaths))
+            .count()
+    }
+
+    /// The dice, deadly rolls and roll outcomes once the first `level` escalation steps apply.
+    pub fn escalated_rolls(&self, level: usize) -> (Dice, Vec<i32>, BTreeMap<String, Outcome>) {
+        let mut dice = self.dice();
+        let mut deadly_rolls = self.deadly_rolls.clone();
+        let mut roll_outcomes = self.roll_outcomes.clone();
+        for step in &self.escalation[..level] {
+            if let Some(range) = step.roll_range {
+                dice = Dice::from_range(range);
+            }
+            deadly_rolls.extend(&step.deadly_rolls);
+            if !roll_outcomes.is_empty() {
+                for num in &step.deadly_rolls {
+                    roll_outcomes
+                        .entry(num.to_string())
+                        .or_insert(Outcome::Reset);
+                }
+            }
+        }
+        (dice, deadly_rolls, roll_outcomes)
+    }
+
+    /// The engine for deaths without rules or `player_rolls` of their own.
+    pub fn global_engine(&self, level: usize) -> PenaltyEngine {
+        let (dice, deadly_rolls, roll_outcomes) = self.escalated_rolls(level);
+        PenaltyEngine::new(dice, &self.roll_weights, &deadly_rolls, &roll_outcomes)
+    }
+
+    /// The dice to roll, from `dice` or else from `roll_range`.
+    pub fn dice(&self) -> Dice {
+        self.dice
+            .unwrap_or_else(|| Dice::from_range(self.roll_range))
+    }
+}
+
+fn default_roll_range() -> (i32, i32) {
+    (1, 20)
+}
+
+pub fn default_recent_lines() -> usize {
+    500
+}
+
+fn default_log_max_kb() -> u64 {
+    10 * 1024
+}
+
+fn default_log_keep() -> u32 {
+    5
+}
+
+/// A config value that may be given either as a single item or as a list.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+impl<T> Default for OneOrMany<T> {
+    fn default() -> Self {
+        OneOrMany::Many(Vec::new())
+    }
+}
+impl<T> OneOrMany<T> {
+    pub fn as_slice(&self) -> &[T] {
+        match self {
+            OneOrMany::One(item) => std::slice::from_ref(item),
+            OneOrMany::Many(items) => items,
+        }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        match self {
+            OneOrMany::One(item) => std::slice::from_mut(item),
+            OneOrMany::Many(items) => items,
+        }
+    }
+}
+
+/// Find the world directory through the `level-name` in the `server.properties` in `dir`.
+fn discover_world(dir: &Path, server_type: ServerType) -> Result<PathBuf, Box<dyn Error>> {
+    let properties = dir.join("server.properties");
+    let props = read_properties(&properties).map_err(|err| {
+        format!(
+            "`world` is not set, and \"{}\" cannot be read to find it: {}",
+            properties.display(),
+            err
+        )
+    })?;
+    let world = match server_type {
+        ServerType::Java => dir.join(props.get("level-name").map_or("world", String::as_str)),
+        ServerType::Bedrock => dir.join("worlds").join(
+            props
+                .get("level-name")
+                .map_or("Bedrock level", String::as_str),
+        ),
+    };
+    info!("found the world through \"{}\"", properties.display());
+    Ok(world)
+}
+
+/// Expand a leading `~` and make a relative path relative to `base`.
+fn resolve_path(base: &Path, path: &Path) -> PathBuf {
+    let path = match path.strip_prefix("~") {
+        Ok(rest) => match env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(rest),
+            None => path.to_path_buf(),
+        },
+        Err(_) => path.to_path_buf(),
+    };
+    base.join(path)
+}
+
+/// Whether a config file is TOML rather than JSON, going by its extension.
+pub fn is_toml(path: &Path) -> bool {
+    path.extension().map(|ext| ext == "toml").unwrap_or(false)
+}
+
+/// Parse a config, pointing at the offending field and line if it does not fit.
+fn parse_config(raw: &str, toml: bool) -> Result<Config, Box<dyn Error>> {
+    let describe = |path: String, msg: String| {
+        if path == "." {
+            msg
+        } else {
+            format!("`{}`: {}", path, msg)
+        }
+    };
+    if toml {
+        serde_path_to_error::deserialize(toml::Deserializer::new(raw)).map_err(|err| {
+            let path = err.path().to_string();
+            let err = err.into_inner();
+            //Errors about the whole document, such as missing fields, have no useful line
+            let line = err
+                .span()
+                .filter(|span| span.start > 0)
+                .map(|span| format!(" at line {}", line_at(raw, span.start)))
+                .unwrap_or_default();
+            describe(path, format!("{}{}", err.message(), line)).into()
+        })
+    } else {
+        let mut de = json::Deserializer::from_str(raw);
+        serde_path_to_error::deserialize(&mut de).map_err(|err| {
+            let path = err.path().to_string();
+            describe(path, err.into_inner().to_string()).into()
+        })
+    }
+}
+
+/// Line number of a byte offset, starting at 1.
+fn line_at(raw: &str, offset: usize) -> usize {
+    raw[..offset.min(raw.len())].matches('\n').count() + 1
+}
+
+/// Where a top-level `key` is set in the raw config, for error messages.
+fn key_line(raw: &str, key: &str) -> String {
+    let quoted = format!("\"{}\"", key);
+    raw.lines()
+        .position(|line| {
+            let line = line.trim_start();
+            line.starts_with(&quoted)
+                || (line.starts_with(key) && line[key.len()..].trim_start().starts_with('='))
+        })
+        .map(|idx| format!(" at line {}", idx + 1))
+        .unwrap_or_default()
+}
+
+/// A file listing several servers to supervise at once, each with its own config.
+#[derive(Deserialize)]
+struct FleetConfig {
+    /// Config file of each server, by the name that tags its output.
+    servers: BTreeMap<String, PathBuf>,
+}
+
+/// The servers a fleet file lists, or nothing if `path` is the config of a single server.
+pub fn load_fleet(path: &Path) -> Result<Option<BTreeMap<String, PathBuf>>, Box<dyn Error>> {
+    let raw = fs::read_to_string(path)?;
+    //Anything unreadable is left for `load_config` to point out
+    let value = match is_toml(path) {
+        true => toml::from_str::<json::Value>(&raw).ok(),
+        false => json::from_str::<json::Value>(&raw).ok(),
+    };
+    let value = match value {
+        Some(value) if value.get("servers").is_some() => value,
+        _ => return Ok(None),
+    };
+    let fleet: FleetConfig =
+        json::from_value(value).map_err(|err| format!("`servers`: {}", err))?;
+    if fleet.servers.is_empty() {
+        return Err("`servers` lists no servers".into());
+    }
+    let base = fs::canonicalize(path)?
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    Ok(Some(
+        fleet
+            .servers
+            .into_iter()
+            .map(|(name, config)| (name, resolve_path(&base, &config)))
+            .collect(),
+    ))
+}
+
+pub fn load_config(path: &Path) -> Result<Config, Box<dyn Error>> {
+    let raw = fs::read_to_string(path)?;
+    //Point at the field that failed a check
+    macro_rules! ensure {
+        ($cond:expr, $key:expr, $($tt:tt)*) => {{
+            if !$cond {
+                return Err(format!(
+                    "`{}` {}{}",
+                    $key,
+                    format!($($tt)*),
+                    key_line(&raw, $key)
+                )
+                .into());
+            }
+        }};
+    }
+    let mut conf = parse_config(&raw, is_toml(path))?;
+    //Resolve paths relative to the config file, not to wherever the wrapper was started from
+    let base = fs::canonicalize(path)?
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    for world in conf.worlds.as_mut_slice() {
+        *world = resolve_path(&base, world);
+    }
+    conf.world = match conf.worlds.as_slice().first() {
+        Some(world) => world.clone(),
+        None => discover_world(&base, conf.server_type)?,
+    };
+    conf.backup_dir = resolve_path(&base, &conf.backup_dir);
+    for lang in conf.lang.as_mut_slice() {
+        *lang = resolve_path(&base, lang);
+    }
+    if let Some(attach) = &mut conf.attach {
+        attach.log = resolve_path(&base, &attach.log);
+    }
+    if let Some(RemoteConfig {
+        target: RemoteTarget::Sftp(sftp),
+        ..
+    }) = &mut conf.remote_backup
+    {
+        if let Some(identity) = &mut sftp.identity {
+            *identity = resolve_path(&base, identity);
+        }
+    }
+    for path in conf
+        .log_dir
+        .iter_mut()
+        .chain(conf.state_file.iter_mut())
+        .chain(conf.state_dir.iter_mut())
+        .chain(conf.hall_of_fame_dir.iter_mut())
+        .chain(conf.seasons_dir.iter_mut())
+        .chain(conf.on_new_world.datapacks.iter_mut())
+        .chain(conf.fair_rolls.audit_file.iter_mut())
+        .chain(conf.script.iter_mut())
+        .chain(conf.plugins.iter_mut())
+    {
+        *path = resolve_path(&base, path);
+    }
+    info!("world directory: \"{}\"", conf.world.display());
+    for dim in conf.worlds.as_slice().iter().skip(1) {
+        info!("dimension world directory: \"{}\"", dim.display());
+    }
+    info!("backup directory: \"{}\"", conf.backup_dir.display());
+    for lang in conf.lang.as_slice() {
+        info!("lang file: \"{}\"", lang.display());
+    }
+    /*ensure!(
+        conf.server.extension() == Some("jar".as_ref()),
+        "server must be a .jar file"
+    );*/
+    ensure!(
+        !matches!(&conf.reset_seed, SeedPolicy::List(seeds) if seeds.is_empty()),
+        "reset_seed",
+        "must list at least one seed"
+    );
+    ensure!(
+        !conf.reset_properties.contains_key("level-name")
+            && !conf.reset_properties.contains_key("level-seed"),
+        "reset_properties",
+        "cannot set `level-name` or `level-seed`, use `world_name_template` and `reset_seed`"
+    );
+    ensure!(
+        conf.on_new_world
+            .datapacks
+            .as_ref()
+            .is_none_or(|dir| dir.is_dir()),
+        "datapacks",
+        "must be an existing directory"
+    );
+    ensure!(
+        !conf.world.exists() || fs::metadata(&conf.world)?.is_dir(),
+        "world",
+        "must be a directory"
+    );
+    ensure!(
+        conf.backup_dir.exists() && fs::metadata(&conf.backup_dir)?.is_dir(),
+        "backup_dir",
+        "must be an existing directory"
+    );
+    ensure!(
+        !conf.server.is_empty() || conf.attach.is_some(),
+        "server",
+        "must not be empty"
+    );
+    ensure!(
+        conf.attach.is_none() || conf.rcon.is_some(),
+        "rcon",
+        "must be set when `attach` is, to send commands to the server"
+    );
+    ensure!(
+        conf.checkpoint_minutes > 0,
+        "checkpoint_minutes",
+        "must be > 0"
+    );
+    ensure!(conf.checkpoint_slots > 0, "checkpoint_slots", "must be > 0");
+    let exclude = GlobMatcher::new(&conf.backup_exclude).map_err(|err| err.to_string());
+    ensure!(
+        exclude.is_ok(),
+        "backup_exclude",
+        "has an invalid pattern: {}",
+        exclude.err().unwrap_or_default()
+    );
+    match conf.remote_backup.as_ref().map(|remote| &remote.target) {
+        Some(RemoteTarget::S3(s3)) => {
+            ensure!(
+                !s3.bucket.is_empty() && !s3.endpoint.is_empty(),
+                "remote_backup",
+                "needs an `endpoint` and a `bucket`"
+            );
+            let creds = s3.credentials().map_err(|err| err.to_string());
+            ensure!(
+                creds.is_ok(),
+                "remote_backup",
+                "has {}",
+                creds.err().unwrap_or_default()
+            );
+        }
+        Some(RemoteTarget::Sftp(sftp)) => {
+            ensure!(!sftp.host.is_empty(), "remote_backup", "needs a `host`");
+        }
+        None => {}
+    }
+    for (event, actions) in &conf.hooks {
+        ensure!(
+            hooks::EVENTS.contains(&event.as_str()),
+            "hooks",
+            "has unknown event \"{}\", expected one of {}",
+            event,
+            hooks::EVENTS.join(", ")
+        );
+        for action in actions {
+            if let HookAction::Run(argv) = action {
+                ensure!(
+                    !argv.is_empty(),
+                    "hooks",
+                    "has an empty `run` for {}",
+                    event
+                );
+            }
+        }
+    }
+    ensure!(
+        conf.proxy
+            .as_ref()
+            .is_none_or(|proxy| !proxy.server.is_empty()),
+        "proxy",
+        "needs `server`, the name of this server on the proxy"
+    );
+    ensure!(
+        conf.api_bind.is_none() || conf.api_token.as_deref().is_some_and(|t| !t.is_empty()),
+        "api_token",
+        "must be set when `api_bind` is"
+    );
+    ensure!(
+        Dice::range_ok(conf.roll_range),
+        "roll_range",
+        "must start at or below its end, within {} of zero and span at most {} numbers",
+        MAX_SIDES,
+        MAX_SIDES
+    );
+    let (low, high) = conf.dice().range();
+    for &num in &conf.deadly_rolls {
+        if num < low || num > high {
+            warn!(
+                "deadly roll {} is outside of roll range [{}, {}]",
+                num, low, high
+            );
+        }
+    }
+    for (key, &weight) in &conf.roll_weights {
+        ensure!(
+            parse_rolls(key).is_some_and(|(start, end)| start <= end),
+            "roll_weights",
+            "has an invalid roll or roll range \"{}\"",
+            key
+        );
+        ensure!(
+            weight >= 0.0 && weight.is_finite(),
+            "roll_weights",
+            "has an invalid weight {} for \"{}\"",
+            weight,
+            key
+        );
+    }
+    ensure!(
+        conf.ceremony
+            .iter()
+            .all(|step| step.pause >= 0.0 && step.pause.is_finite()),
+        "ceremony",
+        "has a pause that is not a number of seconds"
+    );
+    let engine = PenaltyEngine::new(conf.dice(), &conf.roll_weights, &[], &BTreeMap::new());
+    ensure!(
+        (low..=high).any(|num| engine.weight(num) > 0.0),
+        "roll_weights",
+        "leaves no possible roll with a weight above 0"
+    );
+    for key in conf.roll_outcomes.keys() {
+        let valid = parse_rolls(key).filter(|(start, end)| start <= end);
+        ensure!(
+            valid.is_some(),
+            "roll_outcomes",
+            "has an invalid roll or roll range \"{}\"",
+            key
+        );
+        let (start, end) = valid.unwrap_or_default();
+        if end < low || start > high {
+            warn!(
+                "roll outcome \"{}\" is outside of roll range [{}, {}]",
+                key, low, high
+            );
+        }
+    }
+    if conf.twitch.is_some() && conf.chat_commands.death_vote.mode == DeathVoteMode::Off {
+        warn!("twitch viewers only get a say with a chat_commands.death_vote mode set");
+    }
+    if conf.incremental_backups && conf.backup_format != BackupFormat::Directory {
+        warn!("incremental_backups only works with the \"directory\" backup format");
+    }
+    if conf.server_type == ServerType::Bedrock {
+        ensure!(
+            conf.server_version.is_none() && conf.jvm.is_none(),
+            "server_type",
+            "is \"bedrock\", which cannot be downloaded or launched through `jvm`"
+        );
+        if conf.message_style.tellraw
+            || conf.ceremony_display.titles
+            || conf.ceremony_display.bossbar
+        {
+            warn!("bedrock servers only get plain `say` announcements, ignoring `message_style` and `ceremony_display`");
+            conf.message_style.tellraw = false;
+            conf.ceremony_display = CeremonyDisplay::default();
+        }
+        //BDS has neither a world border nor a ban list
+        let unsupported = conf
+            .roll_outcomes
+            .values()
+            .chain(conf.death_rules.iter().flat_map(|rule| {
+                let always = match &rule.action {
+                    RuleAction::Always(outcome) => Some(outcome),
+                    _ => None,
+                };
+                rule.roll_outcomes.values().chain(always)
+            }))
+            .find(|outcome| matches!(outcome, Outcome::Border | Outcome::Ban));
+        ensure!(
+            unsupported.is_none(),
+            "roll_outcomes",
+            "has `{}` outcomes, which bedrock has no command for",
+            unsupported.map_or("", |outcome| outcome.name())
+        );
+        ensure!(
+            conf.rewind_scope == RewindScope::World,
+            "rewind_scope",
+            "is \"player\", but bedrock keeps players inside the world database"
+        );
+        if conf.death_location.enabled() {
+            warn!("bedrock servers cannot be asked where a player died, ignoring `death_location`");
+            conf.death_location = DeathLocationConfig::default();
+        }
+    }
+    ensure!(
+        conf.border_penalty.step > 0.0
+            && conf.border_penalty.step.is_finite()
+            && conf.border_penalty.min >= 1.0
+            && conf.border_penalty.min.is_finite(),
+        "border_penalty",
+        "needs a positive `step` and a `min` of at least 1"
+    );
+    if !conf.roll_outcomes.is_empty() && !conf.deadly_rolls.is_empty() {
+        warn!("deadly_rolls is ignored when roll_outcomes is set");
+    }
+    for step in &conf.escalation {
+        ensure!(
+            step.after_hours.is_some() || step.after_deaths.is_some(),
+            "escalation",
+            "has a step without `after_hours` or `after_deaths`"
+        );
+        ensure!(
+            step.after_hours
+                .is_none_or(|hours| hours >= 0.0 && hours.is_finite()),
+            "escalation",
+            "has an invalid number of hours"
+        );
+        ensure!(
+            step.roll_range.is_none_or(Dice::range_ok),
+            "escalation",
+            "has a roll range that ends below its start or spans more than {} numbers",
+            MAX_SIDES
+        );
+    }
+    for (player, rolls) in &conf.player_rolls {
+        ensure!(
+            rolls.roll_range.is_none_or(Dice::range_ok),
+            "player_rolls",
+            "has a roll range for {} that ends below its start or spans more than {} numbers",
+            player,
+            MAX_SIDES
+        );
+        let (low, high) = rolls.roll_range.unwrap_or((low, high));
+        for &num in rolls.deadly_rolls.iter().flatten() {
+            if num < low || num > high {
+                warn!(
+                    "deadly roll {} of {} is outside of their roll range [{}, {}]",
+                    num, player, low, high
+                );
+            }
+        }
+    }
+    Ok(conf)
+}
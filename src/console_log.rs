@@ -0,0 +1,89 @@
+//! Persistent, rotating log of everything that goes through the server console.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+pub type SharedConsoleLog = Arc<Mutex<ConsoleLog>>;
+
+pub struct ConsoleLog {
+    dir: PathBuf,
+    max_bytes: u64,
+    keep: u32,
+    file: File,
+    size: u64,
+}
+impl ConsoleLog {
+    /// Open (or continue) `console.log` inside `dir`, keeping up to `keep` rotated files of at most
+    /// `max_bytes` each.
+    pub fn open(dir: PathBuf, max_bytes: u64, keep: u32) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join("console.log"))?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            dir,
+            max_bytes,
+            keep,
+            file,
+            size,
+        })
+    }
+
+    fn rotated_path(&self, idx: u32) -> PathBuf {
+        if idx == 0 {
+            self.dir.join("console.log")
+        } else {
+            self.dir.join(format!("console.{}.log", idx))
+        }
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        //Shift `console.N.log` to `console.N+1.log`, dropping the oldest
+        let oldest = self.rotated_path(self.keep);
+        if oldest.exists() {
+            fs::remove_file(oldest)?;
+        }
+        for idx in (0..self.keep).rev() {
+            let from = self.rotated_path(idx);
+            if from.exists() {
+                fs::rename(from, self.rotated_path(idx + 1))?;
+            }
+        }
+        self.file = File::create(self.rotated_path(0))?;
+        self.size = 0;
+        Ok(())
+    }
+
+    /// Append a timestamped line, rotating the file first if it grew too large.
+    pub fn write_line(&mut self, line: &str) {
+        let res = (|| -> io::Result<()> {
+            if self.size >= self.max_bytes {
+                self.rotate()?;
+            }
+            let line = format!(
+                "[{}] {}\n",
+                chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+                line
+            );
+            self.file.write_all(line.as_bytes())?;
+            self.size += line.len() as u64;
+            Ok(())
+        })();
+        if let Err(err) = res {
+            eprintln!("failed to write to console log: {}", err);
+        }
+    }
+}
+
+/// Write a line to the shared log, if there is one.
+pub fn log(log: &Option<SharedConsoleLog>, line: &str) {
+    if let Some(log) = log {
+        log.lock().unwrap().write_line(line);
+    }
+}
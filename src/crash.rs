@@ -0,0 +1,107 @@
+//! Bundling crash evidence after the server exits abnormally.
+//!
+//! Bundles go into `<backup_dir>/crash-<unix time>/` and contain the crash reports and JVM error
+//! logs written since the server started, the tail of `logs/latest.log` and a short summary.
+
+use std::{
+    collections::VecDeque,
+    error::Error,
+    fs::{self, File},
+    io::{prelude::*, BufReader},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// How many lines of `latest.log` to keep.
+const LOG_TAIL_LINES: usize = 500;
+
+/// Gather crash evidence from `server_dir` into a new bundle in `out_dir`.
+///
+/// Only files modified after `since` are collected, so old crashes are not bundled again.
+/// Returns the bundle directory and a one-line summary of the crash.
+pub fn collect(
+    server_dir: &Path,
+    out_dir: &Path,
+    since: SystemTime,
+    status: &str,
+) -> Result<(PathBuf, String), Box<dyn Error>> {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let bundle = out_dir.join(format!("crash-{}", secs));
+    fs::create_dir_all(&bundle)?;
+    let mut summary = format!("server exited abnormally ({})", status);
+    //Crash reports written by Minecraft itself
+    let mut reports = recent_files(&server_dir.join("crash-reports"), since, |_| true)?;
+    reports.sort();
+    if let Some(report) = reports.last() {
+        if let Some(desc) = describe_report(report) {
+            summary += &format!(": {}", desc);
+        }
+    }
+    //Fatal error logs written by the JVM
+    let hs_errs = recent_files(server_dir, since, |name| {
+        name.starts_with("hs_err_pid") && name.ends_with(".log")
+    })?;
+    if !hs_errs.is_empty() {
+        summary += " (JVM fatal error)";
+    }
+    for file in reports.iter().chain(&hs_errs) {
+        if let Some(name) = file.file_name() {
+            fs::copy(file, bundle.join(name))?;
+        }
+    }
+    //Tail of the server log
+    let log = server_dir.join("logs").join("latest.log");
+    if log.exists() {
+        let mut tail = VecDeque::with_capacity(LOG_TAIL_LINES);
+        for line in BufReader::new(File::open(&log)?).lines() {
+            if tail.len() >= LOG_TAIL_LINES {
+                tail.pop_front();
+            }
+            tail.push_back(line?);
+        }
+        let mut out = File::create(bundle.join("latest.log.tail"))?;
+        for line in tail {
+            writeln!(out, "{}", line)?;
+        }
+    }
+    let mut out = File::create(bundle.join("summary.txt"))?;
+    writeln!(out, "{}", summary)?;
+    writeln!(
+        out,
+        "{} crash reports, {} JVM error logs",
+        reports.len(),
+        hs_errs.len()
+    )?;
+    Ok((bundle, summary))
+}
+
+/// Files in `dir` whose name passes `filter` and that were modified after `since`.
+fn recent_files(
+    dir: &Path,
+    since: SystemTime,
+    filter: impl Fn(&str) -> bool,
+) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut files = Vec::new();
+    if !dir.is_dir() {
+        return Ok(files);
+    }
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let meta = entry.metadata()?;
+        if meta.is_file()
+            && filter(&entry.file_name().to_string_lossy())
+            && meta.modified()? >= since
+        {
+            files.push(entry.path());
+        }
+    }
+    Ok(files)
+}
+
+/// The `Description: ...` line of a Minecraft crash report.
+fn describe_report(report: &Path) -> Option<String> {
+    let file = BufReader::new(File::open(report).ok()?);
+    file.lines()
+        .map_while(Result::ok)
+        .find_map(|line| Some(line.strip_prefix("Description: ")?.trim().to_string()))
+}
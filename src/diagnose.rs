@@ -0,0 +1,165 @@
+//! The `diagnose` subcommand, which packs everything needed for a bug report into one archive.
+
+use crate::{
+    config::{is_toml, load_config},
+    nbt::LevelInfo,
+    unix_now,
+};
+use serde_json::Value;
+use std::{
+    env,
+    error::Error,
+    fs::{self, File},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Config keys whose values are replaced before the config goes into the bundle.
+const SECRET_WORDS: &[&str] = &["token", "password", "secret", "webhook", "key"];
+/// How many of the most recent output dumps to include.
+const MAX_DUMPS: usize = 5;
+
+/// Write a support bundle for the config at `config_path` into the current directory.
+pub fn run(config_path: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    let out = PathBuf::from(format!("trust_hardcore-diagnose-{}.tar", unix_now()));
+    let mut tar = tar::Builder::new(File::create(&out)?);
+    //Sanitized config, converted to JSON if written in TOML
+    let raw = fs::read_to_string(config_path)?;
+    let parsed: Result<Value, Box<dyn Error>> = if is_toml(config_path) {
+        toml::from_str::<toml::Value>(&raw)
+            .map_err(Box::from)
+            .and_then(|value| Ok(serde_json::to_value(value)?))
+    } else {
+        serde_json::from_str(&raw).map_err(Box::from)
+    };
+    let sanitized = match parsed {
+        Ok(mut value) => {
+            redact(&mut value);
+            serde_json::to_string_pretty(&value)?
+        }
+        Err(err) => format!("config does not parse: {}", err),
+    };
+    add(&mut tar, "config.json", sanitized.as_bytes())?;
+    //Environment, and whether the config loads at all
+    let config = load_config(config_path);
+    let mut env_info = String::new();
+    env_info += &format!("trust_hardcore {}\n", env!("CARGO_PKG_VERSION"));
+    env_info += &format!("os: {} {}\n", env::consts::OS, env::consts::ARCH);
+    if let Ok(cwd) = env::current_dir() {
+        env_info += &format!("working directory: {}\n", cwd.display());
+    }
+    if let Ok(meminfo) = fs::read_to_string("/proc/meminfo") {
+        if let Some(total) = meminfo.lines().find(|line| line.starts_with("MemTotal:")) {
+            env_info += &format!("{}\n", total);
+        }
+    }
+    let java = match &config {
+        Ok(config) => config.java.clone().unwrap_or_else(|| "java".to_string()),
+        Err(_) => "java".to_string(),
+    };
+    match Command::new(&java).arg("-version").output() {
+        Ok(out) => {
+            env_info += &format!(
+                "{} -version:\n{}",
+                java,
+                String::from_utf8_lossy(&out.stderr)
+            )
+        }
+        Err(err) => env_info += &format!("could not run {}: {}\n", java, err),
+    }
+    match &config {
+        Ok(config) => {
+            env_info += "config loads fine\n";
+            if let Some(version) = &config.server_version {
+                env_info += &format!("server version: {}\n", version);
+            }
+            match LevelInfo::read(&config.world) {
+                Ok(level) => env_info += &format!("level.dat: {:?}\n", level),
+                Err(err) => env_info += &format!("level.dat does not read: {}\n", err),
+            }
+        }
+        Err(err) => env_info += &format!("config fails to load: {}\n", err),
+    }
+    add(&mut tar, "environment.txt", env_info.as_bytes())?;
+    let config = match config {
+        Ok(config) => config,
+        Err(_) => {
+            tar.finish()?;
+            return Ok(out);
+        }
+    };
+    //Wrapper console logs
+    if let Some(log_dir) = &config.log_dir {
+        for path in files_in(log_dir, |name| {
+            name.starts_with("console") && name.ends_with(".log")
+        })? {
+            add(
+                &mut tar,
+                &format!("logs/{}", file_name(&path)),
+                &fs::read(&path)?,
+            )?;
+        }
+    }
+    //Recent output dumps, newest first
+    let mut dumps = files_in(&config.backup_dir, |name| {
+        name.starts_with("recent-output-")
+    })?;
+    dumps.sort();
+    for path in dumps.iter().rev().take(MAX_DUMPS) {
+        add(
+            &mut tar,
+            &format!("recent/{}", file_name(path)),
+            &fs::read(path)?,
+        )?;
+    }
+    tar.finish()?;
+    Ok(out)
+}
+
+fn add(tar: &mut tar::Builder<File>, name: &str, data: &[u8]) -> Result<(), Box<dyn Error>> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(unix_now());
+    header.set_cksum();
+    tar.append_data(&mut header, name, data)?;
+    Ok(())
+}
+
+/// Replace the values of keys that look like they hold secrets.
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                let key = key.to_lowercase();
+                if SECRET_WORDS.iter().any(|word| key.contains(word)) {
+                    *value = Value::String("<redacted>".to_string());
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+fn files_in(dir: &Path, filter: impl Fn(&str) -> bool) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut files = Vec::new();
+    if !dir.is_dir() {
+        return Ok(files);
+    }
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() && filter(&entry.file_name().to_string_lossy()) {
+            files.push(entry.path());
+        }
+    }
+    Ok(files)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
@@ -0,0 +1,198 @@
+//! Fetching server jars by version into a local cache.
+//!
+//! Jars are downloaded to a `.part` file and only moved into place once their checksum matches,
+//! so any jar found in the cache has already been verified.
+
+use log::info;
+use serde_derive::Deserialize;
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+};
+
+#[cfg(feature = "download")]
+const VANILLA_MANIFEST: &str = "https://piston-meta.mojang.com/mc/game/version_manifest_v2.json";
+#[cfg(feature = "download")]
+const PAPER_API: &str = "https://api.papermc.io/v2/projects/paper/versions";
+
+#[derive(Deserialize, Clone, Copy, Default, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Flavor {
+    /// The official server from Mojang.
+    #[default]
+    Vanilla,
+    /// The latest Paper build for the version.
+    Paper,
+    Spigot,
+    Fabric,
+    Forge,
+    /// Learn the output format from the first lines, and download the vanilla server.
+    Auto,
+}
+
+/// Where a jar can be downloaded from, and how to check it.
+#[cfg(feature = "download")]
+struct Source {
+    url: String,
+    checksum: Checksum,
+}
+
+#[cfg(feature = "download")]
+enum Checksum {
+    Sha1(String),
+    Sha256(String),
+}
+
+/// Get the path of the server jar for `version`, downloading it into `cache_dir` if needed.
+pub fn server_jar(
+    cache_dir: &Path,
+    flavor: Flavor,
+    version: &str,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let name = match flavor {
+        Flavor::Vanilla | Flavor::Auto => format!("vanilla-{}.jar", version),
+        Flavor::Paper => format!("paper-{}.jar", version),
+        Flavor::Spigot | Flavor::Fabric | Flavor::Forge => {
+            return Err(format!(
+                "{:?} servers cannot be downloaded, point `server` at the jar instead",
+                flavor
+            )
+            .into())
+        }
+    };
+    let jar = cache_dir.join(name);
+    if jar.exists() {
+        info!("using cached server jar \"{}\"", jar.display());
+        return Ok(jar);
+    }
+    fetch(&jar, flavor, version)?;
+    Ok(jar)
+}
+
+#[cfg(feature = "download")]
+fn fetch(jar: &Path, flavor: Flavor, version: &str) -> Result<(), Box<dyn Error>> {
+    use crate::hex;
+    use sha1::Digest;
+    use std::{fs, io::Read};
+
+    let source = match flavor {
+        Flavor::Paper => paper_source(version)?,
+        _ => vanilla_source(version)?,
+    };
+    info!(
+        "downloading {:?} server {} from \"{}\"",
+        flavor, version, source.url
+    );
+    let mut bytes = Vec::new();
+    ureq::get(&source.url)
+        .call()?
+        .into_reader()
+        .read_to_end(&mut bytes)?;
+    let (expected, actual) = match &source.checksum {
+        Checksum::Sha1(expected) => (expected, hex(&sha1::Sha1::digest(&bytes))),
+        Checksum::Sha256(expected) => (expected, hex(&sha2::Sha256::digest(&bytes))),
+    };
+    if !expected.eq_ignore_ascii_case(&actual) {
+        return Err(format!(
+            "checksum mismatch for downloaded server jar (expected {}, got {})",
+            expected, actual
+        )
+        .into());
+    }
+    if let Some(dir) = jar.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let part = jar.with_extension("jar.part");
+    fs::write(&part, &bytes)?;
+    fs::rename(&part, jar)?;
+    info!("saved server jar to \"{}\"", jar.display());
+    Ok(())
+}
+
+#[cfg(not(feature = "download"))]
+fn fetch(_jar: &Path, _flavor: Flavor, _version: &str) -> Result<(), Box<dyn Error>> {
+    Err(
+        "`server_version` needs the `download` feature, which this build was compiled without"
+            .into(),
+    )
+}
+
+#[cfg(feature = "download")]
+fn vanilla_source(version: &str) -> Result<Source, Box<dyn Error>> {
+    #[derive(Deserialize)]
+    struct Manifest {
+        versions: Vec<ManifestVersion>,
+    }
+    #[derive(Deserialize)]
+    struct ManifestVersion {
+        id: String,
+        url: String,
+    }
+    #[derive(Deserialize)]
+    struct VersionInfo {
+        downloads: Downloads,
+    }
+    #[derive(Deserialize)]
+    struct Downloads {
+        server: Option<Download>,
+    }
+    #[derive(Deserialize)]
+    struct Download {
+        url: String,
+        sha1: String,
+    }
+
+    let manifest: Manifest = ureq::get(VANILLA_MANIFEST).call()?.into_json()?;
+    let entry = manifest
+        .versions
+        .into_iter()
+        .find(|v| v.id == version)
+        .ok_or_else(|| format!("unknown minecraft version \"{}\"", version))?;
+    let info: VersionInfo = ureq::get(&entry.url).call()?.into_json()?;
+    let server = info
+        .downloads
+        .server
+        .ok_or_else(|| format!("minecraft version \"{}\" has no server download", version))?;
+    Ok(Source {
+        url: server.url,
+        checksum: Checksum::Sha1(server.sha1),
+    })
+}
+
+#[cfg(feature = "download")]
+fn paper_source(version: &str) -> Result<Source, Box<dyn Error>> {
+    #[derive(Deserialize)]
+    struct Builds {
+        builds: Vec<Build>,
+    }
+    #[derive(Deserialize)]
+    struct Build {
+        build: u32,
+        downloads: BuildDownloads,
+    }
+    #[derive(Deserialize)]
+    struct BuildDownloads {
+        application: Application,
+    }
+    #[derive(Deserialize)]
+    struct Application {
+        name: String,
+        sha256: String,
+    }
+
+    let builds: Builds = ureq::get(&format!("{}/{}/builds", PAPER_API, version))
+        .call()?
+        .into_json()?;
+    let latest = builds
+        .builds
+        .into_iter()
+        .max_by_key(|b| b.build)
+        .ok_or_else(|| format!("no paper builds for version \"{}\"", version))?;
+    Ok(Source {
+        url: format!(
+            "{}/{}/builds/{}/downloads/{}",
+            PAPER_API, version, latest.build, latest.downloads.application.name
+        ),
+        checksum: Checksum::Sha256(latest.downloads.application.sha256),
+    })
+}
@@ -0,0 +1,219 @@
+//! Events that the main loop reacts to, and the stream they arrive on.
+
+use crate::{api::ApiRequest, parse::bytes_to_string};
+#[cfg(unix)]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::{
+    collections::VecDeque,
+    error::Error,
+    io::{self, prelude::*},
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Something for the main loop to react to.
+pub enum Event {
+    /// A line of server output.
+    Output(String),
+    /// A line typed into the wrapper's own console.
+    Console(String),
+    /// Periodic wakeup, so that timers advance even when the server is quiet.
+    Tick,
+    /// The wrapper was asked to exit, through SIGINT, SIGTERM or the HTTP API.
+    Shutdown,
+    /// The config file should be read again, through SIGHUP or `.reload`.
+    Reload,
+    /// A request from the HTTP API, waiting for a reply.
+    Api(ApiRequest),
+    /// The group of deaths with this id stopped gathering more deaths.
+    DeathGroupClosed(u64),
+    /// Players may start voting on the death with this id.
+    VoteOpened(u64),
+    /// The ceremony for the death with this id finished playing.
+    CeremonyDone(u64),
+    /// The question to the server with this id went unanswered for too long.
+    QuestionExpired(u64),
+    /// A timer that the plugin at this position in `plugins` set went off.
+    PluginTimer { plugin: usize, id: i32 },
+    /// A message in the Twitch channel's chat.
+    Twitch { user: String, text: String },
+    /// A checkpoint upload finished, with an error if it failed for good.
+    UploadDone { name: String, error: Option<String> },
+}
+
+/// Stream of events, which outlives any single server run.
+///
+/// Events can be inspected out of order (eg. to wait for the response to a command), in which case
+/// they are kept around and handed out again in their original order.
+pub struct EventStream {
+    tx: Sender<Event>,
+    rx: Receiver<Event>,
+    pending: VecDeque<Event>,
+}
+impl Default for EventStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl EventStream {
+    /// Create the stream and start the threads that read the wrapper's own console and produce
+    /// periodic ticks.
+    pub fn new() -> Self {
+        Self::start(true)
+    }
+
+    /// Create a stream without the wrapper's console, for a server in a fleet, whose console
+    /// lines are handed over by the fleet instead.
+    pub fn detached() -> Self {
+        Self::start(false)
+    }
+
+    fn start(console: bool) -> Self {
+        let (tx, rx) = mpsc::channel();
+        //Start background thread that reads program stdin
+        if console {
+            let tx = tx.clone();
+            thread::spawn(move || {
+                for line in io::stdin().lock().split(b'\n') {
+                    let line = bytes_to_string(&line.unwrap());
+                    if let Err(_line) = tx.send(Event::Console(line)) {
+                        //Channel closed
+                        break;
+                    }
+                }
+            });
+        }
+        //Send periodic ticks
+        {
+            let tx = tx.clone();
+            thread::spawn(move || loop {
+                thread::sleep(Duration::from_secs(10));
+                if let Err(_closed) = tx.send(Event::Tick) {
+                    break;
+                }
+            });
+        }
+        Self {
+            tx,
+            rx,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Turn SIGINT (Ctrl+C) and SIGTERM into [`Event::Shutdown`] instead of dying on the spot,
+    /// and SIGHUP into [`Event::Reload`].
+    pub fn catch_signals(&self) -> Result<(), Box<dyn Error>> {
+        let tx = self.tx.clone();
+        ctrlc::set_handler(move || {
+            let _ = tx.send(Event::Shutdown);
+        })?;
+        #[cfg(unix)]
+        catch_hangup(self.tx.clone());
+        Ok(())
+    }
+
+    /// Sender for new sources of events, such as the server output.
+    pub fn sender(&self) -> &Sender<Event> {
+        &self.tx
+    }
+
+    pub fn recv(&mut self) -> Option<Event> {
+        match self.pending.pop_front() {
+            Some(event) => Some(event),
+            None => self.rx.recv().ok(),
+        }
+    }
+
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Option<Event> {
+        match self.pending.pop_front() {
+            Some(event) => Some(event),
+            None => self.rx.recv_timeout(timeout).ok(),
+        }
+    }
+
+    /// Drop leftover server output, eg. from a previous server, keeping console input, API
+    /// requests and shutdown requests.
+    pub fn discard_output(&mut self) {
+        self.pending.extend(self.rx.try_iter());
+        self.pending.retain(|event| {
+            matches!(
+                event,
+                Event::Console(_) | Event::Shutdown | Event::Reload | Event::Api(_)
+            )
+        });
+    }
+
+    /// Wait up to `timeout` for a new console line, keeping every other event to be handed out
+    /// later. Gives up early on a shutdown request, which is kept too.
+    pub fn wait_for_console(&mut self, timeout: Duration) -> Option<String> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let event = self
+                .rx
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                .ok()?;
+            match event {
+                Event::Console(line) => return Some(line),
+                Event::Shutdown => {
+                    self.pending.push_back(event);
+                    return None;
+                }
+                event => self.pending.push_back(event),
+            }
+        }
+    }
+
+    /// Wait up to `timeout` for a new line of output for which `f` returns a value.
+    ///
+    /// Every event received in the meantime, including the matching line, is kept to be handed
+    /// out later.
+    pub fn wait_for_output<T>(
+        &mut self,
+        timeout: Duration,
+        mut f: impl FnMut(&str) -> Option<T>,
+    ) -> Option<T> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let event = match self
+                .rx
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            {
+                Ok(event) => event,
+                Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => {
+                    return None
+                }
+            };
+            let found = match &event {
+                Event::Output(line) => f(line),
+                _ => None,
+            };
+            self.pending.push_back(event);
+            if found.is_some() {
+                return found;
+            }
+        }
+    }
+}
+
+/// Set from the SIGHUP handler, which can do little more than that.
+#[cfg(unix)]
+static HANGUP: AtomicBool = AtomicBool::new(false);
+
+/// Take over SIGHUP, which `ctrlc` would otherwise treat as a request to exit.
+#[cfg(unix)]
+fn catch_hangup(tx: Sender<Event>) {
+    extern "C" fn on_hangup(_: libc::c_int) {
+        HANGUP.store(true, Ordering::SeqCst);
+    }
+    let handler = on_hangup as extern "C" fn(libc::c_int);
+    unsafe {
+        libc::signal(libc::SIGHUP, handler as libc::sighandler_t);
+    }
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_millis(500));
+        if HANGUP.swap(false, Ordering::SeqCst) && tx.send(Event::Reload).is_err() {
+            break;
+        }
+    });
+}
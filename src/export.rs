@@ -0,0 +1,222 @@
+//! Campaign statistics written out as CSV or JSON files, for season recaps.
+
+use crate::history::DeathRecord;
+use serde_derive::Serialize;
+use std::{error::Error, fmt::Display, fs, path::Path, str::FromStr};
+
+#[derive(Clone, Copy)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+impl FromStr for ExportFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "csv" => Ok(ExportFormat::Csv),
+            "json" => Ok(ExportFormat::Json),
+            _ => Err(format!(
+                "invalid export format \"{}\", expected csv or json",
+                s
+            )),
+        }
+    }
+}
+
+/// Everything there is to export.
+#[derive(Serialize, Default)]
+pub struct Export {
+    pub players: Vec<PlayerRow>,
+    pub deaths: Vec<DeathRecord>,
+    pub checkpoints: Vec<CheckpointRow>,
+    pub seasons: Vec<SeasonRow>,
+}
+
+#[derive(Serialize)]
+pub struct PlayerRow {
+    pub player: String,
+    /// The UUID, if the player logged in since UUIDs are tracked.
+    pub uuid: Option<String>,
+    pub playtime_secs: u64,
+    /// Deaths over all seasons, according to the history.
+    pub deaths: u32,
+    /// Rolls that came out safe, over all seasons.
+    pub survived: u32,
+    pub lives_left: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct CheckpointRow {
+    pub name: String,
+    pub named: bool,
+    /// Creation time, in seconds since the Unix epoch.
+    pub created: u64,
+    pub intact: bool,
+    pub attempt: Option<u32>,
+    pub playtime_secs: Option<u64>,
+    pub day: Option<u64>,
+    pub deaths: Option<u32>,
+    pub advancements: Option<u32>,
+    pub world_size: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct SeasonRow {
+    pub season: u32,
+    pub deaths: u32,
+    /// Whose death reset the world, if it was reset.
+    pub reset_by: Option<String>,
+    /// When the world was reset, in seconds since the Unix epoch.
+    pub reset_at: Option<u64>,
+    pub won: bool,
+}
+
+/// Write `export` into `dir`, as a single `stats.json` or as one CSV file per table.
+pub fn write(
+    export: &Export,
+    format: ExportFormat,
+    dir: &Path,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    fs::create_dir_all(dir)?;
+    let files = match format {
+        ExportFormat::Json => {
+            fs::write(
+                dir.join("stats.json"),
+                serde_json::to_string_pretty(export)?,
+            )?;
+            vec!["stats.json".to_string()]
+        }
+        ExportFormat::Csv => {
+            let tables = [
+                (
+                    "players.csv",
+                    table(
+                        &[
+                            "player",
+                            "uuid",
+                            "playtime_secs",
+                            "deaths",
+                            "survived",
+                            "lives_left",
+                        ],
+                        export.players.iter().map(|row| {
+                            vec![
+                                field(&row.player),
+                                opt(&row.uuid),
+                                field(row.playtime_secs),
+                                field(row.deaths),
+                                field(row.survived),
+                                opt(&row.lives_left),
+                            ]
+                        }),
+                    ),
+                ),
+                (
+                    "deaths.csv",
+                    table(
+                        &[
+                            "at",
+                            "season",
+                            "player",
+                            "roll",
+                            "outcome",
+                            "deadly",
+                            "penalty",
+                            "playtime_secs",
+                        ],
+                        export.deaths.iter().map(|row| {
+                            vec![
+                                field(row.at),
+                                field(row.season),
+                                field(&row.player),
+                                opt(&row.roll),
+                                field(&row.outcome),
+                                field(row.deadly),
+                                field(&row.penalty),
+                                field(row.playtime_secs),
+                            ]
+                        }),
+                    ),
+                ),
+                (
+                    "checkpoints.csv",
+                    table(
+                        &[
+                            "name",
+                            "named",
+                            "created",
+                            "intact",
+                            "attempt",
+                            "playtime_secs",
+                            "day",
+                            "deaths",
+                            "advancements",
+                            "world_size",
+                        ],
+                        export.checkpoints.iter().map(|row| {
+                            vec![
+                                field(&row.name),
+                                field(row.named),
+                                field(row.created),
+                                field(row.intact),
+                                opt(&row.attempt),
+                                opt(&row.playtime_secs),
+                                opt(&row.day),
+                                opt(&row.deaths),
+                                opt(&row.advancements),
+                                opt(&row.world_size),
+                            ]
+                        }),
+                    ),
+                ),
+                (
+                    "seasons.csv",
+                    table(
+                        &["season", "deaths", "reset_by", "reset_at", "won"],
+                        export.seasons.iter().map(|row| {
+                            vec![
+                                field(row.season),
+                                field(row.deaths),
+                                opt(&row.reset_by),
+                                opt(&row.reset_at),
+                                field(row.won),
+                            ]
+                        }),
+                    ),
+                ),
+            ];
+            let mut files = Vec::new();
+            for (name, contents) in tables.iter() {
+                fs::write(dir.join(name), contents)?;
+                files.push(name.to_string());
+            }
+            files
+        }
+    };
+    Ok(files)
+}
+
+/// A CSV table with a header line, quoting fields where needed.
+fn table(header: &[&str], rows: impl Iterator<Item = Vec<String>>) -> String {
+    let mut out = header.join(",");
+    out.push_str("\r\n");
+    for row in rows {
+        out.push_str(&row.join(","));
+        out.push_str("\r\n");
+    }
+    out
+}
+
+fn field(value: impl Display) -> String {
+    let value = value.to_string();
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+/// An empty field for missing values.
+fn opt<T: Display>(value: &Option<T>) -> String {
+    value.as_ref().map(field).unwrap_or_default()
+}
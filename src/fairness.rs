@@ -0,0 +1,160 @@
+//! Provably fair rolls.
+//!
+//! A death is rolled for with the hash of a secret, picked before the death happened, and of the
+//! death itself. The hash of the secret is announced before the roll as a commitment, and the
+//! secret is revealed after it, so that anyone can check that the secret was not swapped and, with
+//! `verify-rolls`, that it leads to the roll that was announced. Every roll is kept in an audit
+//! file, one JSON object per line.
+
+use crate::{
+    hex,
+    penalty::{Dice, PenaltyEngine},
+};
+use rand::RngCore;
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    error::Error,
+    fs::OpenOptions,
+    io::prelude::*,
+    path::{Path, PathBuf},
+};
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct FairRollsConfig {
+    pub enabled: bool,
+    /// Where to keep the audit trail, `roll_audit.jsonl` next to the state file by default.
+    pub audit_file: Option<PathBuf>,
+}
+
+/// A roll as kept in the audit file, with everything it takes to redo it.
+#[derive(Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// When the roll happened, in seconds since the Unix epoch.
+    pub at: u64,
+    /// What was rolled for, which goes into the seed along with the secret.
+    pub event: String,
+    pub commitment: String,
+    pub secret: String,
+    /// The dice, in `NdM+K` notation.
+    pub dice: String,
+    pub roll_weights: BTreeMap<String, f64>,
+    pub roll: i32,
+    pub rolled_dice: Vec<i32>,
+}
+impl AuditEntry {
+    /// Check that the secret matches the commitment and leads to the roll.
+    pub fn verify(&self) -> Result<(), String> {
+        if commitment_of(&self.secret) != self.commitment {
+            return Err("the secret does not match the commitment".to_string());
+        }
+        let dice = Dice::try_from(self.dice.clone())?;
+        let engine = PenaltyEngine::new(dice, &self.roll_weights, &[], &BTreeMap::new());
+        let roll = engine.roll_with(&mut HashRng::new(&self.secret, &self.event));
+        if roll.num != self.roll || roll.dice != self.rolled_dice {
+            return Err(format!(
+                "the secret leads to a roll of {}, not {}",
+                roll.num, self.roll
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn append(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        writeln!(file, "{}", serde_json::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+/// Read every roll in an audit file.
+pub fn read_audit(path: &Path) -> Result<Vec<AuditEntry>, Box<dyn Error>> {
+    let mut entries = Vec::new();
+    for (idx, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = serde_json::from_str(line)
+            .map_err(|err| format!("invalid audit entry on line {}: {}", idx + 1, err))?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+pub fn commitment_of(secret: &str) -> String {
+    hex(&Sha256::digest(secret.as_bytes()))
+}
+
+/// A secret for the next roll, picked ahead of time.
+pub struct Commitment {
+    secret: String,
+    pub hash: String,
+}
+impl Default for Commitment {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Commitment {
+    pub fn new() -> Self {
+        let secret = hex(&rand::random::<[u8; 32]>());
+        Self {
+            hash: commitment_of(&secret),
+            secret,
+        }
+    }
+
+    pub fn secret(&self) -> &str {
+        &self.secret
+    }
+
+    /// The randomness to roll for `event` with.
+    pub fn rng(&self, event: &str) -> HashRng {
+        HashRng::new(&self.secret, event)
+    }
+}
+
+/// Random numbers from the SHA-256 of a seed and a counter, so that a roll can be redone.
+pub struct HashRng {
+    seed: Vec<u8>,
+    counter: u64,
+}
+impl HashRng {
+    pub fn new(secret: &str, event: &str) -> Self {
+        Self {
+            seed: Sha256::digest(format!("{}:{}", secret, event).as_bytes()).to_vec(),
+            counter: 0,
+        }
+    }
+}
+impl RngCore for HashRng {
+    fn next_u32(&mut self) -> u32 {
+        self.next_u64() as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.seed);
+        hasher.update(self.counter.to_le_bytes());
+        self.counter += 1;
+        let hash = hasher.finalize();
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&hash[..8]);
+        u64::from_le_bytes(bytes)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
@@ -0,0 +1,56 @@
+//! Deliberate failures, used to exercise the recovery paths in tests and staging setups.
+//!
+//! Faults listed in the config are only injected when running with `--debug`.
+
+use log::{info, warn};
+use serde_derive::Deserialize;
+use std::{collections::HashSet, error::Error};
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Fault {
+    /// Copying the world into a checkpoint fails.
+    BackupCopy,
+    /// The server ignores the `stop` command.
+    ServerStop,
+    /// Persisted state is corrupted before it is loaded.
+    CorruptState,
+}
+
+#[derive(Default)]
+pub struct Faults {
+    active: HashSet<Fault>,
+}
+impl Faults {
+    pub fn new(faults: &[Fault], enabled: bool) -> Self {
+        if !enabled {
+            if !faults.is_empty() {
+                info!("ignoring configured faults, run with --debug to inject them");
+            }
+            return Self::default();
+        }
+        for fault in faults {
+            warn!("injecting fault {:?}", fault);
+        }
+        Self {
+            active: faults.iter().copied().collect(),
+        }
+    }
+
+    /// Whether the given fault should be injected now.
+    pub fn check(&self, fault: Fault) -> bool {
+        let active = self.active.contains(&fault);
+        if active {
+            info!("injected fault {:?}", fault);
+        }
+        active
+    }
+
+    /// Fail with an error if the given fault is active.
+    pub fn fail(&self, fault: Fault) -> Result<(), Box<dyn Error>> {
+        if self.check(fault) {
+            return Err(format!("injected fault {:?}", fault).into());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,93 @@
+//! Supervising several servers from one wrapper, such as a lobby next to the hardcore world.
+//!
+//! Each server gets its own wrapper on its own thread, as if it were run alone, with its output
+//! and log messages tagged with its name. Console lines go to the server they name, as in
+//! `lobby: list`, and signals go to every server.
+
+use crate::{
+    events::{Event, EventStream},
+    logging,
+    supervisor::{Options, Supervisor},
+};
+use log::{error, info, warn};
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    path::PathBuf,
+    sync::mpsc::Sender,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// Run every server until they have all stopped.
+pub fn run(servers: BTreeMap<String, PathBuf>, opts: &Options) -> Result<(), Box<dyn Error>> {
+    let mut hub = EventStream::new();
+    if let Err(err) = hub.catch_signals() {
+        warn!("failed to catch termination signals: {}", err);
+    }
+    info!(
+        "supervising {} servers: {}",
+        servers.len(),
+        servers.keys().cloned().collect::<Vec<_>>().join(", ")
+    );
+    let mut senders = BTreeMap::new();
+    let mut threads: Vec<JoinHandle<()>> = Vec::new();
+    for (name, config) in servers {
+        let events = EventStream::detached();
+        senders.insert(name.clone(), events.sender().clone());
+        let opts = Options {
+            config,
+            force: opts.force,
+            debug: opts.debug,
+        };
+        let thread = thread::Builder::new().name(name.clone()).spawn(move || {
+            logging::set_tag(Some(name.into()));
+            match Supervisor::managed(events).run(&opts) {
+                Ok(()) => info!("wrapper stopped"),
+                Err(err) => error!("wrapper stopped: {}", err),
+            }
+        })?;
+        threads.push(thread);
+    }
+    while !threads.iter().all(JoinHandle::is_finished) {
+        match hub.recv_timeout(Duration::from_secs(1)) {
+            Some(Event::Console(line)) => route(&senders, &line),
+            Some(Event::Shutdown) => broadcast(&senders, || Event::Shutdown),
+            Some(Event::Reload) => broadcast(&senders, || Event::Reload),
+            _ => {}
+        }
+    }
+    for thread in threads {
+        let _ = thread.join();
+    }
+    Ok(())
+}
+
+/// Hand a console line to the server it names.
+fn route(senders: &BTreeMap<String, Sender<Event>>, line: &str) {
+    let target = line
+        .split_once(':')
+        .and_then(|(name, rest)| Some((senders.get(name.trim())?, rest.trim_start())));
+    match target {
+        Some((tx, rest)) => {
+            if tx.send(Event::Console(rest.to_string())).is_err() {
+                warn!("that server has stopped");
+            }
+        }
+        None => warn!(
+            "start console lines with the server they are for, as in `{}: list`, one of: {}",
+            senders
+                .keys()
+                .next()
+                .map(String::as_str)
+                .unwrap_or_default(),
+            senders.keys().cloned().collect::<Vec<_>>().join(", ")
+        ),
+    }
+}
+
+fn broadcast(senders: &BTreeMap<String, Sender<Event>>, event: impl Fn() -> Event) {
+    for tx in senders.values() {
+        let _ = tx.send(event());
+    }
+}
@@ -0,0 +1,180 @@
+//! Every death of every season, kept in an SQLite database next to the state file.
+//!
+//! Unlike the counters in the state file, the history is never wound back by a rewind, so it tells
+//! how a season really went. The `deaths.jsonl` file that older versions kept is moved into the
+//! database the first time it is opened.
+
+use log::info;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde_derive::{Deserialize, Serialize};
+use std::{error::Error, fs, io, path::Path};
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DeathRecord {
+    /// When the outcome was applied, in seconds since the Unix epoch.
+    pub at: u64,
+    pub season: u32,
+    pub player: String,
+    /// The roll, or nothing if a death rule decided the outcome without one.
+    pub roll: Option<i32>,
+    /// What the roll led to, eg. `"none"` or `"reset"`.
+    pub outcome: String,
+    /// Whether the roll was a bad one, which costs a life if there are lives.
+    pub deadly: bool,
+    /// What actually happened to the world, after lives, eg. `"none"` or `"reset"`.
+    pub penalty: String,
+    /// Playtime of the world when the player died.
+    pub playtime_secs: u64,
+}
+impl DeathRecord {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(DeathRecord {
+            at: row.get::<_, i64>("at")? as u64,
+            season: row.get("season")?,
+            player: row.get("player")?,
+            roll: row.get("roll")?,
+            outcome: row.get("outcome")?,
+            deadly: row.get("deadly")?,
+            penalty: row.get("penalty")?,
+            playtime_secs: row.get::<_, i64>("playtime_secs")? as u64,
+        })
+    }
+}
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS deaths (
+    id INTEGER PRIMARY KEY,
+    at INTEGER NOT NULL,
+    season INTEGER NOT NULL,
+    player TEXT NOT NULL,
+    roll INTEGER,
+    outcome TEXT NOT NULL,
+    deadly INTEGER NOT NULL,
+    penalty TEXT NOT NULL,
+    playtime_secs INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS deaths_by_season ON deaths (season, player);
+CREATE INDEX IF NOT EXISTS deaths_by_player ON deaths (player);
+";
+
+/// The death history database.
+pub struct History {
+    conn: Connection,
+}
+impl History {
+    /// Open the history, creating it if there is none yet.
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        import_jsonl(&mut conn, &path.with_file_name("deaths.jsonl"))?;
+        Ok(Self { conn })
+    }
+
+    pub fn append(&self, record: &DeathRecord) -> Result<(), Box<dyn Error>> {
+        insert(&self.conn, record)?;
+        Ok(())
+    }
+
+    /// Every death, oldest first, of one season or of all of them.
+    pub fn records(&self, season: Option<u32>) -> Result<Vec<DeathRecord>, Box<dyn Error>> {
+        let mut query = self
+            .conn
+            .prepare("SELECT * FROM deaths WHERE ?1 IS NULL OR season = ?1 ORDER BY at, id")?;
+        let records = query
+            .query_map(params![season], DeathRecord::from_row)?
+            .collect::<Result<_, _>>()?;
+        Ok(records)
+    }
+
+    /// How many deaths a season had.
+    pub fn season_deaths(&self, season: u32) -> Result<u32, Box<dyn Error>> {
+        Ok(self.conn.query_row(
+            "SELECT COUNT(*) FROM deaths WHERE season = ?1",
+            params![season],
+            |row| row.get(0),
+        )?)
+    }
+
+    /// The last season anyone died in, if anyone ever did.
+    pub fn last_season(&self) -> Result<Option<u32>, Box<dyn Error>> {
+        Ok(self
+            .conn
+            .query_row("SELECT MAX(season) FROM deaths", [], |row| row.get(0))
+            .optional()?
+            .flatten())
+    }
+
+    /// Deaths and rolls survived by each player, most deaths first, of one season or of all of
+    /// them.
+    pub fn leaderboard(&self, season: Option<u32>) -> Result<Vec<PlayerDeaths>, Box<dyn Error>> {
+        let mut query = self.conn.prepare(
+            "SELECT player, COUNT(*), SUM(roll IS NOT NULL AND NOT deadly) FROM deaths
+            WHERE ?1 IS NULL OR season = ?1
+            GROUP BY player ORDER BY COUNT(*) DESC, player",
+        )?;
+        let board = query
+            .query_map(params![season], |row| {
+                Ok(PlayerDeaths {
+                    player: row.get(0)?,
+                    deaths: row.get(1)?,
+                    survived: row.get(2)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(board)
+    }
+}
+
+fn insert(conn: &Connection, record: &DeathRecord) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO deaths (at, season, player, roll, outcome, deadly, penalty, playtime_secs)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![
+            record.at as i64,
+            record.season,
+            record.player,
+            record.roll,
+            record.outcome,
+            record.deadly,
+            record.penalty,
+            record.playtime_secs as i64,
+        ],
+    )?;
+    Ok(())
+}
+
+/// Move the deaths of a `deaths.jsonl` file from an older version into the database, leaving the
+/// file behind as `deaths.jsonl.imported`.
+fn import_jsonl(conn: &mut Connection, path: &Path) -> Result<(), Box<dyn Error>> {
+    let raw = match fs::read_to_string(path) {
+        Ok(raw) => raw,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+    let tx = conn.transaction()?;
+    let mut count = 0;
+    for (idx, line) in raw.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = serde_json::from_str(line)
+            .map_err(|err| format!("invalid death record on line {}: {}", idx + 1, err))?;
+        insert(&tx, &record)?;
+        count += 1;
+    }
+    tx.commit()?;
+    fs::rename(path, path.with_file_name("deaths.jsonl.imported"))?;
+    info!(
+        "moved {} deaths from \"{}\" into the history database",
+        count,
+        path.display()
+    );
+    Ok(())
+}
+
+pub struct PlayerDeaths {
+    pub player: String,
+    pub deaths: u32,
+    /// Rolls that came out safe.
+    pub survived: u32,
+}
@@ -0,0 +1,126 @@
+//! Actions that the config attaches to named run events, such as a death or a checkpoint: posting
+//! the event somewhere, running a program or sending a console command.
+//!
+//! Posts and programs run on a background thread, so a slow hook never holds up the server.
+
+use crate::logging;
+use log::warn;
+use serde_derive::Deserialize;
+use serde_json::Value;
+use std::{
+    error::Error,
+    process::Command,
+    sync::mpsc::{self, Sender},
+};
+
+/// Every event that hooks can be attached to.
+pub const EVENTS: &[&str] = &[
+    "player_death",
+    "roll_result",
+    "checkpoint",
+    "restore",
+    "reset",
+    "server_crash",
+    "join",
+    "leave",
+];
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum HookAction {
+    /// POST the event to a URL as a JSON object, with its name under `event`.
+    Post(String),
+    /// Run a program, with its arguments, and the event in `TH_EVENT` and `TH_<FIELD>` environment
+    /// variables.
+    Run(Vec<String>),
+    /// Send a console command, with each `{field}` replaced by that field of the event.
+    Command(String),
+}
+
+/// Replace each `{field}` in `template` with that field of `data`.
+pub fn fill(template: &str, data: &Value) -> String {
+    let mut out = template.to_string();
+    if let Some(fields) = data.as_object() {
+        for (key, value) in fields {
+            out = out.replace(&format!("{{{}}}", key), &text(value));
+        }
+    }
+    out
+}
+
+/// A field as plain text, JSON for anything but strings and numbers.
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+/// Runs posts and programs in the background.
+pub struct HookRunner {
+    tx: Sender<(HookAction, String, Value)>,
+}
+impl Default for HookRunner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl HookRunner {
+    pub fn new() -> Self {
+        let (tx, rx) = mpsc::channel::<(HookAction, String, Value)>();
+        logging::spawn(move || {
+            for (action, event, data) in rx.iter() {
+                if let Err(err) = run(&action, &event, &data) {
+                    warn!("{} hook {:?} failed: {}", event, action, err);
+                }
+            }
+        });
+        Self { tx }
+    }
+
+    /// Queue an action for an event. Console commands are sent by the caller instead.
+    pub fn run(&self, action: &HookAction, event: &str, data: &Value) {
+        let _ = self
+            .tx
+            .send((action.clone(), event.to_string(), data.clone()));
+    }
+}
+
+fn run(action: &HookAction, event: &str, data: &Value) -> Result<(), Box<dyn Error>> {
+    match action {
+        HookAction::Post(url) => {
+            let mut body = data.clone();
+            body["event"] = event.into();
+            post(url, body)
+        }
+        HookAction::Run(argv) => {
+            let (program, args) = argv.split_first().ok_or("nothing to run")?;
+            let mut cmd = Command::new(program);
+            cmd.args(args).env("TH_EVENT", event);
+            if let Some(fields) = data.as_object() {
+                for (key, value) in fields {
+                    cmd.env(format!("TH_{}", key.to_uppercase()), text(value));
+                }
+            }
+            let status = cmd.status()?;
+            if !status.success() {
+                return Err(format!("exited with {}", status).into());
+            }
+            Ok(())
+        }
+        //Sent by the caller, which has the console
+        HookAction::Command(_) => Ok(()),
+    }
+}
+
+#[cfg(feature = "hooks")]
+fn post(url: &str, body: Value) -> Result<(), Box<dyn Error>> {
+    ureq::post(url).send_json(body)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "hooks"))]
+fn post(_url: &str, _body: Value) -> Result<(), Box<dyn Error>> {
+    Err("posting needs the `hooks` feature, which this build was compiled without".into())
+}
@@ -0,0 +1,236 @@
+//! Detecting the Java runtime and composing the command that launches the server with it.
+
+use log::info;
+use serde_derive::Deserialize;
+use std::{error::Error, fs, path::Path, process::Command};
+
+/// JVM options the wrapper puts in front of the `server` arguments.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct JvmConfig {
+    /// Heap size, used for both `-Xms` and `-Xmx`, eg. `"4G"`. Overrides `auto_heap`.
+    pub heap: Option<String>,
+    /// Size the heap from the memory of the machine instead.
+    pub auto_heap: Option<AutoHeap>,
+    pub preset: JvmPreset,
+    /// Extra flags, added after the preset.
+    pub flags: Vec<String>,
+}
+
+/// Heap sizing from the total memory of the machine.
+#[derive(Deserialize, Clone, Copy)]
+#[serde(default)]
+pub struct AutoHeap {
+    /// Memory left over for the OS and everything else, in megabytes.
+    pub reserve_mb: u64,
+    pub min_mb: u64,
+    pub max_mb: Option<u64>,
+}
+impl Default for AutoHeap {
+    fn default() -> Self {
+        Self {
+            reserve_mb: 2048,
+            min_mb: 1024,
+            max_mb: None,
+        }
+    }
+}
+impl AutoHeap {
+    /// Heap size in megabytes for a machine with `total_mb` of memory.
+    fn size(&self, total_mb: u64) -> u64 {
+        let mut heap = total_mb.saturating_sub(self.reserve_mb);
+        if let Some(max) = self.max_mb {
+            heap = heap.min(max);
+        }
+        heap.max(self.min_mb)
+    }
+}
+
+/// Total memory of the machine, in megabytes.
+fn total_memory_mb() -> Result<u64, Box<dyn Error>> {
+    let meminfo = fs::read_to_string("/proc/meminfo").map_err(|err| {
+        format!(
+            "could not detect system memory ({}), set `heap` instead",
+            err
+        )
+    })?;
+    meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemTotal:"))
+        .and_then(|kb| kb.trim().trim_end_matches("kB").trim().parse::<u64>().ok())
+        .map(|kb| kb / 1024)
+        .ok_or_else(|| "no MemTotal in /proc/meminfo, set `heap` instead".into())
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum JvmPreset {
+    #[default]
+    None,
+    /// Aikar's G1 tuning for Minecraft servers, see <https://mcflags.emc.gs>.
+    Aikar,
+}
+
+const AIKAR_FLAGS: &[&str] = &[
+    "-XX:+UseG1GC",
+    "-XX:+ParallelRefProcEnabled",
+    "-XX:MaxGCPauseMillis=200",
+    "-XX:+UnlockExperimentalVMOptions",
+    "-XX:+DisableExplicitGC",
+    "-XX:+AlwaysPreTouch",
+    "-XX:G1HeapWastePercent=5",
+    "-XX:G1MixedGCCountTarget=4",
+    "-XX:G1MixedGCLiveThresholdPercent=90",
+    "-XX:G1RSetUpdatingPauseIntervalMillis=32",
+    "-XX:SurvivorRatio=32",
+    "-XX:+PerfDisableSharedMem",
+    "-XX:MaxTenuringThreshold=1",
+    "-Dusing.aikars.flags=https://mcflags.emc.gs",
+    "-Daikars.new.flags=true",
+];
+/// Aikar flags that depend on the heap size, for heaps of up to 12GB and for larger ones.
+const AIKAR_SMALL_HEAP: &[&str] = &[
+    "-XX:G1NewSizePercent=30",
+    "-XX:G1MaxNewSizePercent=40",
+    "-XX:G1HeapRegionSize=8M",
+    "-XX:G1ReservePercent=20",
+    "-XX:InitiatingHeapOccupancyPercent=15",
+];
+const AIKAR_LARGE_HEAP: &[&str] = &[
+    "-XX:G1NewSizePercent=40",
+    "-XX:G1MaxNewSizePercent=50",
+    "-XX:G1HeapRegionSize=16M",
+    "-XX:G1ReservePercent=15",
+    "-XX:InitiatingHeapOccupancyPercent=20",
+];
+
+/// Parse a JVM memory size such as `4G` or `512M` into megabytes.
+fn heap_megabytes(heap: &str) -> Option<u64> {
+    let heap = heap.trim();
+    let (num, unit) = heap.split_at(heap.len().checked_sub(1)?);
+    let num: u64 = num.parse().ok()?;
+    match unit {
+        "g" | "G" => Some(num * 1024),
+        "m" | "M" => Some(num),
+        "k" | "K" => Some(num / 1024),
+        _ => None,
+    }
+}
+
+/// Build the full launch command, from the Java executable and the jar with its arguments.
+pub fn launch_command(
+    java: &str,
+    jvm: &JvmConfig,
+    server: &[String],
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut cmd = vec![java.to_string()];
+    let heap = match (&jvm.heap, jvm.auto_heap) {
+        (Some(heap), _) => {
+            heap_megabytes(heap).ok_or_else(|| format!("invalid heap size \"{}\"", heap))?;
+            Some(heap.clone())
+        }
+        (None, Some(auto)) => {
+            let total = total_memory_mb()?;
+            let heap = format!("{}M", auto.size(total));
+            info!("sizing heap to {} out of {}M of system memory", heap, total);
+            Some(heap)
+        }
+        (None, None) => None,
+    };
+    if let Some(heap) = &heap {
+        cmd.push(format!("-Xms{}", heap));
+        cmd.push(format!("-Xmx{}", heap));
+    }
+    match jvm.preset {
+        JvmPreset::None => {}
+        JvmPreset::Aikar => {
+            let large = heap
+                .as_deref()
+                .and_then(heap_megabytes)
+                .map(|mb| mb > 12 * 1024)
+                .unwrap_or(false);
+            let sized = if large {
+                AIKAR_LARGE_HEAP
+            } else {
+                AIKAR_SMALL_HEAP
+            };
+            cmd.extend(AIKAR_FLAGS.iter().chain(sized).map(|flag| flag.to_string()));
+        }
+    }
+    cmd.extend(jvm.flags.iter().cloned());
+    cmd.push("-jar".to_string());
+    cmd.extend(server.iter().cloned());
+    Ok(cmd)
+}
+
+/// Whether an executable looks like a Java launcher, such as `java` or `/usr/bin/java.exe`.
+pub fn is_java(exe: &str) -> bool {
+    Path::new(exe)
+        .file_stem()
+        .map(|stem| stem == "java" || stem == "javaw")
+        .unwrap_or(false)
+}
+
+/// Major version of the Java runtime at `java`, eg. 8 for `1.8.0_292` and 17 for `17.0.2`.
+pub fn major_version(java: &str) -> Result<u32, Box<dyn Error>> {
+    let out = Command::new(java).arg("-version").output().map_err(|err| {
+        format!(
+            "could not run java at \"{}\" ({}). install a Java runtime or set `java` to its path",
+            java, err
+        )
+    })?;
+    //`java -version` writes to stderr, eg. `openjdk version "17.0.2" 2022-01-18`
+    let text = String::from_utf8_lossy(&out.stderr);
+    let version = text
+        .split('"')
+        .nth(1)
+        .ok_or_else(|| format!("unrecognized `java -version` output: {}", text.trim()))?;
+    let mut parts = version.split(|c: char| !c.is_ascii_digit());
+    let major = match parts.next() {
+        Some("1") => parts.next(),
+        major => major,
+    };
+    Ok(major
+        .and_then(|major| major.parse().ok())
+        .ok_or_else(|| format!("unrecognized java version \"{}\"", version))?)
+}
+
+/// Minimum Java major version needed by a Minecraft release, if it is a release version.
+pub fn required_version(mc_version: &str) -> Option<u32> {
+    let mut parts = mc_version.split('.').map(str::parse::<u32>);
+    let (major, minor, patch) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(Ok(major)), Some(Ok(minor)), None) => (major, minor, 0),
+        (Some(Ok(major)), Some(Ok(minor)), Some(Ok(patch))) => (major, minor, patch),
+        _ => return None,
+    };
+    Some(match (major, minor, patch) {
+        (1, 0..=16, _) => 8,
+        (1, 17, _) => 16,
+        (1, 18..=19, _) | (1, 20, 0..=4) => 17,
+        _ => 21,
+    })
+}
+
+/// How to react when the JVM reports it is running out of memory.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct MemoryPressureConfig {
+    pub enabled: bool,
+    /// Restart the server some time after memory trouble shows up, before the JVM dies on its own.
+    pub restart: bool,
+    pub restart_delay_seconds: u64,
+}
+impl Default for MemoryPressureConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            restart: false,
+            restart_delay_seconds: 60,
+        }
+    }
+}
+
+/// Whether a line of output shows the JVM struggling for memory.
+pub fn is_memory_pressure(line: &str) -> bool {
+    line.contains("java.lang.OutOfMemoryError") || line.contains("GC overhead limit exceeded")
+}
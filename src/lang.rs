@@ -0,0 +1,138 @@
+//! Death messages, taken from Minecraft language files or from the built-in English set.
+
+use log::info;
+use std::{
+    collections::{BTreeMap, HashSet},
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Vanilla English death messages built into the binary, by the game version they come from.
+const EMBEDDED_LANGS: &[(&str, &str)] = &[("1.20", include_str!("../lang/death_en_us_1.20.json"))];
+
+/// The built-in death messages for a game version, or the newest ones if the version is unknown.
+/// Returns the version they come from along with the messages.
+pub fn embedded_death_messages(version: Option<&str>) -> (&'static str, Vec<String>) {
+    let &(from, text) = version
+        .and_then(|version| {
+            EMBEDDED_LANGS
+                .iter()
+                .rev()
+                .find(|(from, _)| version == *from || version.starts_with(&format!("{}.", from)))
+        })
+        .unwrap_or(&EMBEDDED_LANGS[EMBEDDED_LANGS.len() - 1]);
+    let msgs = parse_json_lang(text).expect("built-in lang files are valid json");
+    (from, msgs)
+}
+
+/// Where the English language file lives inside a jar, in newer and older versions.
+#[cfg(feature = "archives")]
+const JAR_LANGS: &[&str] = &[
+    "assets/minecraft/lang/en_us.json",
+    "assets/minecraft/lang/en_us.lang",
+    "assets/minecraft/lang/en_US.lang",
+];
+
+/// Read the death messages in a lang file, either a JSON one (1.13 and later) or a `key=value`
+/// `.lang` one, going by the extension. Jars have their language file read out of them.
+pub fn parse_lang(path: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+    let (name, text) = if path.extension() == Some("jar".as_ref()) {
+        read_jar_lang(path)?
+    } else {
+        (
+            path.to_string_lossy().into_owned(),
+            fs::read_to_string(path)?,
+        )
+    };
+    if name.ends_with(".json") {
+        parse_json_lang(&text)
+    } else {
+        Ok(parse_lang_text(&text))
+    }
+}
+
+/// Find the language file in a server or client jar, returning its name and contents.
+#[cfg(feature = "archives")]
+fn read_jar_lang(jar: &Path) -> Result<(String, String), Box<dyn Error>> {
+    use std::io::{Cursor, Read, Seek};
+
+    fn search<R: Read + Seek>(jar: R) -> Result<Option<(String, String)>, Box<dyn Error>> {
+        let mut zip = zip::ZipArchive::new(jar)?;
+        for name in JAR_LANGS {
+            if let Ok(mut file) = zip.by_name(name) {
+                let mut text = String::new();
+                file.read_to_string(&mut text)?;
+                return Ok(Some((name.to_string(), text)));
+            }
+        }
+        //Since 1.18 server jars bundle the actual server as a jar inside the jar
+        let nested: Vec<String> = zip
+            .file_names()
+            .filter(|name| name.starts_with("META-INF/versions/") && name.ends_with(".jar"))
+            .map(str::to_string)
+            .collect();
+        for name in nested {
+            let mut bytes = Vec::new();
+            zip.by_name(&name)?.read_to_end(&mut bytes)?;
+            if let Some(found) = search(Cursor::new(bytes))? {
+                return Ok(Some(found));
+            }
+        }
+        Ok(None)
+    }
+
+    search(fs::File::open(jar)?)?
+        .ok_or_else(|| format!("no language file found in \"{}\"", jar.display()).into())
+}
+
+#[cfg(not(feature = "archives"))]
+fn read_jar_lang(_jar: &Path) -> Result<(String, String), Box<dyn Error>> {
+    Err("reading lang files out of jars needs the `archives` feature, which this build was compiled without".into())
+}
+
+/// Extract the death messages in a JSON lang file.
+pub fn parse_json_lang(text: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let entries: BTreeMap<String, String> = serde_json::from_str(text)?;
+    Ok(death_messages(entries))
+}
+
+/// Extract the death messages in a `key=value` lang file.
+pub fn parse_lang_text(text: &str) -> Vec<String> {
+    death_messages(text.lines().filter_map(|line| {
+        let (key, msg) = line.split_once('=')?;
+        Some((key.trim().to_string(), msg.trim().to_string()))
+    }))
+}
+
+/// Pick the death messages out of lang entries, as the full template that follows the name of
+/// the player who died, eg. `" was shot by %2$s using %3$s"`.
+fn death_messages(entries: impl IntoIterator<Item = (String, String)>) -> Vec<String> {
+    entries
+        .into_iter()
+        .filter(|(key, _)| key.starts_with("death."))
+        .filter_map(|(_, msg)| Some(msg.split_once("%1$s")?.1.trim_end().to_string()))
+        .filter(|msg| !msg.is_empty())
+        .collect()
+}
+
+/// Parse several lang files, merging their death messages without duplicates.
+pub fn parse_langs(paths: &[PathBuf]) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut seen = HashSet::new();
+    let mut death_msg = Vec::new();
+    for path in paths {
+        let msgs = parse_lang(path)
+            .map_err(|err| format!("failed to read lang file \"{}\": {}", path.display(), err))?;
+        info!(
+            "{} death messages in lang file \"{}\"",
+            msgs.len(),
+            path.display()
+        );
+        for msg in msgs {
+            if seen.insert(msg.clone()) {
+                death_msg.push(msg);
+            }
+        }
+    }
+    Ok(death_msg)
+}
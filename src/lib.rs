@@ -0,0 +1,69 @@
+//! Hardcore Minecraft server wrapper, where dying rolls the dice on the fate of the world.
+//!
+//! The [`supervisor::Supervisor`] runs the whole thing, but its parts can be used on their own:
+//! [`server::ServerHandle`] runs the server process, [`events::EventStream`] collects its output
+//! along with console input, [`backups::BackupManager`] keeps world checkpoints and
+//! [`penalty::PenaltyEngine`] decides what a death costs.
+
+pub mod afk;
+pub mod announce;
+pub mod api;
+pub mod archive;
+pub mod backups;
+pub mod bedrock;
+pub mod chat;
+pub mod commands;
+pub mod config;
+pub mod console_log;
+pub mod crash;
+pub mod diagnose;
+pub mod download;
+pub mod events;
+pub mod export;
+pub mod fairness;
+pub mod faults;
+pub mod fleet;
+pub mod history;
+pub mod hooks;
+pub mod java;
+pub mod lang;
+pub mod lives;
+pub mod logging;
+pub mod matcher;
+pub mod nbt;
+pub mod notify;
+pub mod parse;
+pub mod penalty;
+pub mod plugins;
+pub mod proxy;
+pub mod rcon;
+pub mod recent;
+pub mod remote;
+pub mod schedule;
+pub mod script;
+pub mod server;
+pub mod state;
+pub mod supervisor;
+pub mod telegram;
+pub mod twitch;
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Seconds since the Unix epoch.
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Lowercase hexadecimal form of some bytes, as checksums are usually written.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Format a duration as hours and minutes, eg. `3h07m`.
+pub fn fmt_duration(d: Duration) -> String {
+    let mins = d.as_secs() / 60;
+    format!("{}h{:02}m", mins / 60, mins % 60)
+}
@@ -0,0 +1,57 @@
+//! Per-player lives, which soak up bad rolls before the world has to pay for them.
+//!
+//! Lives are kept in `lives.json` next to `playtime.txt`, mapping each player, by UUID once it is
+//! known, to the lives they have left. Players missing from the file have not lost any yet.
+
+use std::{collections::BTreeMap, error::Error, fs, path::PathBuf};
+
+pub struct Lives {
+    path: PathBuf,
+    /// Lives every player starts a world with.
+    start: u32,
+    left: BTreeMap<String, u32>,
+}
+impl Lives {
+    pub fn load(path: PathBuf, start: u32) -> Result<Self, Box<dyn Error>> {
+        let left = if path.exists() {
+            serde_json::from_str(&fs::read_to_string(&path)?)?
+        } else {
+            BTreeMap::new()
+        };
+        Ok(Self { path, start, left })
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        fs::write(&self.path, serde_json::to_string_pretty(&self.left)?)?;
+        Ok(())
+    }
+
+    /// Lives a player has left.
+    pub fn left(&self, player: &str) -> u32 {
+        self.left.get(player).copied().unwrap_or(self.start)
+    }
+
+    /// Take a life from a player, returning how many are left afterwards.
+    pub fn lose(&mut self, player: &str) -> u32 {
+        let left = self.left(player).saturating_sub(1);
+        self.left.insert(player.to_string(), left);
+        left
+    }
+
+    /// Move the lives kept under a player's name over to their UUID, returning whether there were
+    /// any.
+    pub fn rekey(&mut self, name: &str, uuid: &str) -> bool {
+        match self.left.remove(name) {
+            Some(left) => {
+                self.left.insert(uuid.to_string(), left);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Give everyone their lives back, for a fresh world.
+    pub fn refill(&mut self) {
+        self.left.clear();
+    }
+}
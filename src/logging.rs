@@ -0,0 +1,126 @@
+//! Wrapper log messages, printed to the terminal and mirrored into the console log.
+//!
+//! The console log keeps everything at `info` and above no matter the terminal level, so the
+//! history around a reset survives the terminal scrolling away.
+//!
+//! When supervising a fleet, each thread working for a server is tagged with its name, which
+//! prefixes what it prints and picks the console log it writes to.
+
+use crate::console_log::SharedConsoleLog;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+};
+
+struct Logger {
+    /// Most verbose level shown on the terminal.
+    terminal: Mutex<LevelFilter>,
+    /// Console logs, by the tag of the server they belong to.
+    files: Mutex<Option<HashMap<Option<Arc<str>>, SharedConsoleLog>>>,
+}
+
+static LOGGER: Logger = Logger {
+    terminal: Mutex::new(LevelFilter::Info),
+    files: Mutex::new(None),
+};
+
+thread_local! {
+    static TAG: RefCell<Option<Arc<str>>> = const { RefCell::new(None) };
+}
+
+impl Log for Logger {
+    fn enabled(&self, meta: &Metadata) -> bool {
+        //Dependencies only get a say when something goes wrong
+        meta.level() <= log::max_level()
+            && (meta.target().starts_with("trust_hardcore") || meta.level() <= Level::Warn)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let level = record.level();
+        let tag = tag();
+        let prefix = tag
+            .as_ref()
+            .map(|tag| format!("[{}] ", tag))
+            .unwrap_or_default();
+        if level <= *self.terminal.lock().unwrap() {
+            match level {
+                Level::Error => eprintln!("{}error: {}", prefix, record.args()),
+                Level::Warn => eprintln!("{}warning: {}", prefix, record.args()),
+                Level::Info => eprintln!("{}{}", prefix, record.args()),
+                Level::Debug | Level::Trace => eprintln!(
+                    "{}{}: {}",
+                    prefix,
+                    level.as_str().to_lowercase(),
+                    record.args()
+                ),
+            }
+        }
+        let files = self.files.lock().unwrap();
+        if let Some(file) = files.as_ref().and_then(|files| files.get(&tag)) {
+            file.lock()
+                .unwrap()
+                .write_line(&format!("[wrapper/{}] {}", level, record.args()));
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Install the logger, showing messages up to `level` on the terminal.
+pub fn init(level: LevelFilter) {
+    let _ = log::set_logger(&LOGGER);
+    set_level(level);
+}
+
+/// Change the most verbose level shown on the terminal.
+pub fn set_level(level: LevelFilter) {
+    *LOGGER.terminal.lock().unwrap() = level;
+    log::set_max_level(level.max(LevelFilter::Info));
+}
+
+/// Start mirroring log messages from threads with the current tag into the console log.
+pub fn mirror_to(log: SharedConsoleLog) {
+    LOGGER
+        .files
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .insert(tag(), log);
+}
+
+/// The name of the server this thread works for, when supervising a fleet.
+pub fn tag() -> Option<Arc<str>> {
+    TAG.with(|tag| tag.borrow().clone())
+}
+
+/// Mark this thread as working for a server of the fleet.
+pub fn set_tag(tag: Option<Arc<str>>) {
+    TAG.with(|cur| *cur.borrow_mut() = tag);
+}
+
+/// Print a line of server output, prefixed with the server when supervising a fleet.
+pub fn output(line: &str) {
+    match tag() {
+        Some(tag) => println!("[{}] {}", tag, line),
+        None => println!("{}", line),
+    }
+}
+
+/// Start a thread that works for the same server as this one.
+pub fn spawn<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let tag = tag();
+    thread::spawn(move || {
+        set_tag(tag);
+        f()
+    })
+}
@@ -0,0 +1,141 @@
+//! Fast matching of a message against many patterns at once.
+
+use aho_corasick::{AhoCorasick, Anchored, BuildError, Input, MatchKind, StartKind};
+use regex::RegexSet;
+use std::path::Path;
+
+/// Checks whether a message starts with any of a set of patterns.
+///
+/// The patterns are compiled into a single Aho-Corasick automaton, so the cost of a check does not
+/// grow with the amount of patterns.
+pub struct PrefixMatcher {
+    ac: AhoCorasick,
+}
+impl PrefixMatcher {
+    pub fn new<P: AsRef<[u8]>>(patterns: &[P]) -> Result<Self, BuildError> {
+        let ac = AhoCorasick::builder()
+            .start_kind(StartKind::Anchored)
+            .match_kind(MatchKind::LeftmostFirst)
+            .build(patterns)?;
+        Ok(Self { ac })
+    }
+
+    /// Index of a pattern that `msg` starts with, if any.
+    pub fn find(&self, msg: &str) -> Option<usize> {
+        self.ac
+            .find(Input::new(msg).anchored(Anchored::Yes))
+            .map(|m| m.pattern().as_usize())
+    }
+
+    pub fn matches(&self, msg: &str) -> bool {
+        self.find(msg).is_some()
+    }
+}
+
+/// Checks whether a message matches any of a set of lang templates, such as
+/// `" was shot by %2$s using %3$s"`, where each placeholder stands for any text.
+///
+/// Unlike prefix matching this checks the whole message, so text after a placeholder still has to
+/// match. The templates are compiled into a single regex set, checked in one pass.
+pub struct TemplateMatcher {
+    set: RegexSet,
+}
+impl TemplateMatcher {
+    pub fn new<T: AsRef<str>>(templates: &[T]) -> Result<Self, regex::Error> {
+        let set = RegexSet::new(templates.iter().map(|t| template_regex(t.as_ref())))?;
+        Ok(Self { set })
+    }
+
+    /// Index of a template that `msg` matches, if any.
+    pub fn find(&self, msg: &str) -> Option<usize> {
+        self.set.matches(msg.trim_end()).into_iter().next()
+    }
+
+    pub fn matches(&self, msg: &str) -> bool {
+        self.find(msg).is_some()
+    }
+}
+
+/// Checks whether a path matches any of a set of glob patterns, such as `session.lock`,
+/// `DIM*/poi` or `**/*.tmp`.
+///
+/// `*` and `?` stay within a path component, while `**` spans any number of them. Patterns
+/// without a `/` match a file or directory of that name anywhere, the rest match from the root.
+pub struct GlobMatcher {
+    set: RegexSet,
+}
+impl GlobMatcher {
+    pub fn new<P: AsRef<str>>(patterns: &[P]) -> Result<Self, regex::Error> {
+        let set = RegexSet::new(patterns.iter().map(|p| glob_regex(p.as_ref())))?;
+        Ok(Self { set })
+    }
+
+    /// Whether `path`, relative to the root, or a directory it is in matches any of the patterns.
+    pub fn matches(&self, path: &Path) -> bool {
+        let path = path
+            .components()
+            .map(|part| part.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        self.set.is_match(&path)
+    }
+}
+
+fn glob_regex(glob: &str) -> String {
+    let mut re = String::from(if glob.trim_end_matches('/').contains('/') {
+        "^"
+    } else {
+        "^(?:.*/)?"
+    });
+    let mut chars = glob.trim_matches('/').chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    re += "(?:.*/)?";
+                } else {
+                    re += ".*";
+                }
+            }
+            '*' => re += "[^/]*",
+            '?' => re += "[^/]",
+            c => re += &regex::escape(c.encode_utf8(&mut [0; 4])),
+        }
+    }
+    //Everything inside a matching directory matches too
+    re += "(?:/.*)?$";
+    re
+}
+
+/// Turn a lang template into a regex matching the whole message. `%s` and `%2$s` style
+/// placeholders match any text, and `%%` is a literal `%`.
+fn template_regex(template: &str) -> String {
+    let mut re = String::from("^");
+    let mut rest = template;
+    while let Some(idx) = rest.find('%') {
+        re += &regex::escape(&rest[..idx]);
+        let spec = &rest[idx + 1..];
+        let digits = spec
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(spec.len());
+        let len = if spec.starts_with('%') {
+            re.push('%');
+            1
+        } else if spec[digits..].starts_with("$s") && digits > 0 {
+            re += "(.+?)";
+            digits + 2
+        } else if spec.starts_with('s') {
+            re += "(.+?)";
+            1
+        } else {
+            re.push('%');
+            0
+        };
+        rest = &spec[len..];
+    }
+    re += &regex::escape(rest);
+    re.push('$');
+    re
+}
@@ -0,0 +1,474 @@
+//! Reading and writing NBT, the format of `level.dat` and `playerdata/*.dat` in Java worlds.
+//!
+//! These files hold a single named compound, usually gzip-compressed. [`LevelInfo`] and
+//! [`PlayerInfo`] pick out the few fields the wrapper cares about, while [`Tag`] gives access to
+//! the rest.
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    error::Error,
+    fs,
+    io::{Read, Write},
+    path::Path,
+};
+
+/// How deep lists and compounds may nest, as in Minecraft itself.
+const MAX_DEPTH: usize = 512;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Tag {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(Vec<i8>),
+    String(String),
+    /// Every element has the same type.
+    List(Vec<Tag>),
+    Compound(BTreeMap<String, Tag>),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+impl Tag {
+    fn id(&self) -> u8 {
+        match self {
+            Tag::Byte(_) => 1,
+            Tag::Short(_) => 2,
+            Tag::Int(_) => 3,
+            Tag::Long(_) => 4,
+            Tag::Float(_) => 5,
+            Tag::Double(_) => 6,
+            Tag::ByteArray(_) => 7,
+            Tag::String(_) => 8,
+            Tag::List(_) => 9,
+            Tag::Compound(_) => 10,
+            Tag::IntArray(_) => 11,
+            Tag::LongArray(_) => 12,
+        }
+    }
+
+    /// A field of a compound.
+    pub fn get(&self, key: &str) -> Option<&Tag> {
+        match self {
+            Tag::Compound(fields) => fields.get(key),
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut Tag> {
+        match self {
+            Tag::Compound(fields) => fields.get_mut(key),
+            _ => None,
+        }
+    }
+
+    /// A field nested in compounds, by its dotted path, as in `Data.WorldGenSettings.seed`.
+    pub fn at(&self, path: &str) -> Option<&Tag> {
+        path.split('.').try_fold(self, |tag, key| tag.get(key))
+    }
+
+    /// Any integer, widened.
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            Tag::Byte(n) => Some(n.into()),
+            Tag::Short(n) => Some(n.into()),
+            Tag::Int(n) => Some(n.into()),
+            Tag::Long(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    /// Any floating point number, widened.
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Tag::Float(n) => Some(n.into()),
+            Tag::Double(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Tag::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[Tag]> {
+        match self {
+            Tag::List(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+/// Parse uncompressed NBT into the name and value of its root tag.
+pub fn from_bytes(data: &[u8]) -> Result<(String, Tag), Box<dyn Error>> {
+    let mut reader = Reader { data };
+    let id = reader.u8()?;
+    if id != 10 {
+        return Err(format!("root tag is of type {} rather than a compound", id).into());
+    }
+    let name = reader.string()?;
+    let root = reader.payload(id, 0)?;
+    Ok((name, root))
+}
+
+/// Write a root tag as uncompressed NBT.
+pub fn to_bytes(name: &str, root: &Tag) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut out = Vec::new();
+    out.push(root.id());
+    write_string(&mut out, name)?;
+    write_payload(&mut out, root)?;
+    Ok(out)
+}
+
+/// Read an NBT file, gzip-compressed or not.
+pub fn read_file(path: &Path) -> Result<(String, Tag), Box<dyn Error>> {
+    let raw = fs::read(path)?;
+    let parsed = if raw.starts_with(&[0x1f, 0x8b]) {
+        let mut data = Vec::new();
+        GzDecoder::new(&raw[..]).read_to_end(&mut data)?;
+        from_bytes(&data)
+    } else {
+        from_bytes(&raw)
+    };
+    parsed.map_err(|err| format!("\"{}\": {}", path.display(), err).into())
+}
+
+/// Write a gzip-compressed NBT file, replacing the old one only once the new one is complete.
+pub fn write_file(path: &Path, name: &str, root: &Tag) -> Result<(), Box<dyn Error>> {
+    let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+    gz.write_all(&to_bytes(name, root)?)?;
+    let mut tmp = path.as_os_str().to_os_string();
+    tmp.push(".tmp");
+    fs::write(&tmp, gz.finish()?)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Box<dyn Error>> {
+        if self.data.len() < len {
+            return Err("data ends in the middle of a tag".into());
+        }
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], Box<dyn Error>> {
+        let mut out = [0; N];
+        out.copy_from_slice(self.take(N)?);
+        Ok(out)
+    }
+
+    fn u8(&mut self) -> Result<u8, Box<dyn Error>> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn i32(&mut self) -> Result<i32, Box<dyn Error>> {
+        Ok(i32::from_be_bytes(self.array()?))
+    }
+
+    fn i64(&mut self) -> Result<i64, Box<dyn Error>> {
+        Ok(i64::from_be_bytes(self.array()?))
+    }
+
+    fn len(&mut self) -> Result<usize, Box<dyn Error>> {
+        let len = self.i32()?;
+        if len < 0 || len as usize > self.data.len() {
+            return Err(format!("bad length {}", len).into());
+        }
+        Ok(len as usize)
+    }
+
+    fn string(&mut self) -> Result<String, Box<dyn Error>> {
+        let len = u16::from_be_bytes(self.array()?);
+        Ok(decode_mutf8(self.take(len.into())?))
+    }
+
+    fn payload(&mut self, id: u8, depth: usize) -> Result<Tag, Box<dyn Error>> {
+        if depth > MAX_DEPTH {
+            return Err("tags nest too deep".into());
+        }
+        Ok(match id {
+            1 => Tag::Byte(self.u8()? as i8),
+            2 => Tag::Short(i16::from_be_bytes(self.array()?)),
+            3 => Tag::Int(self.i32()?),
+            4 => Tag::Long(self.i64()?),
+            5 => Tag::Float(f32::from_be_bytes(self.array()?)),
+            6 => Tag::Double(f64::from_be_bytes(self.array()?)),
+            7 => {
+                let len = self.len()?;
+                Tag::ByteArray(self.take(len)?.iter().map(|&b| b as i8).collect())
+            }
+            8 => Tag::String(self.string()?),
+            9 => {
+                let item = self.u8()?;
+                let len = self.len()?;
+                let items = (0..len)
+                    .map(|_| self.payload(item, depth + 1))
+                    .collect::<Result<_, _>>()?;
+                Tag::List(items)
+            }
+            10 => {
+                let mut fields = BTreeMap::new();
+                loop {
+                    let id = self.u8()?;
+                    if id == 0 {
+                        break;
+                    }
+                    let name = self.string()?;
+                    fields.insert(name, self.payload(id, depth + 1)?);
+                }
+                Tag::Compound(fields)
+            }
+            11 => {
+                let len = self.len()?;
+                Tag::IntArray((0..len).map(|_| self.i32()).collect::<Result<_, _>>()?)
+            }
+            12 => {
+                let len = self.len()?;
+                Tag::LongArray((0..len).map(|_| self.i64()).collect::<Result<_, _>>()?)
+            }
+            _ => return Err(format!("unknown tag type {}", id).into()),
+        })
+    }
+}
+
+fn write_len(out: &mut Vec<u8>, len: usize) -> Result<(), Box<dyn Error>> {
+    let len = i32::try_from(len).map_err(|_| "array too long for NBT")?;
+    out.extend(len.to_be_bytes());
+    Ok(())
+}
+
+fn write_string(out: &mut Vec<u8>, s: &str) -> Result<(), Box<dyn Error>> {
+    let bytes = encode_mutf8(s);
+    let len = u16::try_from(bytes.len()).map_err(|_| "string too long for NBT")?;
+    out.extend(len.to_be_bytes());
+    out.extend(bytes);
+    Ok(())
+}
+
+fn write_payload(out: &mut Vec<u8>, tag: &Tag) -> Result<(), Box<dyn Error>> {
+    match tag {
+        Tag::Byte(n) => out.push(*n as u8),
+        Tag::Short(n) => out.extend(n.to_be_bytes()),
+        Tag::Int(n) => out.extend(n.to_be_bytes()),
+        Tag::Long(n) => out.extend(n.to_be_bytes()),
+        Tag::Float(n) => out.extend(n.to_be_bytes()),
+        Tag::Double(n) => out.extend(n.to_be_bytes()),
+        Tag::ByteArray(items) => {
+            write_len(out, items.len())?;
+            out.extend(items.iter().map(|&b| b as u8));
+        }
+        Tag::String(s) => write_string(out, s)?,
+        Tag::List(items) => {
+            //Empty lists are written as lists of nothing
+            let id = items.first().map_or(0, Tag::id);
+            if items.iter().any(|item| item.id() != id) {
+                return Err("list mixes tags of different types".into());
+            }
+            out.push(id);
+            write_len(out, items.len())?;
+            for item in items {
+                write_payload(out, item)?;
+            }
+        }
+        Tag::Compound(fields) => {
+            for (name, value) in fields {
+                out.push(value.id());
+                write_string(out, name)?;
+                write_payload(out, value)?;
+            }
+            out.push(0);
+        }
+        Tag::IntArray(items) => {
+            write_len(out, items.len())?;
+            items.iter().for_each(|n| out.extend(n.to_be_bytes()));
+        }
+        Tag::LongArray(items) => {
+            write_len(out, items.len())?;
+            items.iter().for_each(|n| out.extend(n.to_be_bytes()));
+        }
+    }
+    Ok(())
+}
+
+/// Decode Java's "modified UTF-8", which writes NUL as two bytes and anything outside the BMP as
+/// two surrogates, three bytes each.
+fn decode_mutf8(bytes: &[u8]) -> String {
+    //Plain UTF-8 reads the same, as long as there are no NULs or surrogates
+    if let Ok(s) = std::str::from_utf8(bytes) {
+        return s.to_string();
+    }
+    let cont = |i: usize| bytes.get(i).map_or(0, |&b| u16::from(b & 0x3f));
+    let mut units = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = u16::from(bytes[i]);
+        if b < 0x80 {
+            units.push(b);
+            i += 1;
+        } else if b & 0xe0 == 0xc0 {
+            units.push((b & 0x1f) << 6 | cont(i + 1));
+            i += 2;
+        } else if b & 0xf0 == 0xe0 {
+            units.push((b & 0x0f) << 12 | cont(i + 1) << 6 | cont(i + 2));
+            i += 3;
+        } else {
+            units.push(0xfffd);
+            i += 1;
+        }
+    }
+    String::from_utf16_lossy(&units)
+}
+
+fn encode_mutf8(s: &str) -> Vec<u8> {
+    let mut out = Vec::with_capacity(s.len());
+    for unit in s.encode_utf16() {
+        match unit {
+            0x01..=0x7f => out.push(unit as u8),
+            0x00 | 0x80..=0x7ff => {
+                out.push(0xc0 | (unit >> 6) as u8);
+                out.push(0x80 | (unit & 0x3f) as u8);
+            }
+            _ => {
+                out.push(0xe0 | (unit >> 12) as u8);
+                out.push(0x80 | (unit >> 6 & 0x3f) as u8);
+                out.push(0x80 | (unit & 0x3f) as u8);
+            }
+        }
+    }
+    out
+}
+
+/// The parts of a world's `level.dat` that matter to the wrapper.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LevelInfo {
+    pub name: String,
+    pub hardcore: bool,
+    pub seed: Option<i64>,
+    /// World spawn, as block coordinates.
+    pub spawn: Option<[i32; 3]>,
+}
+impl LevelInfo {
+    pub fn read(world: &Path) -> Result<Self, Box<dyn Error>> {
+        let (_, root) = read_file(&world.join("level.dat"))?;
+        let data = root.get("Data").ok_or("level.dat has no `Data`")?;
+        //Seeds moved into the world generation settings in 1.16
+        let seed = data
+            .at("WorldGenSettings.seed")
+            .or_else(|| data.get("RandomSeed"))
+            .and_then(Tag::as_i64);
+        //And the spawn into its own compound in 1.21.9
+        let spawn = match data.at("spawn.pos") {
+            Some(Tag::IntArray(pos)) if pos.len() == 3 => Some([pos[0], pos[1], pos[2]]),
+            _ => ["SpawnX", "SpawnY", "SpawnZ"]
+                .iter()
+                .map(|key| Some(data.get(key)?.as_i64()? as i32))
+                .collect::<Option<Vec<_>>>()
+                .map(|pos| [pos[0], pos[1], pos[2]]),
+        };
+        Ok(Self {
+            name: data
+                .get("LevelName")
+                .and_then(Tag::as_str)
+                .unwrap_or_default()
+                .to_string(),
+            hardcore: data.get("hardcore").and_then(Tag::as_i64) == Some(1),
+            seed,
+            spawn,
+        })
+    }
+
+    /// Turn hardcore mode on or off for an existing world, which ignores `server.properties` on
+    /// this. The server must not be running, or it will write its own copy back over this one.
+    pub fn set_hardcore(world: &Path, hardcore: bool) -> Result<(), Box<dyn Error>> {
+        let path = world.join("level.dat");
+        let (name, mut root) = read_file(&path)?;
+        match root.get_mut("Data") {
+            Some(Tag::Compound(data)) => {
+                data.insert("hardcore".to_string(), Tag::Byte(hardcore.into()));
+            }
+            _ => return Err("level.dat has no `Data`".into()),
+        }
+        //Keep the previous copy around, as the server does
+        fs::copy(&path, world.join("level.dat_old"))?;
+        write_file(&path, &name, &root)
+    }
+}
+
+/// The parts of a player's `playerdata/<uuid>.dat` that matter to the wrapper.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlayerInfo {
+    pub uuid: String,
+    pub health: f32,
+    pub pos: [f64; 3],
+    /// As in `minecraft:the_nether`.
+    pub dimension: String,
+    /// 0 for survival, 1 creative, 2 adventure and 3 spectator.
+    pub game_mode: i32,
+}
+impl PlayerInfo {
+    /// Read the last saved state of a player, by their UUID with dashes.
+    pub fn read(world: &Path, uuid: &str) -> Result<Self, Box<dyn Error>> {
+        let path = world.join("playerdata").join(format!("{}.dat", uuid));
+        let (_, root) = read_file(&path)?;
+        let pos = root
+            .get("Pos")
+            .and_then(Tag::as_list)
+            .and_then(|pos| pos.iter().map(Tag::as_f64).collect::<Option<Vec<_>>>())
+            .filter(|pos| pos.len() == 3)
+            .ok_or("player data has no `Pos`")?;
+        //Dimensions were numbered before 1.16
+        let dimension = match root.get("Dimension") {
+            Some(Tag::String(name)) => name.clone(),
+            Some(tag) => match tag.as_i64() {
+                Some(-1) => "minecraft:the_nether",
+                Some(1) => "minecraft:the_end",
+                _ => "minecraft:overworld",
+            }
+            .to_string(),
+            None => "minecraft:overworld".to_string(),
+        };
+        Ok(Self {
+            uuid: uuid.to_string(),
+            health: root.get("Health").and_then(Tag::as_f64).unwrap_or_default() as f32,
+            pos: [pos[0], pos[1], pos[2]],
+            dimension,
+            game_mode: root
+                .get("playerGameType")
+                .and_then(Tag::as_i64)
+                .unwrap_or_default() as i32,
+        })
+    }
+
+    /// Every player with saved data in a world.
+    pub fn read_all(world: &Path) -> Result<Vec<Self>, Box<dyn Error>> {
+        let mut players = Vec::new();
+        let dir = world.join("playerdata");
+        if !dir.is_dir() {
+            return Ok(players);
+        }
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension() != Some("dat".as_ref()) {
+                continue;
+            }
+            if let Some(uuid) = path.file_stem().and_then(|stem| stem.to_str()) {
+                players.push(Self::read(world, uuid)?);
+            }
+        }
+        players.sort_by(|a, b| a.uuid.cmp(&b.uuid));
+        Ok(players)
+    }
+}
@@ -0,0 +1,91 @@
+//! Posting run events to a Discord webhook and a Telegram chat, so people can follow along outside
+//! the game.
+//!
+//! Posting happens on a background thread, a slow or unreachable Discord never holds up the
+//! server.
+
+use crate::{
+    logging,
+    telegram::{self, TelegramConfig},
+};
+use log::warn;
+use std::sync::mpsc::{self, Sender};
+
+pub const RED: u32 = 0xc0392b;
+pub const GREEN: u32 = 0x27ae60;
+pub const BLUE: u32 = 0x2980b9;
+pub const ORANGE: u32 = 0xe67e22;
+pub const BLACK: u32 = 0x23272a;
+
+/// A single embed to post.
+pub struct Notice {
+    pub title: String,
+    pub description: String,
+    pub color: u32,
+}
+
+pub struct Notifier {
+    tx: Option<Sender<Notice>>,
+}
+impl Notifier {
+    /// Start posting to `webhook` and to Telegram, or do nothing if neither is set.
+    pub fn new(webhook: Option<&str>, telegram: Option<&TelegramConfig>) -> Self {
+        let mut webhook = webhook
+            .filter(|webhook| !webhook.is_empty())
+            .map(str::to_string);
+        if webhook.is_some() && !cfg!(feature = "discord") {
+            warn!("`discord_webhook` needs the `discord` feature, which this build was compiled without");
+            webhook = None;
+        }
+        let telegram = telegram.filter(|conf| conf.chat_id != 0).cloned();
+        if webhook.is_none() && telegram.is_none() {
+            return Self { tx: None };
+        }
+        let (tx, rx) = mpsc::channel::<Notice>();
+        logging::spawn(move || {
+            for notice in rx.iter() {
+                if let Some(webhook) = &webhook {
+                    if let Err(err) = post(webhook, &notice) {
+                        warn!("failed to post \"{}\" to discord: {}", notice.title, err);
+                    }
+                }
+                if let Some(conf) = &telegram {
+                    let text = format!("{}\n{}", notice.title, notice.description);
+                    if let Err(err) = telegram::post(conf, &text) {
+                        warn!("failed to post \"{}\" to telegram: {}", notice.title, err);
+                    }
+                }
+            }
+        });
+        Self { tx: Some(tx) }
+    }
+
+    pub fn notify(&self, title: impl Into<String>, description: impl Into<String>, color: u32) {
+        if let Some(tx) = &self.tx {
+            let _ = tx.send(Notice {
+                title: title.into(),
+                description: description.into(),
+                color,
+            });
+        }
+    }
+}
+
+#[cfg(feature = "discord")]
+fn post(webhook: &str, notice: &Notice) -> Result<(), Box<dyn std::error::Error>> {
+    let body = serde_json::json!({
+        "embeds": [{
+            "title": notice.title,
+            "description": notice.description,
+            "color": notice.color,
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+        }],
+    });
+    ureq::post(webhook).send_json(body)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "discord"))]
+fn post(_webhook: &str, _notice: &Notice) -> Result<(), Box<dyn std::error::Error>> {
+    Ok(())
+}
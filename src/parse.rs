@@ -0,0 +1,220 @@
+//! Helpers for picking apart lines of server output.
+
+use crate::download::Flavor;
+use std::fmt;
+
+const USERNAME_CHARS: &str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ_-0123456789";
+pub fn is_username_char(c: char) -> bool {
+    let mut is_username = [false; 128];
+    for &c in USERNAME_CHARS.as_bytes().iter() {
+        is_username[c as usize] = true;
+    }
+    (c as u32) < 128 && is_username[c as usize]
+}
+
+/// The shape of the prefix on each line of output.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct LinePrefix {
+    /// How many `[...]` groups to strip.
+    pub brackets: u32,
+    /// Whether a `(logger)` group may follow the brackets.
+    pub logger: bool,
+}
+impl LinePrefix {
+    /// The prefix lines from a server flavor start with, or nothing if it has to be learned.
+    pub fn for_flavor(flavor: Flavor) -> Option<LinePrefix> {
+        let (brackets, logger) = match flavor {
+            //`[12:00:00] [Server thread/INFO]: `
+            Flavor::Vanilla => (2, false),
+            //`[12:00:00 INFO]: `
+            Flavor::Paper | Flavor::Spigot => (1, false),
+            //`[12:00:00] [Server thread/INFO] (Minecraft) `
+            Flavor::Fabric => (2, true),
+            //`[12:00:00] [Server thread/INFO] [minecraft/DedicatedServer]: `
+            Flavor::Forge => (3, false),
+            Flavor::Auto => return None,
+        };
+        Some(LinePrefix { brackets, logger })
+    }
+
+    /// Strip the prefix off a line, or nothing if the line is too short to have it.
+    pub fn strip(self, mut line: &str) -> Option<&str> {
+        for _ in 0..self.brackets {
+            let bracket = line.find(']')?;
+            line = &line[bracket + 1..];
+        }
+        if self.logger {
+            if let Some(rest) = line.trim_start().strip_prefix('(') {
+                line = rest.split_once(')')?.1;
+            }
+        }
+        Some(line)
+    }
+
+    /// Learn the prefix from a line carrying one of the messages every server prints on startup.
+    pub fn learn(line: &str) -> Option<LinePrefix> {
+        let start = [
+            "Starting minecraft server version ",
+            "Loading properties",
+            "Preparing level ",
+            "Done (",
+        ]
+        .iter()
+        .find_map(|msg| line.find(msg))?;
+        let prefix = &line[..start];
+        let brackets = prefix.matches(']').count() as u32;
+        let logger = prefix
+            .rsplit(']')
+            .next()
+            .is_some_and(|rest| rest.contains('('));
+        Some(LinePrefix { brackets, logger })
+    }
+}
+
+pub fn bytes_to_string(mut bytes: &[u8]) -> String {
+    while bytes
+        .first()
+        .map(|ch| ch.is_ascii_whitespace())
+        .unwrap_or(false)
+    {
+        bytes = &bytes[1..];
+    }
+    while bytes
+        .last()
+        .map(|ch| ch.is_ascii_whitespace())
+        .unwrap_or(false)
+    {
+        bytes = &bytes[..bytes.len() - 1];
+    }
+    String::from_utf8_lossy(bytes).to_string()
+}
+
+/// Extract the advancement name from an advancement message, eg.
+/// ` has made the advancement [We Need to Go Deeper]`.
+pub fn parse_advancement(msg: &str) -> Option<&str> {
+    let rest = [
+        " has made the advancement [",
+        " has reached the goal [",
+        " has completed the challenge [",
+    ]
+    .iter()
+    .find_map(|prefix| msg.strip_prefix(prefix))?;
+    rest.strip_suffix(']')
+}
+
+/// Parse the line a server prints once it has finished starting, eg.
+/// `Done (3.141s)! For help, type "help"`, into how long it took in seconds.
+pub fn parse_done(line: &str) -> Option<f64> {
+    let rest = line.strip_prefix("Done (")?;
+    let (secs, rest) = rest.split_once("s)!")?;
+    if !rest.trim_start().starts_with("For help") {
+        return None;
+    }
+    secs.parse().ok()
+}
+
+/// Parse the reply to the `seed` command.
+pub fn parse_seed(line: &str) -> Option<String> {
+    let (_, seed) = line.split_once("Seed: [")?;
+    let (seed, _) = seed.split_once(']')?;
+    Some(seed.to_string())
+}
+
+/// Parse the reply to `data get entity <player> Dimension`, without the `minecraft:` namespace.
+pub fn parse_dimension(line: &str) -> Option<String> {
+    let (_, dim) = line.split_once(" has the following entity data: ")?;
+    //Not the reply to some other query, such as a position
+    if dim.starts_with(['{', '[']) {
+        return None;
+    }
+    let dim = dim.trim().trim_matches('"');
+    Some(dim.strip_prefix("minecraft:").unwrap_or(dim).to_string())
+}
+
+/// Where something happened in the world.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Location {
+    /// As in `minecraft:the_nether`.
+    pub dimension: String,
+    pub pos: [i32; 3],
+}
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [x, y, z] = self.pos;
+        let dim = self.dimension.strip_prefix("minecraft:");
+        let dim = dim.unwrap_or(&self.dimension).replace('_', " ");
+        write!(
+            f,
+            "{} {} {} in the {}",
+            x,
+            y,
+            z,
+            dim.trim_start_matches("the ")
+        )
+    }
+}
+
+/// Parse the reply to `data get entity <player> LastDeathLocation`, eg.
+/// `Steve has the following entity data: {dimension: "minecraft:overworld", pos: [I; 10, 64, -5]}`.
+pub fn parse_death_location(line: &str) -> Option<Location> {
+    let (_, data) = line.split_once(" has the following entity data: ")?;
+    let (_, dim) = data.split_once("dimension: \"")?;
+    let (dim, _) = dim.split_once('"')?;
+    let (_, pos) = data.split_once("pos: [I;")?;
+    let (pos, _) = pos.split_once(']')?;
+    let pos = pos
+        .split(',')
+        .map(|n| n.trim().parse().ok())
+        .collect::<Option<Vec<i32>>>()?;
+    match pos[..] {
+        [x, y, z] => Some(Location {
+            dimension: dim.to_string(),
+            pos: [x, y, z],
+        }),
+        _ => None,
+    }
+}
+
+/// Parse the reply to `worldborder get`, eg.
+/// `The world border is currently 20000 block(s) wide`, into the border diameter.
+pub fn parse_border(line: &str) -> Option<f64> {
+    let (_, size) = line.split_once("The world border is currently ")?;
+    let (size, _) = size.split_once(" block")?;
+    size.trim().parse().ok()
+}
+
+/// Parse the reply to the `time query day` command.
+pub fn parse_day(line: &str) -> Option<u64> {
+    let (_, day) = line.split_once("The time is ")?;
+    day.trim().parse().ok()
+}
+
+/// Parse the line a server logs as a player logs in, eg.
+/// `UUID of player Steve is 8667ba71-b85a-4004-af54-457a9734eed7`, into the name and UUID.
+pub fn parse_uuid(line: &str) -> Option<(&str, &str)> {
+    let rest = line.strip_prefix("UUID of player ")?;
+    let (name, uuid) = rest.trim_end().split_once(" is ")?;
+    let valid = !name.is_empty()
+        && name.chars().all(is_username_char)
+        && uuid.len() == 36
+        && uuid.chars().all(|c| c == '-' || c.is_ascii_hexdigit());
+    if valid {
+        Some((name, uuid))
+    } else {
+        None
+    }
+}
+
+/// Parse the reply to the `list` command, eg.
+/// `There are 2 of a max of 20 players online: Steve, Alex`, into the names of the players online.
+pub fn parse_list(line: &str) -> Option<Vec<String>> {
+    let (_, names) = line.split_once(" players online:")?;
+    Some(
+        names
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(str::to_string)
+            .collect(),
+    )
+}
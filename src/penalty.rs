@@ -0,0 +1,471 @@
+//! Deciding what a death costs.
+
+use rand::Rng;
+use regex::Regex;
+use serde_derive::Deserialize;
+use std::{collections::BTreeMap, convert::TryFrom, fmt, time::Duration};
+
+#[derive(Debug)]
+pub enum Penalty {
+    None,
+    Rewind,
+    Reset,
+}
+
+/// What a roll leads to, as written in `roll_outcomes`: `"none"`, `"rewind"`, `"reset"`,
+/// `"border"`, `"spectator"`, `"ban"` or `"command:<command>"`.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(try_from = "String")]
+pub enum Outcome {
+    None,
+    Rewind,
+    Reset,
+    /// Shrink the world border by a step of `border_penalty`.
+    Border,
+    /// Take the players who died out of the game for a while, as `spectator_penalty` says.
+    Spectator,
+    /// Ban the players who died for `ban_minutes`.
+    Ban,
+    /// Run a server command, with `{username}` replaced by the player who died.
+    Command(String),
+}
+impl TryFrom<String> for Outcome {
+    type Error = String;
+    fn try_from(s: String) -> Result<Self, String> {
+        Ok(match s.as_str() {
+            "none" => Outcome::None,
+            "rewind" => Outcome::Rewind,
+            "reset" => Outcome::Reset,
+            "border" => Outcome::Border,
+            "spectator" => Outcome::Spectator,
+            "ban" => Outcome::Ban,
+            _ => match s.strip_prefix("command:") {
+                Some(cmd) if !cmd.trim().is_empty() => Outcome::Command(cmd.trim().to_string()),
+                _ => {
+                    return Err(format!(
+                    "invalid roll outcome \"{}\", expected none, rewind, reset, border, spectator, ban or command:<...>",
+                    s
+                ))
+                }
+            },
+        })
+    }
+}
+
+impl Outcome {
+    /// Short name of the outcome, as written in `roll_outcomes` without the command.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Outcome::None => "none",
+            Outcome::Rewind => "rewind",
+            Outcome::Reset => "reset",
+            Outcome::Border => "border",
+            Outcome::Spectator => "spectator",
+            Outcome::Ban => "ban",
+            Outcome::Command(_) => "command",
+        }
+    }
+}
+
+/// What a death rule does, as written in `death_rules`: `"ignore"`, `"roll"`, or an outcome to
+/// apply without rolling.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(try_from = "String")]
+pub enum RuleAction {
+    /// Treat the death as if it never happened.
+    Ignore,
+    /// Roll as usual, with the rule's own rolls if it has any.
+    Roll,
+    Always(Outcome),
+}
+impl TryFrom<String> for RuleAction {
+    type Error = String;
+    fn try_from(s: String) -> Result<Self, String> {
+        Ok(match s.as_str() {
+            "ignore" => RuleAction::Ignore,
+            "roll" => RuleAction::Roll,
+            _ => RuleAction::Always(Outcome::try_from(s).map_err(|_| {
+                "invalid death rule action, expected ignore, roll, none, rewind, reset, border, spectator, ban or command:<...>"
+                    .to_string()
+            })?),
+        })
+    }
+}
+
+/// Special handling for deaths whose message matches a pattern.
+#[derive(Deserialize)]
+#[serde(try_from = "RawDeathRule")]
+pub struct DeathRule {
+    /// Matched anywhere in the death message, after the player name.
+    pub pattern: Regex,
+    /// Only deaths in this dimension, without the `minecraft:` namespace.
+    pub dimension: Option<String>,
+    /// Only deaths whose message names another player.
+    pub pvp: bool,
+    pub action: RuleAction,
+    /// Rolls that reset the world when rolling for this rule, instead of the global ones.
+    pub deadly_rolls: Vec<i32>,
+    /// What each roll leads to when rolling for this rule, instead of the global outcomes.
+    pub roll_outcomes: BTreeMap<String, Outcome>,
+}
+impl DeathRule {
+    /// Whether the rule rolls with its own outcomes rather than the global ones.
+    pub fn has_own_rolls(&self) -> bool {
+        !self.deadly_rolls.is_empty() || !self.roll_outcomes.is_empty()
+    }
+}
+
+/// Roll settings for a single player, as written in `player_rolls`. Whatever is left out follows
+/// the global settings.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct PlayerRolls {
+    pub roll_range: Option<(i32, i32)>,
+    pub deadly_rolls: Option<Vec<i32>>,
+}
+
+/// A step up in the odds, as written in `escalation`, which applies once every threshold it gives
+/// is reached.
+#[derive(Deserialize)]
+pub struct Escalation {
+    #[serde(default)]
+    pub after_hours: Option<f64>,
+    /// Deaths this season, counting the ones undone by rewinds.
+    #[serde(default)]
+    pub after_deaths: Option<u32>,
+    /// More rolls that reset the world from here on, unless `roll_outcomes` says otherwise.
+    #[serde(default)]
+    pub deadly_rolls: Vec<i32>,
+    /// Roll range from here on.
+    #[serde(default)]
+    pub roll_range: Option<(i32, i32)>,
+    /// Said when the step is reached, with `{deadly_rolls}` replaced by the new deadly rolls.
+    #[serde(default)]
+    pub message: Option<String>,
+}
+impl Escalation {
+    pub fn reached(&self, playtime: Duration, deaths: u32) -> bool {
+        self.after_hours
+            .is_none_or(|hours| playtime.as_secs_f64() >= hours * 3600.0)
+            && self.after_deaths.is_none_or(|after| deaths >= after)
+    }
+}
+
+#[derive(Deserialize)]
+struct RawDeathRule {
+    pattern: String,
+    #[serde(default)]
+    dimension: Option<String>,
+    #[serde(default)]
+    pvp: bool,
+    action: RuleAction,
+    #[serde(default)]
+    deadly_rolls: Vec<i32>,
+    #[serde(default)]
+    roll_outcomes: BTreeMap<String, Outcome>,
+}
+impl TryFrom<RawDeathRule> for DeathRule {
+    type Error = String;
+    fn try_from(raw: RawDeathRule) -> Result<Self, String> {
+        let pattern = Regex::new(&raw.pattern)
+            .map_err(|err| format!("invalid death rule pattern \"{}\": {}", raw.pattern, err))?;
+        if let Some(key) = raw
+            .roll_outcomes
+            .keys()
+            .find(|key| parse_rolls(key).is_none_or(|(start, end)| start > end))
+        {
+            return Err(format!("invalid roll or roll range \"{}\"", key));
+        }
+        Ok(DeathRule {
+            pattern,
+            dimension: raw
+                .dimension
+                .map(|dim| dim.trim_start_matches("minecraft:").to_string()),
+            pvp: raw.pvp,
+            action: raw.action,
+            deadly_rolls: raw.deadly_rolls,
+            roll_outcomes: raw.roll_outcomes,
+        })
+    }
+}
+
+/// Parse a `roll_outcomes` key, either a single roll such as `"4"` or an inclusive range such as
+/// `"2-3"`.
+pub fn parse_rolls(key: &str) -> Option<(i32, i32)> {
+    let key = key.trim();
+    //Skip the first character so that negative single rolls parse
+    match key.get(1..)?.find('-') {
+        Some(dash) => {
+            let (start, end) = key.split_at(dash + 1);
+            Some((start.trim().parse().ok()?, end[1..].trim().parse().ok()?))
+        }
+        None => {
+            let num = key.parse().ok()?;
+            Some((num, num))
+        }
+    }
+}
+
+/// The most sides a die may have, and the furthest a modifier may shift a roll, so that totals
+/// always fit in an `i32`.
+pub const MAX_SIDES: u32 = 1_000_000;
+
+/// Dice in `NdM+K` notation, eg. `"2d6+1"` for two six-sided dice plus one.
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(try_from = "String")]
+pub struct Dice {
+    pub count: u32,
+    pub sides: u32,
+    pub modifier: i32,
+}
+impl fmt::Display for Dice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}d{}{:+}", self.count, self.sides, self.modifier)
+    }
+}
+impl TryFrom<String> for Dice {
+    type Error = String;
+    fn try_from(s: String) -> Result<Self, String> {
+        let invalid = || format!("invalid dice \"{}\", expected eg. \"d20\" or \"2d6+1\"", s);
+        let (count, rest) = s.trim().split_once('d').ok_or_else(invalid)?;
+        let (sides, modifier) = match rest.find(['+', '-']) {
+            Some(idx) => rest.split_at(idx),
+            None => (rest, "+0"),
+        };
+        let count = match count.trim() {
+            "" => 1,
+            count => count.parse().map_err(|_| invalid())?,
+        };
+        let sides = sides.trim().parse().map_err(|_| invalid())?;
+        let modifier: i32 = modifier
+            .trim_start_matches('+')
+            .trim()
+            .parse()
+            .map_err(|_| invalid())?;
+        if count == 0 || count > 100 || sides == 0 || sides > MAX_SIDES {
+            return Err(format!(
+                "invalid dice \"{}\", expected 1 to 100 dice with 1 to {} sides",
+                s, MAX_SIDES
+            ));
+        }
+        if modifier.unsigned_abs() > MAX_SIDES {
+            return Err(format!(
+                "invalid dice \"{}\", the modifier must be within {}",
+                s, MAX_SIDES
+            ));
+        }
+        Ok(Dice {
+            count,
+            sides,
+            modifier,
+        })
+    }
+}
+impl Dice {
+    /// Whether an inclusive range can be rolled with `from_range`: in order, within `MAX_SIDES`
+    /// of zero and with at most `MAX_SIDES` numbers.
+    pub fn range_ok((start, end): (i32, i32)) -> bool {
+        start <= end
+            && start.unsigned_abs() <= MAX_SIDES
+            && end.unsigned_abs() <= MAX_SIDES
+            && end - start < MAX_SIDES as i32
+    }
+
+    /// A single die covering an inclusive range, as given by `roll_range`.
+    pub fn from_range((start, end): (i32, i32)) -> Self {
+        Dice {
+            count: 1,
+            sides: (end - start + 1).max(1) as u32,
+            modifier: start - 1,
+        }
+    }
+
+    /// Lowest and highest possible totals.
+    pub fn range(&self) -> (i32, i32) {
+        (
+            self.count as i32 + self.modifier,
+            (self.count * self.sides) as i32 + self.modifier,
+        )
+    }
+}
+
+/// The outcome of a death roll.
+pub struct Roll {
+    pub num: i32,
+    /// Each die that made up the roll, before the modifier.
+    pub dice: Vec<i32>,
+    pub modifier: i32,
+    /// Whether the roll costs the world progress.
+    pub deadly: bool,
+    pub outcome: Outcome,
+}
+
+impl Roll {
+    /// An outcome decided without rolling, such as by a death rule.
+    pub fn forced(outcome: Outcome) -> Self {
+        Roll {
+            num: 0,
+            dice: Vec::new(),
+            modifier: 0,
+            deadly: matches!(outcome, Outcome::Rewind | Outcome::Reset),
+            outcome,
+        }
+    }
+
+    /// How the roll came to be, eg. `"3 + 5 + 1"`, or nothing for a single plain die.
+    pub fn breakdown(&self) -> Option<String> {
+        if self.dice.len() < 2 && self.modifier == 0 {
+            return None;
+        }
+        let mut desc = self
+            .dice
+            .iter()
+            .map(i32::to_string)
+            .collect::<Vec<_>>()
+            .join(" + ");
+        match self.modifier {
+            0 => {}
+            m if m > 0 => desc += &format!(" + {}", m),
+            m => desc += &format!(" - {}", -m),
+        }
+        Some(desc)
+    }
+}
+
+/// Rolls the dice for deaths and decides the penalty.
+pub struct PenaltyEngine {
+    dice: Dice,
+    /// Relative weights of totals, by roll range. Totals missing from it weigh 1.
+    weights: Vec<((i32, i32), f64)>,
+    /// Outcomes by roll range, checked in order. Rolls matching none of them are safe.
+    outcomes: Vec<((i32, i32), Outcome)>,
+}
+impl PenaltyEngine {
+    /// Set up the engine from `roll_outcomes`, or from the older `deadly_rolls` list if there are
+    /// no outcomes configured.
+    pub fn new(
+        dice: Dice,
+        roll_weights: &BTreeMap<String, f64>,
+        deadly_rolls: &[i32],
+        roll_outcomes: &BTreeMap<String, Outcome>,
+    ) -> Self {
+        let weights = roll_weights
+            .iter()
+            .filter_map(|(key, &weight)| Some((parse_rolls(key)?, weight)))
+            .collect();
+        let outcomes = if roll_outcomes.is_empty() {
+            deadly_rolls
+                .iter()
+                .map(|&num| ((num, num), Outcome::Reset))
+                .collect()
+        } else {
+            roll_outcomes
+                .iter()
+                .filter_map(|(key, outcome)| Some((parse_rolls(key)?, outcome.clone())))
+                .collect()
+        };
+        Self {
+            dice,
+            weights,
+            outcomes,
+        }
+    }
+
+    pub fn dice(&self) -> Dice {
+        self.dice
+    }
+
+    /// The rolls that cost the world progress, eg. `"1, 4, 7-9"`.
+    pub fn deadly_rolls(&self) -> String {
+        let mut deadly: Vec<_> = self
+            .outcomes
+            .iter()
+            .filter(|(_, outcome)| matches!(outcome, Outcome::Rewind | Outcome::Reset))
+            .map(|&(range, _)| range)
+            .collect();
+        deadly.sort_unstable();
+        deadly
+            .into_iter()
+            .map(|(start, end)| match start == end {
+                true => start.to_string(),
+                false => format!("{}-{}", start, end),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Relative weight of a total.
+    pub fn weight(&self, num: i32) -> f64 {
+        self.weights
+            .iter()
+            .find(|((start, end), _)| (*start..=*end).contains(&num))
+            .map_or(1.0, |&(_, weight)| weight)
+    }
+
+    /// Roll the dice, without any consequences.
+    ///
+    /// Weights are applied by rerolling: a total is kept with a chance proportional to its
+    /// weight, so each die shown is still a fair roll.
+    pub fn roll(&self) -> Roll {
+        self.roll_with(&mut rand::thread_rng())
+    }
+
+    /// Roll with the given source of randomness, which gives the same roll for the same numbers.
+    pub fn roll_with(&self, rng: &mut impl Rng) -> Roll {
+        let max_weight = self
+            .weights
+            .iter()
+            .map(|&(_, weight)| weight)
+            .fold(1.0, f64::max);
+        let (dice, num) = loop {
+            let dice: Vec<i32> = (0..self.dice.count)
+                .map(|_| rng.gen_range(1, self.dice.sides as i32 + 1))
+                .collect();
+            let num = dice.iter().sum::<i32>() + self.dice.modifier;
+            if rng.gen::<f64>() * max_weight < self.weight(num) {
+                break (dice, num);
+            }
+        };
+        let outcome = self.outcome_of(num);
+        Roll {
+            num,
+            dice,
+            modifier: self.dice.modifier,
+            deadly: matches!(outcome, Outcome::Rewind | Outcome::Reset),
+            outcome,
+        }
+    }
+
+    /// The roll that came out as `num`, such as one picked by a script.
+    pub fn roll_of(&self, num: i32) -> Roll {
+        let outcome = self.outcome_of(num);
+        Roll {
+            num,
+            dice: vec![num - self.dice.modifier],
+            modifier: self.dice.modifier,
+            deadly: matches!(outcome, Outcome::Rewind | Outcome::Reset),
+            outcome,
+        }
+    }
+
+    fn outcome_of(&self, num: i32) -> Outcome {
+        self.outcomes
+            .iter()
+            .find(|((start, end), _)| (*start..=*end).contains(&num))
+            .map(|(_, outcome)| outcome.clone())
+            .unwrap_or(Outcome::None)
+    }
+
+    /// The penalty for a death with the given roll.
+    pub fn penalty(&self, roll: &Roll) -> Penalty {
+        match roll.outcome {
+            Outcome::Rewind => Penalty::Rewind,
+            Outcome::Reset => Penalty::Reset,
+            Outcome::None
+            | Outcome::Border
+            | Outcome::Spectator
+            | Outcome::Ban
+            | Outcome::Command(_) => Penalty::None,
+        }
+    }
+}
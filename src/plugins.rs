@@ -0,0 +1,332 @@
+//! WASM plugins, set with `plugins`, for extensions heavier than a script.
+//!
+//! Plugins run sandboxed: they get no WASI, so no files, network or clock, only the host functions
+//! below, and each call has a fuel budget so that a runaway plugin cannot hang the wrapper.
+//!
+//! A plugin is a core WASM module, binary or text, that exports its `memory` and any of:
+//!
+//! - `init()`, called once the server is up.
+//! - `on_event(ptr: i32, len: i32)`, called for each of the events that `hooks` can be attached
+//!   to, with the event as a JSON object with its name under `event`, written into memory given
+//!   by `alloc`.
+//! - `alloc(len: i32) -> i32`, required along with `on_event`.
+//! - `on_timer(id: i32)`, called when a timer set with `set_timer` goes off.
+//!
+//! It may import these from the `trust_hardcore` module, with strings as UTF-8 in its memory:
+//!
+//! - `command(ptr: i32, len: i32)`, to send a console command.
+//! - `log(ptr: i32, len: i32)`, to write to the log.
+//! - `playtime_secs() -> i64`.
+//! - `online_players(ptr: i32, cap: i32) -> i32`, to write the players online, one per line, and
+//!   return how long the list is. Nothing is written if it does not fit in `cap` bytes.
+//! - `set_timer(id: i32, delay_ms: i32)`, to have `on_timer(id)` called later.
+
+use crate::events::Event;
+use serde_json::Value;
+use std::{path::PathBuf, sync::mpsc::Sender};
+
+pub use imp::Plugins;
+
+/// What plugins can read about the run.
+pub struct Snapshot {
+    pub playtime_secs: u64,
+    pub online_players: Vec<String>,
+}
+
+#[cfg(feature = "plugins")]
+mod imp {
+    use super::{Event, PathBuf, Sender, Snapshot, Value};
+    use crate::logging;
+    use log::{info, warn};
+    use std::{
+        error::Error,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread,
+        time::Duration,
+    };
+    use wasmtime::{
+        Caller, Config, Engine, Instance, Linker, Module, Store, StoreLimits, StoreLimitsBuilder,
+    };
+
+    /// Fuel for a single call, roughly one unit per instruction.
+    const FUEL: u64 = 10_000_000;
+    /// How much memory a plugin may grow to.
+    const MAX_MEMORY: usize = 64 << 20;
+
+    struct Host {
+        name: String,
+        playtime_secs: u64,
+        online_players: Vec<String>,
+        /// Console commands sent during the current call.
+        commands: Vec<String>,
+        /// Timers set during the current call, as id and delay.
+        timers: Vec<(i32, Duration)>,
+        limits: StoreLimits,
+    }
+
+    /// What a call left for the host: console commands, and timers as id and delay.
+    type Left = (Vec<String>, Vec<(i32, Duration)>);
+
+    struct Plugin {
+        store: Store<Host>,
+        instance: Instance,
+    }
+
+    /// The loaded plugins, whose timers stop once dropped.
+    pub struct Plugins {
+        plugins: Vec<Plugin>,
+        events: Sender<Event>,
+        alive: Arc<AtomicBool>,
+    }
+    impl Plugins {
+        pub fn load(paths: &[PathBuf], events: Sender<Event>) -> Result<Self, Box<dyn Error>> {
+            let mut plugins = Vec::new();
+            if !paths.is_empty() {
+                let mut config = Config::new();
+                config.consume_fuel(true);
+                let engine = Engine::new(&config)?;
+                let linker = linker(&engine)?;
+                for path in paths {
+                    let name = path
+                        .file_stem()
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_default();
+                    let load = || -> wasmtime::Result<Plugin> {
+                        let module = Module::from_file(&engine, path)?;
+                        let host = Host {
+                            name: name.clone(),
+                            playtime_secs: 0,
+                            online_players: Vec::new(),
+                            commands: Vec::new(),
+                            timers: Vec::new(),
+                            limits: StoreLimitsBuilder::new().memory_size(MAX_MEMORY).build(),
+                        };
+                        let mut store = Store::new(&engine, host);
+                        store.limiter(|host| &mut host.limits);
+                        store.set_fuel(FUEL)?;
+                        let instance = linker.instantiate(&mut store, &module)?;
+                        Ok(Plugin { store, instance })
+                    };
+                    let plugin = load()
+                        .map_err(|err| format!("plugin \"{}\": {:#}", path.display(), err))?;
+                    info!("loaded plugin \"{}\"", name);
+                    plugins.push(plugin);
+                }
+            }
+            Ok(Self {
+                plugins,
+                events,
+                alive: Arc::new(AtomicBool::new(true)),
+            })
+        }
+
+        pub fn init(&mut self, snapshot: &Snapshot) -> Vec<String> {
+            self.call_all(snapshot, |plugin| {
+                match plugin.instance.get_func(&mut plugin.store, "init") {
+                    Some(func) => func
+                        .typed::<(), ()>(&plugin.store)?
+                        .call(&mut plugin.store, ()),
+                    None => Ok(()),
+                }
+            })
+        }
+
+        pub fn on_event(&mut self, snapshot: &Snapshot, event: &str, data: &Value) -> Vec<String> {
+            let mut body = data.clone();
+            body["event"] = event.into();
+            let body = body.to_string();
+            self.call_all(snapshot, |plugin| {
+                let on_event = match plugin.instance.get_func(&mut plugin.store, "on_event") {
+                    Some(func) => func.typed::<(i32, i32), ()>(&plugin.store)?,
+                    None => return Ok(()),
+                };
+                let alloc = plugin
+                    .instance
+                    .get_typed_func::<i32, i32>(&mut plugin.store, "alloc")?;
+                let len = body.len() as i32;
+                let ptr = alloc.call(&mut plugin.store, len)?;
+                memory(&plugin.instance, &mut plugin.store)?.write(
+                    &mut plugin.store,
+                    ptr as u32 as usize,
+                    body.as_bytes(),
+                )?;
+                on_event.call(&mut plugin.store, (ptr, len))
+            })
+        }
+
+        pub fn on_timer(&mut self, snapshot: &Snapshot, idx: usize, id: i32) -> Vec<String> {
+            let plugin = match self.plugins.get_mut(idx) {
+                Some(plugin) => plugin,
+                None => return Vec::new(),
+            };
+            let res = call(plugin, snapshot, |plugin| {
+                plugin
+                    .instance
+                    .get_typed_func::<i32, ()>(&mut plugin.store, "on_timer")?
+                    .call(&mut plugin.store, id)
+            });
+            self.finish(idx, res)
+        }
+
+        /// Call every plugin, collecting the commands they sent.
+        fn call_all(
+            &mut self,
+            snapshot: &Snapshot,
+            mut f: impl FnMut(&mut Plugin) -> wasmtime::Result<()>,
+        ) -> Vec<String> {
+            let mut commands = Vec::new();
+            for idx in 0..self.plugins.len() {
+                let res = call(&mut self.plugins[idx], snapshot, &mut f);
+                commands.extend(self.finish(idx, res));
+            }
+            commands
+        }
+
+        /// Start the timers a call set, and pass on its commands.
+        fn finish(&self, plugin: usize, (commands, timers): Left) -> Vec<String> {
+            for (id, delay) in timers {
+                let events = self.events.clone();
+                let alive = self.alive.clone();
+                logging::spawn(move || {
+                    thread::sleep(delay);
+                    if alive.load(Ordering::SeqCst) {
+                        let _ = events.send(Event::PluginTimer { plugin, id });
+                    }
+                });
+            }
+            commands
+        }
+    }
+    impl Drop for Plugins {
+        fn drop(&mut self) {
+            self.alive.store(false, Ordering::SeqCst);
+        }
+    }
+
+    /// Run `f` on a plugin with a fresh fuel budget, and take what it left for the host.
+    fn call(
+        plugin: &mut Plugin,
+        snapshot: &Snapshot,
+        f: impl FnOnce(&mut Plugin) -> wasmtime::Result<()>,
+    ) -> Left {
+        let host = plugin.store.data_mut();
+        host.playtime_secs = snapshot.playtime_secs;
+        host.online_players = snapshot.online_players.clone();
+        let res = plugin.store.set_fuel(FUEL).and_then(|()| f(plugin));
+        let host = plugin.store.data_mut();
+        if let Err(err) = res {
+            warn!("plugin \"{}\" failed: {:#}", host.name, err);
+        }
+        (
+            std::mem::take(&mut host.commands),
+            std::mem::take(&mut host.timers),
+        )
+    }
+
+    fn memory(instance: &Instance, store: &mut Store<Host>) -> wasmtime::Result<wasmtime::Memory> {
+        instance
+            .get_memory(store, "memory")
+            .ok_or_else(|| wasmtime::Error::msg("the plugin exports no memory"))
+    }
+
+    /// Read a string out of the calling plugin's memory.
+    fn read_str(caller: &mut Caller<'_, Host>, ptr: i32, len: i32) -> wasmtime::Result<String> {
+        let memory = caller
+            .get_export("memory")
+            .and_then(|export| export.into_memory())
+            .ok_or_else(|| wasmtime::Error::msg("the plugin exports no memory"))?;
+        let mut buf = vec![0; len as u32 as usize];
+        memory.read(&caller, ptr as u32 as usize, &mut buf)?;
+        Ok(String::from_utf8(buf)?)
+    }
+
+    fn linker(engine: &Engine) -> wasmtime::Result<Linker<Host>> {
+        let mut linker = Linker::new(engine);
+        linker.func_wrap(
+            "trust_hardcore",
+            "command",
+            |mut caller: Caller<'_, Host>, ptr: i32, len: i32| {
+                let cmd = read_str(&mut caller, ptr, len)?;
+                caller.data_mut().commands.push(cmd);
+                Ok(())
+            },
+        )?;
+        linker.func_wrap(
+            "trust_hardcore",
+            "log",
+            |mut caller: Caller<'_, Host>, ptr: i32, len: i32| {
+                let text = read_str(&mut caller, ptr, len)?;
+                info!("plugin \"{}\": {}", caller.data().name, text);
+                Ok(())
+            },
+        )?;
+        linker.func_wrap(
+            "trust_hardcore",
+            "playtime_secs",
+            |caller: Caller<'_, Host>| caller.data().playtime_secs as i64,
+        )?;
+        linker.func_wrap(
+            "trust_hardcore",
+            "online_players",
+            |mut caller: Caller<'_, Host>, ptr: i32, cap: i32| {
+                let list = caller.data().online_players.join("\n");
+                if list.len() <= cap as u32 as usize {
+                    let memory = caller
+                        .get_export("memory")
+                        .and_then(|export| export.into_memory())
+                        .ok_or_else(|| wasmtime::Error::msg("the plugin exports no memory"))?;
+                    memory.write(&mut caller, ptr as u32 as usize, list.as_bytes())?;
+                }
+                Ok(list.len() as i32)
+            },
+        )?;
+        linker.func_wrap(
+            "trust_hardcore",
+            "set_timer",
+            |mut caller: Caller<'_, Host>, id: i32, delay_ms: i32| {
+                let delay = Duration::from_millis(delay_ms.max(0) as u64);
+                caller.data_mut().timers.push((id, delay));
+            },
+        )?;
+        Ok(linker)
+    }
+}
+
+#[cfg(not(feature = "plugins"))]
+mod imp {
+    use super::{Event, PathBuf, Sender, Snapshot, Value};
+    use std::error::Error;
+
+    pub struct Plugins;
+    impl Plugins {
+        pub fn load(paths: &[PathBuf], _events: Sender<Event>) -> Result<Self, Box<dyn Error>> {
+            if !paths.is_empty() {
+                return Err(
+                    "`plugins` needs the `plugins` feature, which this build was compiled without"
+                        .into(),
+                );
+            }
+            Ok(Self)
+        }
+
+        pub fn init(&mut self, _snapshot: &Snapshot) -> Vec<String> {
+            Vec::new()
+        }
+
+        pub fn on_event(
+            &mut self,
+            _snapshot: &Snapshot,
+            _event: &str,
+            _data: &Value,
+        ) -> Vec<String> {
+            Vec::new()
+        }
+
+        pub fn on_timer(&mut self, _snapshot: &Snapshot, _plugin: usize, _id: i32) -> Vec<String> {
+            Vec::new()
+        }
+    }
+}
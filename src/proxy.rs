@@ -0,0 +1,52 @@
+//! Moving players to a limbo server on a Velocity or BungeeCord proxy while the world is rewound or
+//! reset, and back once it is up again, so they wait on the proxy instead of being disconnected.
+//!
+//! Neither proxy has a remote console of its own, so commands go over RCON through a proxy plugin
+//! such as velocity-rcon or BungeeRcon.
+
+use crate::rcon::{RconClient, RconConfig};
+use serde_derive::Deserialize;
+use std::error::Error;
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct ProxyConfig {
+    /// RCON of the proxy, not of this server.
+    pub rcon: RconConfig,
+    /// Server on the proxy to park players on.
+    pub limbo: String,
+    /// This server's name on the proxy.
+    pub server: String,
+    /// Proxy command that moves a player, with `{player}` and `{server}` filled in.
+    pub send_command: String,
+}
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        Self {
+            rcon: RconConfig::default(),
+            limbo: "limbo".to_string(),
+            server: String::new(),
+            send_command: "send {player} {server}".to_string(),
+        }
+    }
+}
+
+/// Move `players` to `server` on the proxy, trying every player even if some fail.
+pub fn send(conf: &ProxyConfig, players: &[String], server: &str) -> Result<(), Box<dyn Error>> {
+    let rcon = &conf.rcon;
+    let mut client = RconClient::connect(&rcon.host, rcon.port, &rcon.password)?;
+    let mut failed = Vec::new();
+    for player in players {
+        let cmd = conf
+            .send_command
+            .replace("{player}", player)
+            .replace("{server}", server);
+        if let Err(err) = client.command(&cmd) {
+            failed.push(format!("{} ({})", player, err));
+        }
+    }
+    if !failed.is_empty() {
+        return Err(format!("failed to move {}", failed.join(", ")).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,166 @@
+//! Sending console commands over the Source RCON protocol, which Minecraft servers speak when
+//! `enable-rcon` is set in `server.properties`.
+
+use crate::{
+    console_log::{self, SharedConsoleLog},
+    events::Event,
+    logging,
+};
+use log::warn;
+use serde_derive::Deserialize;
+use std::{
+    error::Error,
+    io::{prelude::*, ErrorKind},
+    net::TcpStream,
+    sync::mpsc::{self, Sender},
+    thread,
+    time::{Duration, Instant},
+};
+
+const LOGIN: i32 = 3;
+const COMMAND: i32 = 2;
+/// Largest response payload the server sends in a single packet.
+const MAX_PACKET: i32 = 4096 + 10;
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct RconConfig {
+    pub host: String,
+    pub port: u16,
+    pub password: String,
+    /// How long to keep trying to connect while the server starts up.
+    pub connect_timeout_secs: u64,
+}
+impl Default for RconConfig {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port: 25575,
+            password: String::new(),
+            connect_timeout_secs: 120,
+        }
+    }
+}
+
+pub struct RconClient {
+    stream: TcpStream,
+    next_id: i32,
+}
+impl RconClient {
+    /// Connect to the server and log in.
+    pub fn connect(host: &str, port: u16, password: &str) -> Result<Self, Box<dyn Error>> {
+        let stream = TcpStream::connect((host, port))?;
+        stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+        let mut client = Self { stream, next_id: 1 };
+        let id = client.send(LOGIN, password)?;
+        let (reply_id, _) = client.recv()?;
+        if reply_id == -1 || reply_id != id {
+            return Err("rcon login failed, check `rcon.password`".into());
+        }
+        Ok(client)
+    }
+
+    /// Run a console command, returning the server's reply.
+    pub fn command(&mut self, cmd: &str) -> Result<String, Box<dyn Error>> {
+        let id = self.send(COMMAND, cmd)?;
+        let (reply_id, body) = self.recv()?;
+        if reply_id != id {
+            return Err(format!("rcon reply to the wrong request ({} != {})", reply_id, id).into());
+        }
+        Ok(body)
+    }
+
+    fn send(&mut self, kind: i32, body: &str) -> Result<i32, Box<dyn Error>> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let mut packet = Vec::with_capacity(14 + body.len());
+        packet.extend_from_slice(&(10 + body.len() as i32).to_le_bytes());
+        packet.extend_from_slice(&id.to_le_bytes());
+        packet.extend_from_slice(&kind.to_le_bytes());
+        packet.extend_from_slice(body.as_bytes());
+        packet.extend_from_slice(&[0, 0]);
+        self.stream.write_all(&packet)?;
+        Ok(id)
+    }
+
+    fn recv(&mut self) -> Result<(i32, String), Box<dyn Error>> {
+        let mut int = [0; 4];
+        self.stream.read_exact(&mut int)?;
+        let len = i32::from_le_bytes(int);
+        if !(10..=MAX_PACKET).contains(&len) {
+            return Err(format!("invalid rcon packet length {}", len).into());
+        }
+        let mut rest = vec![0; len as usize];
+        self.stream.read_exact(&mut rest)?;
+        let id = i32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]);
+        let body = String::from_utf8_lossy(&rest[8..rest.len() - 2]).into_owned();
+        Ok((id, body))
+    }
+}
+
+/// Start a thread that sends the commands it receives over RCON, connecting once the server is
+/// up and reconnecting if the connection drops.
+///
+/// Replies are passed on to `events` as server output, so they can be waited for just like
+/// replies on the console.
+pub fn spawn_sender(
+    conf: RconConfig,
+    log: Option<SharedConsoleLog>,
+    events: Sender<Event>,
+) -> Sender<String> {
+    let (tx, rx) = mpsc::channel::<String>();
+    logging::spawn(move || {
+        let mut client: Option<RconClient> = None;
+        for cmd in rx.iter() {
+            console_log::log(&log, &format!("> {}", cmd));
+            //Commands are retried once on a fresh connection
+            for _ in 0..2 {
+                let conn = match client.as_mut() {
+                    Some(conn) => conn,
+                    None => match connect_with_retries(&conf) {
+                        Ok(conn) => client.insert(conn),
+                        Err(err) => {
+                            warn!("failed to connect over rcon: {}", err);
+                            break;
+                        }
+                    },
+                };
+                match conn.command(&cmd) {
+                    Ok(reply) => {
+                        for line in reply.lines().filter(|line| !line.trim().is_empty()) {
+                            logging::output(line);
+                            console_log::log(&log, line);
+                            let _ = events.send(Event::Output(line.to_string()));
+                        }
+                        break;
+                    }
+                    Err(err) => {
+                        warn!("failed to send command over rcon: {}", err);
+                        client = None;
+                    }
+                }
+            }
+        }
+    });
+    tx
+}
+
+fn connect_with_retries(conf: &RconConfig) -> Result<RconClient, Box<dyn Error>> {
+    let deadline = Instant::now() + Duration::from_secs(conf.connect_timeout_secs);
+    loop {
+        match RconClient::connect(&conf.host, conf.port, &conf.password) {
+            Ok(client) => return Ok(client),
+            Err(err) => {
+                //The server refuses connections until it is done starting up
+                let refused = err
+                    .downcast_ref::<std::io::Error>()
+                    .map(|err| err.kind() == ErrorKind::ConnectionRefused)
+                    .unwrap_or(false);
+                if !refused || Instant::now() >= deadline {
+                    return Err(err);
+                }
+                thread::sleep(Duration::from_secs(1));
+            }
+        }
+    }
+}
@@ -0,0 +1,73 @@
+//! Ring buffer of the most recent server output, dumped to disk when something goes wrong.
+
+use log::{info, warn};
+use std::{
+    collections::VecDeque,
+    error::Error,
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub struct RecentLines {
+    lines: VecDeque<String>,
+    capacity: usize,
+}
+impl RecentLines {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            lines: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.lines.len() > capacity {
+            self.lines.pop_front();
+        }
+    }
+
+    pub fn push(&mut self, line: &str) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.lines.len() >= self.capacity {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line.to_string());
+    }
+
+    /// Write the buffered lines to a new file in `dir` and to stderr.
+    ///
+    /// Failing to write the file is reported but otherwise ignored, as dumps happen on paths that
+    /// must go on regardless.
+    pub fn dump(&self, dir: &Path, reason: &str) {
+        if self.lines.is_empty() {
+            return;
+        }
+        if let Err(err) = self.try_dump(dir, reason) {
+            warn!("failed to dump recent output: {}", err);
+        }
+    }
+
+    fn try_dump(&self, dir: &Path, reason: &str) -> Result<PathBuf, Box<dyn Error>> {
+        let secs = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let path = dir.join(format!("recent-output-{}.log", secs));
+        let mut file = BufWriter::new(File::create(&path)?);
+        writeln!(file, "# {}", reason)?;
+        info!(
+            "last {} lines of output before {}:",
+            self.lines.len(),
+            reason
+        );
+        for line in self.lines.iter() {
+            writeln!(file, "{}", line)?;
+            info!("    {}", line);
+        }
+        file.flush()?;
+        info!("recent output dumped to \"{}\"", path.display());
+        Ok(path)
+    }
+}
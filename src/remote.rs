@@ -0,0 +1,366 @@
+//! Uploading checkpoints off the machine, to S3-compatible storage or over SFTP.
+//!
+//! Each checkpoint is uploaded on a background thread once its local copy is complete, keeping the
+//! same layout as `backup_dir`, eg. `<prefix>/<world>-checkpoints/ckpt-<unix time>.tar.zst`.
+//! Failed uploads are retried a few times before the failure is reported back to the main loop.
+//!
+//! Remote copies are never deleted, prune them with a bucket lifecycle rule or a cron job.
+
+use crate::{events::Event, logging};
+use log::{info, warn};
+use serde_derive::Deserialize;
+use std::{
+    env,
+    error::Error,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::mpsc::{self, Sender},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// Where to upload checkpoints, and how hard to try.
+#[derive(Deserialize, Clone)]
+pub struct RemoteConfig {
+    #[serde(flatten)]
+    pub target: RemoteTarget,
+    /// How many times to retry a failed upload.
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+    #[serde(default = "default_retry_delay")]
+    pub retry_delay_secs: u64,
+}
+fn default_retries() -> u32 {
+    3
+}
+fn default_retry_delay() -> u64 {
+    60
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RemoteTarget {
+    S3(S3Config),
+    Sftp(SftpConfig),
+}
+
+/// An S3 bucket, or a bucket on any service that speaks the S3 API.
+#[derive(Deserialize, Clone)]
+pub struct S3Config {
+    /// Base URL of the service, eg. `https://s3.us-east-1.amazonaws.com`. Buckets are addressed
+    /// by path, as every S3-compatible service supports.
+    pub endpoint: String,
+    #[serde(default = "default_region")]
+    pub region: String,
+    pub bucket: String,
+    /// Key prefix to upload under, eg. `trust_hardcore/`.
+    #[serde(default)]
+    pub prefix: String,
+    /// Access key id, or `AWS_ACCESS_KEY_ID` if not set.
+    #[serde(default)]
+    pub access_key: Option<String>,
+    /// Secret access key, or `AWS_SECRET_ACCESS_KEY` if not set.
+    #[serde(default)]
+    pub secret_key: Option<String>,
+}
+fn default_region() -> String {
+    "us-east-1".to_string()
+}
+impl S3Config {
+    pub fn credentials(&self) -> Result<(String, String), Box<dyn Error>> {
+        let get = |conf: &Option<String>, field: &str, var: &str| {
+            conf.clone()
+                .or_else(|| env::var(var).ok())
+                .filter(|key| !key.is_empty())
+                .ok_or_else(|| format!("no `{}` in the config and no {} set", field, var))
+        };
+        Ok((
+            get(&self.access_key, "access_key", "AWS_ACCESS_KEY_ID")?,
+            get(&self.secret_key, "secret_key", "AWS_SECRET_ACCESS_KEY")?,
+        ))
+    }
+}
+
+/// A directory on a machine reachable over SFTP, through the system's `sftp` client. Logging in
+/// must not need a password, so use a key.
+#[derive(Deserialize, Clone)]
+pub struct SftpConfig {
+    pub host: String,
+    #[serde(default)]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub user: Option<String>,
+    /// Private key to log in with, instead of the client's default ones.
+    #[serde(default)]
+    pub identity: Option<PathBuf>,
+    /// Remote directory to upload into, relative to the login directory unless absolute.
+    #[serde(default)]
+    pub path: String,
+}
+
+/// Uploads checkpoints one after another on a background thread.
+pub struct Uploader {
+    tx: Sender<PathBuf>,
+    thread: JoinHandle<()>,
+}
+impl Uploader {
+    /// Start the upload thread, which reports each finished upload as [`Event::UploadDone`].
+    pub fn spawn(conf: RemoteConfig, backup_dir: PathBuf, events: Sender<Event>) -> Self {
+        if matches!(conf.target, RemoteTarget::S3(_)) && !cfg!(feature = "remote") {
+            warn!("s3 uploads need the `remote` feature, which this build was compiled without");
+        }
+        let (tx, rx) = mpsc::channel::<PathBuf>();
+        let thread = logging::spawn(move || {
+            for path in rx.iter() {
+                let name = remote_name(&backup_dir, &path);
+                //A reset may delete the checkpoint before its turn comes
+                if !path.exists() {
+                    info!("checkpoint \"{}\" was deleted before it was uploaded", name);
+                    continue;
+                }
+                let error = upload(&conf, &backup_dir, &path)
+                    .err()
+                    .map(|err| err.to_string());
+                if events.send(Event::UploadDone { name, error }).is_err() {
+                    break;
+                }
+            }
+        });
+        Self { tx, thread }
+    }
+
+    /// Upload a complete checkpoint in `backup_dir`.
+    pub fn queue(&self, path: PathBuf) {
+        let _ = self.tx.send(path);
+    }
+
+    /// Wait for the queued uploads to finish.
+    pub fn finish(self) {
+        drop(self.tx);
+        let _ = self.thread.join();
+    }
+}
+
+/// Path of a checkpoint relative to `backup_dir`, with `/` separators.
+fn remote_name(backup_dir: &Path, path: &Path) -> String {
+    let rel = path.strip_prefix(backup_dir).unwrap_or(path);
+    let parts: Vec<_> = rel
+        .components()
+        .map(|part| part.as_os_str().to_string_lossy())
+        .collect();
+    parts.join("/")
+}
+
+/// Join remote path segments with single `/`s, leaving out empty ones.
+fn join_remote(base: &str, rel: &str) -> String {
+    match base.trim_end_matches('/') {
+        "" if base.starts_with('/') => format!("/{}", rel),
+        "" => rel.to_string(),
+        base => format!("{}/{}", base, rel),
+    }
+}
+
+/// Upload a checkpoint in `backup_dir`, retrying as configured.
+pub fn upload(conf: &RemoteConfig, backup_dir: &Path, path: &Path) -> Result<(), Box<dyn Error>> {
+    let name = remote_name(backup_dir, path);
+    let mut attempt = 0;
+    loop {
+        let res = match &conf.target {
+            RemoteTarget::S3(s3) => upload_s3(s3, path, &name),
+            RemoteTarget::Sftp(sftp) => upload_sftp(sftp, path, &name),
+        };
+        match res {
+            Err(err) if attempt < conf.retries => {
+                attempt += 1;
+                warn!(
+                    "failed to upload checkpoint \"{}\" (attempt {}): {}",
+                    name, attempt, err
+                );
+                thread::sleep(Duration::from_secs(conf.retry_delay_secs));
+            }
+            res => return res,
+        }
+    }
+}
+
+/// Upload a checkpoint as a single object, or as an object per file if it is a directory.
+fn upload_s3(conf: &S3Config, path: &Path, name: &str) -> Result<(), Box<dyn Error>> {
+    fn walk(conf: &S3Config, dir: &Path, key: &str, count: &mut u64) -> Result<(), Box<dyn Error>> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let key = format!("{}/{}", key, entry.file_name().to_string_lossy());
+            let meta = entry.metadata()?;
+            if meta.is_dir() {
+                walk(conf, &entry.path(), &key, count)?;
+            } else if meta.is_file() {
+                s3::put(conf, &key, &entry.path())?;
+                *count += 1;
+            }
+        }
+        Ok(())
+    }
+    let key = join_remote(&conf.prefix, name);
+    if path.is_dir() {
+        let mut count = 0;
+        walk(conf, path, &key, &mut count)?;
+        info!("uploaded {} files to s3://{}/{}", count, conf.bucket, key);
+    } else {
+        s3::put(conf, &key, path)?;
+        info!("uploaded s3://{}/{}", conf.bucket, key);
+    }
+    Ok(())
+}
+
+/// Upload a checkpoint with `sftp` in batch mode. Archives go to a temporary name first, so that a
+/// broken connection never leaves a truncated checkpoint under the real name.
+fn upload_sftp(conf: &SftpConfig, path: &Path, name: &str) -> Result<(), Box<dyn Error>> {
+    let dest = join_remote(&conf.path, name);
+    let local = path.to_str().ok_or("checkpoint path is not valid UTF-8")?;
+    if dest.contains('"') || local.contains('"') {
+        return Err("paths with `\"` cannot be uploaded over sftp".into());
+    }
+    //Create each missing parent directory, `-` keeps going if it already exists
+    let mut script = String::new();
+    let mut parent = String::new();
+    let dirs: Vec<_> = dest.split('/').collect();
+    for dir in &dirs[..dirs.len() - 1] {
+        parent.push_str(dir);
+        if !dir.is_empty() {
+            script.push_str(&format!("-mkdir \"{}\"\n", parent));
+        }
+        parent.push('/');
+    }
+    if path.is_dir() {
+        script.push_str(&format!("put -r \"{}\" \"{}\"\n", local, dest));
+    } else {
+        script.push_str(&format!("put \"{}\" \"{}.partial\"\n", local, dest));
+        script.push_str(&format!("-rm \"{}\"\n", dest));
+        script.push_str(&format!("rename \"{}.partial\" \"{}\"\n", dest, dest));
+    }
+    let mut command = Command::new("sftp");
+    command.args(["-b", "-", "-o", "BatchMode=yes"]);
+    if let Some(port) = conf.port {
+        command.arg("-P").arg(port.to_string());
+    }
+    if let Some(identity) = &conf.identity {
+        command.arg("-i").arg(identity);
+    }
+    let host = match &conf.user {
+        Some(user) => format!("{}@{}", user, conf.host),
+        None => conf.host.clone(),
+    };
+    let mut child = command
+        .arg(&host)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| format!("failed to run sftp: {}", err))?;
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(script.as_bytes())?;
+    let out = child.wait_with_output()?;
+    if !out.status.success() {
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        let reason: Vec<_> = stderr.lines().filter(|line| !line.is_empty()).collect();
+        return Err(format!("sftp failed ({}): {}", out.status, reason.join("; ")).into());
+    }
+    info!("uploaded \"{}\" to {}:{}", name, host, dest);
+    Ok(())
+}
+
+#[cfg(feature = "remote")]
+mod s3 {
+    use super::S3Config;
+    use crate::hex;
+    use hmac::{Hmac, Mac};
+    use sha2::{Digest, Sha256};
+    use std::{error::Error, fs::File, io, path::Path};
+
+    const SIGNED_HEADERS: &str = "host;x-amz-content-sha256;x-amz-date";
+
+    fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac takes any key length");
+        mac.update(data.as_bytes());
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    /// Percent-encode an object key for a request path, keeping its `/`s.
+    fn encode_key(key: &str) -> String {
+        let mut out = String::new();
+        for b in key.bytes() {
+            match b {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                    out.push(b as char)
+                }
+                b => out.push_str(&format!("%{:02X}", b)),
+            }
+        }
+        out
+    }
+
+    /// Upload a file as an object, signing the request with AWS Signature Version 4.
+    pub fn put(conf: &S3Config, key: &str, file: &Path) -> Result<(), Box<dyn Error>> {
+        let (access_key, secret_key) = conf.credentials()?;
+        let mut hasher = Sha256::new();
+        let len = io::copy(&mut File::open(file)?, &mut hasher)?;
+        let payload = hex(&hasher.finalize());
+        let endpoint = conf.endpoint.trim_end_matches('/');
+        let host = endpoint
+            .split_once("://")
+            .map_or(endpoint, |(_, host)| host);
+        let uri = format!("/{}/{}", conf.bucket, encode_key(key));
+        let stamp = chrono::Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+        let date = &stamp[..8];
+        let scope = format!("{}/{}/s3/aws4_request", date, conf.region);
+        let canonical = format!(
+            "PUT\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            uri, host, payload, stamp, SIGNED_HEADERS, payload
+        );
+        let to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            stamp,
+            scope,
+            hex(&Sha256::digest(canonical.as_bytes()))
+        );
+        let mut signing_key = format!("AWS4{}", secret_key).into_bytes();
+        for part in [date, &conf.region, "s3", "aws4_request"] {
+            signing_key = hmac(&signing_key, part);
+        }
+        let auth = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            access_key,
+            scope,
+            SIGNED_HEADERS,
+            hex(&hmac(&signing_key, &to_sign))
+        );
+        let res = ureq::put(&format!("{}{}", endpoint, uri))
+            .set("x-amz-date", &stamp)
+            .set("x-amz-content-sha256", &payload)
+            .set("Authorization", &auth)
+            .set("Content-Length", &len.to_string())
+            .send(File::open(file)?);
+        match res {
+            Ok(_) => Ok(()),
+            Err(ureq::Error::Status(status, res)) => {
+                let body = res.into_string().unwrap_or_default();
+                Err(format!("s3 answered {}: {}", status, body.trim()).into())
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+#[cfg(not(feature = "remote"))]
+mod s3 {
+    use super::S3Config;
+    use std::{error::Error, path::Path};
+
+    pub fn put(_conf: &S3Config, _key: &str, _file: &Path) -> Result<(), Box<dyn Error>> {
+        Err("s3 uploads need the `remote` feature, which this build was compiled without".into())
+    }
+}
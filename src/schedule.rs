@@ -0,0 +1,71 @@
+//! Wall-clock schedules, such as `every day at 04:00` or `every 2 hours`.
+
+use chrono::{DateTime, Local, NaiveTime, TimeZone};
+use serde_derive::Deserialize;
+use std::{convert::TryFrom, time::Duration};
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(try_from = "String")]
+pub enum Schedule {
+    /// Every day at a local time.
+    Daily(NaiveTime),
+    /// Every so often, counting from when the wrapper started the server.
+    Every(Duration),
+}
+impl TryFrom<String> for Schedule {
+    type Error = String;
+    fn try_from(s: String) -> Result<Self, String> {
+        let bad = || {
+            format!(
+                "invalid schedule \"{}\", expected eg. \"every day at 04:00\" or \"every 2 hours\"",
+                s
+            )
+        };
+        let lower = s.to_lowercase();
+        let words = lower.split_whitespace().collect::<Vec<_>>();
+        let unit = |unit: &str| match unit.trim_end_matches('s') {
+            "minute" => Some(60),
+            "hour" => Some(3600),
+            "day" => Some(86400),
+            _ => None,
+        };
+        match words.as_slice() {
+            ["every", "day", "at", time] => NaiveTime::parse_from_str(time, "%H:%M")
+                .map(Schedule::Daily)
+                .map_err(|_| bad()),
+            ["every", u] => unit(u)
+                .map(|secs| Schedule::Every(Duration::from_secs(secs)))
+                .ok_or_else(bad),
+            ["every", n, u] => match (n.parse::<u64>(), unit(u)) {
+                (Ok(n), Some(secs)) if n > 0 => Ok(Schedule::Every(Duration::from_secs(n * secs))),
+                _ => Err(bad()),
+            },
+            _ => Err(bad()),
+        }
+    }
+}
+impl Schedule {
+    /// When the schedule next comes up after `now`.
+    pub fn next_after(&self, now: DateTime<Local>) -> DateTime<Local> {
+        let day = chrono::Duration::days(1);
+        match *self {
+            Schedule::Every(interval) => now + chrono::Duration::from_std(interval).unwrap_or(day),
+            Schedule::Daily(time) => {
+                let mut date = now.date_naive();
+                loop {
+                    //Days where the time does not exist, because of a clock change, are skipped
+                    let at = Local.from_local_datetime(&date.and_time(time)).earliest();
+                    match at {
+                        Some(at) if at > now => return at,
+                        _ => date = date.succ_opt().unwrap_or(date),
+                    }
+                }
+            }
+        }
+    }
+
+    /// When the first of several schedules next comes up after `now`.
+    pub fn next_of(schedules: &[Schedule], now: DateTime<Local>) -> Option<DateTime<Local>> {
+        schedules.iter().map(|s| s.next_after(now)).min()
+    }
+}
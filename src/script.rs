@@ -0,0 +1,213 @@
+//! Custom rules in a Rhai script, set with `script`.
+//!
+//! A script may define any of these functions, and the wrapper goes with its usual rules for the
+//! ones it does not:
+//!
+//! - `on_event(name, data)`, for each of the events that `hooks` can be attached to.
+//! - `roll(death)`, to pick the roll for a death instead of the dice, or return `()` to roll.
+//! - `ceremony(roll)`, to return the death ceremony as an array of messages to say and numbers of
+//!   seconds to pause for, instead of the configured one.
+//! - `penalty(roll)`, to return the outcome of a roll, eg. `"none"` to veto a reset, or `()` to
+//!   keep it.
+//!
+//! Scripts can send console commands with `command("...")` and write to the log with
+//! `log("...")`.
+
+use std::path::Path;
+
+pub use imp::Script;
+
+#[cfg(feature = "scripting")]
+mod imp {
+    use super::Path;
+    use crate::{
+        announce::{Kind, Message, Step},
+        penalty::Outcome,
+    };
+    use log::{info, warn};
+    use rhai::{
+        serde::{from_dynamic, to_dynamic},
+        CallFnOptions, Dynamic, Engine, Scope, AST,
+    };
+    use serde_json::Value;
+    use std::{
+        cell::RefCell, collections::HashSet, convert::TryFrom, error::Error, rc::Rc, time::Duration,
+    };
+
+    /// How many operations a single call may take, so that a runaway script cannot hang the
+    /// wrapper.
+    const MAX_OPERATIONS: u64 = 1_000_000;
+
+    /// Turn what `ceremony` returned into ceremony steps.
+    fn ceremony_steps(items: Vec<Value>) -> Vec<Step> {
+        items
+            .into_iter()
+            .filter_map(|item| match item {
+                Value::String(text) => Some(Step::Say(Message::new(Kind::Death, text))),
+                Value::Number(secs) => secs
+                    .as_f64()
+                    .filter(|secs| *secs > 0.0)
+                    .map(|secs| Step::Pause(Duration::from_secs_f64(secs))),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Turn what `penalty` returned into an outcome.
+    fn outcome(name: String) -> Result<Outcome, Box<dyn Error>> {
+        Ok(Outcome::try_from(name)?)
+    }
+
+    pub struct Script {
+        engine: Engine,
+        ast: AST,
+        scope: Scope<'static>,
+        functions: HashSet<String>,
+        /// Console commands the script sent, waiting to be passed on.
+        commands: Rc<RefCell<Vec<String>>>,
+    }
+    impl Script {
+        pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+            let mut engine = Engine::new();
+            engine.set_max_operations(MAX_OPERATIONS);
+            let commands = Rc::new(RefCell::new(Vec::new()));
+            {
+                let commands = commands.clone();
+                engine.register_fn("command", move |cmd: &str| {
+                    commands.borrow_mut().push(cmd.to_string())
+                });
+            }
+            engine.register_fn("log", |text: &str| info!("script: {}", text));
+            let ast = engine
+                .compile_file(path.to_path_buf())
+                .map_err(|err| format!("script \"{}\": {}", path.display(), err))?;
+            let functions = ast.iter_functions().map(|f| f.name.to_string()).collect();
+            //Top-level statements run once, to set things up
+            let mut scope = Scope::new();
+            engine
+                .run_ast_with_scope(&mut scope, &ast)
+                .map_err(|err| format!("script \"{}\": {}", path.display(), err))?;
+            Ok(Self {
+                engine,
+                ast,
+                scope,
+                functions,
+                commands,
+            })
+        }
+
+        /// Console commands sent since the last call.
+        pub fn take_commands(&self) -> Vec<String> {
+            self.commands.borrow_mut().drain(..).collect()
+        }
+
+        /// Call a script function with JSON arguments, or return nothing if the script does not
+        /// define it, it fails or it returns `()`.
+        fn call(&mut self, name: &str, args: Vec<Value>) -> Option<Value> {
+            if !self.functions.contains(name) {
+                return None;
+            }
+            let args = args
+                .iter()
+                .map(to_dynamic)
+                .collect::<Result<Vec<_>, _>>()
+                .ok()?;
+            let options = CallFnOptions::new().eval_ast(false);
+            let res = self.engine.call_fn_with_options::<Dynamic>(
+                options,
+                &mut self.scope,
+                &self.ast,
+                name,
+                args,
+            );
+            match res {
+                Ok(res) if res.is_unit() => None,
+                Ok(res) => match from_dynamic(&res) {
+                    Ok(res) => Some(res),
+                    Err(err) => {
+                        warn!("script {}() returned something unusable: {}", name, err);
+                        None
+                    }
+                },
+                Err(err) => {
+                    warn!("script {}() failed: {}", name, err);
+                    None
+                }
+            }
+        }
+
+        pub fn on_event(&mut self, event: &str, data: &Value) {
+            self.call("on_event", vec![event.into(), data.clone()]);
+        }
+
+        pub fn roll(&mut self, death: &Value) -> Option<i32> {
+            let num = self.call("roll", vec![death.clone()])?;
+            match num.as_i64() {
+                Some(num) => Some(num as i32),
+                None => {
+                    warn!("script roll() returned {}, not a number", num);
+                    None
+                }
+            }
+        }
+
+        pub fn ceremony(&mut self, roll: &Value) -> Option<Vec<Step>> {
+            match self.call("ceremony", vec![roll.clone()])? {
+                Value::Array(items) => Some(ceremony_steps(items)),
+                other => {
+                    warn!("script ceremony() returned {}, not an array", other);
+                    None
+                }
+            }
+        }
+
+        pub fn penalty(&mut self, roll: &Value) -> Option<Outcome> {
+            let name = match self.call("penalty", vec![roll.clone()])? {
+                Value::String(name) => name,
+                other => {
+                    warn!("script penalty() returned {}, not an outcome", other);
+                    return None;
+                }
+            };
+            outcome(name)
+                .map_err(|err| warn!("script penalty(): {}", err))
+                .ok()
+        }
+    }
+}
+
+#[cfg(not(feature = "scripting"))]
+mod imp {
+    use super::Path;
+    use crate::{announce::Step, penalty::Outcome};
+    use serde_json::Value;
+    use std::error::Error;
+
+    pub struct Script;
+    impl Script {
+        pub fn load(_path: &Path) -> Result<Self, Box<dyn Error>> {
+            Err(
+                "`script` needs the `scripting` feature, which this build was compiled without"
+                    .into(),
+            )
+        }
+
+        pub fn take_commands(&self) -> Vec<String> {
+            Vec::new()
+        }
+
+        pub fn on_event(&mut self, _event: &str, _data: &Value) {}
+
+        pub fn roll(&mut self, _death: &Value) -> Option<i32> {
+            None
+        }
+
+        pub fn ceremony(&mut self, _roll: &Value) -> Option<Vec<Step>> {
+            None
+        }
+
+        pub fn penalty(&mut self, _roll: &Value) -> Option<Outcome> {
+            None
+        }
+    }
+}
@@ -0,0 +1,467 @@
+//! Running the server process and talking to it.
+
+use crate::{
+    console_log::{self, SharedConsoleLog},
+    events::Event,
+    logging,
+    parse::bytes_to_string,
+    rcon::RconConfig,
+};
+use log::{info, warn};
+use serde_derive::Deserialize;
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fs,
+    fs::File,
+    io::{self, prelude::*, BufReader, SeekFrom},
+    net::{TcpStream, ToSocketAddrs},
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How long an attached server's log has to stay quiet after it starts stopping for it to count
+/// as stopped.
+const ATTACHED_STOP_QUIET: Duration = Duration::from_secs(5);
+
+/// When to start the server again after it dies without being told to stop.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct CrashRestartConfig {
+    pub enabled: bool,
+    /// Delay before the first restart, doubled for every further crash in the window.
+    pub initial_delay_secs: u64,
+    pub max_delay_secs: u64,
+    /// Give up once the server crashes this many times within the window.
+    pub max_crashes: usize,
+    pub window_minutes: u64,
+}
+impl Default for CrashRestartConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            initial_delay_secs: 5,
+            max_delay_secs: 300,
+            max_crashes: 5,
+            window_minutes: 30,
+        }
+    }
+}
+impl CrashRestartConfig {
+    /// How long to wait before restarting after the `nth` crash in the window, starting at 1.
+    pub fn delay(&self, nth: usize) -> Duration {
+        let factor = 1u64
+            .checked_shl(nth.saturating_sub(1) as u32)
+            .unwrap_or(u64::MAX);
+        Duration::from_secs(
+            self.initial_delay_secs
+                .saturating_mul(factor)
+                .min(self.max_delay_secs),
+        )
+    }
+}
+
+/// Watch a server started by something else, such as a hosting panel, instead of starting one.
+///
+/// Output is read from the server's log file and commands go over `rcon`, which must be set.
+#[derive(Deserialize, Clone)]
+pub struct AttachConfig {
+    /// Log file the server writes its output to.
+    #[serde(default = "default_attach_log")]
+    pub log: PathBuf,
+    /// Command that starts the server when it is not running, such as after a rewind or a reset.
+    /// Without it, the wrapper waits for the server to be started some other way.
+    #[serde(default)]
+    pub start_command: Option<Vec<String>>,
+}
+fn default_attach_log() -> PathBuf {
+    "logs/latest.log".into()
+}
+
+/// A running server, whose output goes to an event stream.
+pub struct ServerHandle {
+    process: Process,
+    input: Sender<String>,
+}
+
+enum Process {
+    Child(Child),
+    Attached(Arc<Tail>),
+}
+
+/// What the thread tailing an attached server's log has seen.
+struct Tail {
+    /// When a line was last read, and whether the server said it is stopping.
+    status: Mutex<(Instant, bool)>,
+    /// Set once the handle is dropped, to stop the thread.
+    closed: AtomicBool,
+}
+
+impl Drop for ServerHandle {
+    fn drop(&mut self) {
+        if let Process::Attached(tail) = &self.process {
+            tail.closed.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+impl ServerHandle {
+    /// Start the server with `cmd`, sending its output to `events` and logging it to `log`.
+    pub fn start(
+        cmd: &[String],
+        log: &Option<SharedConsoleLog>,
+        events: &Sender<Event>,
+    ) -> Result<Self, Box<dyn Error>> {
+        //Start server
+        info!("starting server jar using command \"{:?}\"", cmd);
+        let mut command = Command::new(&cmd[0]);
+        command
+            .args(&cmd[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        //Keep Ctrl+C in the terminal from reaching the server, the wrapper stops it properly
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            command.process_group(0);
+        }
+        let mut child = command.spawn()?;
+        //Start threads that accumulate output on the event channel
+        read_pipe(child.stdout.take().unwrap(), events, log);
+        read_pipe(child.stderr.take().unwrap(), events, log);
+        //Start thread that accumulates input and sends it to the server
+        let (in_tx, in_rx) = mpsc::channel::<String>();
+        {
+            let mut stdin = child.stdin.take().unwrap();
+            let log = log.clone();
+            logging::spawn(move || {
+                for cmd in in_rx.iter() {
+                    console_log::log(&log, &format!("> {}", cmd));
+                    if let Err(err) = writeln!(stdin, "{}", cmd) {
+                        warn!("failed to send command to server: {}", err);
+                        break;
+                    }
+                }
+            });
+        }
+        Ok(Self {
+            process: Process::Child(child),
+            input: in_tx,
+        })
+    }
+
+    /// Follow a server that something else runs, starting it with the configured command if it
+    /// does not answer on the RCON port.
+    pub fn attach(
+        conf: &AttachConfig,
+        rcon: &RconConfig,
+        log: &Option<SharedConsoleLog>,
+        events: &Sender<Event>,
+    ) -> Result<Self, Box<dyn Error>> {
+        info!(
+            "attaching to the server logging to \"{}\"",
+            conf.log.display()
+        );
+        let tail = Arc::new(Tail {
+            status: Mutex::new((Instant::now(), false)),
+            closed: AtomicBool::new(false),
+        });
+        tail_log(conf.log.clone(), events, log, tail.clone());
+        if !is_listening(&rcon.host, rcon.port) {
+            match &conf.start_command {
+                Some(cmd) if !cmd.is_empty() => {
+                    info!("server is not running, starting it with \"{:?}\"", cmd);
+                    let status = Command::new(&cmd[0]).args(&cmd[1..]).status()?;
+                    if !status.success() {
+                        return Err(format!("server start command failed ({})", status).into());
+                    }
+                }
+                _ => info!("server is not running, waiting for it to be started"),
+            }
+        }
+        //Commands go over rcon, there is no stdin to write to
+        let (input, _) = mpsc::channel();
+        Ok(Self {
+            process: Process::Attached(tail),
+            input,
+        })
+    }
+
+    /// Channel of console commands for the server.
+    pub fn input(&self) -> Sender<String> {
+        self.input.clone()
+    }
+
+    /// Send a console command to the server.
+    pub fn send(&self, cmd: impl Into<String>) {
+        let _ = self.input.send(cmd.into());
+    }
+
+    /// Whether the server is run by something else, and the wrapper only watches it.
+    pub fn is_attached(&self) -> bool {
+        matches!(self.process, Process::Attached(_))
+    }
+
+    /// Check whether the server has exited, without blocking.
+    ///
+    /// An attached server counts as exited once it said it is stopping and its log went quiet.
+    pub fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+        match &mut self.process {
+            Process::Child(child) => child.try_wait(),
+            Process::Attached(tail) => {
+                let (last_line, stopping) = *tail.status.lock().unwrap();
+                Ok((stopping && last_line.elapsed() >= ATTACHED_STOP_QUIET)
+                    .then(ExitStatus::default))
+            }
+        }
+    }
+
+    /// Wait for the server to exit, killing it if it takes longer than `timeout`.
+    pub fn wait_or_kill(&mut self, timeout: Duration) -> Result<(), Box<dyn Error>> {
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            if self.try_wait()?.is_some() {
+                return Ok(());
+            }
+            thread::sleep(Duration::from_millis(200));
+        }
+        match &mut self.process {
+            Process::Child(child) => {
+                info!(
+                    "server did not stop after {}s, killing it",
+                    timeout.as_secs()
+                );
+                child.kill()?;
+                child.wait()?;
+                Ok(())
+            }
+            Process::Attached(_) => Err(format!(
+                "attached server did not stop after {}s, and cannot be killed",
+                timeout.as_secs()
+            )
+            .into()),
+        }
+    }
+}
+
+/// Whether something accepts connections on `host:port`.
+fn is_listening(host: &str, port: u16) -> bool {
+    let addrs = match (host, port).to_socket_addrs() {
+        Ok(addrs) => addrs,
+        Err(_) => return false,
+    };
+    addrs
+        .into_iter()
+        .any(|addr| TcpStream::connect_timeout(&addr, Duration::from_secs(2)).is_ok())
+}
+
+/// Follow the log file at `path` from its current end, starting over when it is rotated.
+fn tail_log(
+    path: PathBuf,
+    sendback: &Sender<Event>,
+    log: &Option<SharedConsoleLog>,
+    tail: Arc<Tail>,
+) {
+    let sendback = sendback.clone();
+    let log = log.clone();
+    logging::spawn(move || {
+        //Lines already in the log are from before the wrapper was watching
+        let mut pos = fs::metadata(&path).map(|meta| meta.len()).unwrap_or(0);
+        let mut id = file_id(&path);
+        let mut partial = Vec::new();
+        while !tail.closed.load(Ordering::Relaxed) {
+            thread::sleep(Duration::from_millis(250));
+            let len = match fs::metadata(&path) {
+                Ok(meta) => meta.len(),
+                Err(_) => continue,
+            };
+            //A shorter or different file means the server started a new log
+            let new_id = file_id(&path);
+            if len < pos || new_id != id {
+                pos = 0;
+                id = new_id;
+                partial.clear();
+            }
+            if len == pos {
+                continue;
+            }
+            let read = File::open(&path).and_then(|mut file| {
+                file.seek(SeekFrom::Start(pos))?;
+                file.read_to_end(&mut partial)
+            });
+            match read {
+                Ok(n) => pos += n as u64,
+                Err(err) => {
+                    warn!("failed to read \"{}\": {}", path.display(), err);
+                    continue;
+                }
+            }
+            //Hand out complete lines, keeping a trailing partial one for later
+            let complete = match partial.iter().rposition(|&b| b == b'\n') {
+                Some(idx) => partial.drain(..=idx).collect::<Vec<_>>(),
+                None => continue,
+            };
+            for line in complete.split(|&b| b == b'\n') {
+                let line = bytes_to_string(line);
+                if line.is_empty() {
+                    continue;
+                }
+                logging::output(&line);
+                console_log::log(&log, &line);
+                {
+                    let mut status = tail.status.lock().unwrap();
+                    status.0 = Instant::now();
+                    if line.contains("Stopping server") || line.contains("Stopping the server") {
+                        status.1 = true;
+                    }
+                }
+                if let Err(_line) = sendback.send(Event::Output(line)) {
+                    return;
+                }
+            }
+        }
+    });
+}
+
+/// Something that tells files apart, to notice when a log is replaced by a new one.
+#[cfg(unix)]
+fn file_id(path: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata(path).ok().map(|meta| meta.ino())
+}
+
+#[cfg(not(unix))]
+fn file_id(_path: &Path) -> Option<u64> {
+    None
+}
+
+fn read_pipe<R: Read + Send + 'static>(
+    pipe: R,
+    sendback: &Sender<Event>,
+    log: &Option<SharedConsoleLog>,
+) {
+    let sendback = sendback.clone();
+    let log = log.clone();
+    logging::spawn(move || {
+        let buf = BufReader::new(pipe);
+        for line in buf.split(b'\n') {
+            let line = bytes_to_string(&line.unwrap());
+            logging::output(&line);
+            console_log::log(&log, &line);
+            if let Err(_line) = sendback.send(Event::Output(line)) {
+                //Channel closed
+                break;
+            }
+        }
+    });
+}
+
+/// Put the path of a downloaded server jar into the server command.
+pub fn with_server_jar(cmd: &[String], jar: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+    let jar = jar.to_string_lossy();
+    let mut cmd = cmd.to_vec();
+    if cmd.iter().any(|arg| arg.contains("{server_jar}")) {
+        for arg in cmd.iter_mut() {
+            *arg = arg.replace("{server_jar}", &jar);
+        }
+    } else {
+        let idx = cmd
+            .iter()
+            .position(|arg| arg == "-jar")
+            .filter(|&idx| idx + 1 < cmd.len())
+            .ok_or("`server_version` is set, but `server` has no `{server_jar}` or `-jar <jar>`")?;
+        cmd[idx + 1] = jar.into_owned();
+    }
+    Ok(cmd)
+}
+
+/// The server jar a server command launches, if any.
+pub fn server_jar(cmd: &[String]) -> Option<PathBuf> {
+    let jar = match cmd.iter().position(|arg| arg == "-jar") {
+        Some(idx) => cmd.get(idx + 1),
+        None => cmd.iter().find(|arg| arg.ends_with(".jar")),
+    };
+    jar.map(PathBuf::from)
+}
+
+/// Which seed a freshly reset world gets.
+#[derive(Deserialize, Clone, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SeedPolicy {
+    /// Leave `level-seed` in `server.properties` alone.
+    #[default]
+    Keep,
+    /// A new random seed for every world.
+    Random,
+    /// The same seed for every world.
+    Fixed(String),
+    /// Go through a list of seeds, one per season, starting over at the end.
+    List(Vec<String>),
+}
+impl SeedPolicy {
+    /// The seed for the world of a season, or nothing to keep the current one.
+    pub fn seed_for(&self, season: u32) -> Option<String> {
+        match self {
+            SeedPolicy::Keep => None,
+            SeedPolicy::Random => Some(rand::random::<i64>().to_string()),
+            SeedPolicy::Fixed(seed) => Some(seed.clone()),
+            SeedPolicy::List(seeds) => seeds
+                .get(season.saturating_sub(1) as usize % seeds.len().max(1))
+                .cloned(),
+        }
+    }
+}
+
+/// Read a `server.properties` file into its keys and values.
+pub fn read_properties(properties: &Path) -> Result<BTreeMap<String, String>, Box<dyn Error>> {
+    let mut props = BTreeMap::new();
+    for line in fs::read_to_string(properties)?.lines() {
+        let line = line.trim_start();
+        if line.is_empty() || line.starts_with(['#', '!']) {
+            continue;
+        }
+        if let Some((key, value)) = line.split_once('=') {
+            //Minecraft escapes colons and the like with a backslash
+            let value = value.replace("\\:", ":").replace("\\=", "=");
+            props.insert(key.trim().to_string(), value.trim().to_string());
+        }
+    }
+    Ok(props)
+}
+
+/// Point the server at a world, by setting `level-name` in its `server.properties`.
+pub fn set_level_name(properties: &Path, name: &str) -> Result<(), Box<dyn Error>> {
+    set_property(properties, "level-name", name)
+}
+
+/// Set a key in a `server.properties` file, adding it if it is not there yet.
+pub fn set_property(properties: &Path, key: &str, value: &str) -> Result<(), Box<dyn Error>> {
+    let old = fs::read_to_string(properties)?;
+    let prefix = format!("{}=", key);
+    let mut new = String::new();
+    let mut found = false;
+    for line in old.lines() {
+        if line.trim_start().starts_with(&prefix) {
+            new += &format!("{}{}\n", prefix, value);
+            found = true;
+        } else {
+            new += line;
+            new += "\n";
+        }
+    }
+    if !found {
+        new += &format!("{}{}\n", prefix, value);
+    }
+    if new != old {
+        fs::write(properties, new)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,245 @@
+//! Small persistent state file for things that must survive wrapper restarts.
+
+use log::warn;
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct State {
+    /// Extra checkpoints requested by each player through `!checkpoint`.
+    pub checkpoint_quota: HashMap<String, QuotaUse>,
+    /// When the last successful `!rewind` vote happened, in seconds since the Unix epoch.
+    pub last_rewind_vote: Option<u64>,
+    /// How many times the world has been reset.
+    pub resets: u32,
+    /// Advancements made in the current world.
+    pub advancements: u32,
+    /// Deaths in the current world.
+    pub deaths: u32,
+    /// Seconds each player has spent online, over the whole campaign, by UUID or by name for
+    /// players whose UUID is not known yet.
+    pub player_playtime: BTreeMap<String, u64>,
+    /// The name each player UUID last logged in with.
+    pub player_names: BTreeMap<String, String>,
+    /// The UUID each name in the `players` list was first seen logging in with.
+    pub bound_players: BTreeMap<String, String>,
+    /// The most recent death roll.
+    pub last_roll: Option<RollRecord>,
+    /// A rewind or reset under way, to finish on startup if the wrapper died in the middle of it.
+    pub pending_penalty: Option<PendingPenalty>,
+    /// Where a checkpoint was being written to, with saving off on the server.
+    pub checkpoint_in_progress: Option<PathBuf>,
+    /// Whether the current world still has to be set up with `on_new_world` once the server is up.
+    pub world_setup_pending: bool,
+    /// How the current world was won, once it is.
+    pub won: Option<Victory>,
+    /// Players online, to carry on counting their playtime after reattaching to a server that
+    /// kept running.
+    pub online_players: Vec<String>,
+    /// Players parked on the proxy's limbo server, to send back once the server is up.
+    pub handed_off: Vec<String>,
+    /// World border diameter left by `border` rolls in the current world, set again whenever the
+    /// server starts so that rewinds do not undo it.
+    pub border_size: Option<f64>,
+    /// When each player sitting out a `spectator` roll gets back into the game, by UUID or name,
+    /// in seconds since the Unix epoch.
+    pub revivals: BTreeMap<String, u64>,
+    /// When each player banned by a `ban` roll is pardoned, by name as the ban list goes, in
+    /// seconds since the Unix epoch.
+    pub unbans: BTreeMap<String, u64>,
+    /// Mercy tokens each player holds, by UUID or name, each one sparing the world a deadly roll.
+    pub mercy_tokens: BTreeMap<String, u32>,
+    /// The `mercy_advancements` each player has been paid for in the current world, so that
+    /// getting one again after a rewind pays nothing.
+    pub mercy_awarded: BTreeMap<String, BTreeSet<String>>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Victory {
+    pub player: String,
+    pub advancement: String,
+    /// When the run was won, in seconds since the Unix epoch.
+    pub at: u64,
+    pub playtime_secs: u64,
+    pub attempt: u32,
+    pub deaths: u32,
+    pub advancements: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum PendingPenalty {
+    Rewind { checkpoint: String },
+    Reset,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RollRecord {
+    pub player: String,
+    pub roll: i32,
+    /// What the roll led to, eg. `"rewind"`.
+    pub outcome: String,
+    /// When it was rolled, in seconds since the Unix epoch.
+    pub at: u64,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct QuotaUse {
+    /// Local date on which the quota was used, as `YYYY-MM-DD`.
+    pub day: String,
+    pub used: u32,
+}
+
+impl State {
+    /// Load the state file, starting from scratch if it does not exist yet.
+    ///
+    /// A corrupted state file is set aside rather than aborting the run.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let raw = fs::read_to_string(path)?;
+        match serde_json::from_str(&raw) {
+            Ok(state) => Ok(state),
+            Err(err) => {
+                let aside = path.with_extension("json.corrupt");
+                warn!(
+                    "state file \"{}\" is corrupted ({}), moving it to \"{}\" and starting over",
+                    path.display(),
+                    err,
+                    aside.display()
+                );
+                fs::rename(path, aside)?;
+                Ok(Self::default())
+            }
+        }
+    }
+
+    /// Save the state file, replacing the old one atomically.
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    /// The UUID of the player currently going by `name`, or the name itself if it has never been
+    /// seen logging in.
+    pub fn player_id<'a>(&'a self, name: &'a str) -> &'a str {
+        self.player_names
+            .iter()
+            .find(|(_, known)| *known == name)
+            .map(|(uuid, _)| uuid.as_str())
+            .unwrap_or(name)
+    }
+
+    /// The name a player, by UUID or name, last logged in with.
+    pub fn player_name<'a>(&'a self, id: &'a str) -> &'a str {
+        self.player_names.get(id).map(String::as_str).unwrap_or(id)
+    }
+
+    /// The id an entry of the `players` list stands for: the UUID it is bound to, or the UUID of
+    /// whoever goes by that name, or the entry itself.
+    pub fn tracked_id<'a>(&'a self, entry: &'a str) -> &'a str {
+        match self.bound_players.get(entry) {
+            Some(uuid) => uuid,
+            None => self.player_id(entry),
+        }
+    }
+
+    /// Like `tracked_id`, but binds a name to its UUID for good the first time it is known, so
+    /// that the entry keeps following the same player through name changes.
+    pub fn track(&mut self, entry: &str) -> String {
+        let id = self.tracked_id(entry).to_string();
+        if id != entry {
+            self.bound_players
+                .entry(entry.to_string())
+                .or_insert_with(|| id.clone());
+        }
+        id
+    }
+
+    /// Remember the UUID a player logged in with, moving anything kept under their name over to
+    /// it. Returns the name the player had before, if they changed it.
+    pub fn learn_uuid(&mut self, name: &str, uuid: &str) -> Option<String> {
+        //Whoever had this name before does not anymore
+        self.player_names
+            .retain(|other, known| other == uuid || known != name);
+        let old = self.player_names.insert(uuid.to_string(), name.to_string());
+        if let Some(secs) = self.player_playtime.remove(name) {
+            *self.player_playtime.entry(uuid.to_string()).or_default() += secs;
+        }
+        if let Some(usage) = self.checkpoint_quota.remove(name) {
+            self.checkpoint_quota.insert(uuid.to_string(), usage);
+        }
+        if let Some(at) = self.revivals.remove(name) {
+            self.revivals.insert(uuid.to_string(), at);
+        }
+        if let Some(tokens) = self.mercy_tokens.remove(name) {
+            *self.mercy_tokens.entry(uuid.to_string()).or_default() += tokens;
+        }
+        if let Some(awarded) = self.mercy_awarded.remove(name) {
+            self.mercy_awarded
+                .entry(uuid.to_string())
+                .or_default()
+                .extend(awarded);
+        }
+        old.filter(|old| old != name)
+    }
+
+    /// Players by how long they have played, longest first, by name.
+    pub fn playtime_leaderboard(&self) -> Vec<(&str, u64)> {
+        let mut board: Vec<_> = self
+            .player_playtime
+            .iter()
+            .map(|(player, &secs)| (self.player_name(player), secs))
+            .collect();
+        board.sort_by_key(|&(_, secs)| std::cmp::Reverse(secs));
+        board
+    }
+
+    /// Use up one of a player's daily checkpoints, returning how many are left afterwards, or
+    /// `None` if there are none left today.
+    pub fn use_checkpoint_quota(&mut self, player: &str, today: &str, quota: u32) -> Option<u32> {
+        let usage = self.checkpoint_quota.entry(player.to_string()).or_default();
+        if usage.day != today {
+            usage.day = today.to_string();
+            usage.used = 0;
+        }
+        if usage.used >= quota {
+            return None;
+        }
+        usage.used += 1;
+        Some(quota - usage.used)
+    }
+
+    /// Pay a player the mercy tokens for an advancement, returning their balance afterwards, or
+    /// `None` if they were already paid for it in this world.
+    pub fn award_mercy(&mut self, player: &str, advancement: &str, tokens: u32) -> Option<u32> {
+        let fresh = self
+            .mercy_awarded
+            .entry(player.to_string())
+            .or_default()
+            .insert(advancement.to_string());
+        if !fresh {
+            return None;
+        }
+        let balance = self.mercy_tokens.entry(player.to_string()).or_default();
+        *balance += tokens;
+        Some(*balance)
+    }
+
+    /// Spend one of a player's mercy tokens, returning how many are left, or `None` if they have
+    /// none.
+    pub fn spend_mercy(&mut self, player: &str) -> Option<u32> {
+        let balance = self.mercy_tokens.get_mut(player).filter(|n| **n > 0)?;
+        *balance -= 1;
+        Some(*balance)
+    }
+}
@@ -0,0 +1,1087 @@
+//! Supervising the server across runs: reacting to its output, checkpoints and penalties.
+//!
+//! The session of a single server run is handled here as far as joins, leaves and playtime go,
+//! and in the submodules for commands, deaths, checkpoints, penalties and rewinds.
+
+mod chat_commands;
+mod checkpoints;
+mod console;
+mod death;
+mod penalties;
+mod questions;
+mod restore;
+mod run;
+mod voting;
+
+pub(crate) use self::restore::{recover_interrupted, restore_checkpoint};
+use self::{
+    death::PendingDeath,
+    questions::{Question, REPLY_TIMEOUT},
+    run::run_server,
+};
+use crate::{
+    afk::AfkTracker,
+    announce::{Announcer, Kind, Message},
+    api::Feed,
+    backups::{remove_path, BackupManager, Checkpoint, CheckpointMeta},
+    bedrock::{self, ServerType},
+    chat::Cooldowns,
+    config::{default_recent_lines, Config},
+    console_log::SharedConsoleLog,
+    events::EventStream,
+    fairness::Commitment,
+    faults::{Fault, Faults},
+    fmt_duration,
+    history::History,
+    hooks::{self, HookAction, HookRunner},
+    java,
+    lives::Lives,
+    matcher::{PrefixMatcher, TemplateMatcher},
+    nbt::LevelInfo,
+    notify::{self, Notifier},
+    parse::{is_username_char, parse_advancement, parse_done, parse_uuid, LinePrefix},
+    penalty::{Penalty, PenaltyEngine},
+    plugins::{Plugins, Snapshot},
+    proxy,
+    recent::RecentLines,
+    remote::Uploader,
+    script::Script,
+    server::{read_properties, ServerHandle},
+    state::{State, Victory},
+    twitch::Twitch,
+    unix_now,
+};
+use chrono::{DateTime, Local};
+use log::{debug, error, info, warn};
+use serde_json::json;
+use std::{
+    cell::Cell,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    error::Error,
+    fs::{self, File},
+    io::prelude::*,
+    mem,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::mpsc::Sender,
+    time::{Duration, Instant},
+};
+
+/// Describe a checkpoint for the console, eg. "day 43, 12h07m played, attempt 2".
+pub(crate) fn describe_meta(meta: &CheckpointMeta) -> String {
+    let mut desc = String::new();
+    if let Some(day) = meta.day {
+        desc += &format!("day {}, ", day);
+    }
+    desc += &format!(
+        "{} played, attempt {}",
+        fmt_duration(Duration::from_secs(meta.playtime_secs)),
+        meta.attempt
+    );
+    if !meta.online_players.is_empty() {
+        desc += &format!(", online: {}", meta.online_players.join(", "));
+    }
+    if let Some(seed) = &meta.seed {
+        desc += &format!(", seed {}", seed);
+    }
+    desc
+}
+
+/// Join names for chat, eg. "alice, bob and carol".
+fn join_names(names: &[String]) -> String {
+    match names {
+        [] => String::new(),
+        [name] => name.clone(),
+        [rest @ .., last] => format!("{} and {}", rest.join(", "), last),
+    }
+}
+
+/// Where the run state is kept.
+pub(crate) fn state_path(config: &Config) -> PathBuf {
+    config
+        .state_file
+        .clone()
+        .unwrap_or_else(|| match &config.state_dir {
+            Some(dir) => dir.join("state.json"),
+            None => config.backup_dir.join("trust_hardcore_state.json"),
+        })
+}
+
+/// Where the history of deaths is kept.
+pub(crate) fn history_path(config: &Config) -> PathBuf {
+    state_path(config).with_file_name("deaths.sqlite")
+}
+
+/// Where provably fair rolls are kept.
+pub(crate) fn roll_audit_path(config: &Config) -> PathBuf {
+    config
+        .fair_rolls
+        .audit_file
+        .clone()
+        .unwrap_or_else(|| state_path(config).with_file_name("roll_audit.jsonl"))
+}
+
+/// Point `config.world` at the world of the current attempt, returning the world name.
+pub(crate) fn attempt_world(config: &mut Config, state: &State) -> Result<String, Box<dyn Error>> {
+    if let Some(template) = &config.world_name_template {
+        let name = template.replace("{n}", &(state.resets + 1).to_string());
+        config.world.set_file_name(name);
+    }
+    Ok(config
+        .world
+        .file_name()
+        .ok_or("no world name (invalid world path)")?
+        .to_string_lossy()
+        .into_owned())
+}
+
+/// Where the playtime of the current world is kept. Lives are kept next to it.
+pub(crate) fn playtime_path(config: &Config) -> PathBuf {
+    config
+        .state_dir
+        .as_ref()
+        .unwrap_or(&config.world)
+        .join("playtime.txt")
+}
+
+pub(crate) fn backup_manager(config: &Config, world_name: &str) -> BackupManager {
+    BackupManager::new(
+        &config.backup_dir,
+        world_name,
+        config.checkpoint_slots,
+        config.backup_format,
+        config.backup_exclude(),
+        config.incremental_backups,
+        config.disk_space_margin_mb,
+    )
+}
+
+pub(crate) fn save_playtime(path: &Path, playtime: Duration) -> Result<(), Box<dyn Error>> {
+    let mut file = File::create(path)?;
+    write!(file, "{}", playtime.as_secs())?;
+    Ok(())
+}
+
+pub(crate) fn load_playtime(path: &Path) -> Result<Duration, Box<dyn Error>> {
+    let playtime = fs::read_to_string(path)?;
+    let playtime: u64 = playtime.parse()?;
+    Ok(Duration::from_secs(playtime))
+}
+
+/// Why a line of output was not acted upon.
+#[derive(Clone, Copy)]
+enum Unmatched {
+    /// Fewer `[...]` prefixes than expected.
+    Brackets,
+    /// Nothing that looks like a username after the prefixes.
+    Username,
+    /// The player is not in the `players` list.
+    Untracked,
+    /// The message matched one of the `ignore_phrases`.
+    Ignored,
+    /// The message is not a death, join, leave or chat message.
+    NoPattern,
+}
+impl Unmatched {
+    fn describe(self) -> &'static str {
+        match self {
+            Unmatched::Brackets => "failed to strip prefix brackets",
+            Unmatched::Username => "no username character",
+            Unmatched::Untracked => "untracked player",
+            Unmatched::Ignored => "ignored phrase",
+            Unmatched::NoPattern => "no pattern match",
+        }
+    }
+}
+
+/// Counters of how output lines were classified.
+#[derive(Default)]
+struct LineStats {
+    matched: u64,
+    brackets: u64,
+    username: u64,
+    untracked: u64,
+    ignored: u64,
+    no_pattern: u64,
+}
+impl LineStats {
+    fn count(&mut self, why: Unmatched) {
+        *match why {
+            Unmatched::Brackets => &mut self.brackets,
+            Unmatched::Username => &mut self.username,
+            Unmatched::Untracked => &mut self.untracked,
+            Unmatched::Ignored => &mut self.ignored,
+            Unmatched::NoPattern => &mut self.no_pattern,
+        } += 1;
+    }
+}
+
+/// How long to wait for the server to say it is up before going ahead anyway.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// How many lines to look at for a line to learn the output prefix from.
+const PREFIX_LEARN_LINES: u32 = 200;
+
+/// State of a single server run.
+struct Session<'a> {
+    /// The config, which `.reload` and SIGHUP swap for a freshly read one.
+    config: Rc<Config>,
+    events: &'a mut EventStream,
+    world_path: &'a Path,
+    backups: BackupManager,
+    penalties: PenaltyEngine,
+    /// How many `escalation` steps apply to `penalties`.
+    escalation_level: usize,
+    /// Deaths this season according to the history, which rewinds do not undo.
+    season_deaths: u32,
+    /// The secret for the next roll, if rolls are provably fair.
+    fair: Option<Commitment>,
+    players: HashSet<String>,
+    death_msg: TemplateMatcher,
+    ignore_phrases: PrefixMatcher,
+    /// Prefix to strip off each line of output.
+    prefix: LinePrefix,
+    /// How many more lines to try learning the prefix from, if it is still being learned.
+    learning_prefix: Option<u32>,
+    input: Sender<String>,
+    announcer: Announcer,
+    notifier: Notifier,
+    feed: Feed,
+    online_players: HashSet<String>,
+    players_online_since: Option<Instant>,
+    /// Online players, and since when their playtime has not been counted yet.
+    player_since: HashMap<String, Instant>,
+    playtime: Duration,
+    cooldowns: Cooldowns,
+    line_stats: LineStats,
+    faults: Faults,
+    /// When the last player logged off, if a logoff checkpoint is pending.
+    empty_since: Option<Instant>,
+    /// Make a checkpoint as soon as possible.
+    checkpoint_requested: bool,
+    state: State,
+    state_path: PathBuf,
+    playtime_path: PathBuf,
+    /// Players currently voting for a rewind, and when they voted.
+    rewind_votes: HashMap<String, Instant>,
+    /// Checkpoint that the next rewind should go back to, instead of following `rewind_target`.
+    rewind_to: Option<Checkpoint>,
+    /// Lives left for each player, if lives are enabled.
+    lives: Option<Lives>,
+    /// When memory trouble was last reacted to.
+    memory_warned_at: Option<Instant>,
+    /// When to restart the server, if a restart is scheduled.
+    restart_at: Option<Instant>,
+    /// Whether `.reload-config` was typed, to restart the server with the config as it is now.
+    reload_requested: bool,
+    /// Whether to read the config again once no deaths are pending anymore.
+    reload_deferred: bool,
+    /// Whether the server was told to stop, so that exiting is not a crash.
+    stop_sent: Cell<bool>,
+    /// Deaths waiting for their ceremony to finish, in order.
+    pending_deaths: VecDeque<PendingDeath>,
+    /// Every death of every season, if the database could be opened.
+    history: Option<History>,
+    /// Questions to the server waiting for an answer, with their ids, in the order they were
+    /// asked.
+    questions: VecDeque<(u64, Question)>,
+    /// When each player last survived a roll, for `roll_cooldown_seconds`.
+    survived_at: HashMap<String, Instant>,
+    /// Uploads checkpoints off the machine, if `remote_backup` is set.
+    uploader: Option<Uploader>,
+    /// Runs the posts and programs in `hooks`.
+    hook_runner: HookRunner,
+    /// Custom rules, if `script` is set.
+    script: Option<Script>,
+    /// WASM plugins from `plugins`, loaded for the whole run.
+    plugins: Plugins,
+    /// Twitch chat, if viewers vote along.
+    twitch: Option<Twitch>,
+    afk: AfkTracker,
+    /// Whether playtime is stopped because everyone online is AFK.
+    afk_paused: bool,
+    /// When to stop waiting for the server to say it is up, or nothing once it has.
+    ready_deadline: Option<Instant>,
+    /// Messages to say once the server is up.
+    held_announcements: Vec<String>,
+    /// When the next checkpoint from `checkpoint_schedule` is due.
+    next_scheduled: Option<DateTime<Local>>,
+}
+impl Session<'_> {
+    fn cmd(&self, cmd: String) {
+        if cmd.trim() == "stop" {
+            self.stop_sent.set(true);
+        }
+        self.input.send(cmd).unwrap();
+    }
+
+    /// Handle a line of server output.
+    fn on_output(&mut self, line: &str) -> Result<Penalty, Box<dyn Error>> {
+        if !self.questions.is_empty() {
+            self.answer_questions(line);
+        }
+        if self.config.memory_pressure.enabled && java::is_memory_pressure(line) {
+            self.on_memory_pressure();
+        }
+        match self.handle_output(line)? {
+            Ok(penalty) => {
+                self.line_stats.matched += 1;
+                Ok(penalty)
+            }
+            Err(why) => {
+                self.line_stats.count(why);
+                if self.config.debug_unmatched {
+                    info!("unmatched line ({}): {}", why.describe(), line);
+                }
+                Ok(Penalty::None)
+            }
+        }
+    }
+
+    fn handle_output(&mut self, line: &str) -> Result<Result<Penalty, Unmatched>, Box<dyn Error>> {
+        let config = Rc::clone(&self.config);
+        //Learn the prefix from the startup lines, if the flavor is not known
+        if let Some(left) = self.learning_prefix {
+            match LinePrefix::learn(line) {
+                Some(prefix) => {
+                    info!(
+                        "detected {} prefix brackets{} in server output",
+                        prefix.brackets,
+                        if prefix.logger {
+                            " and a logger name"
+                        } else {
+                            ""
+                        }
+                    );
+                    self.prefix = prefix;
+                    self.learning_prefix = None;
+                }
+                None if left == 0 => {
+                    warn!(
+                        "could not detect the server output format, set `server_flavor` or `bracket_count`"
+                    );
+                    self.learning_prefix = None;
+                }
+                None => self.learning_prefix = Some(left - 1),
+            }
+        }
+        //Clean the message of prefixes
+        let line = {
+            let line = match self.prefix.strip(line) {
+                Some(line) => line,
+                None => return Ok(Err(Unmatched::Brackets)),
+            };
+            if config.server_type == ServerType::Bedrock {
+                return Ok(self.handle_bedrock(line));
+            }
+            //Advance until a username character is reached
+            match line.find(is_username_char) {
+                Some(line_start) => &line[line_start..],
+                None => return Ok(Err(Unmatched::Username)),
+            }
+        };
+        if let Some(secs) = parse_done(line) {
+            self.on_ready(Some(secs));
+            return Ok(Ok(Penalty::None));
+        }
+        if let Some((name, uuid)) = parse_uuid(line) {
+            self.on_uuid(name, uuid);
+            return Ok(Ok(Penalty::None));
+        }
+        //Player name is the first word
+        let msg_start = line
+            .find(|c: char| !is_username_char(c))
+            .unwrap_or(line.len());
+        let (username, msg) = line.split_at(msg_start);
+        let username = username.to_string();
+        if !config.allow_all_players && !self.is_tracked(&username) {
+            return Ok(Err(Unmatched::Untracked));
+        }
+        //Handle chat commands
+        if let Some(text) = msg.strip_prefix("> ") {
+            self.afk.active(&username);
+            if config.chat_commands.enabled {
+                return Ok(Ok(self.on_chat_command(&username, text)));
+            }
+            return Ok(Ok(Penalty::None));
+        }
+        //Compare with death messages
+        if self.death_msg.matches(msg) {
+            return Ok(self.on_death_msg(&username, line, msg));
+        } else if let Some(advancement) = parse_advancement(msg) {
+            info!("{} got advancement [{}]", username, advancement);
+            self.afk.active(&username);
+            self.state.advancements += 1;
+            self.save_state();
+            if config
+                .milestone_advancements
+                .iter()
+                .any(|m| m == advancement)
+            {
+                info!("advancement [{}] is a milestone", advancement);
+                self.announcer.say(Message::new(
+                    Kind::Checkpoint,
+                    format!("{} reached a milestone, making a checkpoint", username),
+                ));
+                self.checkpoint_requested = true;
+            }
+            if self.state.won.is_none() && config.win_advancements.iter().any(|w| w == advancement)
+            {
+                self.on_victory(&username, advancement);
+            }
+            if let Some(&tokens) = config.mercy_advancements.get(advancement) {
+                self.award_mercy(&username, advancement, tokens);
+            }
+        } else if msg.starts_with(" joined the game") {
+            self.on_join(&username);
+        } else if msg.starts_with(" left the game") {
+            self.on_leave(&username);
+        } else if let Some(pos) = msg.strip_prefix(" has the following entity data: [") {
+            self.afk.position(&username, pos);
+        } else {
+            return Ok(Err(Unmatched::NoPattern));
+        }
+        Ok(Ok(Penalty::None))
+    }
+
+    /// Handle a line of Bedrock output, which has messages of its own and deaths logged by the
+    /// behavior pack.
+    fn handle_bedrock(&mut self, line: &str) -> Result<Penalty, Unmatched> {
+        let line = match bedrock::parse_line(line) {
+            Some(line) => line,
+            None => return Err(Unmatched::NoPattern),
+        };
+        let username = match line {
+            bedrock::Line::Started => {
+                self.on_ready(None);
+                return Ok(Penalty::None);
+            }
+            bedrock::Line::Connected(name)
+            | bedrock::Line::Disconnected(name)
+            | bedrock::Line::Death { player: name, .. } => name,
+        };
+        if !self.config.allow_all_players && !self.is_tracked(username) {
+            return Err(Unmatched::Untracked);
+        }
+        match line {
+            bedrock::Line::Connected(name) => self.on_join(name),
+            bedrock::Line::Disconnected(name) => self.on_leave(name),
+            bedrock::Line::Death { player, message } => {
+                let msg = message.strip_prefix(player).unwrap_or(message);
+                return self.on_death_msg(player, message, msg);
+            }
+            bedrock::Line::Started => {}
+        }
+        Ok(Penalty::None)
+    }
+
+    fn on_join(&mut self, username: &str) {
+        if self.online_players.is_empty() {
+            //Start counting time
+            info!("started counting time");
+            self.players_online_since = Some(Instant::now());
+        }
+        info!("{} went online", username);
+        let id = self.state.player_id(username).to_string();
+        //A rewind may have brought back their survival self
+        if let Some(&at) = self.state.revivals.get(&id) {
+            if at <= unix_now() {
+                self.revive(&id, username);
+            } else {
+                let mode = self.config.spectator_penalty.game_mode.name();
+                self.cmd(format!("gamemode {} {}", mode, username));
+            }
+        }
+        self.player_since.insert(id, Instant::now());
+        self.afk.active(username);
+        self.online_players.insert(username.to_string());
+        self.empty_since = None;
+        self.save_online();
+        self.emit("join", &json!({ "player": username }));
+    }
+
+    fn on_leave(&mut self, username: &str) {
+        info!("{} went offline", username);
+        self.emit("leave", &json!({ "player": username }));
+        let id = self.state.player_id(username).to_string();
+        if let Some(since) = self.player_since.remove(&id) {
+            *self.state.player_playtime.entry(id).or_default() += since.elapsed().as_secs();
+            self.save_state();
+        }
+        self.online_players.remove(username);
+        self.afk.left(username);
+        self.save_online();
+        if self.online_players.is_empty() {
+            //Stop counting time
+            info!("stopped counting time");
+            self.players_online_since = None;
+            self.empty_since = Some(Instant::now());
+        }
+    }
+
+    /// Keep the players online in the state file, in case the wrapper has to pick up after them.
+    fn save_online(&mut self) {
+        let mut online = self.online_players.iter().cloned().collect::<Vec<_>>();
+        online.sort();
+        self.state.online_players = online;
+        self.save_state();
+    }
+
+    /// Carry on counting playtime for the players that were online on an attached server when the
+    /// wrapper last stopped, leaving out the ones the server says are gone by now.
+    fn resume_online(&mut self) {
+        let online = mem::take(&mut self.state.online_players);
+        if online.is_empty() {
+            return;
+        }
+        self.cmd("list".to_string());
+        self.ask(Question::List(online), REPLY_TIMEOUT);
+    }
+
+    /// Count the players still online by the `list` the server gave, if it did.
+    fn finish_resume_online(&mut self, mut online: Vec<String>, listed: Option<Vec<String>>) {
+        match listed {
+            Some(listed) => online.retain(|player| listed.contains(player)),
+            None => warn!("server did not list who is online, trusting the last known players"),
+        }
+        info!("still online: {}", online.join(", "));
+        let now = Instant::now();
+        for player in online {
+            let id = self.state.player_id(&player).to_string();
+            self.player_since.insert(id, now);
+            self.afk.active(&player);
+            self.online_players.insert(player);
+        }
+        if !self.online_players.is_empty() {
+            self.players_online_since = Some(now);
+        }
+        self.save_online();
+    }
+
+    /// Whether a player, by the name they are online with, is in the `players` list.
+    fn is_tracked(&self, username: &str) -> bool {
+        self.players.contains(self.state.player_id(username))
+    }
+
+    /// Remember which UUID a player logged in with, so they are still recognized under a new name.
+    fn on_uuid(&mut self, name: &str, uuid: &str) {
+        if let Some(old) = self.state.learn_uuid(name, uuid) {
+            info!("{} is now known as {}", old, name);
+        }
+        if self.players.remove(name) {
+            info!("tracked player {} has UUID {}", name, uuid);
+            let id = self.state.track(name);
+            self.players.insert(id);
+        }
+        if let Some(lives) = &mut self.lives {
+            if lives.rekey(name, uuid) {
+                if let Err(err) = lives.save() {
+                    error!("failed to save lives: {}", err);
+                }
+            }
+        }
+        self.save_state();
+    }
+
+    /// The server finished starting up, taking `secs` if it said how long.
+    fn on_ready(&mut self, secs: Option<f64>) {
+        if self.ready_deadline.take().is_none() {
+            return;
+        }
+        if let Some(secs) = secs {
+            info!("server is up after {:.1}s", secs);
+        }
+        if self.state.world_setup_pending {
+            let commands = &self.config.on_new_world.commands;
+            if !commands.is_empty() {
+                info!("setting up the new world with {} commands", commands.len());
+            }
+            for cmd in commands {
+                self.cmd(cmd.clone());
+            }
+            self.state.world_setup_pending = false;
+            self.save_state();
+        }
+        for cmd in &self.config.startup_commands {
+            self.cmd(cmd.clone());
+        }
+        if let Some(size) = self.state.border_size {
+            self.cmd(format!("worldborder set {}", size));
+        }
+        self.bring_back();
+        let snapshot = self.snapshot();
+        for cmd in self.plugins.init(&snapshot) {
+            self.cmd(cmd);
+        }
+        for msg in self.held_announcements.drain(..) {
+            self.announcer.say(msg);
+        }
+    }
+
+    /// Someone won the run: deaths stop rolling and the world goes to the hall of fame.
+    fn on_victory(&mut self, username: &str, advancement: &str) {
+        let victory = Victory {
+            player: username.to_string(),
+            advancement: advancement.to_string(),
+            at: unix_now(),
+            playtime_secs: self.playtime.as_secs(),
+            attempt: self.state.resets + 1,
+            deaths: self.state.deaths,
+            advancements: self.state.advancements,
+        };
+        info!("{} won the run with [{}]", username, advancement);
+        self.state.won = Some(victory.clone());
+        self.save_state();
+        let playtime = fmt_duration(self.playtime);
+        self.announcer.say(format!(
+            "{} got [{}], the run is won after {}! Deaths no longer roll",
+            username, advancement, playtime
+        ));
+        self.notifier.notify(
+            "Run won",
+            format!(
+                "{} got [{}] on attempt {}, after {} of playtime and {} deaths",
+                username, advancement, victory.attempt, playtime, victory.deaths
+            ),
+            notify::GREEN,
+        );
+        self.feed.publish("victory", json!(victory));
+        if let Err(err) = self.archive_victory(&victory) {
+            error!("failed to archive the won world: {}", err);
+            self.notifier.notify(
+                "Archiving the won world failed",
+                err.to_string(),
+                notify::RED,
+            );
+        }
+    }
+
+    /// Keep a copy of the won world in the hall of fame, along with the final stats.
+    fn archive_victory(&mut self, victory: &Victory) -> Result<(), Box<dyn Error>> {
+        let config = Rc::clone(&self.config);
+        let dir = config
+            .hall_of_fame_dir
+            .clone()
+            .unwrap_or_else(|| config.backup_dir.join("hall_of_fame"));
+        let world_name = self
+            .world_path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "world".to_string());
+        let name = format!(
+            "{}-{}",
+            world_name,
+            chrono::Local::now().format("%Y%m%d-%H%M%S")
+        );
+        let (partial, done) = self.backups.archive_paths(&dir, &name)?;
+        let meta = self.checkpoint_meta();
+        if let Err(err) = self.copy_world(&partial, &done, &meta, true) {
+            remove_path(&partial)?;
+            return Err(err);
+        }
+        self.count_player_playtime();
+        let stats = json!({
+            "victory": victory,
+            "world": meta,
+            "player_playtime": self
+                .state
+                .playtime_leaderboard()
+                .into_iter()
+                .collect::<BTreeMap<_, _>>(),
+        });
+        fs::write(
+            dir.join(format!("{}.json", name)),
+            serde_json::to_string_pretty(&stats)?,
+        )?;
+        info!("archived the won world to \"{}\"", done.display());
+        Ok(())
+    }
+
+    /// Take the actions that `hooks` attaches to an event.
+    fn emit(&mut self, event: &str, data: &serde_json::Value) {
+        for action in self.config.hooks.get(event).into_iter().flatten() {
+            match action {
+                HookAction::Command(cmd) => self.cmd(hooks::fill(cmd, data)),
+                action => self.hook_runner.run(action, event, data),
+            }
+        }
+        if let Some(script) = &mut self.script {
+            script.on_event(event, data);
+        }
+        self.flush_script();
+        let snapshot = self.snapshot();
+        for cmd in self.plugins.on_event(&snapshot, event, data) {
+            self.cmd(cmd);
+        }
+    }
+
+    /// What plugins can read about the run right now.
+    fn snapshot(&self) -> Snapshot {
+        let mut online_players = self.online_players.iter().cloned().collect::<Vec<_>>();
+        online_players.sort();
+        Snapshot {
+            playtime_secs: self.playtime.as_secs(),
+            online_players,
+        }
+    }
+
+    fn on_plugin_timer(&mut self, plugin: usize, id: i32) {
+        let snapshot = self.snapshot();
+        for cmd in self.plugins.on_timer(&snapshot, plugin, id) {
+            self.cmd(cmd);
+        }
+    }
+
+    /// Send the console commands the script asked for.
+    fn flush_script(&self) {
+        for cmd in self.script.iter().flat_map(Script::take_commands) {
+            self.cmd(cmd);
+        }
+    }
+
+    fn save_state(&self) {
+        if let Err(err) = self.state.save(&self.state_path) {
+            warn!("failed to save state: {}", err);
+        }
+    }
+
+    /// Park everyone online on the proxy's limbo server, while the world is out of reach.
+    fn hand_off(&mut self) {
+        let conf = match &self.config.proxy {
+            Some(conf) => conf,
+            None => return,
+        };
+        let mut players = self.online_players.iter().cloned().collect::<Vec<_>>();
+        if players.is_empty() {
+            return;
+        }
+        players.sort();
+        info!(
+            "moving {} to \"{}\" on the proxy",
+            players.join(", "),
+            conf.limbo
+        );
+        if let Err(err) = proxy::send(conf, &players, &conf.limbo) {
+            warn!("failed to move players to the limbo server: {}", err);
+        }
+        //Even if some failed, sending them back does no harm
+        self.state.handed_off = players;
+        self.save_state();
+    }
+
+    /// Kick everyone still online with `message`, so that the stop does not look like a crash.
+    fn kick_all(&self, message: Option<&str>) {
+        let message = match message {
+            Some(message) if !message.is_empty() => message,
+            _ => return,
+        };
+        let season = (self.state.resets + 2).to_string();
+        let mut players = self.online_players.iter().collect::<Vec<_>>();
+        players.sort();
+        for player in players {
+            //Parked players are already off the server
+            if self.state.handed_off.contains(player) {
+                continue;
+            }
+            let reason = message
+                .replace("{player}", player)
+                .replace("{season}", &season);
+            self.cmd(format!("kick {} {}", player, reason));
+        }
+    }
+
+    /// Send the players parked on the limbo server back, once the server is up.
+    fn bring_back(&mut self) {
+        if self.state.handed_off.is_empty() {
+            return;
+        }
+        let players = mem::take(&mut self.state.handed_off);
+        self.save_state();
+        let conf = match &self.config.proxy {
+            Some(conf) => conf,
+            None => return,
+        };
+        info!("moving {} back from the limbo server", players.join(", "));
+        if let Err(err) = proxy::send(conf, &players, &conf.server) {
+            warn!("failed to move players back from the limbo server: {}", err);
+        }
+    }
+
+    /// Ask the server to stop and wait for it, killing it if it takes too long.
+    fn stop_server(&self, server: &mut ServerHandle) -> Result<(), Box<dyn Error>> {
+        if !self.faults.check(Fault::ServerStop) {
+            self.cmd("stop".to_string());
+        }
+        server.wait_or_kill(Duration::from_secs(self.config.stop_timeout_secs))
+    }
+
+    /// React to the JVM running out of memory, before it dies on its own.
+    fn on_memory_pressure(&mut self) {
+        //Out of memory errors come in bursts, only react once in a while
+        if let Some(at) = self.memory_warned_at {
+            if at.elapsed() < Duration::from_secs(5 * 60) {
+                return;
+            }
+        }
+        self.memory_warned_at = Some(Instant::now());
+        warn!("server is running out of memory");
+        self.announcer.say(Message::new(
+            Kind::Checkpoint,
+            "The server is running out of memory, making a checkpoint",
+        ));
+        self.checkpoint_requested = true;
+        let conf = &self.config.memory_pressure;
+        if conf.restart && self.restart_at.is_none() {
+            info!(
+                "scheduling a restart in {} seconds",
+                conf.restart_delay_seconds
+            );
+            self.announcer.say(format!(
+                "Restarting the server in {}s to free up memory",
+                conf.restart_delay_seconds
+            ));
+            self.restart_at =
+                Some(Instant::now() + Duration::from_secs(conf.restart_delay_seconds));
+        }
+    }
+
+    /// Count the playtime since the last update and save it, before the server goes away.
+    fn flush_playtime(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(since) = self.players_online_since.as_mut() {
+            let now = Instant::now();
+            self.playtime += now - *since;
+            *since = now;
+            save_playtime(&self.playtime_path, self.playtime)?;
+        }
+        self.count_player_playtime();
+        Ok(())
+    }
+
+    /// Add the time online players have played since the last count to their totals.
+    fn count_player_playtime(&mut self) {
+        if self.player_since.is_empty() {
+            return;
+        }
+        let now = Instant::now();
+        for (player, since) in self.player_since.iter_mut() {
+            let secs = (now - *since).as_secs();
+            //Keep the leftover fraction of a second for the next count
+            *since += Duration::from_secs(secs);
+            *self
+                .state
+                .player_playtime
+                .entry(player.clone())
+                .or_default() += secs;
+        }
+        self.save_state();
+    }
+
+    /// Poll player positions if due, and hold the playtime clock while everyone is AFK. Returns
+    /// whether the clock is held.
+    fn check_afk(&mut self) -> bool {
+        if let Some(players) = self.afk.poll_due() {
+            for player in players {
+                self.cmd(format!("data get entity {} Pos", player));
+            }
+        }
+        if !self.afk.everyone_afk() {
+            if mem::take(&mut self.afk_paused) && !self.online_players.is_empty() {
+                info!("players are back, playtime is counting again");
+            }
+            return false;
+        }
+        if !self.afk_paused {
+            info!("everyone online is AFK, holding playtime");
+            self.afk_paused = true;
+        }
+        //Drop the idle time instead of counting it later
+        let now = Instant::now();
+        if let Some(since) = self.players_online_since.as_mut() {
+            *since = now;
+        }
+        for since in self.player_since.values_mut() {
+            *since = now;
+        }
+        true
+    }
+
+    /// Returns whether a checkpoint boundary was crossed.
+    fn update_playtime(&mut self) -> Result<bool, Box<dyn Error>> {
+        if self.config.afk.enabled && self.check_afk() {
+            return Ok(false);
+        }
+        if let Some(since) = self.players_online_since.as_mut() {
+            //Advance playtime
+            let now = Instant::now();
+            let adv = now - *since;
+            if adv > Duration::from_secs(8) {
+                let old_playtime = self.playtime;
+                self.playtime += adv;
+                *since = now;
+                debug!("advancing by {}ms", adv.as_millis());
+                debug!("new playtime: {}ms", self.playtime.as_millis());
+                //Save playtime
+                save_playtime(&self.playtime_path, self.playtime)?;
+                self.count_player_playtime();
+                self.check_escalation();
+                //Make backup if advanced past the boundary
+                let backup_interval = self.config.checkpoint_minutes * 60;
+                let backup_count = |playtime: Duration| {
+                    (playtime.as_secs() + backup_interval - 30) / backup_interval
+                };
+                if backup_count(self.playtime) > backup_count(old_playtime) {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// How to run the wrapper.
+pub struct Options {
+    pub config: PathBuf,
+    /// Run even if the configuration looks broken.
+    pub force: bool,
+    /// Enable debugging aids, such as fault injection.
+    pub debug: bool,
+}
+
+/// Runs the server over and over, applying penalties in between.
+///
+/// Holds the state that outlives a single server run.
+pub struct Supervisor {
+    events: EventStream,
+    recent: RecentLines,
+    /// Where to dump recent output on failure, once known.
+    dump_dir: Option<PathBuf>,
+    console_log: Option<SharedConsoleLog>,
+    /// Messages to say once the next server starts.
+    startup_announcements: Vec<String>,
+    /// When the server recently crashed, oldest first.
+    crashes: VecDeque<Instant>,
+    /// Whether the HTTP API and Telegram commands are already being served, which happens once per
+    /// process.
+    api_started: bool,
+    feed: Feed,
+    /// Whether a fleet catches signals on behalf of this wrapper.
+    managed: bool,
+}
+impl Supervisor {
+    pub fn new() -> Self {
+        Self::with_events(EventStream::new(), false)
+    }
+
+    /// A wrapper for a server in a fleet, which hands it console lines and signals through
+    /// `events`.
+    pub fn managed(events: EventStream) -> Self {
+        Self::with_events(events, true)
+    }
+
+    fn with_events(events: EventStream, managed: bool) -> Self {
+        Self {
+            events,
+            recent: RecentLines::new(default_recent_lines()),
+            dump_dir: None,
+            console_log: None,
+            startup_announcements: Vec::new(),
+            crashes: VecDeque::new(),
+            api_started: false,
+            feed: Feed::default(),
+            managed,
+        }
+    }
+
+    /// Keep running the server until it stops without a penalty, or something fails.
+    pub fn run(&mut self, opts: &Options) -> Result<(), Box<dyn Error>> {
+        if !self.managed {
+            if let Err(err) = self.events.catch_signals() {
+                warn!("failed to catch termination signals: {}", err);
+            }
+        }
+        loop {
+            match run_server(opts, self) {
+                Ok(true) => {
+                    eprintln!();
+                    eprintln!();
+                }
+                Ok(false) => break,
+                Err(err) => {
+                    if let Some(dump_dir) = self.dump_dir.as_ref() {
+                        self.recent.dump(dump_dir, &format!("error: {}", err));
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+impl Default for Supervisor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Check the config against the `server.properties` of the server, if it can be read, since the
+/// server goes by that file rather than by the config.
+pub(crate) fn check_properties(config: &Config, force: bool) -> Result<(), Box<dyn Error>> {
+    //An existing world keeps the mode it was created in, whatever server.properties says now
+    if config.server_type == ServerType::Java {
+        if let Ok(level) = LevelInfo::read(&config.world) {
+            if !level.hardcore {
+                if config.require_hardcore && !force {
+                    return Err(format!(
+                        "world \"{}\" was created without hardcore. reset it, or unset \
+                         `require_hardcore` or pass `--force` to run anyway",
+                        config.world.display()
+                    )
+                    .into());
+                }
+                warn!(
+                    "world \"{}\" was created without hardcore, so players respawn after dying",
+                    config.world.display()
+                );
+            }
+        }
+    }
+    let path = config.properties_path();
+    let props = match read_properties(&path) {
+        Ok(props) => props,
+        Err(_) => return Ok(()),
+    };
+    let world_name = config
+        .world
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    if let Some(level) = props.get("level-name") {
+        if *level != world_name && config.world_name_template.is_none() {
+            warn!(
+                "\"{}\" points the server at world \"{}\", but `world` is \"{}\", \
+                 so checkpoints would miss the world the server plays",
+                path.display(),
+                level,
+                world_name
+            );
+        }
+    }
+    //Bedrock has no hardcore mode of its own
+    if config.server_type == ServerType::Java
+        && props.get("hardcore").map(String::as_str) != Some("true")
+    {
+        if config.require_hardcore && !force {
+            return Err(format!(
+                "\"{}\" does not set `hardcore=true`. set it, or unset `require_hardcore` \
+                 or pass `--force` to run anyway",
+                path.display()
+            )
+            .into());
+        }
+        warn!(
+            "\"{}\" does not set `hardcore=true`, so players respawn after dying",
+            path.display()
+        );
+    }
+    Ok(())
+}
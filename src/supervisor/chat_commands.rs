@@ -0,0 +1,348 @@
+//! Commands players type in chat.
+
+use super::{death::roll_engine, Session};
+use crate::{
+    announce::{Kind, Message},
+    chat::{self, DeathVoteMode},
+    fmt_duration,
+    penalty::Penalty,
+    unix_now,
+};
+use log::{info, warn};
+use std::{
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+impl Session<'_> {
+    pub(super) fn on_chat_command(&mut self, username: &str, text: &str) -> Penalty {
+        let config = Rc::clone(&self.config);
+        let (name, args) = match chat::parse_command(text) {
+            Some(cmd) => cmd,
+            None => return Penalty::None,
+        };
+        if !self.command_enabled(name) {
+            return Penalty::None;
+        }
+        if !self.may_use(username, name) {
+            self.cmd(format!("tell {} You may not use !{}", username, name));
+            return Penalty::None;
+        }
+        if let Err(wait) = self
+            .cooldowns
+            .try_use(&config.chat_commands, username, name)
+        {
+            self.cmd(format!(
+                "tell {} Sorry, !{} is on cooldown, try again in {}s",
+                username,
+                name,
+                wait.as_secs() + 1
+            ));
+            return Penalty::None;
+        }
+        info!("player {} used chat command !{}", username, name);
+        match name {
+            "roll" => {
+                let own_engine = roll_engine(
+                    &config,
+                    &self.state,
+                    &[username.to_string()],
+                    None,
+                    self.escalation_level,
+                );
+                let engine = own_engine.as_ref().unwrap_or(&self.penalties);
+                let roll = engine.roll();
+                let breakdown = roll
+                    .breakdown()
+                    .map(|breakdown| format!(" ({})", breakdown))
+                    .unwrap_or_default();
+                let deadly_rolls = engine.deadly_rolls();
+                self.announcer.say(
+                    Message::new(
+                        Kind::Roll,
+                        format!(
+                            "{} rolled {}{} (just for fun)",
+                            username, roll.num, breakdown
+                        ),
+                    )
+                    .hover(format!("Deadly rolls: {}", deadly_rolls)),
+                );
+            }
+            "stats" => {
+                let lives = match &self.lives {
+                    Some(lives) => {
+                        let left = lives.left(self.state.player_id(username));
+                        format!(", {} has {} lives left", username, left)
+                    }
+                    None => String::new(),
+                };
+                self.announcer.say(format!(
+                    "Playtime: {}, {} players online{}",
+                    fmt_duration(self.playtime),
+                    self.online_players.len(),
+                    lives
+                ));
+            }
+            "playtime" => {
+                self.count_player_playtime();
+                let player = if args.is_empty() { username } else { args };
+                let secs = self
+                    .state
+                    .player_playtime
+                    .get(self.state.player_id(player))
+                    .copied()
+                    .unwrap_or(0);
+                let top: Vec<_> = self
+                    .state
+                    .playtime_leaderboard()
+                    .into_iter()
+                    .take(5)
+                    .enumerate()
+                    .map(|(idx, (player, secs))| {
+                        format!(
+                            "{}. {} {}",
+                            idx + 1,
+                            player,
+                            fmt_duration(Duration::from_secs(secs))
+                        )
+                    })
+                    .collect();
+                self.announcer.say(format!(
+                    "{} has played for {}",
+                    player,
+                    fmt_duration(Duration::from_secs(secs))
+                ));
+                self.announcer
+                    .say(format!("Leaderboard: {}", top.join(", ")));
+            }
+            "deaths" => {
+                let season = self.state.resets + 1;
+                let board = match &self.history {
+                    Some(history) => history.leaderboard(Some(season)),
+                    None => Err("it is not open".into()),
+                };
+                match board {
+                    Ok(board) => {
+                        let top: Vec<_> = board
+                            .iter()
+                            .take(5)
+                            .enumerate()
+                            .map(|(idx, entry)| {
+                                format!(
+                                    "{}. {} {} ({} survived)",
+                                    idx + 1,
+                                    entry.player,
+                                    entry.deaths,
+                                    entry.survived
+                                )
+                            })
+                            .collect();
+                        if top.is_empty() {
+                            self.announcer
+                                .say(format!("Nobody died yet in season {}", season));
+                        } else {
+                            self.announcer.say(format!(
+                                "Deaths in season {}: {}",
+                                season,
+                                top.join(", ")
+                            ));
+                        }
+                    }
+                    Err(err) => warn!("failed to read the death history: {}", err),
+                }
+            }
+            "checkpoint" => {
+                if !config.make_backups {
+                    self.cmd(format!("tell {} Checkpoints are disabled", username));
+                    return Penalty::None;
+                }
+                if !self.is_tracked(username) {
+                    self.cmd(format!(
+                        "tell {} Only tracked players may request checkpoints",
+                        username
+                    ));
+                    return Penalty::None;
+                }
+                let today = chrono::Local::now().format("%Y-%m-%d").to_string();
+                let quota = config.chat_commands.checkpoint_quota;
+                let id = self.state.player_id(username).to_string();
+                match self.state.use_checkpoint_quota(&id, &today, quota) {
+                    Some(left) => {
+                        self.save_state();
+                        self.announcer.say(format!(
+                            "{} requested a checkpoint ({} left today)",
+                            username, left
+                        ));
+                        self.checkpoint_requested = true;
+                    }
+                    None => {
+                        self.cmd(format!(
+                            "tell {} You have used all {} of your checkpoints for today",
+                            username, quota
+                        ));
+                    }
+                }
+            }
+            "lives" => match &self.lives {
+                None => self.cmd(format!("tell {} There are no lives in this run", username)),
+                Some(lives) if args.is_empty() => {
+                    let mut left = self
+                        .players
+                        .iter()
+                        .map(|id| format!("{} {}", self.state.player_name(id), lives.left(id)))
+                        .collect::<Vec<_>>();
+                    left.sort();
+                    self.announcer
+                        .say(format!("Lives left: {}", left.join(", ")));
+                }
+                Some(lives) => {
+                    let left = lives.left(self.state.player_id(args));
+                    self.announcer
+                        .say(format!("{} has {} lives left", args, left));
+                }
+            },
+            "tokens" => {
+                let player = if args.is_empty() { username } else { args };
+                let balance = self
+                    .state
+                    .mercy_tokens
+                    .get(self.state.player_id(player))
+                    .copied()
+                    .unwrap_or(0);
+                self.announcer.say(format!(
+                    "{} holds {} mercy {}",
+                    player,
+                    balance,
+                    if balance == 1 { "token" } else { "tokens" }
+                ));
+            }
+            "backup" => {
+                if !config.make_backups {
+                    self.cmd(format!("tell {} Checkpoints are disabled", username));
+                    return Penalty::None;
+                }
+                self.announcer.say(Message::new(
+                    Kind::Checkpoint,
+                    format!("{} requested a checkpoint", username),
+                ));
+                self.checkpoint_requested = true;
+            }
+            "help" => {
+                let usable = chat::COMMANDS
+                    .iter()
+                    .filter(|cmd| self.command_enabled(cmd) && self.may_use(username, cmd))
+                    .map(|cmd| format!("!{}", cmd))
+                    .collect::<Vec<_>>();
+                self.cmd(format!("tell {} Commands: {}", username, usable.join(", ")));
+            }
+            "rewind" => return self.on_rewind_vote(username),
+            "vote" => self.on_vote(username, args),
+            "restore" => {
+                if args.is_empty() {
+                    self.cmd(format!("tell {} Usage: !restore <name>", username));
+                    return Penalty::None;
+                }
+                match self.restore_named(args) {
+                    Ok(penalty) => return penalty,
+                    Err(err) => self.cmd(format!("tell {} Not restoring: {}", username, err)),
+                }
+            }
+            _ => unreachable!(),
+        }
+        Penalty::None
+    }
+
+    fn command_enabled(&self, name: &str) -> bool {
+        match name {
+            "rewind" => self.config.chat_commands.rewind_vote.enabled,
+            "vote" => self.config.chat_commands.death_vote.mode != DeathVoteMode::Off,
+            "tokens" => !self.config.mercy_advancements.is_empty(),
+            name => chat::COMMANDS.contains(&name),
+        }
+    }
+
+    /// Whether `chat_commands.permissions` lets a player use a command.
+    fn may_use(&self, username: &str, name: &str) -> bool {
+        let config = Rc::clone(&self.config);
+        let admins_only = vec!["@admins".to_string()];
+        let allowed = match config.chat_commands.permissions.get(name) {
+            Some(allowed) => allowed,
+            None if chat::ADMIN_COMMANDS.contains(&name) => &admins_only,
+            None => return true,
+        };
+        allowed.iter().any(|who| match who.as_str() {
+            "@admins" => config.admins.iter().any(|admin| admin == username),
+            "@players" => self.is_tracked(username),
+            who => who == username || who == self.state.player_id(username),
+        })
+    }
+
+    fn on_rewind_vote(&mut self, username: &str) -> Penalty {
+        let conf = &self.config.chat_commands.rewind_vote;
+        if !self.is_tracked(username) {
+            self.cmd(format!(
+                "tell {} Only tracked players may vote to rewind",
+                username
+            ));
+            return Penalty::None;
+        }
+        if !matches!(self.backups.latest(), Ok(Some(_))) {
+            self.cmd(format!(
+                "tell {} There is no checkpoint to rewind to",
+                username
+            ));
+            return Penalty::None;
+        }
+        let now = unix_now();
+        if let Some(last) = self.state.last_rewind_vote {
+            let ready_at = last + conf.cooldown_hours * 3600;
+            if now < ready_at {
+                self.cmd(format!(
+                    "tell {} Another rewind vote can start in {}",
+                    username,
+                    fmt_duration(Duration::from_secs(ready_at - now))
+                ));
+                return Penalty::None;
+            }
+        }
+        //Forget stale votes and votes from players who left
+        let window = Duration::from_secs(conf.window_seconds);
+        let online = &self.online_players;
+        self.rewind_votes
+            .retain(|voter, at| at.elapsed() < window && online.contains(voter));
+        self.rewind_votes
+            .insert(username.to_string(), Instant::now());
+        let voters = self
+            .online_players
+            .iter()
+            .filter(|player| self.is_tracked(player))
+            .count()
+            .max(1);
+        let needed = ((voters as f64 * conf.majority).ceil() as usize).max(1);
+        let votes = self.rewind_votes.len();
+        info!(
+            "{} voted to rewind ({}/{} votes needed)",
+            username, votes, needed
+        );
+        if votes < needed {
+            self.announcer.say(
+                Message::new(
+                    Kind::Info,
+                    format!(
+                        "{} voted to rewind to a checkpoint ({}/{}), type !rewind within {}s to agree",
+                        username, votes, needed, conf.window_seconds
+                    ),
+                )
+                .hover("Click to vote")
+                .suggest("!rewind"),
+            );
+            return Penalty::None;
+        }
+        self.announcer
+            .say("The vote passed, rewinding to a checkpoint");
+        self.rewind_votes.clear();
+        self.state.last_rewind_vote = Some(now);
+        self.save_state();
+        Penalty::Rewind
+    }
+}
@@ -0,0 +1,304 @@
+//! Making checkpoints of the world while the server runs.
+
+use super::{describe_meta, Session};
+use crate::{
+    announce::{Kind, Message},
+    backups::{remove_path, CheckpointMeta},
+    bedrock::{self, ServerType},
+    faults::Fault,
+    nbt::LevelInfo,
+    notify,
+    parse::{parse_day, parse_seed},
+    schedule::Schedule,
+    unix_now,
+};
+use chrono::Local;
+use log::{error, info, warn};
+use serde_json::json;
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+impl Session<'_> {
+    pub(super) fn make_backup(&mut self) -> Result<(), Box<dyn Error>> {
+        self.save_checkpoint(None)
+    }
+
+    /// Describe the current run, asking the server for the details it knows about.
+    pub(super) fn checkpoint_meta(&mut self) -> CheckpointMeta {
+        let (seed, day) = match self.config.server_type {
+            ServerType::Java => {
+                let timeout = Duration::from_secs(2);
+                self.cmd("seed".to_string());
+                //The world keeps its seed on disk too, for servers that hide the command
+                let seed = self
+                    .events
+                    .wait_for_output(timeout, parse_seed)
+                    .or_else(|| Some(LevelInfo::read(&self.config.world).ok()?.seed?.to_string()));
+                self.cmd("time query day".to_string());
+                (seed, self.events.wait_for_output(timeout, parse_day))
+            }
+            //BDS has no `seed` command
+            ServerType::Bedrock => (None, None),
+        };
+        let mut online_players = self.online_players.iter().cloned().collect::<Vec<_>>();
+        online_players.sort();
+        CheckpointMeta {
+            playtime_secs: self.playtime.as_secs(),
+            day,
+            attempt: self.state.resets + 1,
+            online_players,
+            seed,
+            advancements: self.state.advancements,
+            deaths: self.state.deaths,
+            //Measured when the checkpoint is written
+            world_size: 0,
+        }
+    }
+
+    /// Save a checkpoint, either an automatic one or one with a name.
+    pub(super) fn save_checkpoint(&mut self, label: Option<&str>) -> Result<(), Box<dyn Error>> {
+        let (partial, done) = match label {
+            Some(label) => self.backups.named_paths(label)?,
+            None => self.backups.new_paths(unix_now())?,
+        };
+        info!("making backup");
+        //Better no checkpoint than a truncated one
+        if let Err(err) = self
+            .backups
+            .check_backup_space(self.world_path, &self.config.dimensions())
+        {
+            self.announcer.say(Message::new(
+                Kind::Checkpoint,
+                "Not enough disk space for a checkpoint! Tell an admin.",
+            ));
+            self.notifier
+                .notify("Checkpoint skipped", err.to_string(), notify::RED);
+            return Err(err);
+        }
+        let meta = self.checkpoint_meta();
+        let res = self.copy_world(&partial, &done, &meta, false);
+        if res.is_ok() {
+            let title = match label {
+                Some(label) => format!("Checkpoint \"{}\" saved", label),
+                None => "Checkpoint".to_string(),
+            };
+            self.notifier
+                .notify(title, describe_meta(&meta), notify::BLUE);
+            let event = json!({ "name": label, "meta": meta });
+            self.emit("checkpoint", &event);
+            self.feed.publish("checkpoint", event);
+        }
+        if let (Ok(()), Some(uploader)) = (&res, &self.uploader) {
+            uploader.queue(done.clone());
+        }
+        match (&res, label) {
+            (Ok(()), Some(label)) => self.announcer.say(Message::new(
+                Kind::Checkpoint,
+                format!("Checkpoint \"{}\" saved!", label),
+            )),
+            (Ok(()), None) => self
+                .announcer
+                .say(Message::new(Kind::Checkpoint, "Checkpoint!")),
+            (Err(_), _) => {
+                self.announcer
+                    .say(Message::new(Kind::Checkpoint, "Checkpoint failed!"));
+                remove_path(&partial)?;
+            }
+        }
+        res?;
+        //Rotate out old checkpoints
+        if label.is_none() {
+            self.backups.prune()?;
+        }
+        Ok(())
+    }
+
+    /// Copy the world to `partial` and then `done` with saving off, as a checkpoint or, if
+    /// `archive` is set, as a standalone archive.
+    pub(super) fn copy_world(
+        &mut self,
+        partial: &Path,
+        done: &Path,
+        meta: &CheckpointMeta,
+        archive: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        if self.config.server_type == ServerType::Bedrock {
+            return self.copy_held_world(partial, done, meta, archive);
+        }
+        //Force server to backup, and wait until it says the world is on disk
+        let timeout = Duration::from_secs(self.config.save_timeout_secs);
+        self.cmd("save-all".to_string());
+        let saved = self.events.wait_for_output(timeout, |line| {
+            line.contains("Saved the game").then_some(())
+        });
+        if saved.is_none() {
+            warn!("server did not confirm the save, copying the world anyway");
+        }
+        self.state.checkpoint_in_progress = Some(partial.to_path_buf());
+        self.save_state();
+        self.cmd("save-off".to_string());
+        let disabled = self.events.wait_for_output(timeout, |line| {
+            line.contains("Automatic saving is now disabled")
+                .then_some(())
+        });
+        if disabled.is_none() {
+            warn!("server did not confirm that saving is disabled");
+        }
+        let dimensions = self.config.dimensions();
+        let res = self.copy_retrying(self.world_path, &dimensions, partial, done, meta, archive);
+        //Re-enable saving, even if the backup failed
+        self.cmd("save-on".to_string());
+        self.state.checkpoint_in_progress = None;
+        self.save_state();
+        res
+    }
+
+    /// Back up a Bedrock world with `save hold`, copying only as much of each file as the server
+    /// says is saved.
+    fn copy_held_world(
+        &mut self,
+        partial: &Path,
+        done: &Path,
+        meta: &CheckpointMeta,
+        archive: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        let timeout = Duration::from_secs(self.config.save_timeout_secs);
+        self.state.checkpoint_in_progress = Some(partial.to_path_buf());
+        self.save_state();
+        //Ask until the server has finished saving and lists the files to copy
+        self.cmd("save hold".to_string());
+        let deadline = Instant::now() + timeout;
+        let files = loop {
+            self.cmd("save query".to_string());
+            let mut ready = false;
+            let files = self.events.wait_for_output(Duration::from_secs(2), |line| {
+                if line.contains("Files are now ready to be copied") {
+                    ready = true;
+                    None
+                } else if ready {
+                    bedrock::parse_file_list(line)
+                } else {
+                    None
+                }
+            });
+            if files.is_some() || Instant::now() >= deadline {
+                break files;
+            }
+        };
+        let staging = bedrock::staging_path(partial);
+        let res = match files {
+            Some(files) => bedrock::stage_world(self.world_path, &files, &staging)
+                .and_then(|()| self.copy_retrying(&staging, &[], partial, done, meta, archive)),
+            None => {
+                warn!("server did not list the files to copy, copying the world anyway");
+                self.copy_retrying(self.world_path, &[], partial, done, meta, archive)
+            }
+        };
+        //Let the server write again, even if the backup failed
+        self.cmd("save resume".to_string());
+        let cleaned = remove_path(&staging);
+        self.state.checkpoint_in_progress = None;
+        self.save_state();
+        res.and(cleaned)
+    }
+
+    /// Copy the world at `src` and its `dimensions` into a checkpoint, retrying a few times.
+    fn copy_retrying(
+        &self,
+        src: &Path,
+        dimensions: &[PathBuf],
+        partial: &Path,
+        done: &Path,
+        meta: &CheckpointMeta,
+        archive: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut attempt = 0;
+        loop {
+            let res = self.copy_backup(src, dimensions, partial, done, meta, archive);
+            match res {
+                Err(err) if attempt < self.config.backup_retries => {
+                    attempt += 1;
+                    warn!("failed to copy backup (attempt {}): {}", attempt, err);
+                    thread::sleep(Duration::from_secs(1));
+                }
+                res => break res,
+            }
+        }
+    }
+
+    fn copy_backup(
+        &self,
+        src: &Path,
+        dimensions: &[PathBuf],
+        partial: &Path,
+        done: &Path,
+        meta: &CheckpointMeta,
+        archive: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        self.faults.fail(Fault::BackupCopy)?;
+        match archive {
+            true => self
+                .backups
+                .write_archive(src, dimensions, partial, done, meta),
+            false => self.backups.write(src, dimensions, partial, done, meta),
+        }
+    }
+
+    /// Report how a checkpoint upload went.
+    pub(super) fn on_upload_done(&self, name: &str, error: Option<String>) {
+        match &error {
+            None => info!("checkpoint \"{}\" uploaded", name),
+            Some(err) => {
+                error!("failed to upload checkpoint \"{}\": {}", name, err);
+                self.notifier.notify(
+                    "Checkpoint upload failed",
+                    format!("\"{}\" is only on the local disk: {}", name, err),
+                    notify::RED,
+                );
+                self.announcer
+                    .say(Message::new(Kind::Checkpoint, "Checkpoint upload failed!"));
+            }
+        }
+        self.feed
+            .publish("upload", json!({ "name": name, "error": error }));
+    }
+
+    /// Returns whether the server has been empty for long enough to make a logoff checkpoint.
+    pub(super) fn logoff_checkpoint_due(&mut self) -> bool {
+        let minutes = match self.config.checkpoint_on_logoff_minutes {
+            Some(minutes) => minutes,
+            None => return false,
+        };
+        match self.empty_since {
+            Some(since) if since.elapsed() >= Duration::from_secs(minutes * 60) => {
+                info!("server has been empty for {} minutes", minutes);
+                self.empty_since = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether a checkpoint from `checkpoint_schedule` is due, which is skipped if nobody is online
+    /// to change the world.
+    pub(super) fn scheduled_checkpoint_due(&mut self) -> bool {
+        let now = Local::now();
+        match self.next_scheduled {
+            Some(at) if now >= at => {
+                self.next_scheduled = Schedule::next_of(&self.config.checkpoint_schedule, now);
+                if self.online_players.is_empty() {
+                    info!("nobody is online, skipping the scheduled checkpoint");
+                    false
+                } else {
+                    info!("scheduled checkpoint is due");
+                    true
+                }
+            }
+            _ => false,
+        }
+    }
+}
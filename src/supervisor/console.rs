@@ -0,0 +1,295 @@
+//! Commands typed on the wrapper console or sent through the HTTP API.
+
+use super::{describe_meta, Session, Unmatched};
+use crate::{
+    api::{ApiAction, ApiRequest},
+    backups::Checkpoint,
+    config::load_config,
+    events::Event,
+    fairness::Commitment,
+    fmt_duration,
+    matcher::PrefixMatcher,
+    penalty::Penalty,
+    schedule::Schedule,
+    script::Script,
+    unix_now,
+};
+use chrono::Local;
+use log::{error, info};
+use serde_json::json;
+use std::{
+    collections::{BTreeMap, HashSet},
+    error::Error,
+    path::Path,
+    rc::Rc,
+    time::Duration,
+};
+
+impl Session<'_> {
+    /// Handle a line typed into the wrapper console.
+    ///
+    /// Lines starting with `.` are wrapper commands, anything else is passed on to the server.
+    pub(super) fn on_console(&mut self, line: &str) -> Result<Penalty, Box<dyn Error>> {
+        let line = match line.strip_prefix('.') {
+            Some(line) => line.trim(),
+            None => {
+                self.cmd(line.to_string());
+                return Ok(Penalty::None);
+            }
+        };
+        let mut words = line.split_whitespace();
+        match words.next().unwrap_or("") {
+            "simulate" => match (words.next(), words.next(), words.next()) {
+                (Some("death"), Some(username), dry) => {
+                    let dry = match dry {
+                        None => false,
+                        Some("dry") => true,
+                        Some(other) => {
+                            eprintln!("unknown simulate option \"{}\"", other);
+                            return Ok(Penalty::None);
+                        }
+                    };
+                    info!(
+                        "simulating death of {}{}",
+                        username,
+                        if dry { " (dry run)" } else { "" }
+                    );
+                    if dry {
+                        self.announcer
+                            .say("Simulated death, the outcome will not be applied");
+                    }
+                    self.on_death(
+                        username,
+                        &format!("{} died (simulated)", username),
+                        dry,
+                        None,
+                        None,
+                    );
+                }
+                _ => eprintln!("usage: .simulate death <player> [dry]"),
+            },
+            "roll" => match (words.next(), words.next()) {
+                (Some(username), None) => {
+                    info!("rolling for {} as if they died", username);
+                    self.on_death(
+                        username,
+                        &format!("{} was made to roll by an admin", username),
+                        false,
+                        None,
+                        None,
+                    );
+                }
+                _ => eprintln!("usage: .roll <player>"),
+            },
+            "backup" => {
+                if self.config.make_backups {
+                    info!("checkpoint requested from the console");
+                    self.checkpoint_requested = true;
+                } else {
+                    eprintln!("checkpoints are disabled by `make_backups`");
+                }
+            }
+            "reload" => {
+                let _ = self.events.sender().send(Event::Reload);
+            }
+            "reload-config" => self.reload_requested = true,
+            "stop-run" => {
+                info!("stopping the run from the console");
+                let _ = self.events.sender().send(Event::Shutdown);
+            }
+            "status" => {
+                let mut online = self.online_players.iter().cloned().collect::<Vec<_>>();
+                online.sort();
+                eprintln!("status:");
+                eprintln!("    playtime: {}", fmt_duration(self.playtime));
+                eprintln!("    {} players online: {}", online.len(), online.join(", "));
+                let stats = &self.line_stats;
+                eprintln!("    output lines matched: {}", stats.matched);
+                eprintln!("    output lines unmatched:");
+                for &(why, count) in &[
+                    (Unmatched::Brackets, stats.brackets),
+                    (Unmatched::Username, stats.username),
+                    (Unmatched::Untracked, stats.untracked),
+                    (Unmatched::Ignored, stats.ignored),
+                    (Unmatched::NoPattern, stats.no_pattern),
+                ] {
+                    eprintln!("        {}: {}", why.describe(), count);
+                }
+            }
+            "checkpoint" => match (words.next(), words.next()) {
+                (Some("save"), Some(label)) => {
+                    if let Err(err) = self.save_checkpoint(Some(label)) {
+                        eprintln!("failed to save checkpoint \"{}\": {}", label, err);
+                    }
+                }
+                (Some("restore"), Some(label)) => match self.restore_named(label) {
+                    Ok(penalty) => return Ok(penalty),
+                    Err(err) => eprintln!("not restoring: {}", err),
+                },
+                (Some("list"), None) => {
+                    let now = unix_now();
+                    let ago = |created: u64| {
+                        fmt_duration(Duration::from_secs(now.saturating_sub(created)))
+                    };
+                    let show = |ckpt: &Checkpoint| match ckpt.meta() {
+                        Some(meta) => eprintln!(
+                            "    {} (made {} ago, {})",
+                            ckpt.name,
+                            ago(ckpt.created),
+                            describe_meta(&meta)
+                        ),
+                        None => eprintln!("    {} (made {} ago)", ckpt.name, ago(ckpt.created)),
+                    };
+                    eprintln!("automatic checkpoints:");
+                    self.backups.list()?.iter().for_each(show);
+                    eprintln!("named checkpoints:");
+                    self.backups.list_named()?.iter().for_each(show);
+                }
+                _ => eprintln!("usage: .checkpoint save <name> | restore <name> | list"),
+            },
+            "help" => {
+                eprintln!("wrapper commands:");
+                eprintln!("    .status    show playtime, online players and line counters");
+                eprintln!("    .simulate death <player> [dry]");
+                eprintln!("               run a death through the roll and penalty, without");
+                eprintln!("               applying the penalty if `dry` is given");
+                eprintln!("    .checkpoint save <name> | restore <name> | list");
+                eprintln!("               manage named checkpoints, which are never rotated out");
+                eprintln!("    .backup    make a checkpoint right away");
+                eprintln!("    .roll <player>");
+                eprintln!("               roll for a player as if they died, applying the outcome");
+                eprintln!(
+                    "    .reload    read the config file again and apply what can change while"
+                );
+                eprintln!("               the server runs, also done on SIGHUP");
+                eprintln!("    .reload-config");
+                eprintln!("               check the config file and restart the server with it");
+                eprintln!("    .stop-run  save, stop the server and exit the wrapper");
+                eprintln!("    .help      show this message");
+                eprintln!("anything not starting with `.` is sent to the server");
+            }
+            other => eprintln!("unknown wrapper command \".{}\", try \".help\"", other),
+        }
+        Ok(Penalty::None)
+    }
+
+    /// Answer a request from the HTTP API.
+    pub(super) fn on_api(&mut self, req: ApiRequest) -> Result<Penalty, Box<dyn Error>> {
+        let mut penalty = Penalty::None;
+        let res = match &req.action {
+            ApiAction::Status => Ok(self.status_json()),
+            ApiAction::Backup(None) => {
+                info!("checkpoint requested through the api");
+                self.checkpoint_requested = true;
+                Ok(json!({ "queued": true }))
+            }
+            ApiAction::Backup(Some(label)) => match self.save_checkpoint(Some(label)) {
+                Ok(()) => Ok(json!({ "saved": label })),
+                Err(err) => Err(format!("failed to save checkpoint \"{}\": {}", label, err)),
+            },
+            ApiAction::RestoreNamed(label) => self.restore_named(label).map(|restore| {
+                penalty = restore;
+                json!({ "restoring": label })
+            }),
+            ApiAction::RestoreSlot(slot) => match self.backups.list()?.into_iter().nth(*slot) {
+                Some(ckpt) if self.check_requested_restore(&ckpt).is_err() => {
+                    Err(format!("checkpoint in slot {} is damaged", slot))
+                }
+                Some(ckpt) => {
+                    info!("restoring checkpoint \"{}\" through the api", ckpt.name);
+                    self.announcer.say("Rewinding to an earlier checkpoint");
+                    let name = ckpt.name.clone();
+                    self.rewind_to = Some(ckpt);
+                    penalty = Penalty::Rewind;
+                    Ok(json!({ "restoring": name }))
+                }
+                None => Err(format!("no checkpoint in slot {}", slot)),
+            },
+            ApiAction::Command(cmd) => {
+                info!("sending command through the api: {}", cmd);
+                self.cmd(cmd.clone());
+                Ok(json!({ "sent": cmd }))
+            }
+        };
+        req.respond(res);
+        Ok(penalty)
+    }
+
+    fn status_json(&self) -> serde_json::Value {
+        let mut online = self.online_players.iter().cloned().collect::<Vec<_>>();
+        online.sort();
+        let lives = self.lives.as_ref().map(|lives| {
+            self.players
+                .iter()
+                .map(|player| (self.state.player_name(player), lives.left(player)))
+                .collect::<BTreeMap<_, _>>()
+        });
+        json!({
+            "online_players": online,
+            "playtime_secs": self.playtime.as_secs(),
+            "ready": self.ready_deadline.is_none(),
+            "playtime_held": self.afk_paused,
+            "won": self.state.won,
+            "attempt": self.state.resets + 1,
+            "advancements": self.state.advancements,
+            "deaths": self.state.deaths,
+            "last_roll": self.state.last_roll,
+            "lives": lives,
+            "player_playtime": self
+                .state
+                .playtime_leaderboard()
+                .into_iter()
+                .collect::<BTreeMap<_, _>>(),
+        })
+    }
+
+    /// Read the config file again and apply the settings that can change while the server runs,
+    /// such as `players`, `ignore_phrases`, the rolls, the messages and checkpoint timing.
+    pub(super) fn reload(&mut self, path: &Path) {
+        //Pending deaths refer to death rules by position, so wait until they are settled
+        if !self.pending_deaths.is_empty() {
+            info!("reloading the config once the pending deaths are settled");
+            self.reload_deferred = true;
+            return;
+        }
+        self.reload_deferred = false;
+        let mut config = match load_config(path) {
+            Ok(config) => config,
+            Err(err) => {
+                error!("not reloading, the config is invalid: {}", err);
+                return;
+            }
+        };
+        let ignore_phrases = match PrefixMatcher::new(&config.ignore_phrases) {
+            Ok(matcher) => matcher,
+            Err(err) => {
+                error!("not reloading, invalid `ignore_phrases`: {}", err);
+                return;
+            }
+        };
+        //The world of the attempt is already settled
+        config.world = self.config.world.clone();
+        let mut players = HashSet::new();
+        for player in config.players.drain(..) {
+            players.insert(self.state.track(&player));
+        }
+        self.save_state();
+        info!("{} deadly players", players.len());
+        self.players = players;
+        self.ignore_phrases = ignore_phrases;
+        self.escalation_level = config.escalation_level(self.playtime, self.season_deaths);
+        self.penalties = config.global_engine(self.escalation_level);
+        if config.fair_rolls.enabled != self.fair.is_some() {
+            self.fair = config.fair_rolls.enabled.then(Commitment::new);
+        }
+        self.next_scheduled = Schedule::next_of(&config.checkpoint_schedule, Local::now());
+        self.announcer.set_style(config.message_style.clone());
+        match config.script.as_deref().map(Script::load) {
+            Some(Ok(script)) => self.script = Some(script),
+            Some(Err(err)) => error!("keeping the old script, the new one is invalid: {}", err),
+            None => self.script = None,
+        }
+        self.config = Rc::new(config);
+        info!("config reloaded, changes to the server, world, backups, lives, plugins or logging apply on the next restart");
+    }
+}
@@ -0,0 +1,749 @@
+//! The death pipeline: from a death message, through the rules, the roll and the ceremony, to
+//! the penalty.
+
+use super::{
+    join_names,
+    questions::{Question, REPLY_TIMEOUT},
+    roll_audit_path, Session, Unmatched,
+};
+use crate::{
+    announce::{self, Kind, Message, Step, When},
+    chat::{Ballot, DeathVoteMode},
+    config::{Config, MultiDeathMode, RewindScope},
+    events::Event,
+    fairness::{AuditEntry, Commitment},
+    fmt_duration,
+    history::DeathRecord,
+    hooks, logging,
+    nbt::PlayerInfo,
+    notify,
+    parse::{is_username_char, Location},
+    penalty::{DeathRule, Dice, Outcome, Penalty, PenaltyEngine, Roll, RuleAction},
+    state::{RollRecord, State},
+    unix_now,
+};
+use log::{info, warn};
+use serde_json::json;
+use std::{
+    collections::{BTreeMap, HashMap},
+    rc::Rc,
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// A death whose ceremony is still playing, with its outcome on hold until it finishes.
+pub(super) struct PendingDeath {
+    pub(super) id: u64,
+    /// Everyone who died, more than one when deaths are grouped.
+    pub(super) players: Vec<String>,
+    /// The roll, or nothing while the group is still gathering deaths.
+    roll: Option<Roll>,
+    /// Whether this is a simulated death whose penalty should not be applied.
+    dry: bool,
+    /// Which of the `death_rules` decides the outcome, if any.
+    rule: Option<usize>,
+    /// Votes cast so far, once the `death_vote` on this death has opened.
+    pub(super) ballots: Option<HashMap<String, Ballot>>,
+}
+
+/// A death on its way to `on_death`, while the server is asked about it.
+pub(super) struct DeathReport {
+    pub(super) username: String,
+    /// The line that told of the death.
+    pub(super) line: String,
+    /// What comes after the player's name.
+    msg: String,
+    /// The dimension the player died in, once the server was asked, if it said.
+    pub(super) dimension: Option<Option<String>>,
+}
+
+impl DeathReport {
+    /// Whether a reply to `data get entity` is about the player who died.
+    pub(super) fn is_about(&self, line: &str) -> bool {
+        line.contains(&format!(
+            "{} has the following entity data: ",
+            self.username
+        ))
+    }
+}
+
+/// A death rule depends on the dimension a player died in, which the server has yet to be asked.
+struct NeedDimension;
+
+/// The engine to roll with for a death, if a death rule or the `player_rolls` of whoever died call
+/// for something other than the global settings.
+pub(super) fn roll_engine(
+    config: &Config,
+    state: &State,
+    players: &[String],
+    rule: Option<&DeathRule>,
+    escalation_level: usize,
+) -> Option<PenaltyEngine> {
+    let rule = rule.filter(|rule| rule.has_own_rolls());
+    let own = match players {
+        [player] => config.player_rolls(player, state.player_id(player)),
+        _ => None,
+    };
+    if rule.is_none() && own.is_none() {
+        return None;
+    }
+    let (dice, deadly_rolls, roll_outcomes) = config.escalated_rolls(escalation_level);
+    let dice = own
+        .and_then(|own| own.roll_range)
+        .map(Dice::from_range)
+        .unwrap_or(dice);
+    let no_outcomes = BTreeMap::new();
+    let (deadly_rolls, roll_outcomes) = match (rule, own.and_then(|own| own.deadly_rolls.as_ref()))
+    {
+        (Some(rule), _) => (&rule.deadly_rolls, &rule.roll_outcomes),
+        (None, Some(deadly_rolls)) => (deadly_rolls, &no_outcomes),
+        (None, None) => (&deadly_rolls, &roll_outcomes),
+    };
+    Some(PenaltyEngine::new(
+        dice,
+        &config.roll_weights,
+        deadly_rolls,
+        roll_outcomes,
+    ))
+}
+
+/// Ids for pending deaths, unique across sessions so that a late ceremony never matches a death
+/// from another session.
+static NEXT_DEATH_ID: AtomicU64 = AtomicU64::new(0);
+
+impl Session<'_> {
+    /// A player died, as told by `line`, of which `msg` is what comes after their name.
+    pub(super) fn on_death_msg(
+        &mut self,
+        username: &str,
+        line: &str,
+        msg: &str,
+    ) -> Result<Penalty, Unmatched> {
+        if self.ignore_phrases.matches(msg) {
+            return Err(Unmatched::Ignored);
+        }
+        self.on_death_report(DeathReport {
+            username: username.to_string(),
+            line: line.to_string(),
+            msg: msg.to_string(),
+            dimension: None,
+        })
+    }
+
+    /// Carry on with a death once the server said what the death rules need to know about it.
+    pub(super) fn on_death_report(&mut self, death: DeathReport) -> Result<Penalty, Unmatched> {
+        let rule = match self.death_rule(&death) {
+            Ok(rule) => rule,
+            Err(NeedDimension) => {
+                self.cmd(format!("data get entity {} Dimension", death.username));
+                self.ask(Question::Dimension(death), REPLY_TIMEOUT);
+                return Ok(Penalty::None);
+            }
+        };
+        let username = death.username.as_str();
+        if let Some(idx) = rule {
+            let rule = &self.config.death_rules[idx];
+            info!(
+                "death of {} matches death rule \"{}\" ({:?})",
+                username,
+                rule.pattern.as_str(),
+                rule.action
+            );
+            if let RuleAction::Ignore = rule.action {
+                return Err(Unmatched::Ignored);
+            }
+        }
+        //Player died
+        self.afk.active(username);
+        self.state.deaths += 1;
+        self.save_state();
+        if self.state.won.is_some() {
+            info!("{} died, but the run is already won", username);
+            return Ok(Penalty::None);
+        }
+        self.season_deaths += 1;
+        self.check_escalation();
+        if self.config.death_location.enabled() {
+            //Servers since 1.19 remember it
+            self.cmd(format!("data get entity {} LastDeathLocation", username));
+            self.ask(Question::DeathLocation(death, rule), REPLY_TIMEOUT);
+            return Ok(Penalty::None);
+        }
+        self.on_death(username, &death.line, false, rule, None);
+        Ok(Penalty::None)
+    }
+
+    /// The first of the `death_rules` that applies to a death, if any. Only asks the server which
+    /// dimension the player died in if a rule cares, by failing with `NeedDimension`.
+    fn death_rule(&self, death: &DeathReport) -> Result<Option<usize>, NeedDimension> {
+        for (idx, rule) in self.config.death_rules.iter().enumerate() {
+            if !rule.pattern.is_match(&death.msg) {
+                continue;
+            }
+            if rule.pvp && !self.names_other_player(&death.username, &death.msg) {
+                continue;
+            }
+            if let Some(want) = &rule.dimension {
+                let dim = death.dimension.as_ref().ok_or(NeedDimension)?;
+                if dim.as_ref() != Some(want) {
+                    continue;
+                }
+            }
+            return Ok(Some(idx));
+        }
+        Ok(None)
+    }
+
+    /// Whether a death message names a player other than the one who died.
+    fn names_other_player(&self, username: &str, msg: &str) -> bool {
+        msg.split(|c: char| !is_username_char(c)).any(|word| {
+            word != username && (self.is_tracked(word) || self.online_players.contains(word))
+        })
+    }
+
+    /// Where a player died, going by the last place the server saved them at, for servers that do
+    /// not say.
+    pub(super) fn saved_location(&mut self, username: &str) -> Option<Location> {
+        match PlayerInfo::read(&self.config.world, self.state.player_id(username)) {
+            Ok(player) => {
+                warn!(
+                    "the server did not say where {} died, going by their saved player data",
+                    username
+                );
+                Some(Location {
+                    dimension: player.dimension,
+                    pos: player.pos.map(|n| n.floor() as i32),
+                })
+            }
+            Err(err) => {
+                warn!("could not tell where {} died: {}", username, err);
+                None
+            }
+        }
+    }
+
+    /// Handle a death, where `death_msg` is the message the server printed.
+    ///
+    /// The ceremony plays in the background, and the outcome is applied by `finish_death` once
+    /// it is over, so output keeps being parsed in the meantime.
+    pub(super) fn on_death(
+        &mut self,
+        username: &str,
+        death_msg: &str,
+        dry: bool,
+        rule: Option<usize>,
+        location: Option<Location>,
+    ) {
+        let config = Rc::clone(&self.config);
+        info!("player {} died", username);
+        let cooldown = Duration::from_secs(config.roll_cooldown_seconds);
+        let immune = self
+            .survived_at
+            .get(username)
+            .is_some_and(|at| at.elapsed() < cooldown);
+        let mut event = json!({
+            "player": username,
+            "username": username,
+            "message": death_msg,
+            "immune": immune,
+        });
+        let mut description = death_msg.to_string();
+        if let Some(location) = &location {
+            info!("{} died at {}", username, location);
+            description += &format!("\nat {}", location);
+            let [x, y, z] = location.pos;
+            event["x"] = x.into();
+            event["y"] = y.into();
+            event["z"] = z.into();
+            event["dimension"] = location.dimension.clone().into();
+        }
+        self.notifier
+            .notify(format!("{} died", username), description, notify::RED);
+        self.feed.publish("death", event.clone());
+        self.emit("player_death", &event);
+        if let Some(location) = &location {
+            if config.death_location.announce {
+                self.announcer.say(Message::new(
+                    Kind::Death,
+                    format!("{} died at {}", username, location),
+                ));
+            }
+            if let Some(marker) = &config.death_location.marker_command {
+                self.cmd(hooks::fill(marker, &event));
+            }
+        }
+        if let Some(death_cmd) = config.on_death_command.as_ref() {
+            if !(immune && config.roll_cooldown_skips_death_command) {
+                self.cmd(hooks::fill(death_cmd, &event));
+            }
+        }
+        if immune {
+            info!("{} is within the roll cooldown, not rolling", username);
+            self.announcer.say(Message::new(
+                Kind::Death,
+                format!(
+                    "{} died again, but the dice are still cooling down",
+                    username
+                ),
+            ));
+            return;
+        }
+        if let MultiDeathMode::Group = config.multi_death_mode {
+            //Join the group that is still gathering deaths, if any
+            if let Some(group) = self
+                .pending_deaths
+                .back_mut()
+                .filter(|group| group.roll.is_none() && group.dry == dry && group.rule == rule)
+            {
+                if !group.players.iter().any(|player| player == username) {
+                    group.players.push(username.to_string());
+                }
+                return;
+            }
+            let id = NEXT_DEATH_ID.fetch_add(1, Ordering::Relaxed);
+            self.pending_deaths.push_back(PendingDeath {
+                id,
+                players: vec![username.to_string()],
+                roll: None,
+                dry,
+                rule,
+                ballots: None,
+            });
+            let events = self.events.sender().clone();
+            let window = Duration::from_millis(config.multi_death_window_ms);
+            logging::spawn(move || {
+                thread::sleep(window);
+                let _ = events.send(Event::DeathGroupClosed(id));
+            });
+        } else {
+            let id = NEXT_DEATH_ID.fetch_add(1, Ordering::Relaxed);
+            self.pending_deaths.push_back(PendingDeath {
+                id,
+                players: vec![username.to_string()],
+                roll: None,
+                dry,
+                rule,
+                ballots: None,
+            });
+            self.roll_death(id);
+        }
+    }
+
+    /// Roll for a pending death and queue its ceremony.
+    pub(super) fn roll_death(&mut self, id: u64) {
+        let config = Rc::clone(&self.config);
+        let pending = match self.pending_deaths.iter_mut().find(|p| p.id == id) {
+            Some(pending) if pending.roll.is_none() => pending,
+            _ => return,
+        };
+        let username = join_names(&pending.players);
+        let dry = pending.dry;
+        let rule = pending.rule.map(|idx| &config.death_rules[idx]);
+        //A rule may decide the outcome outright, or roll with its own outcomes
+        let own_engine = roll_engine(
+            &config,
+            &self.state,
+            &pending.players,
+            rule,
+            self.escalation_level,
+        );
+        let engine = own_engine.as_ref().unwrap_or(&self.penalties);
+        if let Some(RuleAction::Always(outcome)) = rule.map(|rule| &rule.action) {
+            info!(
+                "no roll for the death of {}, it is always {:?}",
+                username, outcome
+            );
+            let what = match outcome {
+                Outcome::Reset => "the world pays for it",
+                Outcome::Rewind => "back to a checkpoint",
+                Outcome::Border => "the world border closes in",
+                Outcome::Spectator => "time out on the sidelines",
+                Outcome::Ban => "banned for a while",
+                Outcome::None | Outcome::Command(_) => "the run goes on",
+            };
+            pending.roll = Some(Roll::forced(outcome.clone()));
+            self.announcer.say(Message::new(
+                Kind::Death,
+                format!("{} died, no dice for that one: {}", username, what),
+            ));
+            let events = self.events.sender().clone();
+            self.announcer.then(move || {
+                let _ = events.send(Event::CeremonyDone(id));
+            });
+            return;
+        }
+        //The script may pick the roll itself
+        let (low, high) = engine.dice().range();
+        let scripted = self.script.as_mut().and_then(|script| {
+            script.roll(&json!({
+                "player": username,
+                "players": pending.players,
+                "low": low,
+                "high": high,
+            }))
+        });
+        //A fair roll comes from a secret picked before the death, revealed once the roll is in
+        let (roll, fair) = match scripted {
+            Some(num) => {
+                info!("the script rolled {} for the death of {}", num, username);
+                (engine.roll_of(num), None)
+            }
+            None => {
+                info!("rolling dice for the death of {}", username);
+                //A simulation must not use up the commitment or write to the audit file
+                match self.fair.take_if(|_| !dry) {
+                    Some(commitment) => {
+                        let at = unix_now();
+                        let event =
+                            format!("death of {} #{} at {}", username, self.state.deaths, at);
+                        let roll = engine.roll_with(&mut commitment.rng(&event));
+                        let entry = AuditEntry {
+                            at,
+                            event,
+                            commitment: commitment.hash.clone(),
+                            secret: commitment.secret().to_string(),
+                            dice: engine.dice().to_string(),
+                            roll_weights: config.roll_weights.clone(),
+                            roll: roll.num,
+                            rolled_dice: roll.dice.clone(),
+                        };
+                        if let Err(err) = entry.append(&roll_audit_path(&config)) {
+                            warn!("failed to write roll to the audit file: {}", err);
+                        }
+                        self.fair = Some(Commitment::new());
+                        (roll, Some(commitment))
+                    }
+                    None => (engine.roll(), None),
+                }
+            }
+        };
+        let num = roll.num;
+        let breakdown = roll
+            .breakdown()
+            .map(|breakdown| format!(" ({})", breakdown))
+            .unwrap_or_default();
+        let deadly_rolls = engine.deadly_rolls();
+        let vote = &config.chat_commands.death_vote;
+        let vote_on = (config.chat_commands.enabled || config.twitch.is_some())
+            && match vote.mode {
+                DeathVoteMode::Off => false,
+                DeathVoteMode::Replace => true,
+                DeathVoteMode::Appeal => roll.deadly,
+            };
+        let mut ceremony = Vec::new();
+        //The script may write its own ceremony
+        let scripted = match vote.mode {
+            DeathVoteMode::Replace if vote_on => None,
+            _ => self.script.as_mut().and_then(|script| {
+                script.ceremony(&json!({
+                    "player": username,
+                    "roll": num,
+                    "outcome": roll.outcome.name(),
+                    "deadly": roll.deadly,
+                    "deadly_rolls": deadly_rolls,
+                }))
+            }),
+        };
+        //When the vote stands in for the dice, the roll only comes up on a tie
+        let shown = match vote.mode {
+            DeathVoteMode::Replace if vote_on => &[][..],
+            _ if scripted.is_some() => &[][..],
+            _ => &config.ceremony[..],
+        };
+        ceremony.extend(scripted.into_iter().flatten());
+        for step in shown {
+            let plays = match step.when {
+                When::Always => true,
+                When::Deadly => roll.deadly,
+                When::Safe => !roll.deadly,
+            };
+            if !plays {
+                continue;
+            }
+            if let Some(say) = &step.say {
+                let text = say
+                    .replace("{username}", &username)
+                    .replace("{roll}", &num.to_string())
+                    .replace("{breakdown}", &breakdown)
+                    .replace("{outcome}", roll.outcome.name())
+                    .replace("{deadly_rolls}", &deadly_rolls);
+                //Hovering over the roll tells which numbers would have been deadly
+                let msg = if say.contains("{roll}") {
+                    Message::new(Kind::Roll, text).hover(format!("Deadly rolls: {}", deadly_rolls))
+                } else {
+                    Message::new(Kind::Death, text)
+                };
+                if config.ceremony_display.titles {
+                    ceremony.push(Step::Title(msg));
+                } else {
+                    ceremony.push(Step::Say(msg));
+                }
+            }
+            if step.pause > 0.0 {
+                ceremony.push(Step::Pause(Duration::from_secs_f32(step.pause)));
+            }
+        }
+        if config.ceremony_display.bossbar {
+            ceremony = announce::with_countdown(ceremony);
+        }
+        if let Some(commitment) = &fair {
+            ceremony.insert(
+                0,
+                Step::Say(format!("Fair roll, commitment {}", commitment.hash).into()),
+            );
+        }
+        pending.roll = Some(roll);
+        //Apply the outcome once the ceremony plays out, after any other queued announcements
+        self.announcer.sequence(ceremony);
+        let mut closing = Vec::new();
+        if vote_on {
+            let events = self.events.sender().clone();
+            self.announcer.then(move || {
+                let _ = events.send(Event::VoteOpened(id));
+            });
+            let question = match vote.mode {
+                DeathVoteMode::Appeal => format!(
+                    "Spare the world from {}'s roll? Vote !vote mercy or !vote doom within {}s",
+                    username, vote.window_seconds
+                ),
+                _ => format!(
+                    "{} died, does the world reset? Vote !vote mercy or !vote doom within {}s",
+                    username, vote.window_seconds
+                ),
+            };
+            closing.push(Step::Say(
+                Message::new(Kind::Death, question)
+                    .hover("Click to vote for mercy")
+                    .suggest("!vote mercy"),
+            ));
+            closing.push(Step::Pause(Duration::from_secs(vote.window_seconds)));
+        }
+        if let Some(commitment) = &fair {
+            closing.push(Step::Say(
+                format!("Roll secret: {}", commitment.secret()).into(),
+            ));
+        }
+        self.announcer.sequence(closing);
+        let events = self.events.sender().clone();
+        self.announcer.then(move || {
+            let _ = events.send(Event::CeremonyDone(id));
+        });
+        self.flush_script();
+    }
+
+    /// Apply the outcome of a death once its ceremony is over.
+    pub(super) fn finish_death(&mut self, id: u64) -> Penalty {
+        match self.pending_deaths.front() {
+            Some(pending) if pending.id == id && pending.roll.is_some() => {}
+            _ => return Penalty::None,
+        }
+        let PendingDeath {
+            players,
+            roll,
+            dry,
+            ballots,
+            ..
+        } = self.pending_deaths.pop_front().unwrap();
+        let mut roll = roll.unwrap();
+        let username = join_names(&players);
+        if let Some(ballots) = ballots {
+            self.close_vote(&username, &mut roll, &ballots);
+        }
+        //The script has the last word on the outcome
+        let veto = self.script.as_mut().and_then(|script| {
+            script.penalty(&json!({
+                "player": username,
+                "roll": roll.num,
+                "outcome": roll.outcome.name(),
+                "deadly": roll.deadly,
+            }))
+        });
+        if let Some(outcome) = veto {
+            info!(
+                "the script turned the {:?} outcome into {:?}",
+                roll.outcome, outcome
+            );
+            roll.deadly = matches!(outcome, Outcome::Rewind | Outcome::Reset);
+            roll.outcome = outcome;
+        }
+        self.flush_script();
+        let num = roll.num;
+        let (mut desc, color) = match &roll.outcome {
+            Outcome::None => ("A safe roll, the run goes on".to_string(), notify::GREEN),
+            Outcome::Command(cmd) => {
+                if !dry {
+                    for player in &players {
+                        self.cmd(cmd.replace("{username}", player));
+                    }
+                }
+                ("A safe roll, with a twist".to_string(), notify::GREEN)
+            }
+            Outcome::Border => {
+                if !dry {
+                    self.shrink_border();
+                }
+                (
+                    "A bad roll, the world border closes in".to_string(),
+                    notify::ORANGE,
+                )
+            }
+            Outcome::Spectator => {
+                if !dry {
+                    self.sit_out(&players);
+                }
+                (
+                    format!("A bad roll, {} sits out for a while", username),
+                    notify::ORANGE,
+                )
+            }
+            Outcome::Ban => {
+                if !dry {
+                    self.ban(&players);
+                }
+                (
+                    format!(
+                        "A bad roll, {} is banned for {}",
+                        username,
+                        fmt_duration(Duration::from_secs(self.config.ban_minutes * 60))
+                    ),
+                    notify::ORANGE,
+                )
+            }
+            Outcome::Rewind => (
+                "A bad roll, back to a checkpoint".to_string(),
+                notify::ORANGE,
+            ),
+            Outcome::Reset => (
+                "A deadly roll, the world pays for it".to_string(),
+                notify::BLACK,
+            ),
+        };
+        info!("rolled {}: {:?}", num, roll.outcome);
+        //A simulation leaves nothing behind
+        if !dry {
+            self.state.last_roll = Some(RollRecord {
+                player: username.clone(),
+                roll: num,
+                outcome: roll.outcome.name().to_string(),
+                at: unix_now(),
+            });
+            self.save_state();
+        }
+        let mut penalty = self.penalties.penalty(&roll);
+        //A mercy token spares the world before any life is lost
+        if roll.deadly && !dry {
+            if let Some(spender) = self.spend_mercy(&players) {
+                desc = format!("A bad roll, spared by a mercy token of {}", spender);
+                penalty = Penalty::None;
+                roll.deadly = false;
+            }
+        }
+        //Bad rolls take a life first, the world only pays once someone runs out
+        if let (true, Some(lives)) = (roll.deadly && !dry, &mut self.lives) {
+            let mut out = Vec::new();
+            let mut lost = Vec::new();
+            for player in &players {
+                let left = lives.lose(self.state.player_id(player));
+                info!("{} lost a life, {} left", player, left);
+                if left > 0 {
+                    self.announcer.say(Message::new(
+                        Kind::Death,
+                        format!(
+                            "{} lost a life, {} {} left",
+                            player,
+                            left,
+                            if left == 1 { "life" } else { "lives" }
+                        ),
+                    ));
+                    lost.push(format!("{} lost a life ({} left)", player, left));
+                } else {
+                    self.announcer.say(Message::new(
+                        Kind::Death,
+                        format!("{} is out of lives!", player),
+                    ));
+                    out.push(player.clone());
+                }
+            }
+            if let Err(err) = lives.save() {
+                warn!("failed to save lives: {}", err);
+            }
+            if out.is_empty() {
+                desc = format!("A bad roll, {}", lost.join(", "));
+                penalty = Penalty::None;
+            } else {
+                desc += &format!(", {} out of lives", join_names(&out));
+            }
+        }
+        let player_rewind =
+            matches!(penalty, Penalty::Rewind) && self.config.rewind_scope == RewindScope::Player;
+        if player_rewind {
+            desc = format!("A bad roll, {} goes back to a checkpoint", username);
+        }
+        if !dry {
+            self.record_deaths(&players, &roll, &penalty);
+        }
+        let title = match roll.dice.is_empty() {
+            true => format!("{} died, no roll", username),
+            false => format!("Rolled {}", num),
+        };
+        self.notifier.notify(title, desc, color);
+        let event = json!({
+            "player": username,
+            "players": players,
+            "roll": num,
+            "dice": roll.dice,
+            "outcome": roll.outcome.name(),
+            "penalty": format!("{:?}", penalty).to_lowercase(),
+        });
+        self.emit("roll_result", &event);
+        self.feed.publish("roll", event);
+        if let (Penalty::None, false) = (&penalty, dry) {
+            let now = Instant::now();
+            for player in &players {
+                self.survived_at.insert(player.clone(), now);
+            }
+        }
+        if dry {
+            info!("simulated penalty: {:?} (not applied)", penalty);
+            return Penalty::None;
+        }
+        if player_rewind {
+            self.rewind_players(&players);
+            return Penalty::None;
+        }
+        penalty
+    }
+
+    /// Add the deaths behind a roll to the history.
+    fn record_deaths(&self, players: &[String], roll: &Roll, penalty: &Penalty) {
+        let history = match &self.history {
+            Some(history) => history,
+            None => {
+                warn!(
+                    "the death history is not open, not adding the death of {}",
+                    join_names(players)
+                );
+                return;
+            }
+        };
+        for player in players {
+            let record = DeathRecord {
+                at: unix_now(),
+                season: self.state.resets + 1,
+                player: player.clone(),
+                roll: (!roll.dice.is_empty()).then_some(roll.num),
+                outcome: roll.outcome.name().to_string(),
+                deadly: roll.deadly,
+                penalty: format!("{:?}", penalty).to_lowercase(),
+                playtime_secs: self.playtime.as_secs(),
+            };
+            if let Err(err) = history.append(&record) {
+                warn!(
+                    "failed to add the death of {} to the history: {}",
+                    player, err
+                );
+            }
+        }
+    }
+}
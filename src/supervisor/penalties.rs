@@ -0,0 +1,239 @@
+//! Penalties that fall on players or the border rather than on the whole world.
+
+use super::{
+    join_names,
+    questions::{Question, REPLY_TIMEOUT},
+    Session,
+};
+use crate::{
+    announce::{Kind, Message},
+    fmt_duration, unix_now,
+};
+use log::info;
+use std::{rc::Rc, time::Duration};
+
+impl Session<'_> {
+    /// Take a step of `border_penalty` off the world border, down to its minimum.
+    pub(super) fn shrink_border(&mut self) {
+        match self.state.border_size {
+            Some(size) => self.shrink_border_from(size),
+            None => {
+                self.cmd("worldborder get".to_string());
+                self.ask(Question::Border, REPLY_TIMEOUT);
+            }
+        }
+    }
+
+    /// Take a step of `border_penalty` off a world border this wide.
+    pub(super) fn shrink_border_from(&mut self, size: f64) {
+        let config = &self.config.border_penalty;
+        let new_size = (size - config.step).max(config.min);
+        if new_size >= size {
+            info!("the world border is already down to {} blocks", size);
+            self.announcer.say(Message::new(
+                Kind::Death,
+                "The world border cannot shrink any further",
+            ));
+            return;
+        }
+        info!(
+            "shrinking the world border from {} to {} blocks",
+            size, new_size
+        );
+        self.cmd(format!(
+            "worldborder set {} {}",
+            new_size, config.shrink_seconds
+        ));
+        self.state.border_size = Some(new_size);
+        self.save_state();
+        self.announcer.say(Message::new(
+            Kind::Death,
+            format!("The world border shrinks to {} blocks across", new_size),
+        ));
+    }
+
+    /// Take players out of the game for `spectator_penalty`, keeping track of when they get back
+    /// in so that it happens even across restarts.
+    pub(super) fn sit_out(&mut self, players: &[String]) {
+        let penalty = &self.config.spectator_penalty;
+        let until = unix_now() + penalty.minutes * 60;
+        for player in players {
+            let id = self.state.player_id(player).to_string();
+            self.state.revivals.insert(id, until);
+            self.cmd(format!("gamemode {} {}", penalty.game_mode.name(), player));
+        }
+        self.save_state();
+        info!(
+            "{} sits out in {} mode for {} minutes",
+            join_names(players),
+            penalty.game_mode.name(),
+            penalty.minutes
+        );
+        self.announcer.say(Message::new(
+            Kind::Death,
+            format!(
+                "{} sits out in {} mode for {}",
+                join_names(players),
+                penalty.game_mode.name(),
+                fmt_duration(Duration::from_secs(penalty.minutes * 60))
+            ),
+        ));
+    }
+
+    /// Put the players whose time out is over back in survival. Those offline are brought back as
+    /// they join.
+    pub(super) fn revive_due(&mut self) {
+        let now = unix_now();
+        let due = self
+            .state
+            .revivals
+            .iter()
+            .filter(|&(_, &at)| at <= now)
+            .map(|(id, _)| id.clone())
+            .collect::<Vec<_>>();
+        for id in due {
+            let name = self.state.player_name(&id).to_string();
+            if self.online_players.contains(&name) {
+                self.revive(&id, &name);
+            }
+        }
+    }
+
+    pub(super) fn revive(&mut self, id: &str, name: &str) {
+        self.state.revivals.remove(id);
+        self.save_state();
+        info!("{} is done sitting out", name);
+        self.cmd(format!("gamemode survival {}", name));
+        self.announcer.say(Message::new(
+            Kind::Death,
+            format!("{} is back in the game!", name),
+        ));
+    }
+
+    /// Ban players for `ban_minutes`, keeping track of when to pardon them so that it happens even
+    /// across restarts.
+    pub(super) fn ban(&mut self, players: &[String]) {
+        let length = Duration::from_secs(self.config.ban_minutes * 60);
+        let until = unix_now() + length.as_secs();
+        for player in players {
+            self.state.unbans.insert(player.clone(), until);
+            self.cmd(format!(
+                "ban {} A bad roll, banned for {}",
+                player,
+                fmt_duration(length)
+            ));
+        }
+        self.save_state();
+        info!(
+            "banned {} for {}",
+            join_names(players),
+            fmt_duration(length)
+        );
+        self.announcer.say(Message::new(
+            Kind::Death,
+            format!(
+                "{} is banned for {}",
+                join_names(players),
+                fmt_duration(length)
+            ),
+        ));
+    }
+
+    /// Pay a player the mercy tokens for an advancement, unless they were already paid for it in
+    /// this world.
+    pub(super) fn award_mercy(&mut self, player: &str, advancement: &str, tokens: u32) {
+        let id = self.state.player_id(player).to_string();
+        let balance = match self.state.award_mercy(&id, advancement, tokens) {
+            Some(balance) => balance,
+            None => {
+                info!("{} was already paid for [{}]", player, advancement);
+                return;
+            }
+        };
+        self.save_state();
+        info!(
+            "{} earned {} mercy tokens for [{}], {} in total",
+            player, tokens, advancement, balance
+        );
+        self.announcer.say(format!(
+            "{} earned {} mercy {} for [{}], holding {}",
+            player,
+            tokens,
+            if tokens == 1 { "token" } else { "tokens" },
+            advancement,
+            balance
+        ));
+    }
+
+    /// Spend a mercy token of one of the dying players, returning whose it was.
+    pub(super) fn spend_mercy(&mut self, players: &[String]) -> Option<String> {
+        let (player, left) = players.iter().find_map(|player| {
+            let id = self.state.player_id(player).to_string();
+            Some((player.clone(), self.state.spend_mercy(&id)?))
+        })?;
+        self.save_state();
+        info!("{} spent a mercy token, {} left", player, left);
+        self.announcer.say(Message::new(
+            Kind::Death,
+            format!(
+                "{} spent a mercy token to spare the world, {} left",
+                player, left
+            ),
+        ));
+        Some(player)
+    }
+
+    /// Pardon the players whose ban is over, once the server is up to take the command.
+    pub(super) fn unban_due(&mut self) {
+        if self.ready_deadline.is_some() {
+            return;
+        }
+        let now = unix_now();
+        let due = self
+            .state
+            .unbans
+            .iter()
+            .filter(|&(_, &at)| at <= now)
+            .map(|(player, _)| player.clone())
+            .collect::<Vec<_>>();
+        if due.is_empty() {
+            return;
+        }
+        for player in &due {
+            info!("{}'s ban is over", player);
+            self.cmd(format!("pardon {}", player));
+            self.state.unbans.remove(player);
+        }
+        self.save_state();
+        self.announcer.say(Message::new(
+            Kind::Death,
+            format!("{} may join again", join_names(&due)),
+        ));
+    }
+
+    /// Make the odds worse if playtime or deaths reached the next `escalation` step.
+    pub(super) fn check_escalation(&mut self) {
+        let config = Rc::clone(&self.config);
+        let level = config.escalation_level(self.playtime, self.season_deaths);
+        if level <= self.escalation_level {
+            return;
+        }
+        self.penalties = config.global_engine(level);
+        let deadly_rolls = self.penalties.deadly_rolls();
+        info!(
+            "reached escalation step {} of {}, deadly rolls are now {}",
+            level,
+            config.escalation.len(),
+            deadly_rolls
+        );
+        for step in &config.escalation[self.escalation_level..level] {
+            let msg = step
+                .message
+                .as_deref()
+                .unwrap_or("The odds got worse! Deadly rolls are now {deadly_rolls}")
+                .replace("{deadly_rolls}", &deadly_rolls);
+            self.announcer.say(Message::new(Kind::Roll, msg));
+        }
+        self.escalation_level = level;
+    }
+}
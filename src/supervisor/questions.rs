@@ -0,0 +1,149 @@
+//! Questions to the server, answered by its output while the rest of it keeps being handled.
+
+use super::{death::DeathReport, restore::PlayerRewind, Session};
+use crate::{
+    events::Event,
+    logging,
+    parse::{parse_border, parse_death_location, parse_dimension, parse_list},
+};
+use log::warn;
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+    time::Duration,
+};
+
+/// Something waiting to hear from the server, so that output keeps being handled meanwhile.
+pub(super) enum Question {
+    /// The dimension a player died in, for the death rules.
+    Dimension(DeathReport),
+    /// Where a player died, for `death_location`, with the death rule that applies.
+    DeathLocation(DeathReport, Option<usize>),
+    /// Which of the players last known to be online on an attached server still are.
+    List(Vec<String>),
+    /// How wide the world border is, to take a `border` step off it.
+    Border,
+    /// Kicked players leaving for a player rewind.
+    Leave(PlayerRewind),
+}
+impl Question {
+    /// Whether the question is answered by the reply to a command.
+    fn is_reply(&self) -> bool {
+        !matches!(self, Question::Leave(_))
+    }
+
+    /// Whether a line is the reply this question waits for.
+    fn answered_by(&self, line: &str) -> bool {
+        match self {
+            Question::Dimension(death) => death.is_about(line) && parse_dimension(line).is_some(),
+            Question::DeathLocation(death, _) => {
+                death.is_about(line) && parse_death_location(line).is_some()
+            }
+            Question::List(_) => parse_list(line).is_some(),
+            Question::Border => parse_border(line).is_some(),
+            Question::Leave(_) => false,
+        }
+    }
+}
+
+/// Ids for questions to the server, unique across sessions like death ids.
+static NEXT_QUESTION_ID: AtomicU64 = AtomicU64::new(0);
+
+/// How long to wait for the server to reply to a command.
+pub(super) const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long to wait for kicked players to leave.
+pub(super) const LEAVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+impl Session<'_> {
+    /// Wait for the server to answer a question, for up to `timeout`, while output keeps being
+    /// handled. Commands are sent by the caller.
+    pub(super) fn ask(&mut self, question: Question, timeout: Duration) {
+        let id = NEXT_QUESTION_ID.fetch_add(1, Ordering::Relaxed);
+        self.questions.push_back((id, question));
+        let events = self.events.sender().clone();
+        logging::spawn(move || {
+            thread::sleep(timeout);
+            let _ = events.send(Event::QuestionExpired(id));
+        });
+    }
+
+    /// Settle the questions that a line of output answers.
+    pub(super) fn answer_questions(&mut self, line: &str) {
+        let mut answered = Vec::new();
+        //Kicked players leave in any order
+        for (id, question) in &mut self.questions {
+            if let Question::Leave(rewind) = question {
+                rewind.leaving.retain(|player| {
+                    !line
+                        .trim_end()
+                        .ends_with(&format!("{} left the game", player))
+                });
+                if rewind.leaving.is_empty() {
+                    answered.push(*id);
+                }
+            }
+        }
+        //The server replies to commands in the order they were sent
+        if let Some((id, _)) = self
+            .questions
+            .iter()
+            .find(|(_, question)| question.is_reply())
+            .filter(|(_, question)| question.answered_by(line))
+        {
+            answered.push(*id);
+        }
+        for id in answered {
+            if let Some(question) = self.take_question(id) {
+                self.settle_question(question, Some(line));
+            }
+        }
+    }
+
+    /// Give up on a question the server did not answer in time.
+    pub(super) fn expire_question(&mut self, id: u64) {
+        if let Some(question) = self.take_question(id) {
+            self.settle_question(question, None);
+        }
+    }
+
+    fn take_question(&mut self, id: u64) -> Option<Question> {
+        let idx = self.questions.iter().position(|&(other, _)| other == id)?;
+        self.questions.remove(idx).map(|(_, question)| question)
+    }
+
+    /// Carry on with whatever was waiting on a question, given the line that answered it, or
+    /// nothing if it went unanswered.
+    fn settle_question(&mut self, question: Question, line: Option<&str>) {
+        match question {
+            Question::Dimension(mut death) => {
+                let dim = line.and_then(parse_dimension);
+                if dim.is_none() {
+                    warn!("could not tell which dimension {} died in", death.username);
+                }
+                death.dimension = Some(dim);
+                if let Err(why) = self.on_death_report(death) {
+                    self.line_stats.count(why);
+                }
+            }
+            Question::DeathLocation(death, rule) => {
+                let location = match line.and_then(parse_death_location) {
+                    Some(location) => Some(location),
+                    None => self.saved_location(&death.username),
+                };
+                self.on_death(&death.username, &death.line, false, rule, location);
+            }
+            Question::List(online) => self.finish_resume_online(online, line.and_then(parse_list)),
+            Question::Border => match line.and_then(parse_border) {
+                Some(size) => self.shrink_border_from(size),
+                None => warn!("could not tell how wide the world border is, leaving it be"),
+            },
+            Question::Leave(rewind) => {
+                for player in &rewind.leaving {
+                    warn!("{} was not seen leaving, rewinding them anyway", player);
+                }
+                self.restore_players(rewind);
+            }
+        }
+    }
+}
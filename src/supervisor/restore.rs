@@ -0,0 +1,573 @@
+//! Going back to a checkpoint, or starting over with a fresh world.
+
+use super::{
+    attempt_world, backup_manager, describe_meta, join_names, load_playtime, playtime_path,
+    questions::{Question, LEAVE_TIMEOUT},
+    save_playtime, Session,
+};
+use crate::{
+    announce::{Kind, Message, Step},
+    backups::{self, remove_path, BackupManager, Checkpoint, CheckpointMeta},
+    bedrock,
+    config::{Config, RewindTarget},
+    fmt_duration,
+    lives::Lives,
+    matcher::GlobMatcher,
+    notify,
+    penalty::Penalty,
+    server::set_property,
+    state::{PendingPenalty, State},
+    unix_now,
+};
+use log::{error, info, warn};
+use rand::Rng;
+use serde_json::json;
+use std::{
+    collections::{BTreeMap, HashSet},
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+/// Replace the world with a checkpoint, rolling playtime and the world counters in `state` back to
+/// when it was made. Returns the checkpoint metadata, if it has any.
+pub(crate) fn restore_checkpoint(
+    ckpt: &Checkpoint,
+    config: &Config,
+    playtime_path: &Path,
+    state: &mut State,
+    state_path: &Path,
+    lives: Option<&Lives>,
+    exclude: &GlobMatcher,
+) -> Result<Option<CheckpointMeta>, Box<dyn Error>> {
+    let meta = ckpt.meta();
+    info!(
+        "restoring \"{}\" into world directory \"{}\"",
+        ckpt.path.display(),
+        config.world.display()
+    );
+    ckpt.restore(&config.world, &config.dimensions(), exclude)?;
+    //Lives lost since the checkpoint stay lost
+    if let Some(lives) = lives {
+        lives.save()?;
+    }
+    if let Some(meta) = &meta {
+        save_playtime(playtime_path, Duration::from_secs(meta.playtime_secs))?;
+        state.advancements = meta.advancements;
+        state.deaths = meta.deaths;
+        state.save(state_path)?;
+    }
+    Ok(meta)
+}
+
+/// Move a finished season's world and stats to `seasons/<n>/`.
+fn archive_season(config: &Config, state: &State, season: u32) -> Result<(), Box<dyn Error>> {
+    let dir = config
+        .seasons_dir
+        .clone()
+        .unwrap_or_else(|| config.backup_dir.join("seasons"))
+        .join(season.to_string());
+    fs::create_dir_all(&dir)?;
+    let playtime = load_playtime(&playtime_path(config)).unwrap_or_default();
+    let stats = json!({
+        "season": season,
+        "ended_at": unix_now(),
+        "playtime_secs": playtime.as_secs(),
+        "deaths": state.deaths,
+        "advancements": state.advancements,
+        "won": state.won,
+        "last_roll": state.last_roll,
+        "player_playtime": state
+            .playtime_leaderboard()
+            .into_iter()
+            .collect::<BTreeMap<_, _>>(),
+    });
+    fs::write(
+        dir.join("stats.json"),
+        serde_json::to_string_pretty(&stats)?,
+    )?;
+    if config.world.exists() {
+        let to = dir.join("world");
+        info!("archiving season {} world to \"{}\"", season, to.display());
+        move_dir(&config.world, &to)?;
+        for dim in config.dimensions() {
+            move_dir(&dim, &dir.join(dim.file_name().unwrap_or_default()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Move a directory out of the way, replacing whatever is at `to`.
+fn move_dir(from: &Path, to: &Path) -> Result<(), Box<dyn Error>> {
+    remove_path(to)?;
+    //Renaming fails across filesystems, copy instead
+    if fs::rename(from, to).is_err() {
+        backups::copy_dir(from, to, &GlobMatcher::new::<&str>(&[])?)?;
+        fs::remove_dir_all(from)?;
+    }
+    Ok(())
+}
+
+/// Throw the current world and its checkpoints away, so that the next attempt starts from scratch.
+pub(crate) fn reset_world(
+    config: &Config,
+    backups: &BackupManager,
+    state: &mut State,
+    state_path: &Path,
+    lives: Option<&mut Lives>,
+) -> Result<(), Box<dyn Error>> {
+    let season = state.resets + 1;
+    if config.archive_seasons {
+        if let Err(err) = archive_season(config, state, season) {
+            warn!("failed to archive season {}: {}", season, err);
+        }
+    }
+    let properties = config.properties_path();
+    if let Some(seed) = config.reset_seed.seed_for(season + 1) {
+        info!("season {} gets seed {}", season + 1, seed);
+        if let Err(err) = set_property(&properties, "level-seed", &seed) {
+            warn!(
+                "failed to set a new seed in \"{}\": {}",
+                properties.display(),
+                err
+            );
+        }
+    }
+    for (key, value) in &config.reset_properties {
+        let value = value.replace("{season}", &(season + 1).to_string());
+        info!("season {} gets {}={}", season + 1, key, value);
+        if let Err(err) = set_property(&properties, key, &value) {
+            warn!(
+                "failed to set `{}` in \"{}\": {}",
+                key,
+                properties.display(),
+                err
+            );
+        }
+    }
+    //Delete world, unless the next attempt gets a world of its own
+    if config.world_name_template.is_some() {
+        if config.world.exists() {
+            info!("keeping old world directory \"{}\"", config.world.display());
+        }
+    } else {
+        info!("deleting world directory on \"{}\"", config.world.display());
+        for dim in config.dimensions() {
+            info!("deleting dimension world on \"{}\"", dim.display());
+            remove_path(&dim)?;
+        }
+        remove_path(&config.world)?;
+    }
+    if let Some(lives) = lives {
+        lives.refill();
+        if config.state_dir.is_some() {
+            lives.save()?;
+        }
+    }
+    if config.state_dir.is_some() {
+        save_playtime(&playtime_path(config), Duration::from_secs(0))?;
+    }
+    //Delete backups
+    backups.clear()?;
+    //Count the attempt and start the new world from scratch
+    state.resets += 1;
+    state.advancements = 0;
+    state.deaths = 0;
+    state.won = None;
+    state.border_size = None;
+    state.revivals.clear();
+    state.mercy_tokens.clear();
+    state.mercy_awarded.clear();
+    state.pending_penalty = None;
+    state.save(state_path)?;
+    Ok(())
+}
+
+/// Finish what the wrapper was in the middle of when it last died, be it a checkpoint, a rewind
+/// or a reset. Returns whether saving may have been left off on the server.
+pub(crate) fn recover_interrupted(
+    config: &mut Config,
+    state: &mut State,
+    state_path: &Path,
+    world_name: &mut String,
+) -> Result<bool, Box<dyn Error>> {
+    let mut saving_off = false;
+    if let Some(partial) = state.checkpoint_in_progress.take() {
+        warn!(
+            "removing checkpoint \"{}\", which was never finished",
+            partial.display()
+        );
+        remove_path(&partial)?;
+        remove_path(&bedrock::staging_path(&partial))?;
+        saving_off = true;
+    }
+    let mut lives = match config.lives {
+        Some(start) => Some(Lives::load(
+            playtime_path(config).with_file_name("lives.json"),
+            start,
+        )?),
+        None => None,
+    };
+    let backups = backup_manager(config, world_name);
+    match state.pending_penalty.clone() {
+        Some(PendingPenalty::Rewind { checkpoint }) => {
+            warn!("finishing the interrupted rewind to \"{}\"", checkpoint);
+            let ckpt = backups
+                .list()?
+                .into_iter()
+                .chain(backups.list_named()?)
+                .find(|ckpt| ckpt.name == checkpoint);
+            match ckpt {
+                Some(ckpt) => {
+                    restore_checkpoint(
+                        &ckpt,
+                        config,
+                        &playtime_path(config),
+                        state,
+                        state_path,
+                        lives.as_ref(),
+                        backups.exclude(),
+                    )?;
+                }
+                None => warn!(
+                    "checkpoint \"{}\" is gone, keeping the world as it is",
+                    checkpoint
+                ),
+            }
+            state.pending_penalty = None;
+        }
+        Some(PendingPenalty::Reset) => {
+            warn!("finishing the interrupted world reset");
+            reset_world(config, &backups, state, state_path, lives.as_mut())?;
+            *world_name = attempt_world(config, state)?;
+        }
+        None => {}
+    }
+    state.save(state_path)?;
+    Ok(saving_off)
+}
+
+/// Players going back to a checkpoint with `rewind_scope: "player"`, waiting for the ones that
+/// were kicked to leave before their files are replaced.
+pub(super) struct PlayerRewind {
+    ckpt: Option<Checkpoint>,
+    players: Vec<String>,
+    /// Players kicked but not seen leaving yet.
+    pub(super) leaving: HashSet<String>,
+    /// Players who cannot be rewound, with why.
+    failed: Vec<String>,
+}
+
+impl Session<'_> {
+    /// Rewind to a named checkpoint, or say why not.
+    pub(super) fn restore_named(&mut self, label: &str) -> Result<Penalty, String> {
+        let ckpt = match self.backups.named(label) {
+            Ok(Some(ckpt)) => ckpt,
+            Ok(None) => {
+                info!("no checkpoint named \"{}\"", label);
+                return Err(format!("no checkpoint named \"{}\"", label));
+            }
+            Err(err) => {
+                warn!("failed to look up checkpoint \"{}\": {}", label, err);
+                return Err(format!("failed to look up checkpoint \"{}\"", label));
+            }
+        };
+        self.check_requested_restore(&ckpt)?;
+        info!("restoring named checkpoint \"{}\"", label);
+        self.announcer
+            .say(format!("Rewinding to checkpoint \"{}\"", label));
+        self.rewind_to = Some(ckpt);
+        Ok(Penalty::Rewind)
+    }
+
+    /// Refuse to restore a damaged checkpoint that was asked for by name or slot, keeping the
+    /// current world rather than going back somewhere else.
+    pub(super) fn check_requested_restore(&mut self, ckpt: &Checkpoint) -> Result<(), String> {
+        if ckpt.is_usable() {
+            return Ok(());
+        }
+        error!("checkpoint \"{}\" is damaged, not restoring it", ckpt.name);
+        self.notifier.notify(
+            "Restore refused",
+            format!(
+                "Checkpoint \"{}\" is damaged, the world was kept as it is",
+                ckpt.name
+            ),
+            notify::RED,
+        );
+        Err(format!("checkpoint \"{}\" is damaged", ckpt.name))
+    }
+
+    /// Tell players how much progress a rewind to `meta` will undo, and let it sink in.
+    pub(super) fn announce_rewind_loss(&self, meta: &CheckpointMeta) {
+        let lost = self
+            .playtime
+            .saturating_sub(Duration::from_secs(meta.playtime_secs));
+        let advancements = self.state.advancements.saturating_sub(meta.advancements);
+        let deaths = self.state.deaths.saturating_sub(meta.deaths);
+        info!(
+            "rewind undoes {} of playtime, {} advancements and {} deaths",
+            fmt_duration(lost),
+            advancements,
+            deaths
+        );
+        let plural =
+            |n: u32, what: &str| format!("{} {}{}", n, what, if n == 1 { "" } else { "s" });
+        self.announcer.sequence(vec![
+            Step::Say(
+                format!(
+                    "This rewind will undo {} of playtime, {} and {}",
+                    fmt_duration(lost),
+                    plural(advancements, "advancement"),
+                    plural(deaths, "death")
+                )
+                .into(),
+            ),
+            Step::Pause(Duration::from_secs(5)),
+        ]);
+    }
+
+    /// Choose which checkpoint to rewind to, if there are any.
+    fn choose_rewind_target(&mut self) -> Result<Option<Checkpoint>, Box<dyn Error>> {
+        if let Some(ckpt) = self.rewind_to.take() {
+            return Ok(Some(ckpt));
+        }
+        let mut ckpts = self.backups.list()?;
+        if ckpts.is_empty() {
+            return Ok(None);
+        }
+        if let Some(minutes) = self.config.rewind_minutes {
+            //Go back to the newest checkpoint that undoes at least that much playtime
+            let back = Duration::from_secs(minutes * 60);
+            let target = self.playtime.saturating_sub(back).as_secs();
+            let idx = ckpts
+                .iter()
+                .rposition(|ckpt| ckpt.meta().is_some_and(|meta| meta.playtime_secs <= target))
+                .unwrap_or(0);
+            return Ok(Some(ckpts.swap_remove(idx)));
+        }
+        let idx = match self.config.rewind_target {
+            RewindTarget::Latest => ckpts.len() - 1,
+            RewindTarget::Random => rand::thread_rng().gen_range(0, ckpts.len()),
+            RewindTarget::Prompt => self.prompt_rewind_target(&ckpts),
+        };
+        Ok(Some(ckpts.swap_remove(idx)))
+    }
+
+    /// The checkpoint a rewind goes back to, passing over a damaged one to the newest usable
+    /// checkpoint before it. `Ok(None)` means there are no checkpoints yet, and an error that none
+    /// of them will do.
+    fn usable_rewind_target(&mut self) -> Result<Option<Checkpoint>, Box<dyn Error>> {
+        //Restores asked for by name or slot were checked when they were asked for
+        let requested = self.rewind_to.is_some();
+        let ckpt = match self.choose_rewind_target()? {
+            Some(ckpt) if requested || ckpt.is_usable() => return Ok(Some(ckpt)),
+            Some(ckpt) => ckpt,
+            None => return Ok(None),
+        };
+        warn!(
+            "checkpoint \"{}\" is damaged, looking for an older one",
+            ckpt.name
+        );
+        match self.backups.usable_before(&ckpt)? {
+            Some(older) => {
+                warn!(
+                    "rewinding to \"{}\" instead of the damaged \"{}\"",
+                    older.name, ckpt.name
+                );
+                self.notifier.notify(
+                    "Rewinding to an older checkpoint",
+                    format!(
+                        "\"{}\" is damaged, going back to \"{}\" instead",
+                        ckpt.name, older.name
+                    ),
+                    notify::ORANGE,
+                );
+                Ok(Some(older))
+            }
+            None => Err(format!(
+                "checkpoint \"{}\" is damaged and none before it is usable",
+                ckpt.name
+            )
+            .into()),
+        }
+    }
+
+    /// Settle on the checkpoint a world rewind goes back to, while the server is still up.
+    /// Returns what is left to carry out: the rewind, a reset if there are no checkpoints at all,
+    /// or nothing if no checkpoint is usable, in which case the world is kept.
+    pub(super) fn settle_rewind(&mut self) -> Penalty {
+        match self.usable_rewind_target() {
+            Ok(Some(ckpt)) => {
+                self.rewind_to = Some(ckpt);
+                Penalty::Rewind
+            }
+            Ok(None) => Penalty::Reset,
+            Err(err) => {
+                error!("not rewinding: {}", err);
+                self.announcer.say(Message::new(
+                    Kind::Death,
+                    "There is no usable checkpoint to rewind to, the world is kept as it is",
+                ));
+                self.notifier.notify(
+                    "Rewind failed",
+                    format!("{}, the world was kept as it is", err),
+                    notify::RED,
+                );
+                Penalty::None
+            }
+        }
+    }
+
+    /// Send just these players back to a checkpoint, with `rewind_scope: "player"`, by putting the
+    /// checkpoint's copies of their files in place while they are off the server. Players the
+    /// checkpoint has no files of start over.
+    pub(super) fn rewind_players(&mut self, players: &[String]) {
+        let ckpt = match self.usable_rewind_target() {
+            Ok(ckpt) => ckpt,
+            Err(err) => {
+                error!("not rewinding {}: {}", join_names(players), err);
+                self.notifier.notify(
+                    "Player rewind failed",
+                    format!("{}, {} kept everything", err, join_names(players)),
+                    notify::RED,
+                );
+                return;
+            }
+        };
+        let mut rewind = PlayerRewind {
+            ckpt,
+            players: Vec::new(),
+            leaving: HashSet::new(),
+            failed: Vec::new(),
+        };
+        for player in players {
+            let uuid = self.state.player_id(player).to_string();
+            if uuid == *player {
+                warn!(
+                    "the server never said the UUID of {}, so their files cannot be found",
+                    player
+                );
+                rewind
+                    .failed
+                    .push(format!("{}: their UUID is unknown", player));
+                continue;
+            }
+            //The server writes their files as they leave, and reads them back as they join
+            if self.online_players.contains(player) && !self.state.handed_off.contains(player) {
+                let reason = self.config.kick_messages.player_rewind.clone();
+                self.cmd(format!("kick {} {}", player, reason.unwrap_or_default()));
+                rewind.leaving.insert(player.clone());
+            }
+            rewind.players.push(player.clone());
+        }
+        match rewind.leaving.is_empty() {
+            true => self.restore_players(rewind),
+            false => self.ask(Question::Leave(rewind), LEAVE_TIMEOUT),
+        }
+    }
+
+    /// Put the checkpoint's copies of the files of players being rewound in place, once they are
+    /// off the server.
+    pub(super) fn restore_players(&mut self, rewind: PlayerRewind) {
+        let PlayerRewind {
+            ckpt,
+            players,
+            mut failed,
+            ..
+        } = rewind;
+        let name = ckpt.as_ref().map(|ckpt| ckpt.name.clone());
+        let mut rewound = Vec::new();
+        for player in &players {
+            let uuid = self.state.player_id(player).to_string();
+            let files = [
+                format!("playerdata/{}.dat", uuid),
+                format!("advancements/{}.json", uuid),
+                format!("stats/{}.json", uuid),
+            ]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>();
+            let res = match &ckpt {
+                Some(ckpt) => ckpt.restore_files(&self.config.world, &files),
+                None => files
+                    .iter()
+                    .try_for_each(|rel| remove_path(&self.config.world.join(rel))),
+            };
+            if let Err(err) = res {
+                error!("failed to rewind {}: {}", player, err);
+                failed.push(format!("{}: {}", player, err));
+                continue;
+            }
+            match &name {
+                Some(name) => info!("rewound {} to checkpoint \"{}\"", player, name),
+                None => info!("no checkpoint to rewind {} to, they start over", player),
+            }
+            rewound.push(player.clone());
+        }
+        if !failed.is_empty() {
+            self.notifier
+                .notify("Player rewind failed", failed.join("\n"), notify::RED);
+        }
+        if rewound.is_empty() {
+            return;
+        }
+        self.announcer.say(Message::new(
+            Kind::Death,
+            match &name {
+                Some(_) => format!("{} went back to a checkpoint", join_names(&rewound)),
+                None => format!(
+                    "{} lost everything, with no checkpoint to go back to",
+                    join_names(&rewound)
+                ),
+            },
+        ));
+        self.emit(
+            "restore",
+            &json!({ "checkpoint": name, "players": rewound }),
+        );
+    }
+
+    /// Let the admin pick a checkpoint on the wrapper console.
+    fn prompt_rewind_target(&mut self, ckpts: &[Checkpoint]) -> usize {
+        let latest = ckpts.len() - 1;
+        let timeout = self.config.rewind_prompt_seconds;
+        eprintln!(
+            "pick a checkpoint to rewind to with \".pick <n>\" within {}s (defaults to {}):",
+            timeout, latest
+        );
+        let now = unix_now();
+        for (idx, ckpt) in ckpts.iter().enumerate() {
+            let meta = ckpt
+                .meta()
+                .map(|meta| format!(", {}", describe_meta(&meta)))
+                .unwrap_or_default();
+            eprintln!(
+                "    {}: {} (made {} ago{})",
+                idx,
+                ckpt.name,
+                fmt_duration(Duration::from_secs(now.saturating_sub(ckpt.created))),
+                meta
+            );
+        }
+        self.announcer
+            .say("An admin is choosing how far back to rewind...");
+        let deadline = Instant::now() + Duration::from_secs(timeout);
+        while let Some(line) = self
+            .events
+            .wait_for_console(deadline.saturating_duration_since(Instant::now()))
+        {
+            match line.strip_prefix(".pick") {
+                Some(idx) => match idx.trim().parse::<usize>() {
+                    Ok(idx) if idx < ckpts.len() => return idx,
+                    _ => eprintln!("expected a checkpoint number between 0 and {}", latest),
+                },
+                None if line.starts_with('.') => eprintln!("waiting for \".pick <n>\""),
+                None => self.cmd(line),
+            }
+        }
+        eprintln!("no checkpoint picked, using the latest");
+        latest
+    }
+}
@@ -0,0 +1,753 @@
+//! A single run of the server, from reading the config to the penalty that ends it.
+
+use super::{
+    attempt_world, backup_manager, check_properties, describe_meta, history_path, load_playtime,
+    playtime_path,
+    restore::{recover_interrupted, reset_world, restore_checkpoint},
+    state_path, LineStats, Options, Session, Supervisor, PREFIX_LEARN_LINES, STARTUP_TIMEOUT,
+};
+use crate::{
+    afk::AfkTracker,
+    announce::Announcer,
+    api, backups,
+    bedrock::{self, ServerType},
+    chat::Cooldowns,
+    config::{load_config, Config},
+    console_log::ConsoleLog,
+    crash,
+    download::{self, Flavor},
+    events::Event,
+    fairness::Commitment,
+    faults::{Fault, Faults},
+    fmt_duration,
+    history::History,
+    hooks::HookRunner,
+    java,
+    lang::{embedded_death_messages, parse_langs},
+    lives::Lives,
+    logging,
+    matcher::{GlobMatcher, PrefixMatcher, TemplateMatcher},
+    notify::{self, Notifier},
+    parse::LinePrefix,
+    penalty::Penalty,
+    plugins::Plugins,
+    rcon,
+    remote::Uploader,
+    schedule::Schedule,
+    script::Script,
+    server::{server_jar, set_level_name, with_server_jar, ServerHandle},
+    state::{PendingPenalty, State},
+    telegram,
+    twitch::Twitch,
+    unix_now,
+};
+use chrono::Local;
+use log::{debug, error, info, warn};
+use serde_json::json;
+use std::{
+    cell::Cell,
+    collections::{HashMap, HashSet, VecDeque},
+    error::Error,
+    fs, mem,
+    path::Path,
+    rc::Rc,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Read death messages, from the server jar too if asked to.
+fn load_death_messages(
+    config: &Config,
+    server_cmd: &[String],
+    force: bool,
+) -> Result<TemplateMatcher, Box<dyn Error>> {
+    let mut langs = config.lang.as_slice().to_vec();
+    if config.lang_from_jar {
+        match server_jar(server_cmd) {
+            Some(jar) => langs.push(jar),
+            None => warn!("`lang_from_jar` is set, but there is no jar in the server command"),
+        }
+    }
+    let mut death_msg = parse_langs(&langs)?;
+    if death_msg.is_empty() {
+        let (version, embedded) = embedded_death_messages(config.server_version.as_deref());
+        if langs.is_empty() {
+            info!("no lang files configured");
+        } else {
+            warn!("no death messages found in the configured lang files");
+        }
+        info!(
+            "using {} built-in english death messages from minecraft {}",
+            embedded.len(),
+            version
+        );
+        death_msg = embedded;
+    }
+    info!("{} death messages:", death_msg.len());
+    for msg in death_msg.iter() {
+        debug!("    \"{}\"", msg);
+    }
+    if death_msg.is_empty() {
+        if config.strict && !force {
+            return Err(format!(
+                "no death messages found in lang files {:?}, so deaths could never be detected. \
+                 check that `lang` points to a Minecraft language file (eg. `en_us.json`), \
+                 or set `strict` to false or pass `--force` to run anyway",
+                langs
+            )
+            .into());
+        }
+        warn!("no death messages found, deaths will not be detected");
+    }
+    Ok(TemplateMatcher::new(&death_msg)?)
+}
+
+/// Boolean indicates whether to continue running.
+pub(super) fn run_server(args: &Options, wrapper: &mut Supervisor) -> Result<bool, Box<dyn Error>> {
+    //Load config
+    let mut config = load_config(&args.config)?;
+    wrapper.recent.set_capacity(config.recent_lines);
+    wrapper.dump_dir = Some(config.backup_dir.clone());
+    if wrapper.console_log.is_none() {
+        if let Some(log_dir) = config.log_dir.as_ref() {
+            info!("logging console to \"{}\"", log_dir.display());
+            let log = ConsoleLog::open(log_dir.clone(), config.log_max_kb * 1024, config.log_keep)?;
+            let log = Arc::new(Mutex::new(log));
+            logging::mirror_to(log.clone());
+            wrapper.console_log = Some(log);
+        }
+    }
+    if !wrapper.api_started {
+        if let (Some(bind), Some(token)) = (&config.api_bind, &config.api_token) {
+            api::spawn(
+                bind,
+                token,
+                wrapper.events.sender().clone(),
+                wrapper.feed.clone(),
+            )?;
+        }
+        if let Some(conf) = &config.telegram {
+            telegram::spawn(conf.clone(), wrapper.events.sender().clone());
+        }
+        wrapper.api_started = true;
+    }
+    let faults = Faults::new(&config.faults, args.debug);
+    let state_path = state_path(&config);
+    if let Some(dir) = &config.state_dir {
+        fs::create_dir_all(dir)?;
+    }
+    if faults.check(Fault::CorruptState) && state_path.exists() {
+        fs::write(&state_path, "corrupted")?;
+    }
+    let mut state = State::load(&state_path)?;
+    //Give each attempt its own world directory
+    let mut world_name = attempt_world(&mut config, &state)?;
+    backups::recover_restore(&config.world)?;
+    let saving_off = recover_interrupted(&mut config, &mut state, &state_path, &mut world_name)?;
+    info!("season {}", state.resets + 1);
+    if let Some(won) = &state.won {
+        info!(
+            "{} won this world with [{}], deaths do not roll anymore",
+            won.player, won.advancement
+        );
+    }
+    //Whoever was online on a server started by the wrapper is gone along with it
+    if config.attach.is_none() {
+        state.online_players.clear();
+    }
+    if config.world_name_template.is_some() {
+        info!("attempt world directory: \"{}\"", config.world.display());
+        let properties = config.properties_path();
+        if properties.exists() {
+            set_level_name(&properties, &world_name)?;
+        } else {
+            warn!(
+                "\"{}\" not found, cannot point the server at world \"{}\"",
+                properties.display(),
+                world_name
+            );
+        }
+    }
+    check_properties(&config, args.force)?;
+    if !config.world.exists() {
+        info!("the server will generate a new world");
+        state.world_setup_pending = true;
+        state.save(&state_path)?;
+        if let Some(datapacks) = &config.on_new_world.datapacks {
+            info!("installing datapacks from \"{}\"", datapacks.display());
+            fs::create_dir_all(&config.world)?;
+            backups::copy_dir(
+                datapacks,
+                &config.world.join("datapacks"),
+                &GlobMatcher::new::<&str>(&[])?,
+            )?;
+        }
+    }
+    if config.server_type == ServerType::Bedrock {
+        bedrock::install_pack(&config.world)?;
+    }
+    let backups = backup_manager(&config, &world_name);
+    backups.migrate_legacy(&config.backup_dir.join(&world_name), unix_now())?;
+    let players = {
+        let mut players = HashSet::new();
+        info!("{} deadly players:", config.players.len());
+        for player in config.players.drain(..) {
+            let id = state.track(&player);
+            if id == player {
+                info!("    {}", player);
+            } else {
+                info!("    {} ({})", player, id);
+            }
+            players.insert(id);
+        }
+        players
+    };
+    let ignore_phrases = PrefixMatcher::new(&config.ignore_phrases)?;
+    let playtime_path = playtime_path(&config);
+    if faults.check(Fault::CorruptState) && playtime_path.exists() {
+        fs::write(&playtime_path, "corrupted")?;
+    }
+    let playtime = load_playtime(&playtime_path).unwrap_or_else(|err| {
+        warn!("failed to read playtime: {}", err);
+        Duration::from_secs(0)
+    });
+    info!("have played for {} seconds", playtime.as_secs());
+    let lives = match config.lives {
+        Some(start) => Some(Lives::load(
+            playtime_path.with_file_name("lives.json"),
+            start,
+        )?),
+        None => None,
+    };
+    //Drop leftover output from the previous server, keeping console input
+    wrapper.events.discard_output();
+    //Put together the launch command
+    let server_cmd = match &config.jvm {
+        Some(jvm) => java::launch_command(
+            config.java.as_deref().unwrap_or("java"),
+            jvm,
+            &config.server,
+        )?,
+        None => config.server.clone(),
+    };
+    //Fetch the server jar if launching by version
+    let mut server_cmd = match &config.server_version {
+        Some(version) => {
+            let cache_dir = config
+                .state_dir
+                .as_ref()
+                .unwrap_or(&config.backup_dir)
+                .join("server-jars");
+            let jar = download::server_jar(&cache_dir, config.server_flavor, version)?;
+            with_server_jar(&server_cmd, &jar)?
+        }
+        None => server_cmd,
+    };
+    //Check that java can run the server before the JVM fails with a cryptic error, unless
+    //something else launches it
+    if config.attach.is_none() {
+        if let Some(java) = &config.java {
+            if java::is_java(&server_cmd[0]) {
+                server_cmd[0] = java.clone();
+            }
+        }
+        if java::is_java(&server_cmd[0]) {
+            let major = java::major_version(&server_cmd[0])?;
+            info!("using java {} at \"{}\"", major, server_cmd[0]);
+            let required = config
+                .server_version
+                .as_deref()
+                .and_then(java::required_version);
+            if let Some(required) = required {
+                if major < required && !args.force {
+                    return Err(format!(
+                        "minecraft {} needs java {} or newer, but \"{}\" is java {}. \
+                         install a newer Java runtime and point `java` at it, \
+                         or pass `--force` to try anyway",
+                        config.server_version.as_deref().unwrap_or_default(),
+                        required,
+                        server_cmd[0],
+                        major
+                    )
+                    .into());
+                }
+            }
+        }
+    }
+    let death_msg = match config.server_type {
+        ServerType::Java => load_death_messages(&config, &server_cmd, args.force)?,
+        ServerType::Bedrock => {
+            info!("deaths are logged by the behavior pack installed into the world");
+            TemplateMatcher::new::<&str>(&[])?
+        }
+    };
+    //Start server
+    let started_at = SystemTime::now();
+    let mut server = match (&config.attach, &config.rcon) {
+        (Some(attach), Some(rcon)) => {
+            ServerHandle::attach(attach, rcon, &wrapper.console_log, wrapper.events.sender())?
+        }
+        _ => ServerHandle::start(&server_cmd, &wrapper.console_log, wrapper.events.sender())?,
+    };
+    let input = match &config.rcon {
+        Some(rcon) => {
+            info!("sending commands over rcon to {}:{}", rcon.host, rcon.port);
+            rcon::spawn_sender(
+                rcon.clone(),
+                wrapper.console_log.clone(),
+                wrapper.events.sender().clone(),
+            )
+        }
+        None => server.input(),
+    };
+    let announcer = Announcer::new(
+        input.clone(),
+        Duration::from_millis(config.announce_interval_ms),
+        config.message_style.clone(),
+    );
+    //Until the prefix is learned, assume vanilla
+    let flavor_prefix = LinePrefix::for_flavor(config.server_flavor);
+    let (prefix, learning_prefix) = match (config.bracket_count, flavor_prefix) {
+        //`[2024-01-01 12:00:00:000 INFO] `
+        (None, _) if config.server_type == ServerType::Bedrock => (
+            LinePrefix {
+                brackets: 1,
+                logger: false,
+            },
+            None,
+        ),
+        (Some(brackets), _) => (
+            LinePrefix {
+                brackets,
+                logger: false,
+            },
+            None,
+        ),
+        (None, Some(prefix)) => (prefix, None),
+        (None, None) => (
+            LinePrefix::for_flavor(Flavor::Vanilla).unwrap(),
+            Some(PREFIX_LEARN_LINES),
+        ),
+    };
+    let uploader = config.remote_backup.clone().map(|remote| {
+        Uploader::spawn(
+            remote,
+            config.backup_dir.clone(),
+            wrapper.events.sender().clone(),
+        )
+    });
+    let twitch = config
+        .twitch
+        .clone()
+        .map(|conf| Twitch::spawn(conf, wrapper.events.sender().clone()));
+    let history = History::open(&history_path(&config))
+        .map_err(|err| warn!("failed to open the death history: {}", err))
+        .ok();
+    let season_deaths = match history.as_ref().map(|h| h.season_deaths(state.resets + 1)) {
+        Some(Ok(deaths)) => deaths,
+        Some(Err(err)) => {
+            warn!("failed to read the death history: {}", err);
+            state.deaths
+        }
+        None => state.deaths,
+    };
+    let escalation_level = config.escalation_level(playtime, season_deaths);
+    let script = config.script.as_deref().map(Script::load).transpose()?;
+    let plugins = Plugins::load(&config.plugins, wrapper.events.sender().clone())?;
+    let config = Rc::new(config);
+    let mut session = Session {
+        config: Rc::clone(&config),
+        events: &mut wrapper.events,
+        world_path: &config.world,
+        backups,
+        penalties: config.global_engine(escalation_level),
+        escalation_level,
+        season_deaths,
+        fair: config.fair_rolls.enabled.then(Commitment::new),
+        players,
+        death_msg,
+        ignore_phrases,
+        prefix,
+        learning_prefix,
+        input,
+        announcer,
+        notifier: Notifier::new(config.discord_webhook.as_deref(), config.telegram.as_ref()),
+        feed: wrapper.feed.clone(),
+        online_players: HashSet::new(),
+        players_online_since: None,
+        player_since: HashMap::new(),
+        playtime,
+        cooldowns: Cooldowns::default(),
+        line_stats: LineStats::default(),
+        faults,
+        empty_since: None,
+        checkpoint_requested: false,
+        state,
+        state_path,
+        playtime_path,
+        lives,
+        rewind_votes: HashMap::new(),
+        rewind_to: None,
+        memory_warned_at: None,
+        restart_at: None,
+        reload_requested: false,
+        reload_deferred: false,
+        stop_sent: Cell::new(false),
+        pending_deaths: VecDeque::new(),
+        history,
+        questions: VecDeque::new(),
+        survived_at: HashMap::new(),
+        afk: AfkTracker::new(&config.afk),
+        afk_paused: false,
+        ready_deadline: Some(Instant::now() + STARTUP_TIMEOUT),
+        held_announcements: mem::take(&mut wrapper.startup_announcements),
+        next_scheduled: Schedule::next_of(&config.checkpoint_schedule, Local::now()),
+        uploader,
+        hook_runner: HookRunner::new(),
+        script,
+        plugins,
+        twitch,
+    };
+    if server.is_attached() {
+        //Already up, there will be no startup message
+        session.on_ready(None);
+        session.resume_online();
+    }
+    if saving_off {
+        session.cmd(config.server_type.resume_saving().to_string());
+    }
+    let world_path = session.world_path;
+    //Parse output to detect deaths
+    let mut penalty = Penalty::None;
+    let mut restart = false;
+    let mut shutdown = false;
+    let mut crash = None;
+    while let Some(event) = session.events.recv() {
+        match event {
+            Event::Output(line) => {
+                wrapper.recent.push(&line);
+                session.feed.publish("output", json!({ "line": line }));
+                penalty = session.on_output(&line)?;
+            }
+            Event::Console(line) => penalty = session.on_console(&line)?,
+            Event::Api(req) => penalty = session.on_api(req)?,
+            Event::DeathGroupClosed(id) => session.roll_death(id),
+            Event::VoteOpened(id) => session.open_vote(id),
+            Event::Twitch { user, text } => session.on_twitch(&user, &text),
+            Event::PluginTimer { plugin, id } => session.on_plugin_timer(plugin, id),
+            Event::CeremonyDone(id) => penalty = session.finish_death(id),
+            Event::QuestionExpired(id) => session.expire_question(id),
+            Event::UploadDone { name, error } => session.on_upload_done(&name, error),
+            Event::Reload => session.reload(&args.config),
+            Event::Tick => {}
+            Event::Shutdown => {
+                shutdown = true;
+                break;
+            }
+        }
+        if let Penalty::Rewind = penalty {
+            penalty = session.settle_rewind();
+        }
+        if let Penalty::Rewind | Penalty::Reset = penalty {
+            break;
+        }
+        session.revive_due();
+        session.unban_due();
+        //Bookkeep playtime
+        let checkpoint_due = session.update_playtime()?
+            || session.logoff_checkpoint_due()
+            || session.scheduled_checkpoint_due()
+            || mem::take(&mut session.checkpoint_requested);
+        if session.reload_deferred && session.pending_deaths.is_empty() {
+            session.reload(&args.config);
+        }
+        if checkpoint_due && session.config.make_backups {
+            if let Err(err) = session.make_backup() {
+                warn!("failed to make backup: {}", err);
+            }
+        }
+        if session
+            .ready_deadline
+            .is_some_and(|at| Instant::now() >= at)
+        {
+            warn!("server never said it finished starting, carrying on as if it did");
+            session.on_ready(None);
+        }
+        //Restart with the new config if it loads
+        if mem::take(&mut session.reload_requested) {
+            match load_config(&args.config) {
+                Ok(_) => {
+                    info!("config reloaded, restarting the server to apply it");
+                    restart = true;
+                    break;
+                }
+                Err(err) => error!("not reloading, the config is invalid: {}", err),
+            }
+        }
+        //Restart if scheduled
+        if let Some(at) = session.restart_at {
+            if Instant::now() >= at {
+                restart = true;
+                break;
+            }
+        }
+        //Stop if server stopped
+        if let Some(status) = server.try_wait()? {
+            //Exiting with an error without being told to stop is a crash, a clean exit is more
+            //likely a `/stop` from inside the game
+            if !session.stop_sent.get() && !status.success() {
+                crash = Some(format!("server exited abnormally ({})", status));
+            }
+            if !status.success() {
+                error!("server exited abnormally ({})", status);
+                wrapper
+                    .recent
+                    .dump(&config.backup_dir, &format!("server crash ({})", status));
+                let server_dir = world_path.parent().unwrap_or_else(|| Path::new("."));
+                match crash::collect(
+                    server_dir,
+                    &config.backup_dir,
+                    started_at,
+                    &status.to_string(),
+                ) {
+                    Ok((bundle, summary)) => {
+                        warn!("{}", summary);
+                        if crash.is_some() {
+                            crash = Some(summary);
+                        }
+                        info!("crash evidence saved to \"{}\"", bundle.display());
+                    }
+                    Err(err) => warn!("failed to collect crash evidence: {}", err),
+                }
+            }
+            break;
+        }
+    }
+    let recent = &wrapper.recent;
+    match penalty {
+        Penalty::Rewind | Penalty::Reset => {
+            recent.dump(&config.backup_dir, "world penalty");
+        }
+        Penalty::None => {}
+    }
+    //The checkpoint to rewind to was settled on before leaving the loop
+    let rewind_to = match penalty {
+        Penalty::Rewind => session.rewind_to.take(),
+        _ => None,
+    };
+    match (penalty, rewind_to) {
+        (Penalty::None, _) if shutdown => {
+            //Save everything and stop the server before exiting
+            info!("shutting down");
+            session.flush_playtime()?;
+            session.announcer.say("Server is shutting down...");
+            session.announcer.flush();
+            //An attached server keeps running without the wrapper
+            if server.try_wait()?.is_none() && !server.is_attached() {
+                //Bedrock has no `save-all`, but saves on `stop` anyway
+                if session.config.server_type == ServerType::Java {
+                    session.cmd("save-all".to_string());
+                    thread::sleep(Duration::from_secs(2));
+                }
+                session.stop_server(&mut server)?;
+            }
+            if let Some(uploader) = session.uploader.take() {
+                info!("waiting for checkpoint uploads to finish");
+                uploader.finish();
+            }
+            Ok(false)
+        }
+        (Penalty::None, _) if crash.is_some() && config.crash_restart.enabled => {
+            let summary = crash.unwrap_or_default();
+            let policy = &config.crash_restart;
+            let now = Instant::now();
+            let window = Duration::from_secs(policy.window_minutes * 60);
+            let crashes = &mut wrapper.crashes;
+            while crashes.front().map(|at| now - *at > window) == Some(true) {
+                crashes.pop_front();
+            }
+            crashes.push_back(now);
+            session.emit(
+                "server_crash",
+                &json!({ "summary": summary, "recent": crashes.len() }),
+            );
+            session.feed.publish(
+                "crash",
+                json!({ "summary": summary, "recent": crashes.len() }),
+            );
+            if crashes.len() > policy.max_crashes {
+                error!(
+                    "server crashed {} times in {} minutes, giving up",
+                    crashes.len(),
+                    policy.window_minutes
+                );
+                session.notifier.notify(
+                    "Server is crash looping",
+                    format!(
+                        "{}\nCrashed {} times in {} minutes, not restarting again",
+                        summary,
+                        crashes.len(),
+                        policy.window_minutes
+                    ),
+                    notify::RED,
+                );
+                return Ok(false);
+            }
+            let delay = policy.delay(crashes.len());
+            warn!("restarting crashed server in {}s", delay.as_secs());
+            session.notifier.notify(
+                "Server crashed",
+                format!("{}\nRestarting in {}s", summary, delay.as_secs()),
+                notify::RED,
+            );
+            //Wait out the backoff, unless asked to shut down meanwhile
+            let deadline = Instant::now() + delay;
+            while let Some(event) = session
+                .events
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            {
+                if let Event::Shutdown = event {
+                    info!("shutting down instead of restarting");
+                    return Ok(false);
+                }
+            }
+            wrapper
+                .startup_announcements
+                .push("The server crashed and was restarted".to_string());
+            Ok(true)
+        }
+        (Penalty::None, _) if restart => {
+            //Restart server
+            info!("restarting server");
+            session.announcer.say("Restarting...");
+            session.announcer.flush();
+            session.stop_server(&mut server)?;
+            //Continue running
+            Ok(true)
+        }
+        (Penalty::None, _) => {
+            //Stop running
+            Ok(false)
+        }
+        (Penalty::Rewind, None) => {
+            //A rewind never turns into a reset on its own, keep the world and start over
+            error!("no checkpoint was settled on for the rewind, keeping the world");
+            session.announcer.say("Restarting...");
+            session.announcer.flush();
+            session.stop_server(&mut server)?;
+            Ok(true)
+        }
+        (Penalty::Rewind, Some(ckpt)) => {
+            //Restore backup
+            info!("restoring checkpoint {}", ckpt.name);
+            let meta = ckpt.meta();
+            session.notifier.notify(
+                "Rewinding",
+                match &meta {
+                    Some(meta) => format!("Back to {}", describe_meta(meta)),
+                    None => format!("Back to checkpoint {}", ckpt.name),
+                },
+                notify::ORANGE,
+            );
+            let event = json!({ "checkpoint": ckpt.name, "meta": meta });
+            session.emit("restore", &event);
+            session.feed.publish("rewind", event);
+            match &meta {
+                Some(meta) => session.announce_rewind_loss(meta),
+                None => warn!(
+                    "checkpoint \"{}\" has no metadata, playtime and stats will not be rewound",
+                    ckpt.name
+                ),
+            }
+            if let Err(err) = session.backups.check_restore_space(&ckpt, world_path) {
+                session
+                    .announcer
+                    .say("Not enough disk space to rewind! Stopping until an admin frees some.");
+                session.announcer.flush();
+                session
+                    .notifier
+                    .notify("Rewind failed", err.to_string(), notify::RED);
+                session.stop_server(&mut server)?;
+                return Err(err);
+            }
+            session.state.pending_penalty = Some(PendingPenalty::Rewind {
+                checkpoint: ckpt.name.clone(),
+            });
+            session.save_state();
+            //Stop server
+            session.announcer.say("Winding back...");
+            session.announcer.flush();
+            thread::sleep(Duration::from_secs(2));
+            session.hand_off();
+            session.kick_all(config.kick_messages.rewind.as_deref());
+            session.stop_server(&mut server)?;
+            //Restore backup, rolling progress back with it
+            restore_checkpoint(
+                &ckpt,
+                &config,
+                &session.playtime_path,
+                &mut session.state,
+                &session.state_path,
+                session.lives.as_ref(),
+                session.backups.exclude(),
+            )?;
+            session.state.pending_penalty = None;
+            session.state.save(&session.state_path)?;
+            //Tell players where they ended up once the server is back
+            if let Some(meta) = meta {
+                let playtime = fmt_duration(Duration::from_secs(meta.playtime_secs));
+                wrapper.startup_announcements.push(match meta.day {
+                    Some(day) => format!("Rewound to day {}, {}", day, playtime),
+                    None => format!("Rewound to {} of playtime", playtime),
+                });
+            }
+            //Continue running
+            Ok(true)
+        }
+        _ => {
+            //Reset world
+            info!("resetting world");
+            session.notifier.notify(
+                "World reset",
+                format!(
+                    "Season {} is over after {} of playtime, season {} begins",
+                    session.state.resets + 1,
+                    fmt_duration(session.playtime),
+                    session.state.resets + 2
+                ),
+                notify::BLACK,
+            );
+            let event = json!({
+                "attempt": session.state.resets + 2,
+                "season": session.state.resets + 2,
+                "playtime_secs": session.playtime.as_secs(),
+            });
+            session.emit("reset", &event);
+            session.feed.publish("reset", event);
+            session.state.pending_penalty = Some(PendingPenalty::Reset);
+            session.save_state();
+            //Stop server
+            session.announcer.say("Destroying world...");
+            session.announcer.flush();
+            thread::sleep(Duration::from_secs(2));
+            session.hand_off();
+            session.kick_all(config.kick_messages.reset.as_deref());
+            session.stop_server(&mut server)?;
+            //Count the last seconds into the season's stats
+            session.flush_playtime()?;
+            reset_world(
+                &config,
+                &session.backups,
+                &mut session.state,
+                &session.state_path,
+                session.lives.as_mut(),
+            )?;
+            wrapper
+                .startup_announcements
+                .push(format!("Season {} begins!", session.state.resets + 1));
+            //Continue running
+            Ok(true)
+        }
+    }
+}
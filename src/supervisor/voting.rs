@@ -0,0 +1,146 @@
+//! Votes on the outcome of a death, from chat and from Twitch.
+
+use super::{join_names, Session};
+use crate::{
+    announce::{Kind, Message},
+    chat::{self, Ballot, DeathVoteMode},
+    penalty::{Outcome, Roll},
+};
+use log::{debug, info};
+use serde_json::json;
+use std::collections::HashMap;
+
+impl Session<'_> {
+    /// Let players vote on a pending death.
+    pub(super) fn open_vote(&mut self, id: u64) {
+        if let Some(pending) = self.pending_deaths.iter_mut().find(|p| p.id == id) {
+            info!("vote on the death of {} open", join_names(&pending.players));
+            pending.ballots = Some(HashMap::new());
+            if let Some(twitch) = &self.twitch {
+                twitch.say(&format!(
+                    "{} died! Vote !vote mercy or !vote doom within {}s",
+                    join_names(&pending.players),
+                    self.config.chat_commands.death_vote.window_seconds
+                ));
+            }
+        }
+    }
+
+    /// Count a vote on the death currently up for a vote, returning the votes for mercy and doom
+    /// so far, or nothing if there is no vote open.
+    fn cast_ballot(&mut self, voter: String, ballot: Ballot) -> Option<(usize, usize)> {
+        let ballots = self
+            .pending_deaths
+            .iter_mut()
+            .find_map(|pending| pending.ballots.as_mut())?;
+        ballots.insert(voter, ballot);
+        Some(chat::tally(ballots))
+    }
+
+    /// A message from Twitch chat, which counts if it is a `!vote`.
+    pub(super) fn on_twitch(&mut self, user: &str, text: &str) {
+        let ballot = match chat::parse_command(text) {
+            Some(("vote", args)) => Ballot::parse(args),
+            _ => None,
+        };
+        if let Some(ballot) = ballot {
+            if self
+                .cast_ballot(format!("twitch:{}", user), ballot)
+                .is_some()
+            {
+                debug!("twitch viewer {} voted for {}", user, ballot.name());
+            }
+        }
+    }
+
+    /// Count the votes on a death and change its roll to match.
+    pub(super) fn close_vote(
+        &mut self,
+        username: &str,
+        roll: &mut Roll,
+        ballots: &HashMap<String, Ballot>,
+    ) {
+        let conf = &self.config.chat_commands.death_vote;
+        let (mercy, doom) = chat::tally(ballots);
+        let verdict = chat::verdict(conf, mercy, doom);
+        let count = format!("{} for mercy, {} for doom", mercy, doom);
+        info!("vote on the death of {}: {}", username, count);
+        let replace = conf.mode == DeathVoteMode::Replace;
+        let text = match verdict {
+            Some(Ballot::Mercy) => {
+                if replace {
+                    *roll = Roll::forced(Outcome::None);
+                } else {
+                    roll.outcome = Outcome::None;
+                    roll.deadly = false;
+                }
+                format!("The vote is in ({}): mercy! The run goes on", count)
+            }
+            Some(Ballot::Doom) if replace => {
+                *roll = Roll::forced(Outcome::Reset);
+                format!("The vote is in ({}): doom! The world pays for it", count)
+            }
+            Some(Ballot::Doom) => format!("The vote is in ({}): doom! The roll stands", count),
+            None if replace => {
+                let breakdown = roll
+                    .breakdown()
+                    .map(|breakdown| format!(" ({})", breakdown))
+                    .unwrap_or_default();
+                format!(
+                    "The vote is tied ({}), the dice decide: {}{}, {}",
+                    count,
+                    roll.num,
+                    breakdown,
+                    if roll.deadly { "deadly!" } else { "safe" }
+                )
+            }
+            None => format!("The vote is tied ({}), the roll stands", count),
+        };
+        if let Some(twitch) = &self.twitch {
+            twitch.say(&text);
+        }
+        self.announcer.say(Message::new(Kind::Roll, text));
+        self.feed.publish(
+            "vote",
+            json!({
+                "player": username,
+                "mercy": mercy,
+                "doom": doom,
+                "verdict": verdict.map(Ballot::name),
+            }),
+        );
+    }
+
+    /// A `!vote` on the death currently up for a vote.
+    pub(super) fn on_vote(&mut self, username: &str, args: &str) {
+        let ballot = match Ballot::parse(args) {
+            Some(ballot) => ballot,
+            None => {
+                self.cmd(format!(
+                    "tell {} Usage: !vote mercy or !vote doom",
+                    username
+                ));
+                return;
+            }
+        };
+        if !self.is_tracked(username) {
+            self.cmd(format!("tell {} Only tracked players may vote", username));
+            return;
+        }
+        let (mercy, doom) = match self.cast_ballot(username.to_string(), ballot) {
+            Some(tally) => tally,
+            None => {
+                self.cmd(format!("tell {} There is nothing to vote on", username));
+                return;
+            }
+        };
+        info!("{} voted for {}", username, ballot.name());
+        self.cmd(format!(
+            "tell {} You voted for {}, {} for mercy and {} for doom so far",
+            username,
+            ballot.name(),
+            mercy,
+            doom
+        ));
+    }
+}
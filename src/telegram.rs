@@ -0,0 +1,203 @@
+//! A Telegram bot that posts run events to a chat and takes `/status`, `/backup` and `/stop` from
+//! admins, for those who follow the run away from Discord.
+
+use crate::events::Event;
+use serde_derive::Deserialize;
+use std::{error::Error, sync::mpsc::Sender};
+
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct TelegramConfig {
+    /// Token from @BotFather.
+    pub bot_token: String,
+    /// Chat to post run events to.
+    pub chat_id: i64,
+    /// Telegram user ids allowed to send commands.
+    pub admins: Vec<i64>,
+}
+
+/// Post a message to the configured chat.
+pub fn post(conf: &TelegramConfig, text: &str) -> Result<(), Box<dyn Error>> {
+    imp::send(conf, conf.chat_id, text)
+}
+
+/// Start answering commands, or do nothing if the build has no Telegram support.
+pub fn spawn(conf: TelegramConfig, events: Sender<Event>) {
+    imp::spawn(conf, events)
+}
+
+#[cfg(feature = "telegram")]
+mod imp {
+    use super::TelegramConfig;
+    use crate::{
+        api::{ApiAction, ApiRequest},
+        events::Event,
+        fmt_duration, logging,
+    };
+    use log::{info, warn};
+    use serde_json::{json, Value};
+    use std::{
+        error::Error,
+        sync::mpsc::{self, Sender},
+        thread,
+        time::Duration,
+    };
+
+    /// How long Telegram holds a request open waiting for new messages.
+    const POLL_SECS: u64 = 30;
+    /// How long to wait for the main loop, which may be busy with a death or a checkpoint.
+    const REPLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+    fn call(conf: &TelegramConfig, method: &str, body: Value) -> Result<Value, Box<dyn Error>> {
+        let url = format!("https://api.telegram.org/bot{}/{}", conf.bot_token, method);
+        //Errors name the url, which has the token in it
+        let hide = |err: &dyn Error| err.to_string().replace(&conf.bot_token, "<token>");
+        let res: Value = ureq::post(&url)
+            .timeout(Duration::from_secs(POLL_SECS + 10))
+            .send_json(body)
+            .map_err(|err| hide(&err))?
+            .into_json()
+            .map_err(|err| hide(&err))?;
+        Ok(res["result"].clone())
+    }
+
+    pub fn send(conf: &TelegramConfig, chat_id: i64, text: &str) -> Result<(), Box<dyn Error>> {
+        call(
+            conf,
+            "sendMessage",
+            json!({ "chat_id": chat_id, "text": text }),
+        )?;
+        Ok(())
+    }
+
+    pub fn spawn(conf: TelegramConfig, events: Sender<Event>) {
+        if conf.admins.is_empty() {
+            return;
+        }
+        info!("taking telegram commands from {} admins", conf.admins.len());
+        logging::spawn(move || {
+            let mut offset = 0;
+            loop {
+                let updates = call(
+                    &conf,
+                    "getUpdates",
+                    json!({ "offset": offset, "timeout": POLL_SECS, "allowed_updates": ["message"] }),
+                );
+                let updates = match updates {
+                    Ok(Value::Array(updates)) => updates,
+                    Ok(_) => Vec::new(),
+                    Err(err) => {
+                        warn!("failed to read telegram messages: {}", err);
+                        thread::sleep(Duration::from_secs(POLL_SECS));
+                        continue;
+                    }
+                };
+                for update in updates {
+                    offset = offset.max(update["update_id"].as_i64().unwrap_or(0) + 1);
+                    let msg = &update["message"];
+                    let (from, chat, text) = match (
+                        msg["from"]["id"].as_i64(),
+                        msg["chat"]["id"].as_i64(),
+                        msg["text"].as_str(),
+                    ) {
+                        (Some(from), Some(chat), Some(text)) => (from, chat, text),
+                        _ => continue,
+                    };
+                    let reply = answer(&conf, &events, from, text);
+                    if let Err(err) = send(&conf, chat, &reply) {
+                        warn!("failed to answer on telegram: {}", err);
+                    }
+                }
+            }
+        });
+    }
+
+    /// Describe `/status`, from the same status the HTTP API gives.
+    fn describe_status(status: &Value) -> String {
+        let num = |key: &str| status.get(key).and_then(Value::as_u64).unwrap_or(0);
+        let mut text = format!(
+            "Attempt {}, played for {}\n{} deaths, {} advancements",
+            num("attempt"),
+            fmt_duration(Duration::from_secs(num("playtime_secs"))),
+            num("deaths"),
+            num("advancements"),
+        );
+        let online = status
+            .get("online_players")
+            .and_then(Value::as_array)
+            .map(|online| {
+                online
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            })
+            .unwrap_or_default();
+        if online.is_empty() {
+            text += "\nNobody online";
+        } else {
+            text += &format!("\nOnline: {}", online);
+        }
+        if let Some(roll) = status.get("last_roll").filter(|roll| !roll.is_null()) {
+            text += &format!(
+                "\nLast roll: {} for {} ({})",
+                roll["roll"],
+                roll["player"].as_str().unwrap_or("?"),
+                roll["outcome"].as_str().unwrap_or("?")
+            );
+        }
+        if status.get("won").is_some_and(|won| !won.is_null()) {
+            text += "\nThe run is won!";
+        }
+        text
+    }
+
+    fn answer(conf: &TelegramConfig, events: &Sender<Event>, from: i64, text: &str) -> String {
+        if !conf.admins.contains(&from) {
+            warn!(
+                "ignoring telegram command from user {}, who is not an admin",
+                from
+            );
+            return "You are not allowed to control this server".to_string();
+        }
+        //Commands may be addressed to the bot, as in `/status@somebot`
+        let cmd = text.split_whitespace().next().unwrap_or_default();
+        let cmd = cmd.split('@').next().unwrap_or_default();
+        info!("telegram user {} sent {}", from, cmd);
+        let action = match cmd {
+            "/status" => ApiAction::Status,
+            "/backup" => ApiAction::Backup(None),
+            "/stop" => {
+                return match events.send(Event::Shutdown) {
+                    Ok(()) => "Stopping the server".to_string(),
+                    Err(_) => "The wrapper is not running".to_string(),
+                };
+            }
+            _ => return "Commands: /status, /backup, /stop".to_string(),
+        };
+        let (tx, rx) = mpsc::channel();
+        let sent = events.send(Event::Api(ApiRequest { action, reply: tx }));
+        match sent.ok().and_then(|()| rx.recv_timeout(REPLY_TIMEOUT).ok()) {
+            Some(Ok(res)) if cmd == "/status" => describe_status(&res),
+            Some(Ok(_)) => "Checkpoint queued".to_string(),
+            Some(Err(msg)) => msg,
+            None => "The wrapper is busy, try again later".to_string(),
+        }
+    }
+}
+
+#[cfg(not(feature = "telegram"))]
+mod imp {
+    use super::TelegramConfig;
+    use crate::events::Event;
+    use log::warn;
+    use std::{error::Error, sync::mpsc::Sender};
+
+    pub fn send(_conf: &TelegramConfig, _chat_id: i64, _text: &str) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    pub fn spawn(_conf: TelegramConfig, _events: Sender<Event>) {
+        warn!("`telegram` needs the `telegram` feature, which this build was compiled without");
+    }
+}
@@ -0,0 +1,144 @@
+//! Reading and writing a Twitch channel's chat over IRC, so that stream viewers can take part in
+//! death votes.
+
+use crate::{events::Event, logging};
+use log::{info, warn};
+use serde_derive::Deserialize;
+use std::{
+    error::Error,
+    io::{prelude::*, BufReader},
+    net::{Shutdown, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Sender,
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+/// How long to wait before connecting again after losing the connection.
+const RECONNECT_DELAY: Duration = Duration::from_secs(15);
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct TwitchConfig {
+    /// Channel to join, without the leading `#`.
+    pub channel: String,
+    /// Account to chat as, in lowercase.
+    pub nick: String,
+    /// Chat token of that account, `oauth:...`.
+    pub oauth_token: String,
+    /// IRC server, as `host:port`. The plain text port, so the token is sent unencrypted.
+    pub server: String,
+}
+impl Default for TwitchConfig {
+    fn default() -> Self {
+        Self {
+            channel: String::new(),
+            nick: String::new(),
+            oauth_token: String::new(),
+            server: "irc.chat.twitch.tv:6667".to_string(),
+        }
+    }
+}
+
+/// A connection to Twitch chat, kept up on a background thread until dropped.
+pub struct Twitch {
+    channel: String,
+    stream: Arc<Mutex<Option<TcpStream>>>,
+    stop: Arc<AtomicBool>,
+}
+impl Twitch {
+    /// Join the channel, sending every chat message to `events`.
+    pub fn spawn(conf: TwitchConfig, events: Sender<Event>) -> Self {
+        let channel = conf.channel.trim_start_matches('#').to_lowercase();
+        let stream = Arc::new(Mutex::new(None));
+        let stop = Arc::new(AtomicBool::new(false));
+        {
+            let channel = channel.clone();
+            let stream = stream.clone();
+            let stop = stop.clone();
+            logging::spawn(move || {
+                while !stop.load(Ordering::SeqCst) {
+                    if let Err(err) = run(&conf, &channel, &stream, &events) {
+                        warn!("twitch chat connection failed: {}", err);
+                    }
+                    *stream.lock().unwrap() = None;
+                    if stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    thread::sleep(RECONNECT_DELAY);
+                }
+            });
+        }
+        Self {
+            channel,
+            stream,
+            stop,
+        }
+    }
+
+    /// Post a message to the channel, if connected.
+    pub fn say(&self, text: &str) {
+        if let Some(stream) = self.stream.lock().unwrap().as_mut() {
+            if let Err(err) = write!(stream, "PRIVMSG #{} :{}\r\n", self.channel, text) {
+                warn!("failed to post to twitch chat: {}", err);
+            }
+        }
+    }
+}
+impl Drop for Twitch {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(stream) = self.stream.lock().unwrap().as_ref() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+/// Connect and relay chat messages until the connection drops.
+fn run(
+    conf: &TwitchConfig,
+    channel: &str,
+    shared: &Mutex<Option<TcpStream>>,
+    events: &Sender<Event>,
+) -> Result<(), Box<dyn Error>> {
+    let mut stream = TcpStream::connect(&conf.server)?;
+    write!(
+        stream,
+        "PASS {}\r\nNICK {}\r\nJOIN #{}\r\n",
+        conf.oauth_token,
+        conf.nick.to_lowercase(),
+        channel
+    )?;
+    *shared.lock().unwrap() = Some(stream.try_clone()?);
+    info!("joined twitch channel #{}", channel);
+    for line in BufReader::new(stream.try_clone()?).lines() {
+        let line = line?;
+        if let Some(server) = line.strip_prefix("PING ") {
+            write!(stream, "PONG {}\r\n", server)?;
+        } else if line.contains("Login authentication failed") {
+            return Err("login failed, check `nick` and `oauth_token`".into());
+        } else if let Some((user, text)) = parse_privmsg(&line) {
+            let sent = events.send(Event::Twitch {
+                user: user.to_string(),
+                text: text.to_string(),
+            });
+            if sent.is_err() {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Pick the sender and text out of a line like `:nick!nick@nick.tmi.twitch.tv PRIVMSG #chan :hi`.
+fn parse_privmsg(line: &str) -> Option<(&str, &str)> {
+    let rest = line.strip_prefix(':')?;
+    let (source, rest) = rest.split_once(' ')?;
+    let rest = rest.strip_prefix("PRIVMSG ")?;
+    let (_channel, text) = rest.split_once(" :")?;
+    let user = source.split('!').next()?;
+    Some((user, text))
+}